@@ -0,0 +1,419 @@
+use std::io::{IsTerminal, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use bytes::Bytes;
+use clap::Parser;
+use futures::{StreamExt, stream};
+use http::Method;
+use http::HeaderMap;
+use http::header::{CONTENT_TYPE, HeaderName, HeaderValue};
+use quic_demo::client::{Client, RequestBody, RequestError};
+use quic_demo::fingerprint::Fingerprint;
+use quic_demo::logging;
+use quic_demo::priority::{self, Priority};
+use quic_demo::server::check_initial_rtt;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+#[derive(Parser)]
+#[command(about = "HTTP/3 demo client")]
+struct Args {
+    /// Server to connect to, as HOST:PORT. Host names are resolved and their addresses raced,
+    /// IPv6 first (Happy Eyeballs)
+    #[arg(long, default_value = "127.0.0.1:4433", value_name = "HOST:PORT", value_parser = parse_server)]
+    server: (String, u16),
+
+    /// Local address to bind the UDP socket to, e.g. "0.0.0.0:5000" for a fixed source port or
+    /// "192.0.2.10:0" to send from one interface [default: an ephemeral port on every address]
+    #[arg(long, value_name = "ADDR:PORT")]
+    local_addr: Option<SocketAddr>,
+
+    /// SNI to send in the TLS handshake [default: the --server host]
+    #[arg(long, value_name = "NAME")]
+    server_name: Option<String>,
+
+    /// :authority (Host) of the requests, e.g. to test virtual hosts [default: the server name]
+    #[arg(long)]
+    authority: Option<String>,
+
+    /// Extra header to send with every request, as "Name: Value" (repeatable)
+    #[arg(short = 'H', long = "header", value_name = "NAME: VALUE", value_parser = parse_header)]
+    headers: Vec<(HeaderName, HeaderValue)>,
+
+    /// Request method; defaults to POST when --data is given, GET otherwise
+    #[arg(short = 'X', long, value_parser = parse_method)]
+    method: Option<Method>,
+
+    /// Request body: inline text, or @path to stream a file
+    #[arg(short, long, value_name = "DATA | @FILE", value_parser = parse_body)]
+    data: Option<RequestBody>,
+
+    /// Number of requests in flight at once, each on its own stream of the one connection
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    concurrency: u16,
+
+    /// Only trust a server whose certificate has this SHA-256 fingerprint (printed by the server
+    /// on startup). Without it, certificate verification is skipped.
+    #[arg(long, value_name = "FINGERPRINT")]
+    pin_sha256: Option<Fingerprint>,
+
+    /// PEM certificate chain to authenticate with when the server asks for one (mutual TLS)
+    #[arg(long, value_name = "FILE", requires = "key")]
+    cert: Option<PathBuf>,
+
+    /// PEM private key of --cert
+    #[arg(long, value_name = "FILE", requires = "cert")]
+    key: Option<PathBuf>,
+
+    /// Urgency to request responses with, 0 (most urgent) to 7, sent in the `priority` header
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=7))]
+    urgency: Option<u8>,
+
+    /// Mark responses as incremental in the `priority` header
+    #[arg(long)]
+    incremental: bool,
+
+    /// Print every response header after the status line, one "Header: name: value" line each,
+    /// followed by trailers as "Trailer: name: value"
+    #[arg(short = 'i', long)]
+    show_headers: bool,
+
+    /// Print the HTTP/3 SETTINGS the server sent, once they arrive after the handshake
+    #[arg(long)]
+    show_settings: bool,
+
+    /// Write response bodies to stdout byte for byte, whatever their Content-Type. Without it,
+    /// binary bodies are only written to stdout when it isn't a terminal
+    #[arg(long)]
+    binary: bool,
+
+    /// Write the response body to this file as received instead of printing it (one path only)
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// RTT to assume before the first measurement, e.g. "600ms" over satellite links
+    /// (1ms to 10s) [default: quinn's, 333ms]
+    #[arg(long, value_name = "DURATION", value_parser = parse_initial_rtt)]
+    initial_rtt: Option<Duration>,
+
+    /// Keep retrying the first connection with backoff for up to this long, e.g. "30s" while the
+    /// server is still starting. Later reconnects don't wait
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    wait_for_server: Option<Duration>,
+
+    /// After the requests, wait for this many custom unidirectional streams from the server
+    /// (e.g. opened by /push) and print them
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    uni_streams: u32,
+
+    /// Send the whole set of requests this many times over the same connection and report how
+    /// long it took, e.g. to soak-test stream cleanup. Only the first round's responses are
+    /// printed.
+    #[arg(long, default_value_t = 1, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    iterations: u32,
+
+    /// With --iterations, close the connection after every round so the next one reconnects,
+    /// for comparing against connection reuse
+    #[arg(long)]
+    reconnect_each_iteration: bool,
+
+    /// Instead of the paths, download this many megabytes of zeros from /debug/stream over one
+    /// stream and report the throughput as it goes and on average. The server needs
+    /// --generate-route, and sends 10000 MB at most
+    #[arg(long, value_name = "MB", conflicts_with_all = ["paths", "data", "output", "iterations"], value_parser = clap::value_parser!(u64).range(1..))]
+    download_benchmark: Option<u64>,
+
+    /// How often --download-benchmark prints the rate of the last interval
+    #[arg(long, default_value = "1s", value_name = "DURATION", value_parser = humantime::parse_duration)]
+    sample_interval: Duration,
+
+    /// Paths to request, in order
+    #[arg(default_values = ["/", "/test", "/health", "/unknown"])]
+    paths: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    logging::init();
+    // Parsed up front so malformed headers are reported before connecting
+    let args = Args::parse();
+    if args.output.is_some() && args.paths.len() > 1 {
+        anyhow::bail!("--output takes the body of a single path, got {}", args.paths.len());
+    }
+    let method = args.method.clone().unwrap_or(if args.data.is_some() { Method::POST } else { Method::GET });
+    rustls::crypto::aws_lc_rs::default_provider().install_default().unwrap();
+
+    let (host, port) = &args.server;
+    let shown = if host.contains(':') { format!("[{}]:{}", host, port) } else { format!("{}:{}", host, port) };
+    println!("Connecting to server at {}...", shown);
+
+    let mut builder = Client::builder_for_host(host, *port);
+    if let Some(name) = &args.server_name {
+        builder = builder.server_name(name);
+    }
+    if let Some(authority) = &args.authority {
+        builder = builder.authority(authority);
+    }
+    if let Some(fingerprint) = args.pin_sha256 {
+        builder = builder.pin_certificate(fingerprint);
+    }
+    if let Some(rtt) = args.initial_rtt {
+        builder = builder.initial_rtt(rtt);
+    }
+    if let Some(wait) = args.wait_for_server {
+        builder = builder.wait_for_server(wait);
+    }
+    if let Some(addr) = args.local_addr {
+        builder = builder.local_addr(addr);
+    }
+    if let (Some(cert), Some(key)) = (&args.cert, &args.key) {
+        let chain = CertificateDer::pem_file_iter(cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .with_context(|| format!("cannot read certificates from {}", cert.display()))?;
+        let key = PrivateKeyDer::from_pem_file(key).with_context(|| format!("cannot read private key from {}", key.display()))?;
+        builder = builder.client_certificate(chain, key);
+    }
+    let client = builder.connect().await?;
+
+    if let Some(info) = client.connection_info().await {
+        println!(
+            "Connected! ALPN: {}, QUIC version: {:#010x}, 0-RTT: {:?}, initial RTT: {:?}",
+            info.alpn.as_deref().unwrap_or("none"),
+            info.quic_version,
+            info.zero_rtt,
+            info.initial_rtt,
+        );
+    }
+    if args.show_settings {
+        println!("Server SETTINGS: {}", client.peer_settings().await?);
+    }
+
+    if let Some(mb) = args.download_benchmark {
+        benchmark_download(&client, &args, mb).await?;
+        client.close().await;
+        return Ok(());
+    }
+
+    let priority = (args.urgency.is_some() || args.incremental).then(|| Priority {
+        urgency: args.urgency.unwrap_or(Priority::default().urgency),
+        incremental: args.incremental,
+    });
+
+    // Rounds are timed without the closing handshake of --reconnect-each-iteration, so they
+    // compare reconnecting (which lands in the next round) against reuse
+    let (mut elapsed, mut slowest) = (Duration::ZERO, Duration::ZERO);
+    for iteration in 0..args.iterations {
+        let round = Instant::now();
+        run_requests(&client, &args, &method, priority, iteration == 0).await?;
+        elapsed += round.elapsed();
+        slowest = slowest.max(round.elapsed());
+        if args.reconnect_each_iteration && iteration + 1 < args.iterations {
+            client.close().await;
+        }
+    }
+    if args.iterations > 1 {
+        println!(
+            "\n{} iterations of {} request(s) in {:?} ({:?} per iteration on average, slowest {:?}) over {} connection(s)",
+            args.iterations,
+            args.paths.len(),
+            elapsed,
+            elapsed / args.iterations,
+            slowest,
+            client.connections_established(),
+        );
+    }
+
+    for _ in 0..args.uni_streams {
+        let Some(data) = client.accept_uni(1024 * 1024).await? else {
+            println!("\nConnection closed before the server opened a unidirectional stream");
+            break;
+        };
+        println!("\n--- Unidirectional stream from server ---");
+        println!("Data: {}", String::from_utf8_lossy(&data));
+    }
+
+    println!("\n✅ All requests completed successfully!");
+
+    client.close().await;
+
+    Ok(())
+}
+
+// Send every path once. Requests run concurrently on their own streams; `buffered` yields the
+// responses in path order so the output stays stable.
+async fn run_requests(client: &Client, args: &Args, method: &Method, priority: Option<Priority>, print: bool) -> anyhow::Result<()> {
+    let mut responses = stream::iter(&args.paths)
+        .map(|path| {
+            let mut req = client.request(method.clone(), path);
+            if let Some(priority) = priority {
+                req = req.header(priority::HEADER, priority.to_string());
+            }
+            for (name, value) in &args.headers {
+                req = req.header(name, value);
+            }
+            let body = args.data.as_ref();
+            async move { client.send(req.body(())?, body).await }
+        })
+        .buffered(args.concurrency.into());
+
+    for path in &args.paths {
+        let response = responses.next().await.context("missing response")?;
+        if print {
+            println!("\n--- Requesting {} ---", path);
+        }
+        let response = match response {
+            Ok(response) => response,
+            // h3's codes print by name (H3_REQUEST_CANCELLED, ...), unknown ones as hex
+            Err(e) => match e.downcast_ref::<RequestError>() {
+                Some(RequestError::Reset(code)) => {
+                    if print {
+                        println!("Stream reset by server: {}", code);
+                    }
+                    continue;
+                }
+                _ => return Err(e),
+            },
+        };
+        if !print {
+            continue;
+        }
+
+        if let Some(code) = response.upload_stopped {
+            println!("Server stopped reading the request body ({}), read its response", code);
+        }
+        println!("Status: {}", response.status);
+        if args.show_headers {
+            print_fields("Header", &response.headers);
+            if let Some(trailers) = &response.trailers {
+                print_fields("Trailer", trailers);
+            }
+        }
+        print_body(args, &response.headers, &response.body)?;
+    }
+    Ok(())
+}
+
+// Time one large download: the rate over each sample interval while the body arrives, then the
+// average over all of it. It's a single stream, so this stresses flow and congestion control
+// rather than request handling.
+async fn benchmark_download(client: &Client, args: &Args, mb: u64) -> anyhow::Result<()> {
+    let mut req = client.request(Method::GET, &format!("/debug/stream?mb={}", mb));
+    for (name, value) in &args.headers {
+        req = req.header(name, value);
+    }
+    println!("\n--- Downloading {} MB ---", mb);
+    let start = Instant::now();
+    let (mut sample_start, mut sample_bytes) = (start, 0);
+    let download = client
+        .download(req.body(())?, |len| {
+            sample_bytes += len as u64;
+            let elapsed = sample_start.elapsed();
+            if elapsed >= args.sample_interval {
+                println!("{:>10.2?}  {:>10.2} MB/s", start.elapsed(), megabytes_per_second(sample_bytes, elapsed));
+                (sample_start, sample_bytes) = (Instant::now(), 0);
+            }
+        })
+        .await?;
+    let elapsed = start.elapsed();
+    if !download.status.is_success() {
+        anyhow::bail!("the server answered {}", download.status);
+    }
+    println!(
+        "Received {} bytes in {:.2?}: {:.2} MB/s on average",
+        download.received,
+        elapsed,
+        megabytes_per_second(download.received, elapsed),
+    );
+    Ok(())
+}
+
+fn megabytes_per_second(bytes: u64, elapsed: Duration) -> f64 {
+    bytes as f64 / 1e6 / elapsed.as_secs_f64()
+}
+
+// Text is printed as text (lossily, as a few stray bytes don't make it binary); anything else is
+// written out untouched, unless that would dump it onto a terminal
+fn print_body(args: &Args, headers: &HeaderMap, body: &[u8]) -> anyhow::Result<()> {
+    if let Some(path) = &args.output {
+        std::fs::write(path, body).with_context(|| format!("cannot write {}", path.display()))?;
+        println!("Body: {} bytes written to {}", body.len(), path.display());
+        return Ok(());
+    }
+    if !args.binary && is_text(headers, body) {
+        println!("Body: {}", String::from_utf8_lossy(body));
+        return Ok(());
+    }
+    let mut stdout = std::io::stdout().lock();
+    if !args.binary && stdout.is_terminal() {
+        let content_type = headers.get(CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or("no Content-Type");
+        writeln!(stdout, "Body: {} bytes of binary data ({}); use --output FILE or --binary to see them", body.len(), content_type)?;
+        return Ok(());
+    }
+    write!(stdout, "Body: ")?;
+    stdout.write_all(body)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+// Whether a body is text by its Content-Type, or by being valid UTF-8 if it has none
+fn is_text(headers: &HeaderMap, body: &[u8]) -> bool {
+    let Some(content_type) = headers.get(CONTENT_TYPE).and_then(|value| value.to_str().ok()) else {
+        return std::str::from_utf8(body).is_ok();
+    };
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    let Some((kind, subtype)) = mime.split_once('/') else { return false };
+    kind == "text"
+        || (kind == "application"
+            && (matches!(subtype, "json" | "x-ndjson" | "xml" | "javascript" | "x-www-form-urlencoded")
+                || subtype.ends_with("+json")
+                || subtype.ends_with("+xml")))
+}
+
+// One line per field, in the order received; names are lowercase in HTTP/3
+fn print_fields(kind: &str, fields: &HeaderMap) {
+    for (name, value) in fields {
+        println!("{}: {}: {}", kind, name, String::from_utf8_lossy(value.as_bytes()));
+    }
+}
+
+// Parse `--server`; IPv6 literals need brackets, as in "[::1]:4433"
+fn parse_server(raw: &str) -> Result<(String, u16), String> {
+    let (host, port) = raw
+        .rsplit_once(':')
+        .ok_or_else(|| format!("expected HOST:PORT, got \"{}\"", raw))?;
+    let port = port.parse().map_err(|_| format!("invalid port \"{}\"", port))?;
+    let host = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+    Ok((host.to_string(), port))
+}
+
+// Parse a `--header` argument of the form "Name: Value"
+fn parse_header(raw: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = raw
+        .split_once(':')
+        .ok_or_else(|| format!("expected \"Name: Value\", got \"{}\"", raw))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("invalid header name \"{}\"", name.trim()))?;
+    let value = HeaderValue::from_str(value.trim())
+        .map_err(|_| format!("invalid value for header \"{}\"", name))?;
+    Ok((name, value))
+}
+
+fn parse_body(raw: &str) -> Result<RequestBody, String> {
+    Ok(match raw.strip_prefix('@') {
+        Some(path) => RequestBody::File(PathBuf::from(path)),
+        None => RequestBody::Inline(Bytes::copy_from_slice(raw.as_bytes())),
+    })
+}
+
+fn parse_method(raw: &str) -> Result<Method, String> {
+    Method::from_bytes(raw.to_ascii_uppercase().as_bytes())
+        .map_err(|_| format!("invalid method \"{}\"", raw))
+}
+
+fn parse_initial_rtt(raw: &str) -> Result<Duration, String> {
+    let rtt = humantime::parse_duration(raw).map_err(|e| e.to_string())?;
+    check_initial_rtt(rtt).map_err(|e| e.to_string())
+}
@@ -0,0 +1,531 @@
+// Request bodies read incrementally from the stream, so handlers can process large uploads
+// (hash, forward, write to disk) without holding them in memory. A body knows its declared
+// Content-Length and the server's size limit, and stops with an error as soon as either is
+// exceeded, or when the client stops sending for longer than the read timeout. A body sent with
+// a Content-Encoding is decoded as it arrives. A body sent with a Content-Digest is hashed on the
+// way in and checked against it at its end, so there is no second pass over the data.
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+
+use bytes::{Buf, Bytes};
+use h3::error::{Code, StreamError};
+use h3::server::RequestStream;
+use http::Request;
+use http::header::{CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH, EXPECT, HeaderName, TE, TRANSFER_ENCODING, UPGRADE};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::compress::{Decoded, Decoder, Encoding};
+use crate::priority;
+
+/// How large a body given to [`Body::decode`] may grow once decoded when it has no
+/// [`Body::limit`]; set a limit to allow more.
+pub const MAX_DECODED_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The receiving half of a request stream. Chunks are pulled from the client as they are asked
+/// for, so flow control holds back a client that sends faster than the handler reads.
+pub struct Body {
+    stream: RequestStream<priority::RecvStream, Bytes>,
+    limit: Option<u64>,
+    content_length: Option<u64>,
+    read_timeout: Option<Duration>,
+    decoder: Option<Decoder>,
+    // The hash of the body as sent so far, and the digest it must end up with
+    digest: Option<(Sha256, [u8; 32])>,
+    received: u64,
+    decoded: u64,
+    ended: bool,
+    #[cfg(feature = "trace-bodies")]
+    trace: crate::trace_bodies::Capture,
+}
+
+/// Why reading a body failed.
+#[derive(Debug)]
+pub enum BodyError {
+    /// The body is longer than the limit given to [`Body::limit`] or [`Body::read_to_vec`],
+    /// before or after decoding, or decodes to more than [`MAX_DECODED_SIZE`] without a limit
+    TooLarge { limit: u64 },
+    /// The body doesn't have the length its Content-Length header declared. The request is
+    /// malformed (RFC 9114, section 4.1.2) and its stream should be reset with
+    /// `H3_MESSAGE_ERROR`.
+    LengthMismatch { declared: u64, received: u64 },
+    /// No data arrived within the timeout given to [`Body::read_timeout`]. The body has already
+    /// asked the client to stop sending; the response side is left to the caller.
+    TimedOut { after: Duration },
+    /// The SHA-256 of the body as sent isn't the one given to [`Body::expect_digest`]. The
+    /// request should get 400 Bad Request.
+    DigestMismatch { expected: [u8; 32], actual: [u8; 32] },
+    /// The body isn't valid in the coding given to [`Body::decode`], or stops short of its end.
+    /// The request should get 400 Bad Request.
+    Decode(io::Error),
+    /// The sink given to [`Body::copy_hashed`] failed
+    Write(io::Error),
+    Stream(StreamError),
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::TooLarge { limit } => write!(f, "request body exceeds {} bytes", limit),
+            BodyError::LengthMismatch { declared, received } if received > declared => {
+                write!(f, "request body is longer than its Content-Length of {} bytes", declared)
+            }
+            BodyError::LengthMismatch { declared, received } => {
+                write!(f, "request body ended after {} of its declared {} bytes", received, declared)
+            }
+            BodyError::TimedOut { after } => write!(f, "no request body data arrived for {:?}", after),
+            BodyError::DigestMismatch { expected, actual } => write!(
+                f,
+                "request body has SHA-256 {}, not the {} its Content-Digest declared",
+                hex(actual),
+                hex(expected)
+            ),
+            BodyError::Decode(e) => write!(f, "failed to decode request body: {}", e),
+            BodyError::Write(e) => write!(f, "failed to write request body: {}", e),
+            BodyError::Stream(e) => write!(f, "failed to read request body: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BodyError {}
+
+impl From<StreamError> for BodyError {
+    fn from(e: StreamError) -> Self {
+        BodyError::Stream(e)
+    }
+}
+
+impl Body {
+    pub fn new(stream: RequestStream<priority::RecvStream, Bytes>) -> Self {
+        Body {
+            #[cfg(feature = "trace-bodies")]
+            trace: crate::trace_bodies::Capture::new("Request", stream.id()),
+            stream,
+            limit: None,
+            content_length: None,
+            read_timeout: None,
+            decoder: None,
+            digest: None,
+            received: 0,
+            decoded: 0,
+            ended: false,
+        }
+    }
+
+    /// Fail with [`BodyError::TooLarge`] once more than `limit` bytes arrive. `None` means no
+    /// limit.
+    pub fn limit(mut self, limit: Option<u64>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Fail with [`BodyError::LengthMismatch`] if the body turns out longer or shorter than
+    /// `len`, usually the request's [`content_length`].
+    pub fn content_length(mut self, len: Option<u64>) -> Self {
+        self.content_length = len;
+        self
+    }
+
+    /// Fail with [`BodyError::TimedOut`] when a chunk takes longer than `timeout` to arrive, and
+    /// reset the receiving side with `H3_REQUEST_CANCELLED`, so a client that stalls mid-body
+    /// doesn't hold the stream forever. `None` (the default) waits as long as the connection
+    /// lives.
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Undo `encoding`, usually the request's [`content_encoding`], so chunks come out decoded,
+    /// [`DECODE_STEP`](crate::compress::DECODE_STEP) bytes at most at a time. The limit then
+    /// applies to the decoded size as well, checked after every step, or [`MAX_DECODED_SIZE`]
+    /// does without a limit, so a small compressed body can't expand into an unbounded one.
+    /// `None` reads the body as it is.
+    pub fn decode(mut self, encoding: Option<Encoding>) -> Self {
+        self.decoder = encoding.map(Decoder::new);
+        self
+    }
+
+    /// Hash the body as it arrives, before any decoding, and fail with
+    /// [`BodyError::DigestMismatch`] at its end unless its SHA-256 is `expected`, usually the
+    /// request's [`content_digest`]. `None` (the default) checks nothing.
+    pub fn expect_digest(mut self, expected: Option<[u8; 32]>) -> Self {
+        self.digest = expected.map(|expected| (Sha256::new(), expected));
+        self
+    }
+
+    /// Ask the client to stop sending the rest of the body, e.g. after rejecting it, with `code`
+    /// as the reason. The response can still be sent.
+    pub fn stop_sending(&mut self, code: Code) {
+        self.stream.stop_sending(code);
+    }
+
+    /// Body bytes read so far, as sent (before decoding).
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// The next chunk, or `None` at the end of the body.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, BodyError> {
+        let limit = self.limit.unwrap_or(MAX_DECODED_SIZE);
+        loop {
+            let Some(decoder) = &mut self.decoder else {
+                return self.raw_chunk().await;
+            };
+            match decoder.read().map_err(BodyError::Decode)? {
+                Decoded::Data(decoded) => {
+                    self.decoded += decoded.len() as u64;
+                    if self.decoded > limit {
+                        return Err(BodyError::TooLarge { limit });
+                    }
+                    return Ok(Some(decoded));
+                }
+                Decoded::NeedsInput => {
+                    let raw = self.raw_chunk().await?;
+                    if let Some(decoder) = &mut self.decoder {
+                        decoder.feed(raw);
+                    }
+                }
+                Decoded::End => {
+                    // Read to the end of the body, so its length and digest are still checked
+                    while let Some(raw) = self.raw_chunk().await? {
+                        if !raw.is_empty() {
+                            let e = io::Error::new(io::ErrorKind::InvalidData, "data after the end of the compressed stream");
+                            return Err(BodyError::Decode(e));
+                        }
+                    }
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    // The next chunk as sent
+    async fn raw_chunk(&mut self) -> Result<Option<Bytes>, BodyError> {
+        if self.ended {
+            return Ok(None);
+        }
+        let next = match self.read_timeout {
+            Some(after) => match tokio::time::timeout(after, self.stream.recv_data()).await {
+                Ok(next) => next,
+                Err(_) => {
+                    self.stream.stop_sending(Code::H3_REQUEST_CANCELLED);
+                    return Err(BodyError::TimedOut { after });
+                }
+            },
+            None => self.stream.recv_data().await,
+        };
+        let Some(mut chunk) = next? else {
+            self.ended = true;
+            if let Some(declared) = self.content_length
+                && declared != self.received
+            {
+                return Err(BodyError::LengthMismatch { declared, received: self.received });
+            }
+            if let Some((hasher, expected)) = self.digest.take() {
+                let actual: [u8; 32] = hasher.finalize().into();
+                if actual != expected {
+                    return Err(BodyError::DigestMismatch { expected, actual });
+                }
+            }
+            return Ok(None);
+        };
+        let chunk = chunk.copy_to_bytes(chunk.remaining());
+        self.received += chunk.len() as u64;
+        if let Some((hasher, _)) = &mut self.digest {
+            hasher.update(&chunk);
+        }
+        #[cfg(feature = "trace-bodies")]
+        self.trace.add(&chunk);
+        if let Some(limit) = self.limit
+            && self.received > limit
+        {
+            return Err(BodyError::TooLarge { limit });
+        }
+        if let Some(declared) = self.content_length
+            && self.received > declared
+        {
+            return Err(BodyError::LengthMismatch { declared, received: self.received });
+        }
+        Ok(Some(chunk))
+    }
+
+    /// Read the whole body into memory, failing with [`BodyError::TooLarge`] as soon as it
+    /// grows past `limit` bytes.
+    pub async fn read_to_vec(&mut self, limit: usize) -> Result<Vec<u8>, BodyError> {
+        let mut body = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            if body.len() + chunk.len() > limit {
+                return Err(BodyError::TooLarge { limit: limit as u64 });
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+
+    /// Hand each chunk to `f` as it arrives, waiting for `f` before reading the next one.
+    /// Returns the total number of bytes read.
+    pub async fn for_each_chunk<F, Fut>(&mut self, mut f: F) -> Result<u64, BodyError>
+    where
+        F: FnMut(Bytes) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut total = 0;
+        while let Some(chunk) = self.chunk().await? {
+            total += chunk.len() as u64;
+            f(chunk).await;
+        }
+        Ok(total)
+    }
+
+    /// Write the body to `sink` as it arrives, hashing it on the way, and return its length and
+    /// SHA-256, both of the data as read (after decoding). `tokio::io::sink()` just hashes it,
+    /// and a `Vec<u8>` collects it too.
+    pub async fn copy_hashed<W: AsyncWrite + Unpin>(&mut self, sink: &mut W) -> Result<(u64, [u8; 32]), BodyError> {
+        let mut hasher = Sha256::new();
+        let mut total = 0;
+        while let Some(chunk) = self.chunk().await? {
+            hasher.update(&chunk);
+            sink.write_all(&chunk).await.map_err(BodyError::Write)?;
+            total += chunk.len() as u64;
+        }
+        sink.flush().await.map_err(BodyError::Write)?;
+        Ok((total, hasher.finalize().into()))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The Content-Length `req` declares, `None` if it has none, or an error if the header is
+/// malformed or repeated with different values.
+pub fn content_length<T>(req: &Request<T>) -> Result<Option<u64>, &'static str> {
+    let mut declared = None;
+    for value in req.headers().get_all(CONTENT_LENGTH) {
+        let len = value
+            .to_str()
+            .ok()
+            .filter(|text| !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|text| text.parse::<u64>().ok())
+            .ok_or("invalid Content-Length")?;
+        if declared.is_some_and(|declared| declared != len) {
+            return Err("conflicting Content-Length values");
+        }
+        declared = Some(len);
+    }
+    Ok(declared)
+}
+
+/// Check the headers that frame `req`'s body, before any handler sees it: Content-Length must be
+/// a number, the same one if repeated, and the HTTP/1.1 connection-specific fields HTTP/3 forbids
+/// (Transfer-Encoding, Connection and its relatives, and TE other than `trailers`) must be
+/// absent, or the request is malformed (RFC 9114, section 4.2) and should get 400 Bad Request.
+/// Whether the body matches the Content-Length only shows while reading it, see
+/// [`BodyError::LengthMismatch`].
+pub fn check_framing<T>(req: &Request<T>) -> Result<(), &'static str> {
+    content_length(req)?;
+    let headers = req.headers();
+    if headers.contains_key(TRANSFER_ENCODING) {
+        return Err("Transfer-Encoding isn't allowed in HTTP/3");
+    }
+    if [CONNECTION, UPGRADE].iter().any(|name| headers.contains_key(name))
+        || ["keep-alive", "proxy-connection"].iter().any(|name| headers.contains_key(*name))
+    {
+        return Err("connection-specific header fields aren't allowed in HTTP/3");
+    }
+    if headers.get_all(TE).iter().any(|value| !value.as_bytes().eq_ignore_ascii_case(b"trailers")) {
+        return Err("TE may only be \"trailers\" in HTTP/3");
+    }
+    Ok(())
+}
+
+/// The coding `req`'s body was sent in, `None` if it has no Content-Encoding or `identity`, or
+/// an error for a coding the server can't decode, which should get 415 Unsupported Media Type
+/// (RFC 9110, section 8.4). Stacked codings (`gzip, br`) are refused too.
+pub fn content_encoding<T>(req: &Request<T>) -> Result<Option<Encoding>, &'static str> {
+    let mut codings = req
+        .headers()
+        .get_all(CONTENT_ENCODING)
+        .iter()
+        // A value that isn't visible ASCII names no coding the server knows
+        .flat_map(|value| value.to_str().unwrap_or("?").split(','))
+        .map(str::trim)
+        .filter(|coding| !coding.is_empty() && !coding.eq_ignore_ascii_case("identity"));
+    let Some(coding) = codings.next() else {
+        return Ok(None);
+    };
+    if codings.next().is_some() {
+        return Err("more than one Content-Encoding");
+    }
+    Encoding::from_name(coding).map(Some).ok_or("unsupported Content-Encoding")
+}
+
+/// Whether `req` asks for `100 Continue` before sending its body (`Expect: 100-continue`), or an
+/// error for any other expectation, which the server can't meet and should answer with 417
+/// Expectation Failed (RFC 9110, section 10.1.1).
+pub fn expects_continue<T>(req: &Request<T>) -> Result<bool, &'static str> {
+    let mut expects = false;
+    for value in req.headers().get_all(EXPECT) {
+        if !value.as_bytes().eq_ignore_ascii_case(b"100-continue") {
+            return Err("unsupported expectation");
+        }
+        expects = true;
+    }
+    Ok(expects)
+}
+
+/// The SHA-256 `req`'s body should have, from its `Content-Digest: sha-256=:<base64>:` header
+/// (RFC 9530), for [`Body::expect_digest`]. `None` if there is no such header or it only names
+/// other algorithms, which the server can't check; an error if the `sha-256` entry is malformed
+/// or repeated with different values, which should get 400 Bad Request.
+pub fn content_digest<T>(req: &Request<T>) -> Result<Option<[u8; 32]>, &'static str> {
+    let mut expected = None;
+    for value in req.headers().get_all(CONTENT_DIGEST) {
+        let value = value.to_str().map_err(|_| "invalid Content-Digest")?;
+        for entry in value.split(',') {
+            let (algorithm, digest) = entry.split_once('=').ok_or("invalid Content-Digest")?;
+            if !algorithm.trim().eq_ignore_ascii_case("sha-256") {
+                continue;
+            }
+            let digest = digest
+                .trim()
+                .strip_prefix(':')
+                .and_then(|digest| digest.strip_suffix(':'))
+                .and_then(decode_base64)
+                .and_then(|digest| <[u8; 32]>::try_from(digest).ok())
+                .ok_or("invalid sha-256 Content-Digest")?;
+            if expected.is_some_and(|expected| expected != digest) {
+                return Err("conflicting sha-256 Content-Digest values");
+            }
+            expected = Some(digest);
+        }
+    }
+    Ok(expected)
+}
+
+const CONTENT_DIGEST: HeaderName = HeaderName::from_static("content-digest");
+
+// Standard base64 with padding, as structured field byte sequences use (RFC 8941, section 3.3.5)
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let text = text.as_bytes();
+    let padding = text.iter().rev().take_while(|b| **b == b'=').count();
+    if padding > 2 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    for (i, quad) in text.chunks(4).enumerate() {
+        let last = i == text.len() / 4 - 1;
+        let mut bits = 0u32;
+        for (j, &c) in quad.iter().enumerate() {
+            let value = match c {
+                b'A'..=b'Z' => c - b'A',
+                b'a'..=b'z' => c - b'a' + 26,
+                b'0'..=b'9' => c - b'0' + 52,
+                b'+' => 62,
+                b'/' => 63,
+                b'=' if last && j >= 4 - padding => 0,
+                _ => return None,
+            };
+            bits = bits << 6 | value as u32;
+        }
+        out.extend_from_slice(&bits.to_be_bytes()[1..]);
+    }
+    out.truncate(out.len() - padding);
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)]) -> Request<()> {
+        let mut req = Request::post("https://localhost/upload");
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        req.body(()).unwrap()
+    }
+
+    #[test]
+    fn connection_specific_headers_are_rejected() {
+        for (name, value) in [
+            ("transfer-encoding", "chunked"),
+            ("connection", "close"),
+            ("upgrade", "websocket"),
+            ("keep-alive", "timeout=5"),
+            ("proxy-connection", "keep-alive"),
+            ("te", "gzip"),
+        ] {
+            assert!(check_framing(&request(&[(name, value)])).is_err(), "{}", name);
+        }
+        assert_eq!(check_framing(&request(&[("te", "Trailers")])), Ok(()));
+        assert_eq!(check_framing(&request(&[])), Ok(()));
+    }
+
+    #[test]
+    fn content_length_must_be_one_number() {
+        assert_eq!(content_length(&request(&[])), Ok(None));
+        assert_eq!(content_length(&request(&[("content-length", "42")])), Ok(Some(42)));
+        assert_eq!(content_length(&request(&[("content-length", "42"), ("content-length", "42")])), Ok(Some(42)));
+        assert_eq!(
+            content_length(&request(&[("content-length", "42"), ("content-length", "43")])),
+            Err("conflicting Content-Length values")
+        );
+        for value in ["", "abc", "-1", "+1", "4 2", "0x10", "99999999999999999999"] {
+            assert_eq!(content_length(&request(&[("content-length", value)])), Err("invalid Content-Length"), "{:?}", value);
+        }
+        // A bad Content-Length fails the framing check as well
+        assert!(check_framing(&request(&[("content-length", "abc")])).is_err());
+    }
+
+    #[test]
+    fn base64_needs_whole_quads_and_valid_padding() {
+        for (text, decoded) in [
+            ("", Some(&b""[..])),
+            ("aGVsbG8=", Some(b"hello")),
+            ("aGVsbA==", Some(b"hell")),
+            ("aGVs", Some(b"hel")),
+            ("+/+/", Some(&[0xfb, 0xff, 0xbf][..])),
+            ("aGVsbG8", None),
+            ("aGVsbA=", None),
+            ("aGVs====", None),
+            ("aG=sbG8=", None),
+            ("aGVsbG8=aGVs", None),
+            ("aGVs-_8=", None),
+            ("aGVs bG8", None),
+        ] {
+            assert_eq!(decode_base64(text).as_deref(), decoded, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn only_the_sha256_digest_is_taken() {
+        const HELLO: &str = "sha-256=:LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=:";
+        const WORLD: &str = "sha-256=:SG6kYiTRu0+2gPNPfJrZao8k7Ii+c+qOWmxlJg6cuKc=:";
+        const SHA512: &str = "sha-512=:m3HSJL1i83hdltRq0+o9czGb+8KJDKra4t/3JRlnPKcjI8PZm6XBHXx6zG4UuMXaDEZjR1wuXDre9G9zvN7AQw==:";
+        let hello: [u8; 32] = Sha256::digest(b"hello").into();
+        let digest = |values: &[&str]| {
+            let headers: Vec<_> = values.iter().map(|value| ("content-digest", *value)).collect();
+            content_digest(&request(&headers))
+        };
+
+        assert_eq!(digest(&[]), Ok(None));
+        assert_eq!(digest(&[HELLO]), Ok(Some(hello)));
+        assert_eq!(digest(&["SHA-256=:LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=:"]), Ok(Some(hello)));
+        assert_eq!(digest(&[&format!("{}, {}", SHA512, HELLO)]), Ok(Some(hello)));
+        assert_eq!(digest(&[SHA512, HELLO, HELLO]), Ok(Some(hello)));
+        assert_eq!(digest(&[SHA512]), Ok(None));
+        assert_eq!(digest(&["md5=:XUFAKrxLKna5cZ2REBfFkg==:"]), Ok(None));
+        assert_eq!(digest(&[HELLO, WORLD]), Err("conflicting sha-256 Content-Digest values"));
+        for value in ["sha-256=LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=", "sha-256=:aGVsbG8=:", "sha-256=:!!!!:"] {
+            assert_eq!(digest(&[value]), Err("invalid sha-256 Content-Digest"), "{}", value);
+        }
+        assert_eq!(digest(&["sha-256"]), Err("invalid Content-Digest"));
+    }
+}
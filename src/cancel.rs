@@ -0,0 +1,42 @@
+// Per-request cancellation: a token that fires when the client stops the response stream, which
+// is how HTTP/3 clients cancel requests (RFC 9114, section 4.1.1), or the connection goes away,
+// so handlers can give up on work nobody is waiting for anymore.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use h3::quic::StreamId;
+use h3_quinn::quinn;
+use tokio_util::sync::CancellationToken;
+
+/// Cancellation tokens of the request streams on one connection.
+#[derive(Debug, Clone, Default)]
+pub struct Cancellations {
+    tokens: Arc<Mutex<HashMap<u64, CancellationToken>>>,
+}
+
+impl Cancellations {
+    /// The token for `stream`. An unknown stream gets a token that never fires.
+    pub fn token(&self, stream: StreamId) -> CancellationToken {
+        let tokens = self.tokens.lock().unwrap();
+        tokens.get(&stream.into_inner()).cloned().unwrap_or_default()
+    }
+
+    pub(crate) fn watch(&self, stream: &quinn::SendStream) {
+        let token = CancellationToken::new();
+        self.tokens.lock().unwrap().insert(stream.id().into(), token.clone());
+
+        // Resolves with `None` once the response was finished and acknowledged; everything else
+        // (STOP_SENDING, connection lost) means the client is no longer listening
+        let stopped = stream.stopped();
+        tokio::spawn(async move {
+            if !matches!(stopped.await, Ok(None)) {
+                token.cancel();
+            }
+        });
+    }
+
+    pub(crate) fn forget(&self, stream: u64) {
+        self.tokens.lock().unwrap().remove(&stream);
+    }
+}
@@ -1,71 +1,1132 @@
+// Reusable HTTP/3 client: owns one QUIC connection to a server and transparently
+// re-establishes it when it goes away (idle timeout, peer close, GOAWAY).
+
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
-use bytes::Buf;
+use anyhow::{Context, anyhow, bail};
+use bytes::{Buf, Bytes, BytesMut};
+use futures::future::{BoxFuture, Shared};
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, StreamExt};
+use h3::client::{RequestStream, SendRequest};
+use h3::error::{Code, ConnectionError, StreamError};
+use h3::quic::ConnectionErrorIncoming;
 use h3_quinn::quinn;
-use http::Request;
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::{HeaderMap, Method, Request, StatusCode};
 use quinn::Endpoint;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio::io::AsyncReadExt;
+use tokio::sync::{Mutex, mpsc, watch};
+
+use crate::fingerprint::Fingerprint;
+use crate::server::check_initial_rtt;
+use crate::settings::PeerSettings;
+use crate::uni;
+
+// Size of the chunks a file body is read and sent in
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+// QUIC v1 (RFC 9000), the only version the client offers
+const QUIC_VERSION: u32 = 0x0000_0001;
+
+// How long a connection attempt to one address gets before the next address is tried in
+// parallel (RFC 8305 recommends 250ms)
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+// Pauses between tries while waiting for the server to come up, doubling from the first
+const WAIT_BACKOFF_START: Duration = Duration::from_millis(100);
+const WAIT_BACKOFF_MAX: Duration = Duration::from_secs(2);
+
+// Where to connect: a fixed address, or a host name that is resolved again on every (re)connect
+#[derive(Debug, Clone)]
+enum Target {
+    Addr(SocketAddr),
+    Host(String, u16),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Addr(addr) => write!(f, "{}", addr),
+            Target::Host(host, port) => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+/// When a request that failed because the connection went away, or because it was sent as 0-RTT
+/// early data that the server rejected, is retried on a fresh connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reconnect {
+    /// Never retry; the connection error is returned
+    Never,
+    /// Retry GET, HEAD, PUT, DELETE, OPTIONS and TRACE, which are safe to replay (the default)
+    IdempotentOnly,
+    /// Retry every method, even ones the server may already have acted on
+    Always,
+}
+
+impl Reconnect {
+    fn allows(self, method: &Method) -> bool {
+        match self {
+            Reconnect::Never => false,
+            Reconnect::IdempotentOnly => matches!(
+                *method,
+                Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE
+            ),
+            Reconnect::Always => true,
+        }
+    }
+}
+
+pub struct ClientBuilder {
+    target: Target,
+    server_name: String,
+    authority: Option<String>,
+    reconnect: Reconnect,
+    connect_attempts: u32,
+    connect_timeout: Duration,
+    pinned_certificate: Option<Fingerprint>,
+    client_certificate: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    initial_rtt: Option<Duration>,
+    request_timeout: Option<Duration>,
+    wait_for_server: Option<Duration>,
+    local_addr: Option<SocketAddr>,
+}
+
+impl ClientBuilder {
+    /// Name sent as SNI in the TLS handshake, independent of the address connected to.
+    pub fn server_name(mut self, name: impl Into<String>) -> Self {
+        self.server_name = name.into();
+        self
+    }
+
+    /// `:authority` of requests (the HTTP/3 Host header), e.g. for virtual hosts behind one
+    /// address. Defaults to the server name.
+    pub fn authority(mut self, authority: impl Into<String>) -> Self {
+        self.authority = Some(authority.into());
+        self
+    }
+
+    /// Which requests are retried once on a new connection after the old one closed.
+    pub fn reconnect(mut self, reconnect: Reconnect) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// How many times establishing a connection is tried before giving up (at least 1).
+    pub fn connect_attempts(mut self, attempts: u32) -> Self {
+        self.connect_attempts = attempts.max(1);
+        self
+    }
+
+    /// Deadline for each connection attempt, so an unreachable server fails fast.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Only accept a server whose leaf certificate has this SHA-256 fingerprint, e.g. the one the
+    /// server prints for its self-signed certificate. Without a pin, verification is skipped.
+    pub fn pin_certificate(mut self, fingerprint: Fingerprint) -> Self {
+        self.pinned_certificate = Some(fingerprint);
+        self
+    }
+
+    /// Authenticate to servers that ask for a client certificate (mutual TLS) with this chain,
+    /// leaf first, and its private key.
+    pub fn client_certificate(mut self, chain: Vec<CertificateDer<'static>>, key: PrivateKeyDer<'static>) -> Self {
+        self.client_certificate = Some((chain, key));
+        self
+    }
+
+    /// RTT assumed before the first sample is taken (quinn's default is 333ms), e.g. to avoid
+    /// needless retransmits on high-latency paths. Must lie within [`INITIAL_RTT_RANGE`](crate::server::INITIAL_RTT_RANGE).
+    pub fn initial_rtt(mut self, rtt: Duration) -> Self {
+        self.initial_rtt = Some(rtt);
+        self
+    }
+
+    /// Give up on a request that hasn't been answered in full within this time, with
+    /// [`RequestError::TimedOut`]; for [`Client::request_streaming`], until its response head
+    /// arrives. There is no limit by default.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Keep retrying the first connection, with exponential backoff, for up to `wait` while the
+    /// server isn't reachable yet, e.g. when it is started alongside the client. Only
+    /// [`ClientBuilder::connect`] waits; reconnects later on follow [`ClientBuilder::reconnect`].
+    pub fn wait_for_server(mut self, wait: Duration) -> Self {
+        self.wait_for_server = Some(wait);
+        self
+    }
+
+    /// Bind the client's UDP socket to `addr` instead of an ephemeral port on every address
+    /// (dual-stack where possible), e.g. for a source port that firewall rules let through, or to
+    /// send from one interface's address. Port 0 still picks a free port. Servers can only be
+    /// reached at addresses of the family `addr` can send to; host names are resolved to those.
+    pub fn local_addr(mut self, addr: SocketAddr) -> Self {
+        self.local_addr = Some(addr);
+        self
+    }
+
+    pub async fn connect(self) -> anyhow::Result<Client> {
+        self.connect_inner(None).await
+    }
+
+    /// Like [`ClientBuilder::connect`], but instead of spawning each connection's [`Driver`] on
+    /// the current runtime, hand it to the caller through the returned [`Drivers`], to run on a
+    /// task or runtime of their choosing and see why the connection ended. The first driver is
+    /// ready as soon as this returns, and every reconnect adds one. Requests need their
+    /// connection's driver to be running; drivers that can't be handed over because [`Drivers`]
+    /// was dropped are spawned after all.
+    pub async fn connect_with_drivers(self) -> anyhow::Result<(Client, Drivers)> {
+        let (drivers, receiver) = mpsc::unbounded_channel();
+        let client = self.connect_inner(Some(drivers)).await?;
+        Ok((client, Drivers(receiver)))
+    }
+
+    async fn connect_inner(self, drivers: Option<mpsc::UnboundedSender<Driver>>) -> anyhow::Result<Client> {
+        if let Some(rtt) = self.initial_rtt {
+            check_initial_rtt(rtt)?;
+        }
+
+        // Self-signed certificates can't be verified against a CA, so either pin or skip (for development)
+        let verifier: Arc<dyn rustls::client::danger::ServerCertVerifier> = match self.pinned_certificate {
+            Some(fingerprint) => Arc::new(PinnedServerVerification::new(fingerprint)),
+            None => Arc::new(SkipServerVerification),
+        };
+        let tls_config = rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(verifier);
+        let mut tls_config = match self.client_certificate {
+            Some((chain, key)) => tls_config.with_client_auth_cert(chain, key).context("unusable client certificate")?,
+            None => tls_config.with_no_client_auth(),
+        };
+
+        // Must match server's ALPN protocol for HTTP/3
+        tls_config.alpn_protocols = vec![b"h3".to_vec()];
+        // Lets reconnects offer 0-RTT when the server issued a ticket allowing it
+        tls_config.enable_early_data = true;
+
+        let mut client_config = quinn::ClientConfig::new(Arc::new(
+            quinn::crypto::rustls::QuicClientConfig::try_from(tls_config)?
+        ));
+        client_config.version(QUIC_VERSION);
+        if let Some(rtt) = self.initial_rtt {
+            let mut transport = quinn::TransportConfig::default();
+            transport.initial_rtt(rtt);
+            client_config.transport_config(Arc::new(transport));
+        }
+
+        let mut endpoint = match self.local_addr {
+            Some(local) => {
+                if let Target::Addr(remote) = self.target
+                    && !can_reach(local, remote)
+                {
+                    bail!("cannot reach {} from local address {}, which is of another address family", remote, local);
+                }
+                Endpoint::client(local).with_context(|| format!("failed to bind UDP socket on {}", local))?
+            }
+            // Dual-stack so both A and AAAA records can be tried; fall back to IPv4 where IPv6 is unavailable
+            None => Endpoint::client((Ipv6Addr::UNSPECIFIED, 0).into())
+                .or_else(|_| Endpoint::client((Ipv4Addr::UNSPECIFIED, 0).into()))?,
+        };
+        endpoint.set_default_client_config(client_config);
+
+        // IPv6 literals need brackets in a URI authority
+        let authority = match self.authority {
+            Some(authority) => authority,
+            None if self.server_name.parse::<Ipv6Addr>().is_ok() => format!("[{}]", self.server_name),
+            None => self.server_name.clone(),
+        };
+        let authority = authority
+            .parse::<http::uri::Authority>()
+            .with_context(|| format!("invalid authority \"{}\"", authority))?;
+
+        let client = Client {
+            endpoint,
+            target: self.target,
+            server_name: self.server_name,
+            authority,
+            reconnect: self.reconnect,
+            connect_attempts: self.connect_attempts,
+            connect_timeout: self.connect_timeout,
+            request_timeout: self.request_timeout,
+            connection: Mutex::new(None),
+            connections_established: AtomicU64::new(0),
+            drivers,
+        };
+        match self.wait_for_server {
+            Some(wait) => client.wait_for_server(wait).await?,
+            None => drop(client.connection(None).await?),
+        }
+        Ok(client)
+    }
+}
+
+/// What was negotiated when the current connection was established.
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub remote_address: SocketAddr,
+    /// Application protocol selected by the server (always `h3` for a working connection)
+    pub alpn: Option<String>,
+    /// QUIC version in use; the client only offers one, so a successful handshake means it was accepted
+    pub quic_version: u32,
+    pub zero_rtt: ZeroRtt,
+    /// The RTT estimate right after the handshake completed, or for a connection resumed with
+    /// 0-RTT, when it was set up: quinn's initial estimate, before any sample
+    pub initial_rtt: Duration,
+}
+
+/// Whether the connection was resumed with 0-RTT. A resumed connection is handed out before its
+/// handshake completes, so its first requests go out as early data; if the server rejects that,
+/// the connection is replaced and those requests fail with [`RequestError::ZeroRttRejected`] or
+/// are retried, as [`Reconnect`] says.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZeroRtt {
+    /// No resumption ticket allowing early data was available (e.g. first connection)
+    NotAttempted,
+    /// Early data was sent and the handshake hasn't completed yet
+    Pending,
+    Accepted,
+    Rejected,
+}
+
+/// Why the QUIC/TLS handshake with the server failed. Connection errors from [`ClientBuilder::connect`]
+/// and [`Client::send`] carry one of these; get it with `error.downcast_ref::<HandshakeError>()`.
+#[derive(Debug)]
+pub enum HandshakeError {
+    /// Nothing came back within the connect timeout or quinn's idle timeout
+    NoResponse,
+    /// The server offered no `h3` ALPN, so it doesn't speak HTTP/3
+    NoH3Alpn,
+    /// The server's certificate was not trusted, e.g. it doesn't match the pinned fingerprint
+    CertificateRejected(String),
+    /// The server doesn't support QUIC version 1
+    VersionMismatch,
+    /// Any other TLS alert, sent by either side
+    Tls { alert: u8, reason: String },
+    /// The connection failed for a reason unrelated to TLS
+    Connection(quinn::ConnectionError),
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::NoResponse => {
+                write!(f, "no response from the server (is it running, and is UDP to it allowed?)")
+            }
+            HandshakeError::NoH3Alpn => write!(f, "server did not offer ALPN h3 (is it an HTTP/3 server?)"),
+            HandshakeError::CertificateRejected(reason) => write!(
+                f,
+                "server certificate not trusted: {} (compare --pin-sha256 with the fingerprint the server prints)",
+                reason
+            ),
+            HandshakeError::VersionMismatch => write!(f, "server does not support QUIC version 1"),
+            HandshakeError::Tls { alert, reason } => write!(
+                f,
+                "TLS handshake failed with alert {:?}: {}",
+                rustls::AlertDescription::from(*alert),
+                reason
+            ),
+            HandshakeError::Connection(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+impl From<quinn::ConnectionError> for HandshakeError {
+    fn from(e: quinn::ConnectionError) -> Self {
+        // TLS alerts travel as the CRYPTO_ERROR range of transport error codes (RFC 9001, section 4.8)
+        let (code, reason) = match &e {
+            quinn::ConnectionError::VersionMismatch => return HandshakeError::VersionMismatch,
+            quinn::ConnectionError::TimedOut => return HandshakeError::NoResponse,
+            quinn::ConnectionError::TransportError(error) => (u64::from(error.code), error.reason.clone()),
+            quinn::ConnectionError::ConnectionClosed(close) => {
+                (u64::from(close.error_code), String::from_utf8_lossy(&close.reason).into_owned())
+            }
+            _ => return HandshakeError::Connection(e),
+        };
+        let Some(alert) = code.checked_sub(0x100).filter(|alert| *alert <= 0xff) else {
+            return HandshakeError::Connection(e);
+        };
+
+        match alert as u8 {
+            // no_application_protocol
+            120 => HandshakeError::NoH3Alpn,
+            // bad_certificate, unsupported_certificate, certificate_revoked, certificate_expired,
+            // certificate_unknown, unknown_ca
+            42..=46 | 48 => HandshakeError::CertificateRejected(reason),
+            alert => HandshakeError::Tls { alert, reason },
+        }
+    }
+}
+
+/// Why a request failed once the client had a connection to send it on. Errors from
+/// [`Client::send`] carry one of these (a failed connect carries a [`HandshakeError`] instead);
+/// get it with `error.downcast_ref::<RequestError>()` to decide whether a retry makes sense.
+#[derive(Debug)]
+pub enum RequestError {
+    /// The server reset the request stream with this HTTP/3 code. Only `H3_REQUEST_REJECTED`
+    /// promises the request wasn't processed (RFC 9114, section 4.1.1); others, like
+    /// `H3_REQUEST_CANCELLED` or `H3_INTERNAL_ERROR`, are the server's answer to this request
+    Reset(Code),
+    /// The connection went away under the request: the server closed it or sent GOAWAY, it timed
+    /// out idle, or the path broke. Requests that hadn't been processed can go on a new connection
+    ConnectionClosed(String),
+    /// The request wasn't answered in full within [`ClientBuilder::request_timeout`]
+    TimedOut(Duration),
+    /// The client closed the connection itself ([`Client::close`]) while the request was in flight
+    Cancelled,
+    /// The server broke the protocol on this stream, e.g. with a malformed or oversized header
+    /// section
+    Protocol(String),
+    /// The request went out as 0-RTT early data and the server rejected it, so it was never
+    /// processed. Returned when [`Reconnect`] doesn't allow sending it again
+    ZeroRttRejected,
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::Reset(code) => write!(f, "stream reset by the server with {}", code),
+            RequestError::ConnectionClosed(reason) => write!(f, "connection closed: {}", reason),
+            RequestError::TimedOut(timeout) => write!(f, "no complete response within {:?}", timeout),
+            RequestError::Cancelled => write!(f, "request cancelled: the client closed the connection"),
+            RequestError::Protocol(reason) => write!(f, "HTTP/3 protocol error: {}", reason),
+            RequestError::ZeroRttRejected => {
+                write!(f, "sent as 0-RTT early data, which the server rejected (the request was not processed)")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+impl From<StreamError> for RequestError {
+    fn from(e: StreamError) -> Self {
+        match e {
+            StreamError::RemoteTerminate { code, .. } => RequestError::Reset(code),
+            StreamError::ConnectionError { 0: e, .. } => RequestError::from(e),
+            StreamError::RemoteClosing { .. } => RequestError::ConnectionClosed("the server sent GOAWAY".to_string()),
+            e => RequestError::Protocol(e.to_string()),
+        }
+    }
+}
+
+impl From<ConnectionError> for RequestError {
+    fn from(e: ConnectionError) -> Self {
+        let reason = match &e {
+            ConnectionError::Remote { 0: ConnectionErrorIncoming::Timeout, .. } | ConnectionError::Timeout { .. } => {
+                "idle timeout".to_string()
+            }
+            ConnectionError::Remote { 0: ConnectionErrorIncoming::ApplicationClose { error_code }, .. } => {
+                format!("closed by the server with {}", Code::from(*error_code))
+            }
+            ConnectionError::Remote { 0: ConnectionErrorIncoming::Undefined(e), .. } => e.to_string(),
+            e => e.to_string(),
+        };
+        RequestError::ConnectionClosed(reason)
+    }
+}
+
+/// The response to a request, with its body read in full.
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    /// Trailer fields sent after the body, if any
+    pub trailers: Option<HeaderMap>,
+    /// Set when the server stopped reading the request body early, with the code it used
+    pub upload_stopped: Option<Code>,
+}
+
+/// The head of a response whose body [`Client::download`] read without keeping it.
+pub struct Download {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// Body bytes received
+    pub received: u64,
+}
+
+/// A response whose body is read as the caller asks for it, from [`Client::request_streaming`].
+pub struct StreamingResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: ResponseBody,
+    /// Set when the server stopped reading the request body early, with the code it used
+    pub upload_stopped: Option<Code>,
+}
+
+/// The body of a [`StreamingResponse`]. Data is only taken from the stream as chunks are asked
+/// for, so flow control holds the server back while the caller is busy with the last one. Dropped
+/// before its end, it asks the server to stop sending with `H3_REQUEST_CANCELLED`.
+pub struct ResponseBody {
+    stream: RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>,
+    connection: Arc<Connection>,
+    received: u64,
+    ended: bool,
+}
+
+impl ResponseBody {
+    /// The next chunk, or `None` at the end of the body. Failures come as a [`RequestError`].
+    pub async fn chunk(&mut self) -> anyhow::Result<Option<Bytes>> {
+        let result = self.next_chunk().await.map_err(anyhow::Error::from);
+        classify_failure(&self.connection, result).await
+    }
+
+    /// The trailer fields sent after the body, if any. Only there once [`ResponseBody::chunk`]
+    /// has returned `None`.
+    pub async fn trailers(&mut self) -> anyhow::Result<Option<HeaderMap>> {
+        let result = self.stream.recv_trailers().await.map_err(|e| RequestError::from(e).into());
+        classify_failure(&self.connection, result).await
+    }
+
+    /// Body bytes received so far.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    async fn next_chunk(&mut self) -> Result<Option<Bytes>, RequestError> {
+        let Some(mut chunk) = self.stream.recv_data().await? else {
+            self.ended = true;
+            return Ok(None);
+        };
+        let chunk = chunk.copy_to_bytes(chunk.remaining());
+        self.received += chunk.len() as u64;
+        Ok(Some(chunk))
+    }
+}
+
+impl Drop for ResponseBody {
+    fn drop(&mut self) {
+        if !self.ended {
+            self.stream.stop_sending(Code::H3_REQUEST_CANCELLED);
+        }
+    }
+}
+
+/// A request body that can be sent (and replayed on retry) any number of times.
+#[derive(Clone)]
+pub enum RequestBody {
+    Inline(Bytes),
+    /// Streamed from disk chunk by chunk instead of being loaded into memory
+    File(PathBuf),
+}
+
+impl RequestBody {
+    pub async fn len(&self) -> anyhow::Result<u64> {
+        match self {
+            RequestBody::Inline(data) => Ok(data.len() as u64),
+            RequestBody::File(path) => Ok(tokio::fs::metadata(path)
+                .await
+                .with_context(|| format!("cannot read {}", path.display()))?
+                .len()),
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            RequestBody::Inline(_) => "text/plain",
+            RequestBody::File(_) => "application/octet-stream",
+        }
+    }
+
+    async fn send(
+        &self,
+        stream: &mut RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>,
+    ) -> anyhow::Result<()> {
+        match self {
+            RequestBody::Inline(data) => stream.send_data(data.clone()).await?,
+            RequestBody::File(path) => {
+                let mut file = tokio::fs::File::open(path)
+                    .await
+                    .with_context(|| format!("cannot open {}", path.display()))?;
+                loop {
+                    let mut chunk = BytesMut::with_capacity(UPLOAD_CHUNK_SIZE);
+                    if file.read_buf(&mut chunk).await? == 0 {
+                        break;
+                    }
+                    stream.send_data(chunk.freeze()).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What keeps one HTTP/3 connection going: it reads the server's control stream (SETTINGS,
+/// GOAWAY) and notices the connection closing. The client spawns one per connection unless it
+/// was built with [`ClientBuilder::connect_with_drivers`].
+pub struct Driver {
+    connection: h3::client::Connection<uni::Connection, Bytes>,
+    remote_address: SocketAddr,
+}
+
+impl Driver {
+    /// The address of the server this connection is to.
+    pub fn remote_address(&self) -> SocketAddr {
+        self.remote_address
+    }
+
+    /// Drive the connection until it closes, and return why. An orderly close by either side
+    /// is an error for which `is_h3_no_error()` holds.
+    pub async fn run(mut self) -> ConnectionError {
+        self.connection.wait_idle().await
+    }
+}
+
+/// The [`Driver`]s of a client's connections, in the order they are established, from
+/// [`ClientBuilder::connect_with_drivers`].
+pub struct Drivers(mpsc::UnboundedReceiver<Driver>);
+
+impl Drivers {
+    /// The next connection's driver, once the client establishes one; `None` after the client
+    /// is dropped.
+    pub async fn next(&mut self) -> Option<Driver> {
+        self.0.recv().await
+    }
+}
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    rustls::crypto::aws_lc_rs::default_provider().install_default().unwrap();
+// One established HTTP/3 connection; replaced wholesale on reconnect
+struct Connection {
+    quic: quinn::Connection,
+    send_request: SendRequest<h3_quinn::OpenStreams, Bytes>,
+    info: ConnectionInfo,
+    uni_streams: Mutex<uni::UniStreams>,
+    peer_settings: watch::Receiver<Option<PeerSettings>>,
+    // Distinguishes connections so concurrent failures only trigger one reconnect
+    generation: u64,
+    early_data: Option<EarlyData>,
+}
+
+// For a connection resumed with 0-RTT, whether the server took the early data, once the handshake
+// is done; `None` if the connection failed before that
+type EarlyData = Shared<BoxFuture<'static, Option<ZeroRtt>>>;
+
+impl Connection {
+    fn zero_rtt(&self) -> ZeroRtt {
+        match &self.early_data {
+            Some(outcome) => outcome.clone().now_or_never().flatten().unwrap_or(ZeroRtt::Pending),
+            None => ZeroRtt::NotAttempted,
+        }
+    }
+
+    // Whether requests on this connection failed because the server rejected its early data;
+    // waits for the handshake to find out
+    async fn zero_rtt_rejected(&self) -> bool {
+        match &self.early_data {
+            Some(outcome) => outcome.clone().await == Some(ZeroRtt::Rejected),
+            None => false,
+        }
+    }
+}
+
+pub struct Client {
+    endpoint: Endpoint,
+    target: Target,
+    server_name: String,
+    authority: http::uri::Authority,
+    reconnect: Reconnect,
+    connect_attempts: u32,
+    connect_timeout: Duration,
+    request_timeout: Option<Duration>,
+    connection: Mutex<Option<Arc<Connection>>>,
+    connections_established: AtomicU64,
+    // Where drivers go instead of being spawned, see `ClientBuilder::connect_with_drivers`
+    drivers: Option<mpsc::UnboundedSender<Driver>>,
+}
+
+impl Client {
+    /// Start configuring a client for `server_addr`, using `server_name` for SNI and the request
+    /// authority unless overridden.
+    pub fn builder(server_addr: SocketAddr, server_name: impl Into<String>) -> ClientBuilder {
+        Client::builder_for_target(Target::Addr(server_addr), server_name.into())
+    }
+
+    /// Start configuring a client for `host`, which is resolved (A and AAAA records) on every
+    /// connect. Addresses are tried Happy Eyeballs style (RFC 8305): IPv6 first, alternating with
+    /// IPv4, each new one started shortly after the previous, and the first handshake to complete wins.
+    /// `host` is also used for SNI and the request authority unless overridden.
+    pub fn builder_for_host(host: impl Into<String>, port: u16) -> ClientBuilder {
+        let host = host.into();
+        Client::builder_for_target(Target::Host(host.clone(), port), host)
+    }
+
+    fn builder_for_target(target: Target, server_name: String) -> ClientBuilder {
+        ClientBuilder {
+            target,
+            server_name,
+            authority: None,
+            reconnect: Reconnect::IdempotentOnly,
+            connect_attempts: 1,
+            connect_timeout: Duration::from_secs(10),
+            pinned_certificate: None,
+            client_certificate: None,
+            initial_rtt: None,
+            request_timeout: None,
+            wait_for_server: None,
+            local_addr: None,
+        }
+    }
+
+    /// A request builder for `path` on this client's server.
+    pub fn request(&self, method: Method, path: &str) -> http::request::Builder {
+        Request::builder()
+            .method(method)
+            .uri(format!("https://{}{}", self.authority, path))
+    }
+
+    /// Send a request and read the whole response. If the connection turns out to be closed,
+    /// it is re-established and the request retried once, subject to the [`Reconnect`] policy.
+    /// HTTP/3 failures come as a [`RequestError`].
+    pub async fn send(&self, req: Request<()>, body: Option<&RequestBody>) -> anyhow::Result<Response> {
+        let connection = self.connection(None).await?;
+        match exchange(&connection, req.clone(), body, self.request_timeout).await {
+            Err(e) if is_retryable(&e) && self.reconnect.allows(req.method()) => {
+                let connection = self.connection(Some(connection.generation)).await?;
+                exchange(&connection, req, body, self.request_timeout).await
+            }
+            result => result,
+        }
+    }
+
+    /// Send a request and return once the response head arrives, leaving the body to be read
+    /// chunk by chunk at the caller's pace, e.g. to pipe it elsewhere or to follow an endless
+    /// event stream. Getting the head is retried like [`Client::send`] and bounded by the
+    /// request timeout; reading the body is up to the caller and never times out.
+    pub async fn request_streaming(&self, req: Request<()>, body: Option<&RequestBody>) -> anyhow::Result<StreamingResponse> {
+        let connection = self.connection(None).await?;
+        match request_head(&connection, req.clone(), body, self.request_timeout).await {
+            Err(e) if is_retryable(&e) && self.reconnect.allows(req.method()) => {
+                let connection = self.connection(Some(connection.generation)).await?;
+                request_head(&connection, req, body, self.request_timeout).await
+            }
+            result => result,
+        }
+    }
+
+    /// Send a request without a body and read the response body as it arrives, calling
+    /// `progress` with the size of each chunk and dropping it, so bodies larger than memory can be
+    /// timed. Never retried: a body counted halfway would be counted twice.
+    pub async fn download(&self, req: Request<()>, progress: impl FnMut(usize)) -> anyhow::Result<Download> {
+        let connection = self.connection(None).await?;
+        let receiving = receive_discarding(&connection, req, progress);
+        let result = match self.request_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, receiving)
+                .await
+                .unwrap_or_else(|_| Err(RequestError::TimedOut(timeout).into())),
+            None => receiving.await,
+        };
+        classify_failure(&connection, result).await
+    }
+
+    /// Details of the current connection, or `None` if it has been closed.
+    pub async fn connection_info(&self) -> Option<ConnectionInfo> {
+        let current = self.connection.lock().await;
+        let connection = current.as_ref().filter(|c| c.quic.close_reason().is_none())?;
+        Some(ConnectionInfo {
+            remote_address: connection.quic.remote_address(),
+            zero_rtt: connection.zero_rtt(),
+            ..connection.info.clone()
+        })
+    }
+
+    /// The HTTP/3 SETTINGS the server sent on the current connection, waiting for them if they
+    /// haven't arrived yet.
+    pub async fn peer_settings(&self) -> anyhow::Result<PeerSettings> {
+        let connection = self.connection(None).await?;
+        let mut settings = connection.peer_settings.clone();
+        let settings = settings
+            .wait_for(Option::is_some)
+            .await
+            .map_err(|_| anyhow!("the connection closed before the server's SETTINGS arrived"))?;
+        Ok(settings.clone().unwrap())
+    }
+
+    /// The local address of the client's UDP socket.
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.endpoint.local_addr()?)
+    }
 
-    // Configure client to accept self-signed certificates (for development)
-    let mut tls_config = rustls::ClientConfig::builder()
-        .dangerous()
-        .with_custom_certificate_verifier(Arc::new(SkipServerVerification))
-        .with_no_client_auth();
-    
-    // Must match server's ALPN protocol for HTTP/3
-    tls_config.alpn_protocols = vec![b"h3".to_vec()];
+    /// Switch to a new UDP socket bound to `addr`, e.g. `[::]:0` for a fresh port after a network
+    /// change, and return its address. The current connection comes along: quinn migrates it to
+    /// the new path (RFC 9000, section 9) and requests in flight, downloads included, carry on.
+    /// The server must allow migration; if it doesn't, the connection times out and the next
+    /// request reconnects from the new socket.
+    ///
+    /// `addr` must be of the same family as [`Client::local_addr`]: the client's socket is
+    /// dual-stack IPv6 where possible, reaching IPv4 servers through mapped addresses, which an
+    /// IPv4 socket couldn't send to.
+    pub fn rebind(&self, addr: SocketAddr) -> anyhow::Result<SocketAddr> {
+        if addr.is_ipv4() != self.local_addr()?.is_ipv4() {
+            bail!("cannot rebind from {} to {}, which is of another address family", self.local_addr()?, addr);
+        }
+        let socket = std::net::UdpSocket::bind(addr).with_context(|| format!("failed to bind UDP socket on {}", addr))?;
+        self.endpoint.rebind(socket).context("failed to move the endpoint to the new socket")?;
+        self.local_addr()
+    }
+
+    /// How many connections this client has established so far, counting reconnects.
+    pub fn connections_established(&self) -> u64 {
+        self.connections_established.load(Ordering::Relaxed)
+    }
+
+    /// Wait for the next custom unidirectional stream the server opens on the current connection
+    /// (see [`uni::send`]) and read it to the end, up to `max_len` bytes. Returns `None` once the
+    /// connection is closed.
+    pub async fn accept_uni(&self, max_len: usize) -> anyhow::Result<Option<Bytes>> {
+        let connection = self.connection(None).await?;
+        let Some(mut stream) = connection.uni_streams.lock().await.accept().await else { return Ok(None) };
+        let data = stream.read_to_end(max_len).await.context("failed to read unidirectional stream")?;
+        Ok(Some(Bytes::from(data)))
+    }
+
+    /// Close the connection and wait for the server to acknowledge it.
+    pub async fn close(&self) {
+        self.close_with(Code::H3_NO_ERROR, b"").await
+    }
+
+    /// Close the connection with an application error code and reason, e.g.
+    /// `Code::H3_REQUEST_CANCELLED`, and wait for the server to acknowledge it.
+    pub async fn close_with(&self, code: Code, reason: &[u8]) {
+        if let Some(connection) = self.connection.lock().await.take() {
+            connection.quic.close(code.value().try_into().unwrap(), reason);
+        }
+        self.endpoint.wait_idle().await;
+    }
+
+    // The current connection, reconnecting first if it is closed, if the server rejected its
+    // 0-RTT data, or if it is the (failed) connection `stale` and nobody has replaced it yet
+    async fn connection(&self, stale: Option<u64>) -> anyhow::Result<Arc<Connection>> {
+        let mut current = self.connection.lock().await;
+        match current.as_ref() {
+            Some(c) if c.quic.close_reason().is_none() && Some(c.generation) != stale && c.zero_rtt() != ZeroRtt::Rejected => {
+                Ok(c.clone())
+            }
+            previous => {
+                // The HTTP/3 session of a rejected connection went out as early data and was lost with
+                // it (its control stream among it), so the connection is no use for requests. Its
+                // replacement does without early data: other tickets from the same server are
+                // likely to be rejected as well.
+                let rejected = previous.is_some_and(|c| c.zero_rtt() == ZeroRtt::Rejected);
+                if let Some(previous) = previous.filter(|_| rejected) {
+                    previous.quic.close(Code::H3_NO_ERROR.value().try_into().unwrap(), b"");
+                }
+                let generation = previous.map_or(0, |c| c.generation + 1);
+                let connection = Arc::new(self.establish(generation, !rejected).await?);
+                self.connections_established.fetch_add(1, Ordering::Relaxed);
+                *current = Some(connection.clone());
+                Ok(connection)
+            }
+        }
+    }
+
+    // The first connection, tried again after each failure until `wait` runs out. An attempt
+    // still in progress at the deadline is abandoned.
+    async fn wait_for_server(&self, wait: Duration) -> anyhow::Result<()> {
+        let deadline = Instant::now() + wait;
+        let mut backoff = WAIT_BACKOFF_START;
+        loop {
+            let error = match tokio::time::timeout_at(deadline.into(), self.connection(None)).await {
+                Ok(Ok(_)) => return Ok(()),
+                Ok(Err(e)) => e,
+                Err(_) => anyhow::Error::new(HandshakeError::NoResponse),
+            };
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(error.context(format!("{} didn't become reachable within {:?}", self.target, wait)));
+            }
+            log::debug!("Server {} not reachable yet, retrying in {:?}: {:#}", self.target, backoff.min(remaining), error);
+            tokio::time::sleep(backoff.min(remaining)).await;
+            backoff = (backoff * 2).min(WAIT_BACKOFF_MAX);
+        }
+    }
 
-    let client_config = quinn::ClientConfig::new(Arc::new(
-        quinn::crypto::rustls::QuicClientConfig::try_from(tls_config)?
-    ));
+    async fn establish(&self, generation: u64, early_data: bool) -> anyhow::Result<Connection> {
+        let mut attempt = 1;
+        loop {
+            match self.try_establish(generation, early_data).await {
+                Ok(connection) => return Ok(connection),
+                Err(e) if attempt >= self.connect_attempts => {
+                    return Err(e.context(format!("failed to connect to {} after {} attempt(s)", self.target, attempt)));
+                }
+                Err(_) => attempt += 1,
+            }
+        }
+    }
 
-    let mut endpoint = Endpoint::client("0.0.0.0:0".parse()?)?;
-    endpoint.set_default_client_config(client_config);
+    async fn try_establish(&self, generation: u64, early_data: bool) -> anyhow::Result<Connection> {
+        let (quic, early_data) = match tokio::time::timeout(self.connect_timeout, self.race_handshakes(early_data)).await {
+            Ok(result) => result?,
+            Err(_) => {
+                let error = anyhow::Error::new(HandshakeError::NoResponse);
+                return Err(error.context(format!("timed out after {:?} connecting to {}", self.connect_timeout, self.target)));
+            }
+        };
 
-    println!("Connecting to server at 127.0.0.1:4433...");
+        let alpn = quic
+            .handshake_data()
+            .and_then(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
+            .and_then(|data| data.protocol)
+            .map(|protocol| String::from_utf8_lossy(&protocol).into_owned());
+        let info = ConnectionInfo {
+            remote_address: quic.remote_address(),
+            alpn,
+            quic_version: QUIC_VERSION,
+            zero_rtt: if early_data.is_some() { ZeroRtt::Pending } else { ZeroRtt::NotAttempted },
+            initial_rtt: quic.stats().path.rtt,
+        };
 
-    let conn = endpoint
-        .connect("127.0.0.1:4433".parse()?, "localhost")?
-        .await?;
+        let (transport, uni_streams) = uni::Connection::new(quic.clone());
+        let peer_settings = transport.peer_settings();
+        let (connection, send_request) = h3::client::new(transport).await?;
+        let driver = Driver { connection, remote_address: info.remote_address };
+        match &self.drivers {
+            Some(drivers) => {
+                if let Err(unwanted) = drivers.send(driver) {
+                    tokio::spawn(unwanted.0.run());
+                }
+            }
+            None => {
+                tokio::spawn(driver.run());
+            }
+        }
 
-    println!("Connected! Establishing HTTP/3 connection...");
+        let uni_streams = Mutex::new(uni_streams);
+        Ok(Connection { quic, send_request, info, uni_streams, peer_settings, generation, early_data })
+    }
+}
 
-    let (mut driver, mut send_request) = h3::client::new(h3_quinn::Connection::new(conn)).await?;
+impl Client {
+    // Happy Eyeballs: start with the first address and add the next one every
+    // CONNECTION_ATTEMPT_DELAY, or right away when an attempt fails. The losers are dropped
+    // (which abandons their handshakes) as soon as one succeeds.
+    async fn race_handshakes(&self, early_data: bool) -> anyhow::Result<(quinn::Connection, Option<EarlyData>)> {
+        let mut addrs = self.resolve().await?.into_iter();
+        let mut attempts = FuturesUnordered::new();
+        let mut last_error = None;
 
-    // Spawn driver to handle connection
-    tokio::spawn(async move {
-        futures::future::poll_fn(|cx| driver.poll_close(cx)).await;
-    });
+        loop {
+            if attempts.is_empty() {
+                match addrs.next() {
+                    Some(addr) => attempts.push(self.handshake(addr, early_data)),
+                    None => return Err(last_error.unwrap_or_else(|| anyhow!("{} has no addresses", self.target))),
+                }
+            }
 
-    // Test different endpoints
-    for path in &["/", "/test", "/health", "/unknown"] {
-        println!("\n--- Requesting {} ---", path);
-        
-        let req = Request::builder()
-            .method("GET")
-            .uri(format!("https://localhost{}", path))
-            .body(())?;
+            tokio::select! {
+                Some(result) = attempts.next() => match result {
+                    Ok(connection) => return Ok(connection),
+                    Err(e) => last_error = Some(e),
+                },
+                _ = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY), if !addrs.as_slice().is_empty() => {
+                    attempts.push(self.handshake(addrs.next().unwrap(), early_data));
+                }
+            }
+        }
+    }
 
-        let mut stream = send_request.send_request(req).await?;
-        stream.finish().await?;
+    async fn resolve(&self) -> anyhow::Result<Vec<SocketAddr>> {
+        let (host, port) = match &self.target {
+            Target::Addr(addr) => return Ok(vec![*addr]),
+            Target::Host(host, port) => (host, *port),
+        };
+        let resolved = tokio::net::lookup_host((host.as_str(), port))
+            .await
+            .with_context(|| format!("failed to resolve {}", host))?;
 
-        let response = stream.recv_response().await?;
-        println!("Status: {}", response.status());
-        
-        // Read response body
-        let mut body = Vec::new();
-        while let Some(chunk) = stream.recv_data().await? {
-            body.extend(chunk.chunk());
+        // Alternate address families, IPv6 first (RFC 8305, section 4)
+        let (mut v6, mut v4): (Vec<_>, Vec<_>) = resolved.partition(SocketAddr::is_ipv6);
+        let local = self.endpoint.local_addr()?;
+        v6.retain(|remote| can_reach(local, *remote));
+        v4.retain(|remote| can_reach(local, *remote));
+        let mut ordered = Vec::with_capacity(v6.len() + v4.len());
+        for i in 0..v6.len().max(v4.len()) {
+            ordered.extend(v6.get(i).into_iter().chain(v4.get(i)));
         }
-        println!("Body: {}", String::from_utf8_lossy(&body));
+        Ok(ordered)
     }
 
-    println!("\n✅ All requests completed successfully!");
+    async fn handshake(&self, addr: SocketAddr, early_data: bool) -> anyhow::Result<(quinn::Connection, Option<EarlyData>)> {
+        let connecting = self.endpoint.connect(addr, &self.server_name)?;
+        if !early_data {
+            return Ok((connecting.await.map_err(HandshakeError::from)?, None));
+        }
+        match connecting.into_0rtt() {
+            // Ready right away, so requests go out as early data along with the handshake. This also
+            // wins the race against the other addresses, which a resumed server needs no help with.
+            Ok((quic, accepted)) => {
+                let watched = quic.clone();
+                let outcome = async move {
+                    // `accepted` is false as well when the handshake failed, which is no rejection
+                    match accepted.await {
+                        true => Some(ZeroRtt::Accepted),
+                        false => watched.close_reason().is_none().then_some(ZeroRtt::Rejected),
+                    }
+                };
+                Ok((quic, Some(outcome.boxed().shared())))
+            }
+            Err(connecting) => Ok((connecting.await.map_err(HandshakeError::from)?, None)),
+        }
+    }
+}
+
+// Send one request on its own stream and read back the whole response, within `timeout`
+async fn exchange(
+    connection: &Arc<Connection>,
+    req: Request<()>,
+    body: Option<&RequestBody>,
+    timeout: Option<Duration>,
+) -> anyhow::Result<Response> {
+    let result = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, send_and_receive(connection, req, body))
+            .await
+            .unwrap_or_else(|_| Err(RequestError::TimedOut(timeout).into())),
+        None => send_and_receive(connection, req, body).await,
+    };
+    classify_failure(connection, result).await
+}
 
-    Ok(())
+// Send one request and wait for its response head, within `timeout`
+async fn request_head(
+    connection: &Arc<Connection>,
+    req: Request<()>,
+    body: Option<&RequestBody>,
+    timeout: Option<Duration>,
+) -> anyhow::Result<StreamingResponse> {
+    let result = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, send_head(connection, req, body))
+            .await
+            .unwrap_or_else(|_| Err(RequestError::TimedOut(timeout).into())),
+        None => send_head(connection, req, body).await,
+    };
+    classify_failure(connection, result).await
+}
+
+// Streams see early data the server rejected, and a connection this client closed, as lost like
+// any other, so tell those apart here. Rejection is checked first: a rejected connection is
+// closed when it's replaced, possibly while other requests on it are still failing.
+async fn classify_failure<T>(connection: &Connection, result: anyhow::Result<T>) -> anyhow::Result<T> {
+    let Err(e) = result else { return result };
+    if !e.is::<RequestError>() {
+        return Err(e);
+    }
+    // A request that ran out of time was in flight long after any rejection would have failed it
+    if !matches!(e.downcast_ref(), Some(RequestError::TimedOut(_))) && connection.zero_rtt_rejected().await {
+        return Err(RequestError::ZeroRttRejected.into());
+    }
+    if matches!(connection.quic.close_reason(), Some(quinn::ConnectionError::LocallyClosed)) {
+        return Err(RequestError::Cancelled.into());
+    }
+    Err(e)
+}
+
+async fn send_and_receive(connection: &Arc<Connection>, req: Request<()>, body: Option<&RequestBody>) -> anyhow::Result<Response> {
+    let mut response = send_head(connection, req, body).await?;
+    let mut body = BytesMut::new();
+    while let Some(chunk) = response.body.next_chunk().await? {
+        body.extend_from_slice(&chunk);
+    }
+    let trailers = response.body.stream.recv_trailers().await.map_err(RequestError::from)?;
+
+    Ok(Response {
+        status: response.status,
+        headers: response.headers,
+        body: body.freeze(),
+        trailers,
+        upload_stopped: response.upload_stopped,
+    })
+}
+
+// Send a request and its body, and wait for the final response head
+async fn send_head(connection: &Arc<Connection>, req: Request<()>, body: Option<&RequestBody>) -> anyhow::Result<StreamingResponse> {
+    let mut req = req;
+    if let Some(body) = body {
+        let length = body.len().await?;
+        req.headers_mut().insert(CONTENT_LENGTH, length.into());
+        if !req.headers().contains_key(CONTENT_TYPE) {
+            req.headers_mut().insert(CONTENT_TYPE, body.content_type().parse()?);
+        }
+    }
+
+    let mut send_request = connection.send_request.clone();
+    let mut stream = send_request.send_request(req).await.map_err(RequestError::from)?;
+    let mut upload_stopped = None;
+    if let Some(body) = body
+        && let Err(e) = body.send(&mut stream).await
+    {
+        // A server may answer before reading the whole body and stop the upload;
+        // its response is still valid (RFC 9114, section 4.1)
+        match e.downcast::<StreamError>() {
+            Ok(StreamError::RemoteTerminate { code, .. }) => upload_stopped = Some(code),
+            Ok(e) => return Err(RequestError::from(e).into()),
+            Err(e) => return Err(e),
+        }
+    }
+    if upload_stopped.is_none() {
+        let stopped = finish(&mut stream).await?;
+        // Without a body there was no upload to stop
+        upload_stopped = stopped.filter(|_| body.is_some());
+    }
+
+    // Interim responses (e.g. 100 Continue) come as header sections of their own before the
+    // final one, and h3 hands them out like final responses
+    let mut response = stream.recv_response().await.map_err(RequestError::from)?;
+    while response.status().is_informational() {
+        response = stream.recv_response().await.map_err(RequestError::from)?;
+    }
+
+    Ok(StreamingResponse {
+        status: response.status(),
+        headers: response.headers().clone(),
+        body: ResponseBody { stream, connection: connection.clone(), received: 0, ended: false },
+        upload_stopped,
+    })
+}
+
+async fn receive_discarding(connection: &Arc<Connection>, req: Request<()>, mut progress: impl FnMut(usize)) -> anyhow::Result<Download> {
+    let mut response = send_head(connection, req, None).await?;
+    while let Some(chunk) = response.body.next_chunk().await? {
+        progress(chunk.len());
+    }
+
+    Ok(Download {
+        status: response.status,
+        headers: response.headers,
+        received: response.body.received(),
+    })
+}
+
+// End the request. A server that has answered already may stop reading before the end arrives,
+// which fails this without making its response any less valid, so the code is returned instead
+async fn finish(stream: &mut RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>) -> Result<Option<Code>, RequestError> {
+    match stream.finish().await {
+        Ok(()) => Ok(None),
+        Err(StreamError::RemoteTerminate { code, .. }) => Ok(Some(code)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Whether a socket bound to `local` can send to `remote`. Only one bound to IPv6's unspecified
+// address is dual-stack, reaching IPv4 servers through mapped addresses.
+fn can_reach(local: SocketAddr, remote: SocketAddr) -> bool {
+    local.is_ipv4() == remote.is_ipv4() || (local.is_ipv6() && local.ip().is_unspecified())
+}
+
+// Whether a request failed because its connection is gone or its early data was rejected
+// (rather than because of the request itself), so it can go on a new connection
+fn is_retryable(error: &anyhow::Error) -> bool {
+    matches!(error.downcast_ref::<RequestError>(), Some(RequestError::ConnectionClosed(_) | RequestError::ZeroRttRejected))
 }
 
 // Custom certificate verifier that skips verification (for self-signed certs in development)
@@ -117,3 +1178,79 @@ impl rustls::client::danger::ServerCertVerifier for SkipServerVerification {
         ]
     }
 }
+
+// Accepts exactly one certificate, identified by its SHA-256 fingerprint. The handshake signature
+// is still checked so the server must hold the matching private key.
+#[derive(Debug)]
+struct PinnedServerVerification {
+    fingerprint: Fingerprint,
+    algorithms: rustls::crypto::WebPkiSupportedAlgorithms,
+}
+
+impl PinnedServerVerification {
+    fn new(fingerprint: Fingerprint) -> Self {
+        let algorithms = rustls::crypto::aws_lc_rs::default_provider().signature_verification_algorithms;
+        PinnedServerVerification { fingerprint, algorithms }
+    }
+}
+
+struct FingerprintMismatch {
+    pinned: Fingerprint,
+    actual: Fingerprint,
+}
+
+impl fmt::Display for FingerprintMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fingerprint {} does not match the pinned {}", self.actual, self.pinned)
+    }
+}
+
+// rustls formats certificate errors with `{:?}`, which ends up in the handshake error message
+impl fmt::Debug for FingerprintMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for FingerprintMismatch {}
+
+impl rustls::client::danger::ServerCertVerifier for PinnedServerVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        _server_name: &rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        let actual = Fingerprint::of(end_entity);
+        if actual != self.fingerprint {
+            let mismatch = FingerprintMismatch { pinned: self.fingerprint, actual };
+            let error = rustls::CertificateError::Other(rustls::OtherError(Arc::new(mismatch)));
+            return Err(rustls::Error::InvalidCertificate(error));
+        }
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
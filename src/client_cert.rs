@@ -0,0 +1,115 @@
+// Client certificates (mutual TLS): the CA bundle the server verifies them against, and what a
+// verified certificate says about its holder, for handlers and the authorizer.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, anyhow};
+use rustls::RootCertStore;
+use rustls::pki_types::CertificateDer;
+use rustls::pki_types::pem::PemObject;
+use rustls::server::WebPkiClientVerifier;
+use rustls::server::danger::ClientCertVerifier;
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+/// A verifier that only lets clients in with a certificate issued by one of the CAs in the PEM
+/// file at `path`.
+pub(crate) fn verifier(path: &Path) -> anyhow::Result<Arc<dyn ClientCertVerifier>> {
+    let mut roots = RootCertStore::empty();
+    let certs = CertificateDer::pem_file_iter(path)
+        .with_context(|| format!("cannot read client CA file {}", path.display()))?;
+    for cert in certs {
+        let cert = cert.with_context(|| format!("invalid certificate in {}", path.display()))?;
+        roots.add(cert).with_context(|| format!("unusable CA certificate in {}", path.display()))?;
+    }
+    if roots.is_empty() {
+        anyhow::bail!("no certificates in client CA file {}", path.display());
+    }
+    Ok(WebPkiClientVerifier::builder(Arc::new(roots)).build()?)
+}
+
+/// The parts of a client's verified certificate that authorization usually looks at.
+#[derive(Debug, Clone)]
+pub struct PeerCertificate {
+    /// The whole subject, e.g. `CN=alice, OU=ops, O=Example`
+    pub subject: String,
+    pub common_name: Option<String>,
+    /// Every OU of the subject, in order
+    pub organizational_units: Vec<String>,
+    /// DNS names, IP addresses, e-mail addresses and URIs from the subjectAltName extension
+    pub subject_alt_names: Vec<SubjectAltName>,
+    pub issuer: String,
+    /// Hex, as printed by `openssl x509 -serial`
+    pub serial: String,
+    pub not_before: SystemTime,
+    pub not_after: SystemTime,
+}
+
+/// One subjectAltName entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubjectAltName {
+    Dns(String),
+    Ip(IpAddr),
+    Email(String),
+    Uri(String),
+}
+
+impl fmt::Display for SubjectAltName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubjectAltName::Dns(name) => write!(f, "DNS:{}", name),
+            SubjectAltName::Ip(ip) => write!(f, "IP:{}", ip),
+            SubjectAltName::Email(email) => write!(f, "email:{}", email),
+            SubjectAltName::Uri(uri) => write!(f, "URI:{}", uri),
+        }
+    }
+}
+
+impl PeerCertificate {
+    /// Parse a DER certificate. The server only calls this for certificates rustls has already
+    /// verified.
+    pub fn parse(der: &CertificateDer<'_>) -> anyhow::Result<PeerCertificate> {
+        let (_, cert) = X509Certificate::from_der(der).map_err(|e| anyhow!("invalid certificate: {}", e))?;
+        let subject = cert.subject();
+        let text = |attr: &x509_parser::x509::AttributeTypeAndValue<'_>| attr.as_str().ok().map(String::from);
+
+        // Other kinds of names (directory names, other names, ...) are left out
+        let mut subject_alt_names = Vec::new();
+        if let Some(extension) = cert.subject_alternative_name()? {
+            for name in &extension.value.general_names {
+                subject_alt_names.push(match *name {
+                    GeneralName::DNSName(dns) => SubjectAltName::Dns(dns.to_string()),
+                    GeneralName::RFC822Name(email) => SubjectAltName::Email(email.to_string()),
+                    GeneralName::URI(uri) => SubjectAltName::Uri(uri.to_string()),
+                    GeneralName::IPAddress(bytes) => match bytes.len() {
+                        4 => SubjectAltName::Ip(IpAddr::from(<[u8; 4]>::try_from(bytes).unwrap())),
+                        16 => SubjectAltName::Ip(IpAddr::from(<[u8; 16]>::try_from(bytes).unwrap())),
+                        _ => continue,
+                    },
+                    _ => continue,
+                });
+            }
+        }
+
+        Ok(PeerCertificate {
+            subject: subject.to_string(),
+            common_name: subject.iter_common_name().find_map(text),
+            organizational_units: subject.iter_organizational_unit().filter_map(text).collect(),
+            subject_alt_names,
+            issuer: cert.issuer().to_string(),
+            serial: cert.raw_serial_as_string().replace(':', "").to_uppercase(),
+            not_before: timestamp(cert.validity().not_before.timestamp()),
+            not_after: timestamp(cert.validity().not_after.timestamp()),
+        })
+    }
+}
+
+fn timestamp(seconds: i64) -> SystemTime {
+    match u64::try_from(seconds) {
+        Ok(seconds) => SystemTime::UNIX_EPOCH + Duration::from_secs(seconds),
+        Err(_) => SystemTime::UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs()),
+    }
+}
@@ -0,0 +1,190 @@
+// Response compression, and decoding of compressed request bodies. Each supported content coding
+// is an `Encoding` variant; the client's Accept-Encoding header picks one by quality value, with
+// the order of `Encoding::ALL` breaking ties. Adding a coding means adding a variant and its arms
+// below.
+
+use std::io::{self, Write};
+
+use bytes::{Buf, Bytes};
+use http::HeaderMap;
+
+use crate::negotiate;
+
+/// Bodies shorter than this are sent uncompressed: the saving would be a few bytes at best, and
+/// small bodies often come out larger.
+pub const MIN_SIZE: usize = 1024;
+
+/// A content coding the server can compress responses with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// Every supported coding, most preferred first (brotli compresses text better than gzip).
+    pub const ALL: [Encoding; 2] = [Encoding::Brotli, Encoding::Gzip];
+
+    /// The name used in Accept-Encoding and Content-Encoding.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// The coding a Content-Encoding `name` stands for, ignoring case; `x-gzip` is an alias of
+    /// `gzip` (RFC 9110, section 8.4.1.3).
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        if name.eq_ignore_ascii_case("x-gzip") {
+            return Some(Encoding::Gzip);
+        }
+        Encoding::ALL.into_iter().find(|encoding| name.eq_ignore_ascii_case(encoding.name()))
+    }
+
+    /// Extension of precompressed files in this coding, e.g. `app.js.br` next to `app.js`.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gz",
+        }
+    }
+
+    /// The best coding the request accepts, or `None` to send the body as is.
+    pub fn negotiate(headers: &HeaderMap) -> Option<Encoding> {
+        let names = Encoding::ALL.map(Encoding::name);
+        let name = negotiate::best_encoding(headers, &names)?;
+        Encoding::ALL.into_iter().find(|encoding| encoding.name() == name)
+    }
+
+    pub fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut out = Vec::new();
+                // Quality 5 of 11 and a 4 MiB window (lgwin 22): most of the ratio for a fraction
+                // of the CPU of the maximum settings
+                let mut writer = brotli::CompressorWriter::new(&mut out, 4096, 5, 22);
+                writer.write_all(data)?;
+                drop(writer);
+                Ok(out)
+            }
+            Encoding::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Compress `body` with the best coding the request accepts, if it is at least [`MIN_SIZE`]
+/// bytes. Returns the coding used (to go in Content-Encoding) and the body to send.
+pub fn compress_body(headers: &HeaderMap, body: Bytes) -> (Option<Encoding>, Bytes) {
+    if body.len() < MIN_SIZE {
+        return (None, body);
+    }
+    let Some(encoding) = Encoding::negotiate(headers) else {
+        return (None, body);
+    };
+    match encoding.compress(&body) {
+        Ok(compressed) => (Some(encoding), Bytes::from(compressed)),
+        // Compressing into memory doesn't fail in practice; sending it as is is always correct
+        Err(_) => (None, body),
+    }
+}
+
+/// Decoded bytes [`Decoder::read`] hands out at a time, so the size of what a chunk of encoded
+/// data expands to can be checked as it grows rather than after the fact.
+pub const DECODE_STEP: usize = 32 * 1024;
+
+/// Undoes a content coding as the encoded data arrives in chunks, so a compressed request body
+/// never has to be in memory whole, decoded or not.
+pub struct Decoder(Decoding);
+
+enum Decoding {
+    Brotli(Box<brotli::Decompressor<Input>>),
+    Gzip(Box<flate2::bufread::GzDecoder<Input>>),
+}
+
+/// What [`Decoder::read`] got out of the encoded data so far.
+#[derive(Debug)]
+pub enum Decoded {
+    /// Up to [`DECODE_STEP`] decoded bytes
+    Data(Bytes),
+    /// Everything fed so far is decoded; [`Decoder::feed`] the next chunk
+    NeedsInput,
+    /// The end of the compressed stream
+    End,
+}
+
+// The encoded chunk being decoded. Running dry before the end is `WouldBlock`, which both
+// decompressors treat as "call again with more input", keeping their state.
+struct Input {
+    chunk: Bytes,
+    ended: bool,
+}
+
+impl io::BufRead for Input {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.chunk.is_empty() && !self.ended {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        Ok(&self.chunk)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.chunk.advance(amt);
+    }
+}
+
+impl io::Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = io::BufRead::fill_buf(self)?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        io::BufRead::consume(self, len);
+        Ok(len)
+    }
+}
+
+impl Decoder {
+    pub fn new(encoding: Encoding) -> Decoder {
+        let input = Input { chunk: Bytes::new(), ended: false };
+        Decoder(match encoding {
+            Encoding::Brotli => Decoding::Brotli(Box::new(brotli::Decompressor::new(input, 4096))),
+            Encoding::Gzip => Decoding::Gzip(Box::new(flate2::bufread::GzDecoder::new(input))),
+        })
+    }
+
+    /// Hand over the next `chunk` of encoded data once [`Decoder::read`] asks for it, or `None`
+    /// at the end of the encoded data.
+    pub fn feed(&mut self, chunk: Option<Bytes>) {
+        let input = match &mut self.0 {
+            Decoding::Brotli(reader) => reader.get_mut(),
+            Decoding::Gzip(reader) => reader.get_mut(),
+        };
+        match chunk {
+            Some(chunk) => input.chunk = chunk,
+            None => input.ended = true,
+        }
+    }
+
+    /// Decode the next step of the data fed so far. Fails on data that isn't valid in the
+    /// coding, or that stops short of the end of the compressed stream.
+    pub fn read(&mut self) -> io::Result<Decoded> {
+        let mut out = vec![0; DECODE_STEP];
+        let read = match &mut self.0 {
+            Decoding::Brotli(reader) => io::Read::read(reader, &mut out),
+            Decoding::Gzip(reader) => io::Read::read(reader, &mut out),
+        };
+        match read {
+            Ok(0) => Ok(Decoded::End),
+            Ok(len) => {
+                out.truncate(len);
+                Ok(Decoded::Data(out.into()))
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Decoded::NeedsInput),
+            Err(e) => Err(e),
+        }
+    }
+}
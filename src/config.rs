@@ -0,0 +1,208 @@
+// Server settings loaded from a TOML file. Keys are the long command-line flags without the
+// leading dashes (`max-inflight-per-connection = 8`); every key is optional.
+
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+use http::{HeaderName, HeaderValue};
+use serde::{Deserialize, Deserializer};
+
+use crate::server::{self, MIN_UNI_STREAMS, OverloadPolicy};
+use crate::simulate;
+use crate::static_files::DirectoryListing;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub listen: Option<SocketAddr>,
+    pub san: Option<Vec<String>>,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    #[serde(deserialize_with = "at_least::<1, _, _>")]
+    pub max_inflight_per_connection: Option<u32>,
+    #[serde(deserialize_with = "at_least::<1, _, _>")]
+    pub max_inflight_requests: Option<u32>,
+    #[serde(deserialize_with = "duration")]
+    pub queue_timeout: Option<Duration>,
+    pub overload: Option<OverloadPolicy>,
+    #[serde(deserialize_with = "duration")]
+    pub retry_after: Option<Duration>,
+    #[serde(deserialize_with = "at_least::<1, _, _>")]
+    pub max_requests_per_connection: Option<u64>,
+    #[serde(deserialize_with = "duration")]
+    pub max_connection_age: Option<Duration>,
+    #[serde(deserialize_with = "at_least::<1, _, _>")]
+    pub error_budget: Option<u32>,
+    pub max_concurrent_bidi_streams: Option<u32>,
+    #[serde(deserialize_with = "at_least::<MIN_UNI_STREAMS, _, _>")]
+    pub max_concurrent_uni_streams: Option<u32>,
+    pub datagram_receive_buffer: Option<usize>,
+    pub datagram_send_buffer: Option<usize>,
+    #[serde(deserialize_with = "at_least::<1, _, _>")]
+    pub max_datagram_frame_size: Option<u32>,
+    pub enable_spin_bit: Option<bool>,
+    #[serde(deserialize_with = "initial_rtt")]
+    pub initial_rtt: Option<Duration>,
+    #[serde(deserialize_with = "initial_cwnd")]
+    pub initial_cwnd: Option<u64>,
+    pub early_data: Option<bool>,
+    #[serde(deserialize_with = "at_least::<1, _, _>")]
+    pub max_handshakes: Option<u32>,
+    #[serde(deserialize_with = "duration")]
+    pub handshake_timeout: Option<Duration>,
+    #[serde(deserialize_with = "duration")]
+    pub header_timeout: Option<Duration>,
+    #[serde(deserialize_with = "duration")]
+    pub body_read_timeout: Option<Duration>,
+    #[serde(deserialize_with = "duration")]
+    pub request_timeout: Option<Duration>,
+    #[serde(deserialize_with = "route_timeouts")]
+    pub route_timeout: Option<Vec<(String, Duration)>>,
+    #[serde(deserialize_with = "redirects")]
+    pub redirect: Option<Vec<(String, HeaderValue)>>,
+    #[serde(deserialize_with = "response_headers")]
+    pub response_header: Option<Vec<(HeaderName, HeaderValue)>>,
+    #[serde(deserialize_with = "duration")]
+    pub shutdown_timeout: Option<Duration>,
+    #[serde(deserialize_with = "duration")]
+    pub stats_interval: Option<Duration>,
+    pub body_size_buckets: Option<Vec<u64>>,
+    #[serde(deserialize_with = "request_rate_windows")]
+    pub request_rate_windows: Option<Vec<Duration>>,
+    pub no_access_log: Option<bool>,
+    #[serde(deserialize_with = "duration")]
+    pub slow_request_threshold: Option<Duration>,
+    pub static_dir: Option<PathBuf>,
+    pub directory_listing: Option<DirectoryListing>,
+    pub browser_probes: Option<bool>,
+    pub generate_route: Option<bool>,
+    pub debug_routes: Option<bool>,
+    pub client_ca: Option<PathBuf>,
+    pub max_body_size: Option<u64>,
+    #[serde(deserialize_with = "at_least::<1, _, _>")]
+    pub kv_max_entries: Option<u32>,
+    #[serde(deserialize_with = "at_least::<1, _, _>")]
+    pub kv_max_size: Option<u64>,
+    pub connect_udp: Option<bool>,
+    pub connect_udp_allow_private: Option<bool>,
+    #[serde(deserialize_with = "cid_length")]
+    pub cid_length: Option<usize>,
+    pub no_migration: Option<bool>,
+    pub no_gso: Option<bool>,
+    #[serde(deserialize_with = "at_least::<1, _, _>")]
+    pub shards: Option<u32>,
+    #[serde(deserialize_with = "loss")]
+    pub simulate_loss: Option<f64>,
+    #[serde(deserialize_with = "duration")]
+    pub simulate_delay: Option<Duration>,
+    #[serde(deserialize_with = "duration")]
+    pub simulate_jitter: Option<Duration>,
+}
+
+impl Config {
+    /// Errors point at the offending key and its line, e.g. an unknown key or a value out of range.
+    pub fn load(path: &Path) -> anyhow::Result<Config> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("invalid config file {}", path.display()))
+    }
+}
+
+// Durations are written the same way as on the command line: "500ms", "10s", "2m"
+fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    humantime::parse_duration(&text).map(Some).map_err(serde::de::Error::custom)
+}
+
+// A list of "PATH=DURATION" strings, like the repeated flag
+fn route_timeouts<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<(String, Duration)>>, D::Error> {
+    let entries = Vec::<String>::deserialize(deserializer)?;
+    let timeouts = entries.iter().map(|entry| server::parse_route_timeout(entry));
+    timeouts.collect::<anyhow::Result<_>>().map(Some).map_err(serde::de::Error::custom)
+}
+
+// A list of "PATH=LOCATION" strings, like the repeated flag
+fn redirects<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<(String, HeaderValue)>>, D::Error> {
+    let entries = Vec::<String>::deserialize(deserializer)?;
+    let redirects = entries.iter().map(|entry| server::parse_redirect(entry));
+    redirects.collect::<anyhow::Result<_>>().map(Some).map_err(serde::de::Error::custom)
+}
+
+// A list of "NAME: VALUE" strings, like the repeated flag
+fn response_headers<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<(HeaderName, HeaderValue)>>, D::Error> {
+    let entries = Vec::<String>::deserialize(deserializer)?;
+    let headers = entries.iter().map(|entry| server::parse_response_header(entry));
+    headers.collect::<anyhow::Result<_>>().map(Some).map_err(serde::de::Error::custom)
+}
+
+// A list of durations, like the comma-separated flag
+fn request_rate_windows<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<Duration>>, D::Error> {
+    let entries = Vec::<String>::deserialize(deserializer)?;
+    let windows = entries.iter().map(|entry| humantime::parse_duration(entry));
+    let windows = windows.collect::<Result<Vec<_>, _>>().map_err(serde::de::Error::custom)?;
+    server::check_request_rate_windows(&windows).map_err(serde::de::Error::custom)?;
+    Ok(Some(windows))
+}
+
+fn initial_rtt<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    let rtt = humantime::parse_duration(&text).map_err(serde::de::Error::custom)?;
+    server::check_initial_rtt(rtt).map(Some).map_err(serde::de::Error::custom)
+}
+
+fn initial_cwnd<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    let bytes = u64::deserialize(deserializer)?;
+    server::check_initial_cwnd(bytes).map(Some).map_err(serde::de::Error::custom)
+}
+
+fn cid_length<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<usize>, D::Error> {
+    let len = usize::deserialize(deserializer)?;
+    server::check_cid_length(len).map(Some).map_err(serde::de::Error::custom)
+}
+
+fn loss<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    let loss = f64::deserialize(deserializer)?;
+    simulate::check_loss(loss).map(Some).map_err(serde::de::Error::custom)
+}
+
+fn at_least<'de, const MIN: u32, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + PartialOrd + From<u32> + Display,
+{
+    let value = T::deserialize(deserializer)?;
+    if value < T::from(MIN) {
+        return Err(serde::de::Error::custom(format!("must be at least {}, got {}", MIN, value)));
+    }
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(text: &str) -> String {
+        toml::from_str::<Config>(text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn errors_point_at_the_key() {
+        let error = parse_error("listen = \"127.0.0.1:4433\"\nmax-handshakes = 0\n");
+        assert!(error.contains("line 2") && error.contains("max-handshakes = 0"), "{}", error);
+        assert!(error.contains("must be at least 1, got 0"), "{}", error);
+
+        let error = parse_error("\nheader-timeout = \"soon\"\n");
+        assert!(error.contains("line 2") && error.contains("header-timeout"), "{}", error);
+
+        let error = parse_error("max-handshake = 10\n");
+        assert!(error.contains("line 1") && error.contains("unknown field `max-handshake`"), "{}", error);
+
+        let error = parse_error("redirect = [\"/a=/b\", \"b=/c\"]\n");
+        assert!(error.contains("redirect path must start with '/'"), "{}", error);
+    }
+}
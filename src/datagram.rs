@@ -0,0 +1,60 @@
+// Sending QUIC datagrams (RFC 9221) with the size checked against what the peer accepts at that
+// moment. quinn's limit moves with the path MTU estimate, so it is looked up for every datagram
+// rather than once after the handshake. The server can lower it further with
+// `ServerBuilder::max_datagram_frame_size`; quinn derives the limit it advertises from the receive
+// buffer and offers no separate knob, so that lower limit is enforced here and on receipt.
+
+use std::fmt;
+
+use bytes::Bytes;
+use h3_quinn::quinn;
+
+/// Why a datagram wasn't sent.
+#[derive(Debug)]
+pub enum DatagramError {
+    /// The datagram is `size` bytes, but at most `max` fit right now: the peer's limit, the
+    /// path MTU or the server's own frame size limit, whichever is lowest
+    TooLarge { size: usize, max: usize },
+    /// The peer doesn't accept datagrams, or they are disabled on this side
+    Unsupported,
+    /// The connection is gone
+    ConnectionLost(quinn::ConnectionError),
+}
+
+impl fmt::Display for DatagramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatagramError::TooLarge { size, max } => write!(f, "datagram of {} bytes exceeds the limit of {} bytes", size, max),
+            DatagramError::Unsupported => write!(f, "datagrams are not enabled on this connection"),
+            DatagramError::ConnectionLost(e) => write!(f, "connection lost: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DatagramError {}
+
+/// Largest datagram that can be sent on `conn` now, given the server's own `frame_limit`, or
+/// `None` if datagrams aren't enabled.
+pub fn max_size(conn: &quinn::Connection, frame_limit: Option<usize>) -> Option<usize> {
+    let max = conn.max_datagram_size()?;
+    Some(frame_limit.map_or(max, |limit| max.min(limit)))
+}
+
+/// Send `data` as one datagram on `conn`, failing with [`DatagramError::TooLarge`] if it is
+/// larger than [`max_size`] allows.
+pub fn send(conn: &quinn::Connection, frame_limit: Option<usize>, data: Bytes) -> Result<(), DatagramError> {
+    if let Some(reason) = conn.close_reason() {
+        return Err(DatagramError::ConnectionLost(reason));
+    }
+    let max = max_size(conn, frame_limit).ok_or(DatagramError::Unsupported)?;
+    if data.len() > max {
+        return Err(DatagramError::TooLarge { size: data.len(), max });
+    }
+    let size = data.len();
+    conn.send_datagram(data).map_err(|e| match e {
+        // The MTU estimate may have dropped since it was looked up
+        quinn::SendDatagramError::TooLarge => DatagramError::TooLarge { size, max: conn.max_datagram_size().unwrap_or(0) },
+        quinn::SendDatagramError::UnsupportedByPeer | quinn::SendDatagramError::Disabled => DatagramError::Unsupported,
+        quinn::SendDatagramError::ConnectionLost(e) => DatagramError::ConnectionLost(e),
+    })
+}
@@ -0,0 +1,44 @@
+// HTTP/3 error codes (RFC 9114, section 8.1) for resetting streams and closing connections.
+// h3's `Code` already names them (`Code::H3_REQUEST_CANCELLED`, ...) and prints them symbolically;
+// this adds the list of standard codes and parsing them back from text.
+
+pub use h3::error::Code;
+
+/// The error codes defined by RFC 9114, in numeric order.
+pub const STANDARD: &[Code] = &[
+    Code::H3_NO_ERROR,
+    Code::H3_GENERAL_PROTOCOL_ERROR,
+    Code::H3_INTERNAL_ERROR,
+    Code::H3_STREAM_CREATION_ERROR,
+    Code::H3_CLOSED_CRITICAL_STREAM,
+    Code::H3_FRAME_UNEXPECTED,
+    Code::H3_FRAME_ERROR,
+    Code::H3_EXCESSIVE_LOAD,
+    Code::H3_ID_ERROR,
+    Code::H3_SETTINGS_ERROR,
+    Code::H3_MISSING_SETTINGS,
+    Code::H3_REQUEST_REJECTED,
+    Code::H3_REQUEST_CANCELLED,
+    Code::H3_REQUEST_INCOMPLETE,
+    Code::H3_MESSAGE_ERROR,
+    Code::H3_CONNECT_ERROR,
+    Code::H3_VERSION_FALLBACK,
+];
+
+/// Parse a code given by name (`H3_REQUEST_CANCELLED`, case-insensitive, prefix optional) or by
+/// number (`268` or `0x10c`). Numbers outside the standard set are accepted as-is.
+pub fn parse(text: &str) -> Option<Code> {
+    let text = text.trim();
+    let number = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    };
+    if let Some(number) = number {
+        // Codes are QUIC varints
+        return (number < 1 << 62).then(|| Code::from(number));
+    }
+
+    let upper = text.to_ascii_uppercase();
+    let name = if upper.starts_with("H3_") { upper } else { format!("H3_{}", upper) };
+    STANDARD.iter().copied().find(|code| code.to_string() == name)
+}
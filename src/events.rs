@@ -0,0 +1,83 @@
+// Structured lifecycle events for embedders, as an alternative to scraping the logs: connections
+// opening and closing, requests starting and completing, and errors. They go out on a tokio
+// broadcast channel, so any number of subscribers see every event and a slow one can't hold up
+// the server: once it is a full buffer behind, the oldest events are overwritten and its next
+// `recv` says how many it missed (`RecvError::Lagged`). Off unless
+// `ServerBuilder::event_buffer` turns it on; events are only built while someone subscribes.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use h3_quinn::quinn;
+use http::{Method, StatusCode};
+use tokio::sync::broadcast;
+
+/// Something that happened on the server, from [`Server::events`](crate::server::Server::events).
+/// `connection` is quinn's stable id of the connection, the same in every event about it.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A connection completed its handshake
+    ConnectionOpened {
+        connection: usize,
+        remote_address: SocketAddr,
+        /// SNI sent by the client, if any
+        server_name: Option<String>,
+        alpn: Option<String>,
+    },
+    /// The server is done with a connection
+    ConnectionClosed {
+        connection: usize,
+        remote_address: SocketAddr,
+        /// Time since the handshake completed
+        duration: Duration,
+        /// HTTP/3 requests accepted on the connection
+        requests: u64,
+        /// Why it closed, or `None` if the server closes it now that every request has finished
+        reason: Option<quinn::ConnectionError>,
+    },
+    /// A request's header section arrived and passed validation
+    RequestStarted {
+        connection: usize,
+        stream: u64,
+        method: Method,
+        path: String,
+    },
+    /// A request's handler is done, whether or not its response made it out
+    RequestCompleted {
+        connection: usize,
+        stream: u64,
+        /// The status of the response sent, or `None` if there was none, e.g. after a timeout or
+        /// for a CONNECT-UDP tunnel
+        status: Option<StatusCode>,
+        /// Time since [`Event::RequestStarted`]
+        duration: Duration,
+    },
+    /// Something failed that is otherwise only logged: a handshake (`connection` is `None` then),
+    /// a malformed request, a request timeout, or a response that couldn't be sent
+    Error {
+        connection: Option<usize>,
+        remote_address: SocketAddr,
+        message: String,
+    },
+}
+
+// The sending side, shared by every connection task
+#[derive(Debug, Default)]
+pub(crate) struct Emitter(Option<broadcast::Sender<Event>>);
+
+impl Emitter {
+    pub(crate) fn new(capacity: usize) -> Emitter {
+        Emitter(Some(broadcast::channel(capacity).0))
+    }
+
+    // `event` is only called if someone is listening
+    pub(crate) fn emit(&self, event: impl FnOnce() -> Event) {
+        if let Some(sender) = self.0.as_ref().filter(|sender| sender.receiver_count() > 0) {
+            let _ = sender.send(event());
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> Option<broadcast::Receiver<Event>> {
+        self.0.as_ref().map(broadcast::Sender::subscribe)
+    }
+}
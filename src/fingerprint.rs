@@ -0,0 +1,49 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::bail;
+use rustls::pki_types::CertificateDer;
+use sha2::{Digest, Sha256};
+
+// SHA-256 of a DER certificate, shown as colon-separated hex (the format `openssl x509 -fingerprint` uses)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    pub fn of(cert: &CertificateDer<'_>) -> Self {
+        Fingerprint(Sha256::digest(cert.as_ref()).into())
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+// Accepts hex with or without colons, in either case
+impl FromStr for Fingerprint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let hex: String = s.chars().filter(|c| *c != ':').collect();
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("fingerprint must be hex digits, optionally separated by ':'");
+        }
+        if hex.len() != 64 {
+            bail!("expected a SHA-256 fingerprint (32 bytes), got {} hex digits", hex.len());
+        }
+
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)?;
+        }
+        Ok(Fingerprint(bytes))
+    }
+}
@@ -0,0 +1,57 @@
+// `/generate?bytes=N`: N bytes of data a client can check without a copy of its own. The body is
+// a run of 64-bit big-endian counters, 0, 1, 2, ..., cut off after N bytes, so every byte follows
+// from its offset and a corrupted, dropped or reordered chunk shows up at the offset it hit.
+// Generated as it's sent, so N can be much more than fits in memory. Off unless
+// `ServerBuilder::generate_route` turns it on: it's for tests and benchmarks, not production.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use http::StatusCode;
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::response::Response;
+
+pub const PATH: &str = "/generate";
+
+/// The byte at `offset` of every generated body.
+pub fn byte_at(offset: u64) -> u8 {
+    (offset / 8).to_be_bytes()[(offset % 8) as usize]
+}
+
+/// Checks `data`, found at `offset` of a generated body, and returns the offset of the first
+/// byte that's wrong, if any.
+pub fn verify(offset: u64, data: &[u8]) -> Option<u64> {
+    (offset..).zip(data).find(|&(at, &byte)| byte != byte_at(at)).map(|(at, _)| at)
+}
+
+/// The response to `/generate`, given its `bytes` query parameter: the body, or 400 if that
+/// isn't a number.
+pub fn respond(bytes: Option<&str>) -> Response {
+    let Some(len) = bytes.and_then(|bytes| bytes.parse::<u64>().ok()) else {
+        return Response::text(StatusCode::BAD_REQUEST, "400 Bad Request: expected ?bytes=N");
+    };
+    Response::new(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/octet-stream")
+        .header(CONTENT_LENGTH, len)
+        .stream(Generated { offset: 0, len })
+}
+
+struct Generated {
+    offset: u64,
+    len: u64,
+}
+
+impl AsyncRead for Generated {
+    fn poll_read(mut self: Pin<&mut Self>, _: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let n = buf.remaining().min((self.len - self.offset).try_into().unwrap_or(usize::MAX));
+        for (at, byte) in (self.offset..).zip(buf.initialize_unfilled_to(n)) {
+            *byte = byte_at(at);
+        }
+        buf.advance(n);
+        self.offset += n as u64;
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,108 @@
+// Why QUIC handshakes fail, as far as the server can tell: the TLS alert behind a CRYPTO_ERROR
+// (RFC 9001, section 4.8), a version mismatch, the client giving up, or the handshake timeout.
+
+use std::fmt;
+
+use h3_quinn::quinn;
+
+/// A failed handshake, sorted into what is most likely wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeFailure {
+    /// Not finished within the server's handshake timeout
+    Timeout,
+    /// The client offered none of the server's ALPN protocols
+    NoApplicationProtocol,
+    /// A certificate was rejected or missing, by either side
+    Certificate { alert: u8, by_client: bool },
+    /// Any other TLS alert
+    Tls { alert: u8, by_client: bool },
+    /// The client only speaks QUIC versions the server doesn't
+    VersionMismatch,
+    /// The client closed the connection or went silent before the handshake was done
+    ClientGaveUp,
+    Other,
+}
+
+impl HandshakeFailure {
+    /// Label values of [`HandshakeFailure::label`], for the metrics.
+    pub const LABELS: &'static [&'static str] = &["timeout", "alpn", "certificate", "tls", "version", "client", "other"];
+
+    pub fn classify(e: &quinn::ConnectionError) -> HandshakeFailure {
+        let (code, by_client) = match e {
+            quinn::ConnectionError::TransportError(error) => (u64::from(error.code), false),
+            quinn::ConnectionError::ConnectionClosed(close) => (u64::from(close.error_code), true),
+            quinn::ConnectionError::ApplicationClosed(_) | quinn::ConnectionError::TimedOut | quinn::ConnectionError::Reset => {
+                return HandshakeFailure::ClientGaveUp;
+            }
+            quinn::ConnectionError::VersionMismatch => return HandshakeFailure::VersionMismatch,
+            _ => return HandshakeFailure::Other,
+        };
+        // CRYPTO_ERROR codes carry the TLS alert in the low byte
+        if !(0x100..=0x1ff).contains(&code) {
+            return if by_client { HandshakeFailure::ClientGaveUp } else { HandshakeFailure::Other };
+        }
+        let alert = code as u8;
+        match alert {
+            120 => HandshakeFailure::NoApplicationProtocol,
+            // bad_certificate up to unknown_ca, and certificate_required
+            42..=48 | 116 => HandshakeFailure::Certificate { alert, by_client },
+            _ => HandshakeFailure::Tls { alert, by_client },
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            HandshakeFailure::Timeout => "timeout",
+            HandshakeFailure::NoApplicationProtocol => "alpn",
+            HandshakeFailure::Certificate { .. } => "certificate",
+            HandshakeFailure::Tls { .. } => "tls",
+            HandshakeFailure::VersionMismatch => "version",
+            HandshakeFailure::ClientGaveUp => "client",
+            HandshakeFailure::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for HandshakeFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeFailure::Timeout => write!(f, "timed out"),
+            HandshakeFailure::NoApplicationProtocol => write!(f, "the client offers none of the served ALPN protocols"),
+            HandshakeFailure::Certificate { alert, by_client } | HandshakeFailure::Tls { alert, by_client } => {
+                let side = if *by_client { "the client" } else { "the server" };
+                match alert_name(*alert) {
+                    Some(name) => write!(f, "{} sent the TLS alert {} ({})", side, name, alert),
+                    None => write!(f, "{} sent TLS alert {}", side, alert),
+                }
+            }
+            HandshakeFailure::VersionMismatch => write!(f, "no QUIC version in common"),
+            HandshakeFailure::ClientGaveUp => write!(f, "the client closed the connection"),
+            HandshakeFailure::Other => write!(f, "unexpected error"),
+        }
+    }
+}
+
+// The alerts a QUIC handshake can realistically end with (RFC 8446, section 6)
+fn alert_name(alert: u8) -> Option<&'static str> {
+    Some(match alert {
+        10 => "unexpected_message",
+        40 => "handshake_failure",
+        42 => "bad_certificate",
+        43 => "unsupported_certificate",
+        44 => "certificate_revoked",
+        45 => "certificate_expired",
+        46 => "certificate_unknown",
+        47 => "illegal_parameter",
+        48 => "unknown_ca",
+        50 => "decode_error",
+        51 => "decrypt_error",
+        70 => "protocol_version",
+        80 => "internal_error",
+        109 => "missing_extension",
+        110 => "unsupported_extension",
+        112 => "unrecognized_name",
+        116 => "certificate_required",
+        120 => "no_application_protocol",
+        _ => return None,
+    })
+}
@@ -0,0 +1,212 @@
+// An in-memory key-value store behind `/kv/{key}`: GET reads a value, PUT stores the request
+// body under the key, DELETE removes it. Shared by every connection of a server and gone when it
+// stops, so it's a demo of routes that keep state, not a database. A mutex around a `HashMap`
+// rather than a concurrent map: each operation holds the lock only for a lookup or an insert.
+//
+// Every write gives the value a new version, sent as its ETag, so a client can PUT or DELETE with
+// `If-Match` and only overwrite the value it last read (RFC 9110, section 13.1.1). The check and
+// the write happen under the same lock, so two clients racing with the same ETag can't both win.
+//
+// The store holds a bounded number of keys and value bytes, so clients can't fill the server's
+// memory through it; a PUT that would go past either gets 507 Insufficient Storage.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use http::header::{ALLOW, CONTENT_TYPE, ETAG, IF_MATCH};
+use http::{HeaderMap, Method, StatusCode};
+
+use crate::response::Response;
+
+pub const PREFIX: &str = "/kv/";
+
+/// Largest value a PUT may store, in bytes; the server's body size limit applies too
+pub const MAX_VALUE: usize = 1024 * 1024;
+
+/// Most keys a store holds unless [`Store::new`] says otherwise
+pub const MAX_ENTRIES: usize = 10_000;
+
+/// Most value bytes a store holds over all keys unless [`Store::new`] says otherwise
+pub const MAX_TOTAL_SIZE: usize = 64 * 1024 * 1024;
+
+const ALLOWED: &str = "GET, PUT, DELETE";
+
+/// The key in a `/kv/{key}` path. `None` for other paths, an empty key, or one with a `/` in it.
+pub fn key(path: &str) -> Option<&str> {
+    path.strip_prefix(PREFIX).filter(|key| !key.is_empty() && !key.contains('/'))
+}
+
+/// A request's `If-Match` condition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IfMatch {
+    /// `*`: there must be a current value, whatever it is
+    Any,
+    /// The current value's ETag must be one of these, compared strongly
+    Tags(Vec<String>),
+}
+
+impl IfMatch {
+    /// `None` without an `If-Match` header. Several headers count as one list.
+    pub fn from_headers(headers: &HeaderMap) -> Option<IfMatch> {
+        let mut tags = Vec::new();
+        for value in headers.get_all(IF_MATCH) {
+            // A header that isn't text can't name an ETag, so it matches nothing
+            let Ok(value) = value.to_str() else { continue };
+            for tag in value.split(',').map(str::trim).filter(|tag| !tag.is_empty()) {
+                if tag == "*" {
+                    return Some(IfMatch::Any);
+                }
+                tags.push(tag.to_string());
+            }
+        }
+        headers.contains_key(IF_MATCH).then_some(IfMatch::Tags(tags))
+    }
+
+    // Weak tags never match strongly, so a `W/` tag in the header can only fail
+    fn matches(&self, current: Option<&Entry>) -> bool {
+        match (self, current) {
+            (_, None) => false,
+            (IfMatch::Any, Some(_)) => true,
+            (IfMatch::Tags(tags), Some(entry)) => tags.iter().any(|tag| *tag == entry.etag()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: Bytes,
+    version: u64,
+}
+
+impl Entry {
+    fn etag(&self) -> String {
+        format!("\"{}\"", self.version)
+    }
+}
+
+/// What a PUT or DELETE did; `None` from [`Store::put`] or [`Store::delete`] if `If-Match`
+/// didn't hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Written {
+    Created { etag: String },
+    Replaced { etag: String },
+    Deleted,
+    /// DELETE of a key that had no value
+    Missing,
+    /// PUT that would take the store past its number of keys or total size; nothing changed
+    Full,
+}
+
+#[derive(Debug)]
+pub struct Store {
+    inner: Mutex<Inner>,
+    max_entries: usize,
+    max_total_size: usize,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    values: HashMap<String, Entry>,
+    // Bytes of all values together
+    total_size: usize,
+    // The version of the last write
+    version: u64,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Store::new(MAX_ENTRIES, MAX_TOTAL_SIZE)
+    }
+}
+
+impl Store {
+    /// A store of at most `max_entries` keys and `max_total_size` bytes of values.
+    pub fn new(max_entries: usize, max_total_size: usize) -> Store {
+        Store { inner: Mutex::default(), max_entries, max_total_size }
+    }
+
+    /// The value under `key` and its ETag.
+    pub fn get(&self, key: &str) -> Option<(Bytes, String)> {
+        self.inner.lock().unwrap().values.get(key).map(|entry| (entry.value.clone(), entry.etag()))
+    }
+
+    /// Stores `value` under `key` unless `if_match` fails or the store has no room for it.
+    pub fn put(&self, key: &str, value: Bytes, if_match: Option<&IfMatch>) -> Option<Written> {
+        let mut inner = self.inner.lock().unwrap();
+        let current = inner.values.get(key);
+        if let Some(condition) = if_match
+            && !condition.matches(current)
+        {
+            return None;
+        }
+        // A replaced value frees its own size, and the key it was under
+        let replaced = current.map_or(0, |entry| entry.value.len());
+        let total_size = inner.total_size - replaced + value.len();
+        if (current.is_none() && inner.values.len() >= self.max_entries) || total_size > self.max_total_size {
+            return Some(Written::Full);
+        }
+        inner.total_size = total_size;
+        inner.version += 1;
+        let entry = Entry { value, version: inner.version };
+        let etag = entry.etag();
+        Some(match inner.values.insert(key.to_string(), entry) {
+            None => Written::Created { etag },
+            Some(_) => Written::Replaced { etag },
+        })
+    }
+
+    /// Removes the value under `key` unless `if_match` fails.
+    pub fn delete(&self, key: &str, if_match: Option<&IfMatch>) -> Option<Written> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(condition) = if_match
+            && !condition.matches(inner.values.get(key))
+        {
+            return None;
+        }
+        Some(match inner.values.remove(key) {
+            Some(entry) => {
+                inner.total_size -= entry.value.len();
+                Written::Deleted
+            }
+            None => Written::Missing,
+        })
+    }
+
+    /// The response to a `/kv/{key}` request other than PUT, whose body the caller reads and
+    /// hands to [`Store::respond_put`].
+    pub fn respond(&self, method: &Method, key: &str, headers: &HeaderMap) -> Response {
+        match *method {
+            Method::GET => match self.get(key) {
+                Some((value, etag)) => Response::new(StatusCode::OK)
+                    .header(CONTENT_TYPE, "application/octet-stream")
+                    .header(ETAG, etag)
+                    .body(value),
+                None => not_found(),
+            },
+            Method::DELETE => written(self.delete(key, IfMatch::from_headers(headers).as_ref())),
+            _ => Response::text(StatusCode::METHOD_NOT_ALLOWED, "405 Method Not Allowed").header(ALLOW, ALLOWED),
+        }
+    }
+
+    /// Stores a PUT's body: 201 for a new key, 204 when it replaces a value, both with the new
+    /// ETag, or 507 if the store is full.
+    pub fn respond_put(&self, key: &str, value: Bytes, headers: &HeaderMap) -> Response {
+        written(self.put(key, value, IfMatch::from_headers(headers).as_ref()))
+    }
+}
+
+fn written(written: Option<Written>) -> Response {
+    match written {
+        Some(Written::Created { etag }) => Response::new(StatusCode::CREATED).header(ETAG, etag),
+        Some(Written::Replaced { etag }) => Response::new(StatusCode::NO_CONTENT).header(ETAG, etag),
+        Some(Written::Deleted) => Response::new(StatusCode::NO_CONTENT),
+        Some(Written::Missing) => not_found(),
+        Some(Written::Full) => Response::text(StatusCode::INSUFFICIENT_STORAGE, "507 Insufficient Storage: the store is full"),
+        None => Response::text(StatusCode::PRECONDITION_FAILED, "412 Precondition Failed"),
+    }
+}
+
+fn not_found() -> Response {
+    Response::text(StatusCode::NOT_FOUND, "404 Not Found")
+}
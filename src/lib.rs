@@ -0,0 +1,32 @@
+pub mod body;
+pub mod cancel;
+pub mod client;
+pub mod client_cert;
+pub mod compress;
+pub mod config;
+pub mod datagram;
+pub mod error_code;
+pub mod events;
+pub mod fingerprint;
+pub mod generate;
+pub mod handshake;
+pub mod kv;
+pub mod logging;
+pub mod masque;
+pub mod metrics;
+pub mod negotiate;
+pub mod priority;
+pub mod privileges;
+pub mod probes;
+pub mod response;
+pub mod router;
+pub mod server;
+pub mod settings;
+pub mod simulate;
+pub mod socket;
+pub mod static_files;
+pub mod uni;
+#[cfg(feature = "templates")]
+pub mod template;
+#[cfg(feature = "trace-bodies")]
+pub mod trace_bodies;
@@ -0,0 +1,54 @@
+// Debug diagnostics through the `log` crate, gated by RUST_LOG the way env_logger reads it: a
+// comma-separated list of `LEVEL` or `TARGET=LEVEL` directives, the longest matching target
+// winning (`RUST_LOG=quic_demo=debug,rustls=info`). Without RUST_LOG only this crate's warnings
+// are logged. The server's regular output stays on stdout; these lines go to stderr.
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+struct Logger {
+    // (target prefix, level), longest prefix first; the empty prefix matches every target
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.level_for(metadata.target()) >= metadata.level()
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+impl Logger {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let matching = self.directives.iter().find(|(prefix, _)| {
+            prefix.is_empty() || target == prefix || target.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.starts_with("::"))
+        });
+        matching.map_or(LevelFilter::Off, |(_, level)| *level)
+    }
+}
+
+/// Install the logger with the directives in RUST_LOG, or `quic_demo=warn` if it isn't set.
+/// Directives that don't parse are reported and skipped. Does nothing if another logger is
+/// already installed.
+pub fn init() {
+    let spec = std::env::var("RUST_LOG").unwrap_or_else(|_| "quic_demo=warn".to_string());
+    let mut directives = Vec::new();
+    for directive in spec.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+        let (target, level) = directive.rsplit_once('=').unwrap_or(("", directive));
+        match level.parse::<LevelFilter>() {
+            Ok(level) => directives.push((target.to_string(), level)),
+            Err(_) => eprintln!("Ignoring RUST_LOG directive {:?}: unknown level", directive),
+        }
+    }
+    directives.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+    let max = directives.iter().map(|(_, level)| *level).max().unwrap_or(LevelFilter::Off);
+    if log::set_boxed_logger(Box::new(Logger { directives })).is_ok() {
+        log::set_max_level(max);
+    }
+}
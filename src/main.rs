@@ -1,83 +1,505 @@
-use std::sync::Arc;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 
-use bytes::Bytes;
-use h3_quinn::quinn;
-use quinn::{Endpoint, ServerConfig};
-use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use http::{HeaderName, HeaderValue};
+
+use quic_demo::config::Config;
+use quic_demo::{kv, logging, privileges};
+use quic_demo::simulate::{self, NetworkConditions};
+use quic_demo::static_files::DirectoryListing;
+use quic_demo::server::{self, DEFAULT_SUBJECT_ALT_NAMES, MIN_UNI_STREAMS, OverloadPolicy, Server};
+
+// Exit status when the listen port is taken, so scripts can tell it apart from other failures
+// (matches EADDRINUSE on Linux)
+const EXIT_ADDR_IN_USE: i32 = 98;
+
+#[derive(Parser)]
+#[command(about = "HTTP/3 demo server")]
+struct Args {
+    /// TOML file with default settings; its keys are these flags without the dashes.
+    /// Flags given on the command line take precedence over the file.
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// UDP address to listen on
+    #[arg(long, default_value = "127.0.0.1:4433")]
+    listen: SocketAddr,
+
+    /// Extra DNS name or IP address for the self-signed certificate, on top of localhost and
+    /// 127.0.0.1 (repeatable)
+    #[arg(long = "san", value_name = "NAME")]
+    subject_alt_names: Vec<String>,
+
+    /// PEM file with the certificate chain to serve, leaf first, instead of a self-signed
+    /// certificate; needs --key
+    #[arg(long, value_name = "FILE")]
+    cert: Option<PathBuf>,
+
+    /// PEM file with the private key of --cert
+    #[arg(long, value_name = "FILE")]
+    key: Option<PathBuf>,
+
+    /// Unprivileged user to switch to once the port is bound (Unix only)
+    #[arg(long)]
+    user: Option<String>,
+
+    /// Group to switch to once the port is bound; defaults to the user's primary group (Unix only)
+    #[arg(long)]
+    group: Option<String>,
+
+    /// Maximum number of requests a single connection may have in progress at once.
+    /// Further streams wait for a slot; this is separate from the QUIC stream limit.
+    #[arg(long, default_value_t = 32, value_parser = clap::value_parser!(u32).range(1..))]
+    max_inflight_per_connection: u32,
+
+    /// Maximum number of requests the whole server may have in progress at once, across all
+    /// connections [default: no limit beyond the per-connection one]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_inflight_requests: Option<u32>,
+
+    /// How long a request may wait for a server-wide in-flight slot with --overload=queue before
+    /// it gets 503
+    #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    queue_timeout: Duration,
+
+    /// What to do with new requests while a connection or the server is at its in-flight limit
+    #[arg(long, value_enum, default_value_t = OverloadPolicy::Queue)]
+    overload: OverloadPolicy,
+
+    /// Retry-After sent with 503 responses when --overload=reject
+    #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+    retry_after: Duration,
+
+    /// Send GOAWAY and close a connection once it has carried this many requests,
+    /// so clients rotate to fresh connections (e.g. to rebalance behind a load balancer)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_requests_per_connection: Option<u64>,
+
+    /// Send GOAWAY to connections this old, busy or not, and close them once their requests
+    /// are done (e.g. to rotate long-lived clients across servers) [default: no limit]
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    max_connection_age: Option<Duration>,
+
+    /// Close connections whose requests went wrong this many times more than they went right
+    /// (malformed or late headers, responses stopped by the client), to cut off abusive peers
+    #[arg(long, value_name = "ERRORS", value_parser = clap::value_parser!(u32).range(1..))]
+    error_budget: Option<u32>,
+
+    /// Maximum number of concurrent bidirectional (request) streams a peer may open
+    #[arg(long, default_value_t = 100)]
+    max_concurrent_bidi_streams: u32,
+
+    /// Maximum number of concurrent unidirectional streams a peer may open. HTTP/3 needs at
+    /// least 3 (control, QPACK encoder and QPACK decoder streams), so lower values are rejected.
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(MIN_UNI_STREAMS as i64..))]
+    max_concurrent_uni_streams: u32,
+
+    /// Bytes of incoming QUIC datagrams buffered per connection; 0 disables datagrams
+    /// [default: quinn's, about 1.25 MB]
+    #[arg(long, value_name = "BYTES")]
+    datagram_receive_buffer: Option<usize>,
+
+    /// Bytes of outgoing QUIC datagrams buffered per connection [default: quinn's, 1 MB]
+    #[arg(long, value_name = "BYTES")]
+    datagram_send_buffer: Option<usize>,
+
+    /// Largest QUIC datagram sent or accepted, below what the peer and the path allow; larger
+    /// ones fail to send and are dropped on receipt [default: no limit of its own]
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u32).range(1..))]
+    max_datagram_frame_size: Option<u32>,
+
+    /// Enable the QUIC spin bit so on-path tools can measure RTT passively. This exposes the RTT
+    /// (and so hints at the client's location) to anyone observing the path, hence off by default.
+    #[arg(long)]
+    enable_spin_bit: bool,
+
+    /// RTT assumed before the first measurement, e.g. "600ms" for satellite links, so early
+    /// packets aren't retransmitted needlessly (1ms to 10s) [default: quinn's, 333ms]
+    #[arg(long, value_name = "DURATION", value_parser = parse_initial_rtt)]
+    initial_rtt: Option<Duration>,
+
+    /// Congestion window of new connections in bytes, e.g. 1000000 so short transfers over
+    /// high bandwidth-delay links skip most of slow start (2400 to 16777216)
+    /// [default: quinn's, 12000]
+    #[arg(long, value_name = "BYTES", value_parser = parse_initial_cwnd)]
+    initial_cwnd: Option<u64>,
+
+    /// Accept 0-RTT early data from resuming clients, saving them a round trip on their first
+    /// requests. Those are still only handled once the handshake completes.
+    #[arg(long)]
+    early_data: bool,
+
+    /// Maximum number of connections in the QUIC/TLS handshake at once; further ones wait until
+    /// a handshake finishes
+    #[arg(long, default_value_t = server::DEFAULT_MAX_HANDSHAKES as u32, value_parser = clap::value_parser!(u32).range(1..))]
+    max_handshakes: u32,
+
+    /// Abort connections that don't complete the QUIC/TLS handshake within this time
+    #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    handshake_timeout: Duration,
+
+    /// Reset request streams whose headers haven't fully arrived within this time
+    /// (Slowloris protection)
+    #[arg(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    header_timeout: Duration,
+
+    /// Reset request streams whose body goes this long without any data arriving
+    /// [default: no limit]
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    body_read_timeout: Option<Duration>,
+
+    /// Reset requests that haven't been answered within this time after their headers arrived
+    /// [default: no limit]
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    request_timeout: Option<Duration>,
+
+    /// Timeout for one path instead of --request-timeout, as PATH=DURATION, e.g.
+    /// "/health=500ms" (repeatable)
+    #[arg(long, value_name = "PATH=DURATION", value_parser = server::parse_route_timeout)]
+    route_timeout: Vec<(String, Duration)>,
+
+    /// Answer requests for PATH with 308 Permanent Redirect to LOCATION, as PATH=LOCATION, e.g.
+    /// "/docs=https://example.com/docs" (repeatable)
+    #[arg(long, value_name = "PATH=LOCATION", value_parser = server::parse_redirect)]
+    redirect: Vec<(String, HeaderValue)>,
+
+    /// Add this header to every response that doesn't set it itself, as "NAME: VALUE", e.g.
+    /// "X-Content-Type-Options: nosniff" (repeatable)
+    #[arg(long, value_name = "NAME: VALUE", value_parser = server::parse_response_header)]
+    response_header: Vec<(HeaderName, HeaderValue)>,
+
+    /// How long to wait for open connections to finish after Ctrl-C before aborting them
+    #[arg(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    shutdown_timeout: Duration,
+
+    /// How often RTT, congestion window and packet loss of open connections are sampled for
+    /// /metrics; 0s turns sampling off
+    #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    stats_interval: Duration,
+
+    /// Upper bounds of the request and response body size histograms on /metrics, in bytes,
+    /// comma-separated and ascending [default: powers of four from 64 to 1073741824]
+    #[arg(long, value_name = "BYTES,...", value_delimiter = ',')]
+    body_size_buckets: Option<Vec<u64>>,
+
+    /// Windows the request rate gauge on /metrics averages over, comma-separated, in whole
+    /// seconds up to 1h [default: 1m,5m]
+    #[arg(long, value_name = "DURATION,...", value_delimiter = ',', value_parser = humantime::parse_duration)]
+    request_rate_windows: Option<Vec<Duration>>,
+
+    /// Don't print a line for every request as it arrives
+    #[arg(long)]
+    no_access_log: bool,
+
+    /// Log requests that take longer than this, at warn level with their timing
+    /// [default: none]
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    slow_request_threshold: Option<Duration>,
+
+    /// Directory whose files are served under /static/; precompressed FILE.br and FILE.gz
+    /// siblings are sent to clients that accept them
+    #[arg(long, value_name = "DIR")]
+    static_dir: Option<PathBuf>,
+
+    /// How to answer for a directory under /static/ that has no index.html
+    #[arg(long, value_enum, default_value_t = DirectoryListing::NotFound)]
+    directory_listing: DirectoryListing,
+
+    /// Serve a favicon and answer the paths browsers request on their own without logging them
+    #[arg(long)]
+    browser_probes: bool,
+
+    /// Serve /generate?bytes=N, N bytes of verifiable data, and /debug/stream?bytes=N, N zero
+    /// bytes, for tests and benchmarks. Any client can then ask for as much as it likes, so keep
+    /// it off in production.
+    #[arg(long)]
+    generate_route: bool,
+
+    /// Serve /debug/slow?secs=N, which answers after N seconds (at most 60),
+    /// /debug/ndjson?records=N, which sends N JSON lines 100ms apart (at most 1000), and
+    /// /debug/reset?code=, which resets the response stream, for trying out timeouts,
+    /// cancellation, streaming and resets. Any client can then hold requests open, so keep it
+    /// off in production.
+    #[arg(long)]
+    debug_routes: bool,
+
+    /// PEM file of CA certificates; clients must present a certificate issued by one of them
+    /// (mutual TLS)
+    #[arg(long, value_name = "FILE")]
+    client_ca: Option<PathBuf>,
+
+    /// Largest request body accepted, in bytes; uploads declaring or sending more get 413
+    /// [default: no limit]
+    #[arg(long, value_name = "BYTES")]
+    max_body_size: Option<u64>,
+
+    /// Most keys the /kv/ store holds; PUTs of new keys beyond it get 507
+    #[arg(long, default_value_t = kv::MAX_ENTRIES as u32, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    kv_max_entries: u32,
+
+    /// Most bytes of values the /kv/ store holds over all keys; PUTs beyond it get 507
+    #[arg(long, default_value_t = kv::MAX_TOTAL_SIZE as u64, value_name = "BYTES", value_parser = clap::value_parser!(u64).range(1..))]
+    kv_max_size: u64,
+
+    /// Act as a MASQUE proxy, tunnelling UDP for CONNECT-UDP requests (RFC 9298). Anyone who
+    /// can connect can then send UDP from this host, so only enable it on trusted networks.
+    #[arg(long)]
+    connect_udp: bool,
+
+    /// Let --connect-udp tunnels reach loopback, private and link-local addresses, which are
+    /// refused with 403 otherwise
+    #[arg(long)]
+    connect_udp_allow_private: bool,
+
+    /// Length in bytes of the connection IDs the server issues (0 to 20), for load balancers
+    /// that route on them. 0 ties connections to the client's address, so migration breaks.
+    /// [default: quinn's, 8]
+    #[arg(long, value_name = "BYTES", value_parser = parse_cid_length)]
+    cid_length: Option<usize>,
+
+    /// Drop packets that come from anywhere but the address a connection started on instead of
+    /// following the client to its new address (connection migration)
+    #[arg(long)]
+    no_migration: bool,
+
+    /// Send every packet with its own system call instead of batching them with generic
+    /// segmentation offload. A workaround for kernels, NICs or virtual network drivers that
+    /// mishandle GSO (Linux and Windows only; other platforms never use it).
+    #[arg(long)]
+    no_gso: bool,
+
+    /// Serve from this many endpoints sharing the port through SO_REUSEPORT, spreading packet
+    /// processing over cores. Clients are assigned by address, so migration doesn't survive it
+    /// (Linux only; elsewhere a single endpoint is used)
+    #[arg(long, default_value_t = 1, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    shards: u32,
+
+    /// Share of outgoing packets to drop, from 0 to 1, for experimenting with lossy networks
+    #[arg(long, default_value_t = 0.0, value_name = "RATIO", value_parser = parse_loss)]
+    simulate_loss: f64,
+
+    /// Delay added to every outgoing packet, e.g. "50ms" (the RTT grows by this much)
+    #[arg(long, default_value = "0s", value_parser = humantime::parse_duration)]
+    simulate_delay: Duration,
+
+    /// Random extra delay of up to this much per outgoing packet, which reorders packets
+    #[arg(long, default_value = "0s", value_parser = humantime::parse_duration)]
+    simulate_jitter: Duration,
+}
+
+impl Args {
+    // Take each setting from the config file unless it was given on the command line
+    // (precedence: command line > config file > built-in defaults)
+    fn merge(&mut self, config: Config, matches: &ArgMatches) {
+        fn pick<T>(matches: &ArgMatches, id: &str, arg: &mut T, file: Option<T>) {
+            if matches.value_source(id) != Some(ValueSource::CommandLine)
+                && let Some(value) = file
+            {
+                *arg = value;
+            }
+        }
+
+        pick(matches, "listen", &mut self.listen, config.listen);
+        pick(matches, "subject_alt_names", &mut self.subject_alt_names, config.san);
+        pick(matches, "cert", &mut self.cert, config.cert.map(Some));
+        pick(matches, "key", &mut self.key, config.key.map(Some));
+        pick(matches, "user", &mut self.user, config.user.map(Some));
+        pick(matches, "group", &mut self.group, config.group.map(Some));
+        pick(matches, "max_inflight_per_connection", &mut self.max_inflight_per_connection, config.max_inflight_per_connection);
+        pick(matches, "max_inflight_requests", &mut self.max_inflight_requests, config.max_inflight_requests.map(Some));
+        pick(matches, "queue_timeout", &mut self.queue_timeout, config.queue_timeout);
+        pick(matches, "overload", &mut self.overload, config.overload);
+        pick(matches, "retry_after", &mut self.retry_after, config.retry_after);
+        pick(matches, "max_requests_per_connection", &mut self.max_requests_per_connection, config.max_requests_per_connection.map(Some));
+        pick(matches, "max_connection_age", &mut self.max_connection_age, config.max_connection_age.map(Some));
+        pick(matches, "error_budget", &mut self.error_budget, config.error_budget.map(Some));
+        pick(matches, "max_concurrent_bidi_streams", &mut self.max_concurrent_bidi_streams, config.max_concurrent_bidi_streams);
+        pick(matches, "max_concurrent_uni_streams", &mut self.max_concurrent_uni_streams, config.max_concurrent_uni_streams);
+        pick(matches, "datagram_receive_buffer", &mut self.datagram_receive_buffer, config.datagram_receive_buffer.map(Some));
+        pick(matches, "datagram_send_buffer", &mut self.datagram_send_buffer, config.datagram_send_buffer.map(Some));
+        pick(matches, "max_datagram_frame_size", &mut self.max_datagram_frame_size, config.max_datagram_frame_size.map(Some));
+        pick(matches, "enable_spin_bit", &mut self.enable_spin_bit, config.enable_spin_bit);
+        pick(matches, "initial_rtt", &mut self.initial_rtt, config.initial_rtt.map(Some));
+        pick(matches, "initial_cwnd", &mut self.initial_cwnd, config.initial_cwnd.map(Some));
+        pick(matches, "early_data", &mut self.early_data, config.early_data);
+        pick(matches, "max_handshakes", &mut self.max_handshakes, config.max_handshakes);
+        pick(matches, "handshake_timeout", &mut self.handshake_timeout, config.handshake_timeout);
+        pick(matches, "header_timeout", &mut self.header_timeout, config.header_timeout);
+        pick(matches, "body_read_timeout", &mut self.body_read_timeout, config.body_read_timeout.map(Some));
+        pick(matches, "request_timeout", &mut self.request_timeout, config.request_timeout.map(Some));
+        pick(matches, "route_timeout", &mut self.route_timeout, config.route_timeout);
+        pick(matches, "redirect", &mut self.redirect, config.redirect);
+        pick(matches, "response_header", &mut self.response_header, config.response_header);
+        pick(matches, "shutdown_timeout", &mut self.shutdown_timeout, config.shutdown_timeout);
+        pick(matches, "stats_interval", &mut self.stats_interval, config.stats_interval);
+        pick(matches, "body_size_buckets", &mut self.body_size_buckets, config.body_size_buckets.map(Some));
+        pick(matches, "request_rate_windows", &mut self.request_rate_windows, config.request_rate_windows.map(Some));
+        pick(matches, "no_access_log", &mut self.no_access_log, config.no_access_log);
+        pick(matches, "slow_request_threshold", &mut self.slow_request_threshold, config.slow_request_threshold.map(Some));
+        pick(matches, "static_dir", &mut self.static_dir, config.static_dir.map(Some));
+        pick(matches, "directory_listing", &mut self.directory_listing, config.directory_listing);
+        pick(matches, "browser_probes", &mut self.browser_probes, config.browser_probes);
+        pick(matches, "generate_route", &mut self.generate_route, config.generate_route);
+        pick(matches, "debug_routes", &mut self.debug_routes, config.debug_routes);
+        pick(matches, "client_ca", &mut self.client_ca, config.client_ca.map(Some));
+        pick(matches, "max_body_size", &mut self.max_body_size, config.max_body_size.map(Some));
+        pick(matches, "kv_max_entries", &mut self.kv_max_entries, config.kv_max_entries);
+        pick(matches, "kv_max_size", &mut self.kv_max_size, config.kv_max_size);
+        pick(matches, "connect_udp", &mut self.connect_udp, config.connect_udp);
+        pick(matches, "connect_udp_allow_private", &mut self.connect_udp_allow_private, config.connect_udp_allow_private);
+        pick(matches, "cid_length", &mut self.cid_length, config.cid_length.map(Some));
+        pick(matches, "no_migration", &mut self.no_migration, config.no_migration);
+        pick(matches, "no_gso", &mut self.no_gso, config.no_gso);
+        pick(matches, "shards", &mut self.shards, config.shards);
+        pick(matches, "simulate_loss", &mut self.simulate_loss, config.simulate_loss);
+        pick(matches, "simulate_delay", &mut self.simulate_delay, config.simulate_delay);
+        pick(matches, "simulate_jitter", &mut self.simulate_jitter, config.simulate_jitter);
+    }
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    logging::init();
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches)?;
+    if let Some(path) = &args.config {
+        let config = Config::load(path)?;
+        args.merge(config, &matches);
+    }
+    let certificate = match (args.cert, args.key) {
+        (Some(cert), Some(key)) => Some((cert, key)),
+        (None, None) => None,
+        _ => anyhow::bail!("--cert and --key have to be given together"),
+    };
     rustls::crypto::aws_lc_rs::default_provider().install_default().unwrap();
 
-    let cert = generate_self_signed_cert()?;
-    let mut tls_config = rustls::ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(cert.cert_chain, cert.private_key)?;
-    tls_config.alpn_protocols = vec![b"h3".to_vec()];
-
-    let server_config = ServerConfig::with_crypto(Arc::new(
-        quinn::crypto::rustls::QuicServerConfig::try_from(tls_config)?
-    ));
-
-    let endpoint = Endpoint::server(server_config, "127.0.0.1:4433".parse()?)?;
-
-    println!("HTTP/3 server listening on 127.0.0.1:4433");
-
-    while let Some(conn) = endpoint.accept().await {
-        let conn = conn.await?;
-        tokio::spawn(async move {
-            let mut h3_conn: h3::server::Connection<h3_quinn::Connection, Bytes> = 
-                h3::server::Connection::new(h3_quinn::Connection::new(conn))
-                    .await
-                    .unwrap();
-
-            loop {
-                match h3_conn.accept().await {
-                    Ok(Some(resolver)) => {
-                        tokio::spawn(async move {
-                            // Resolve the request to get the actual request and stream
-                            let (req, mut stream) = resolver.resolve_request().await.unwrap();
-                            
-                            println!("Got request for path: {}, protocol: {:?}", req.uri().path(), req.version());
-
-                            let response_body: &str = match req.uri().path() {
-                                "/" => "Hello from http3 server",
-                                "/test" => "Hello from http3 test endpoint", 
-                                "/health" => "hello from http3 health check",
-                                _ => "404 Not Found", 
-                            };
-
-                            let response = http::Response::builder()
-                                .status(http::StatusCode::OK)
-                                .header("Content-Type", "text/plain")
-                                .body(())
-                                .unwrap();
-
-                            stream.send_response(response).await.unwrap();
-                            stream.send_data(Bytes::from(response_body)).await.unwrap();
-                            stream.finish().await.unwrap();
-                        });    
-                    }
-                    Ok(None) => break,
-                    Err(_e) => break, 
-                }
-            }
-        });
-    }
+    let server = Server::builder()
+        .listen(args.listen)
+        .subject_alt_names(DEFAULT_SUBJECT_ALT_NAMES.iter().copied().map(String::from).chain(args.subject_alt_names))
+        .certificate(certificate)
+        .max_inflight_per_connection(args.max_inflight_per_connection as usize)
+        .max_inflight_requests(args.max_inflight_requests.map(|max| max as usize))
+        .queue_timeout(args.queue_timeout)
+        .overload_policy(args.overload)
+        .retry_after(args.retry_after)
+        .max_requests_per_connection(args.max_requests_per_connection)
+        .max_connection_age(args.max_connection_age)
+        .error_budget(args.error_budget)
+        .max_concurrent_bidi_streams(args.max_concurrent_bidi_streams)
+        .max_concurrent_uni_streams(args.max_concurrent_uni_streams)
+        .datagram_receive_buffer(args.datagram_receive_buffer)
+        .datagram_send_buffer(args.datagram_send_buffer)
+        .max_datagram_frame_size(args.max_datagram_frame_size.map(|bytes| bytes as usize))
+        .spin_bit(args.enable_spin_bit)
+        .initial_rtt(args.initial_rtt)
+        .initial_cwnd(args.initial_cwnd)
+        .early_data(args.early_data)
+        .max_handshakes(args.max_handshakes as usize)
+        .handshake_timeout(args.handshake_timeout)
+        .header_timeout(args.header_timeout)
+        .body_read_timeout(args.body_read_timeout)
+        .request_timeout(args.request_timeout)
+        .shutdown_timeout(args.shutdown_timeout)
+        .stats_interval(args.stats_interval)
+        .body_size_buckets(args.body_size_buckets)
+        .request_rate_windows(args.request_rate_windows)
+        .access_log(!args.no_access_log)
+        .slow_request_threshold(args.slow_request_threshold)
+        .static_dir(args.static_dir)
+        .directory_listing(args.directory_listing)
+        .browser_probes(args.browser_probes)
+        .generate_route(args.generate_route)
+        .debug_routes(args.debug_routes)
+        .client_ca(args.client_ca)
+        .max_body_size(args.max_body_size)
+        .kv_limits(args.kv_max_entries as usize, args.kv_max_size as usize)
+        .connect_udp(args.connect_udp)
+        .connect_udp_allow_private(args.connect_udp_allow_private)
+        .cid_length(args.cid_length)
+        .migration(!args.no_migration)
+        .gso(!args.no_gso)
+        .shards(args.shards as usize)
+        .simulate(NetworkConditions { loss: args.simulate_loss, delay: args.simulate_delay, jitter: args.simulate_jitter });
+
+    let server = args.route_timeout.into_iter().fold(server, |server, (path, timeout)| server.route_timeout(path, timeout));
+    let server = args.redirect.into_iter().fold(server, |server, (path, location)| server.redirect(path, location));
+    let server = args.response_header.into_iter().fold(server, |server, (name, value)| server.response_header(name, value));
+
+    let server = match server.bind() {
+        Ok(server) => server,
+        Err(e) if is_addr_in_use(&e) => {
+            eprintln!("Error: port {} is already in use (is another server running?)", args.listen.port());
+            std::process::exit(EXIT_ADDR_IN_USE);
+        }
+        Err(e) => return Err(e),
+    };
 
-    Ok(())
+    // The socket is bound, so root is no longer needed. bind has already read --cert/--key and
+    // --client-ca as root; --static-dir is only read per request, as the new user.
+    privileges::drop_privileges(args.user.as_deref(), args.group.as_deref())?;
+
+    println!("HTTP/3 server listening on {}", server.local_addr()?);
+    println!("Certificate SHA-256 fingerprint: {}", server.certificate_fingerprint());
+
+    server.serve().await
 }
 
-struct CertificateChain {
-    cert_chain: Vec<CertificateDer<'static>>,
-    private_key: PrivateKeyDer<'static>
+fn parse_initial_rtt(text: &str) -> anyhow::Result<Duration> {
+    server::check_initial_rtt(humantime::parse_duration(text)?)
 }
 
-// generate self signed certificate
-fn generate_self_signed_cert() -> anyhow::Result<CertificateChain> {
-    let certified_key = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
-    let private_key_der = certified_key.signing_key.serialize_der();
-    let private_key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(private_key_der));
-    let cert_chain = vec![certified_key.cert.der().clone()];
-    Ok(CertificateChain { cert_chain, private_key })
+fn parse_initial_cwnd(text: &str) -> anyhow::Result<u64> {
+    server::check_initial_cwnd(text.parse()?)
+}
+
+fn parse_cid_length(text: &str) -> anyhow::Result<usize> {
+    server::check_cid_length(text.parse()?)
+}
+
+fn parse_loss(text: &str) -> anyhow::Result<f64> {
+    simulate::check_loss(text.parse()?)
+}
+
+fn is_addr_in_use(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .any(|e| e.kind() == std::io::ErrorKind::AddrInUse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merged(argv: &[&str], config: &str) -> Args {
+        let matches = Args::command().try_get_matches_from(std::iter::once("server").chain(argv.iter().copied())).unwrap();
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        args.merge(toml::from_str(config).unwrap(), &matches);
+        args
+    }
+
+    #[test]
+    fn command_line_beats_config_file_beats_defaults() {
+        let config = r#"
+            listen = "127.0.0.1:5000"
+            max-handshakes = 10
+            cert = "file.pem"
+            redirect = ["/a=/b"]
+        "#;
+        let args = merged(&["--listen", "127.0.0.1:6000", "--redirect", "/c=/d"], config);
+        assert_eq!(args.listen, "127.0.0.1:6000".parse::<SocketAddr>().unwrap());
+        assert_eq!(args.redirect, [("/c".to_string(), HeaderValue::from_static("/d"))]);
+        assert_eq!(args.max_handshakes, 10);
+        assert_eq!(args.cert, Some(PathBuf::from("file.pem")));
+        assert_eq!(args.header_timeout, Duration::from_secs(10));
+        assert_eq!(args.key, None);
+
+        // Also when the command line repeats the default
+        let args = merged(&["--max-handshakes", &server::DEFAULT_MAX_HANDSHAKES.to_string()], config);
+        assert_eq!(args.max_handshakes as usize, server::DEFAULT_MAX_HANDSHAKES);
+        assert_eq!(args.listen, "127.0.0.1:5000".parse::<SocketAddr>().unwrap());
+    }
 }
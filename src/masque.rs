@@ -0,0 +1,308 @@
+// MASQUE CONNECT-UDP (RFC 9298): an extended CONNECT request with `:protocol = connect-udp` for
+// /.well-known/masque/udp/{host}/{port}/ makes the server open a UDP socket to that target, and
+// HTTP datagrams (RFC 9297) carry the packets both ways until the request stream closes. HTTP
+// datagrams are QUIC datagrams that start with the request's quarter stream ID, so every tunnel
+// of a connection shares one reader that hands each datagram to its tunnel. Targets on loopback,
+// private and link-local addresses are refused unless the server allows them, so the proxy can't
+// be used to reach the server's own network.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use bytes::{Buf, Bytes};
+use h3::ext::Protocol;
+use h3::quic;
+use h3::server::RequestStream;
+use h3_quinn::quinn;
+use http::{HeaderMap, Method, Request, StatusCode};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::datagram::{self, DatagramError};
+use crate::priority::decode_varint;
+use crate::response::Response;
+use crate::router::percent_decode;
+use crate::uni::encode_varint;
+
+/// Path prefix of the URI template in RFC 9298, section 2,
+/// `/.well-known/masque/udp/{target_host}/{target_port}/`.
+pub const PATH_PREFIX: &str = "/.well-known/masque/udp/";
+
+// Context ID of datagrams carrying a whole UDP payload (RFC 9298, section 4); others are dropped
+const UDP_PAYLOAD_CONTEXT: u64 = 0;
+
+// Datagrams from the client waiting for the UDP socket, per tunnel. More are dropped, which is
+// fine for UDP.
+const QUEUED_DATAGRAMS: usize = 256;
+
+// Largest UDP payload read from the target
+const MAX_UDP_PAYLOAD: usize = 65527;
+
+/// Whether `req` asks for a CONNECT-UDP tunnel.
+pub fn is_connect_udp<T>(req: &Request<T>) -> bool {
+    req.method() == Method::CONNECT && req.extensions().get::<Protocol>() == Some(&Protocol::CONNECT_UDP)
+}
+
+/// The HTTP datagrams arriving on one connection, sorted by the tunnel they belong to.
+#[derive(Clone)]
+pub(crate) struct Datagrams {
+    conn: quinn::Connection,
+    // From `ServerBuilder::max_datagram_frame_size`
+    frame_limit: Option<usize>,
+    tunnels: Arc<Mutex<Tunnels>>,
+}
+
+#[derive(Default)]
+struct Tunnels {
+    by_quarter_id: HashMap<u64, mpsc::Sender<Bytes>>,
+    // Stops the reader once no tunnel is left, so it doesn't keep the connection alive
+    reader: Option<CancellationToken>,
+}
+
+impl Datagrams {
+    pub(crate) fn new(conn: quinn::Connection, frame_limit: Option<usize>) -> Datagrams {
+        Datagrams { conn, frame_limit, tunnels: Arc::default() }
+    }
+
+    fn register(&self, quarter_id: u64) -> Registration {
+        let (tx, rx) = mpsc::channel(QUEUED_DATAGRAMS);
+        let mut tunnels = self.tunnels.lock().unwrap();
+        tunnels.by_quarter_id.insert(quarter_id, tx);
+        if tunnels.reader.is_none() {
+            let token = CancellationToken::new();
+            tokio::spawn(read_datagrams(self.conn.clone(), self.frame_limit, self.tunnels.clone(), token.clone()));
+            tunnels.reader = Some(token);
+        }
+        Registration { datagrams: self.clone(), quarter_id, rx }
+    }
+}
+
+async fn read_datagrams(conn: quinn::Connection, frame_limit: Option<usize>, tunnels: Arc<Mutex<Tunnels>>, stop: CancellationToken) {
+    loop {
+        let mut datagram = tokio::select! {
+            datagram = conn.read_datagram() => match datagram {
+                Ok(datagram) => datagram,
+                Err(_) => return,
+            },
+            _ = stop.cancelled() => return,
+        };
+        if frame_limit.is_some_and(|limit| datagram.len() > limit) {
+            continue;
+        }
+        let Some((quarter_id, len)) = decode_varint(&datagram) else { continue };
+        datagram.advance(len);
+        let tunnel = tunnels.lock().unwrap().by_quarter_id.get(&quarter_id).cloned();
+        if let Some(tunnel) = tunnel {
+            let _ = tunnel.try_send(datagram);
+        }
+    }
+}
+
+// A tunnel's share of the datagrams, until it is dropped
+struct Registration {
+    datagrams: Datagrams,
+    quarter_id: u64,
+    rx: mpsc::Receiver<Bytes>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut tunnels = self.datagrams.tunnels.lock().unwrap();
+        tunnels.by_quarter_id.remove(&self.quarter_id);
+        if tunnels.by_quarter_id.is_empty()
+            && let Some(reader) = tunnels.reader.take()
+        {
+            reader.cancel();
+        }
+    }
+}
+
+/// Answer a CONNECT-UDP request and relay datagrams between the client and the target until
+/// either side closes the request stream or the connection goes away. A malformed target gets
+/// 400, one on an internal address 403 unless `allow_private`, and one that can't be reached 502,
+/// like any other response, and every answer carries the server's default headers.
+pub(crate) async fn tunnel<S>(
+    req: &Request<()>,
+    stream: &mut RequestStream<S, Bytes>,
+    datagrams: &Datagrams,
+    default_headers: &HeaderMap,
+    allow_private: bool,
+) where
+    S: quic::RecvStream + quic::SendStream<Bytes>,
+{
+    let remote = datagrams.conn.remote_address();
+    if datagram::max_size(&datagrams.conn, datagrams.frame_limit).is_none() {
+        let response = Response::text(StatusCode::BAD_REQUEST, "400 Bad Request: QUIC datagrams are not enabled");
+        let _ = response.default_headers(default_headers).send(stream).await;
+        return;
+    }
+    let Some((host, port)) = target(req.uri().path()) else {
+        let response = Response::text(StatusCode::BAD_REQUEST, "400 Bad Request: expected /.well-known/masque/udp/{host}/{port}/");
+        let _ = response.default_headers(default_headers).send(stream).await;
+        return;
+    };
+    let socket = match connect(&host, port, allow_private).await {
+        Ok(socket) => socket,
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            println!("Refusing CONNECT-UDP from {} to {}:{}: {}", remote, host, port, e);
+            let response = Response::text(StatusCode::FORBIDDEN, "403 Forbidden: the target is on an internal address");
+            let _ = response.default_headers(default_headers).send(stream).await;
+            return;
+        }
+        Err(e) => {
+            println!("CONNECT-UDP from {} to {}:{} failed: {}", remote, host, port, e);
+            let response = Response::text(StatusCode::BAD_GATEWAY, "502 Bad Gateway");
+            let _ = response.default_headers(default_headers).send(stream).await;
+            return;
+        }
+    };
+
+    let quarter_id = stream.id().into_inner() / 4;
+    let mut registration = datagrams.register(quarter_id);
+    let mut head = http::Response::new(());
+    *head.status_mut() = StatusCode::OK;
+    head.headers_mut().insert("capsule-protocol", http::HeaderValue::from_static("?1"));
+    for name in default_headers.keys() {
+        if !head.headers().contains_key(name) {
+            head.headers_mut().extend(default_headers.get_all(name).iter().map(|value| (name.clone(), value.clone())));
+        }
+    }
+    if stream.send_response(head).await.is_err() {
+        return;
+    }
+    println!("CONNECT-UDP tunnel from {} to {}:{} open", remote, host, port);
+
+    // Prefix of every datagram to the client: the quarter stream ID and the UDP payload context
+    let mut prefix = encode_varint(quarter_id);
+    prefix.extend_from_slice(&encode_varint(UDP_PAYLOAD_CONTEXT));
+    let mut buf = vec![0; MAX_UDP_PAYLOAD];
+    let (mut to_target, mut to_client, mut too_large) = (0u64, 0u64, 0u64);
+    loop {
+        tokio::select! {
+            datagram = registration.rx.recv() => {
+                let Some(datagram) = datagram else { break };
+                if let Some((UDP_PAYLOAD_CONTEXT, len)) = decode_varint(&datagram)
+                    && socket.send(&datagram[len..]).await.is_ok()
+                {
+                    to_target += 1;
+                }
+            }
+            received = socket.recv(&mut buf) => {
+                // ICMP errors (e.g. port unreachable) surface here; the target may still answer later
+                let Ok(len) = received else { continue };
+                let mut datagram = Vec::with_capacity(prefix.len() + len);
+                datagram.extend_from_slice(&prefix);
+                datagram.extend_from_slice(&buf[..len]);
+                match datagram::send(&datagrams.conn, datagrams.frame_limit, Bytes::from(datagram)) {
+                    Ok(()) => to_client += 1,
+                    // Payloads that don't fit into a QUIC datagram are dropped, as RFC 9298 allows
+                    Err(DatagramError::TooLarge { .. }) => too_large += 1,
+                    Err(e) => {
+                        println!("CONNECT-UDP tunnel from {} to {}:{} can't send datagrams any more: {}", remote, host, port, e);
+                        break;
+                    }
+                }
+            }
+            // Capsules on the stream are ignored; its end closes the tunnel
+            data = stream.recv_data() => match data {
+                Ok(Some(_)) => {}
+                Ok(None) | Err(_) => break,
+            },
+        }
+    }
+    let _ = stream.finish().await;
+    println!(
+        "CONNECT-UDP tunnel from {} to {}:{} closed after {} datagram(s) to the target and {} back ({} too large to send back)",
+        remote, host, port, to_target, to_client, too_large,
+    );
+}
+
+// `{host}/{port}/` after the prefix, with the host percent-decoded (IPv6 literals come as
+// `2001%3Adb8%3A%3A1`)
+fn target(path: &str) -> Option<(String, u16)> {
+    let rest = path.strip_prefix(PATH_PREFIX)?;
+    let (host, rest) = rest.split_once('/')?;
+    let port = rest.strip_suffix('/').unwrap_or(rest);
+    let host = percent_decode(host)?;
+    let port = port.parse().ok().filter(|port| *port != 0)?;
+    (!host.is_empty()).then_some((host, port))
+}
+
+// The check is on the resolved address, so a public name pointing at an internal one is refused too
+async fn connect(host: &str, port: u16, allow_private: bool) -> io::Result<UdpSocket> {
+    let target = tokio::net::lookup_host((host, port))
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address"))?;
+    if !allow_private && is_internal(target.ip()) {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} is an internal address", target.ip())));
+    }
+    let local: IpAddr = match target {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind((local, 0)).await?;
+    socket.connect(target).await?;
+    Ok(socket)
+}
+
+// Loopback, private, shared (CGNAT), link-local, unspecified, broadcast and multicast addresses,
+// and IPv4 ones in their IPv6-mapped form, which `to_canonical` turns back into IPv4 first
+fn is_internal(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || (a == 100 && b & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            // Unique local fc00::/7 and link-local fe80::/10
+            ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_addresses_are_recognised() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "100.127.255.255",
+            "0.0.0.0",
+            "255.255.255.255",
+            "224.0.0.1",
+            "::1",
+            "::",
+            "fe80::1",
+            "fd00::1",
+            "ff02::1",
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+            "::ffff:169.254.169.254",
+            "::ffff:100.64.0.1",
+            "::ffff:0.0.0.0",
+        ] {
+            assert!(is_internal(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["1.1.1.1", "100.63.255.255", "100.128.0.1", "172.32.0.1", "2606:4700::1111", "::ffff:1.1.1.1"] {
+            assert!(!is_internal(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+}
@@ -0,0 +1,513 @@
+// Server metrics in the Prometheus text format, served on /metrics. Connection statistics are
+// sampled on an interval and aggregated across connections (no per-connection labels), so the
+// number of series stays fixed however many clients connect. Everything a request or a sample
+// touches is an atomic counter, so busy connections don't contend on a lock.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use h3_quinn::quinn;
+
+use crate::handshake::HandshakeFailure;
+
+/// Default buckets of the body size histograms, in bytes: powers of four from 64 B to 1 GiB.
+pub const DEFAULT_BODY_SIZE_BUCKETS: &[u64] = &[
+    64,
+    256,
+    1 << 10,
+    4 << 10,
+    16 << 10,
+    64 << 10,
+    256 << 10,
+    1 << 20,
+    4 << 20,
+    16 << 20,
+    64 << 20,
+    256 << 20,
+    1 << 30,
+];
+
+/// Default windows of the request rate gauge: one and five minutes.
+pub const DEFAULT_REQUEST_RATE_WINDOWS: &[Duration] = &[Duration::from_secs(60), Duration::from_secs(300)];
+
+/// Longest window the request rate gauge can average over, which bounds its ring of per-second
+/// counts.
+pub const MAX_REQUEST_RATE_WINDOW: Duration = Duration::from_secs(3600);
+
+/// Every metric the server exports.
+pub struct Metrics {
+    pub connections: Gauge,
+    pub accepted_connections: Counter,
+    pub inflight_requests: Gauge,
+    pub active_streams: Gauge,
+    pub peak_active_streams: Gauge,
+    pub connection_peak_streams: Histogram,
+    pub requests: Counter,
+    pub overloaded_requests: Counter,
+    pub request_rate: RateWindows,
+    pub rtt: Histogram,
+    pub congestion_window: Histogram,
+    pub sent_packets: Counter,
+    pub lost_packets: Counter,
+    pub sent_bytes: Counter,
+    pub received_bytes: Counter,
+    pub request_sent_bytes: Counter,
+    pub request_received_bytes: Counter,
+    pub handshake_failures: LabeledCounter,
+    pub request_header_timeouts: Counter,
+    pub request_body_timeouts: Counter,
+    pub connection_migrations: Counter,
+    pub error_budget_closes: Counter,
+    pub request_body_size: Histogram,
+    pub response_body_size: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::with_body_size_buckets(DEFAULT_BODY_SIZE_BUCKETS)
+    }
+}
+
+impl Metrics {
+    /// Metrics whose body size histograms have these upper bounds, in bytes, ascending.
+    pub fn with_body_size_buckets(bounds: &[u64]) -> Self {
+        let bounds: Vec<f64> = bounds.iter().map(|&bound| bound as f64).collect();
+        Metrics {
+            connections: Gauge::new("quic_connections", "Connections currently open"),
+            accepted_connections: Counter::new("quic_connections_accepted_total", "Connections whose handshake completed"),
+            inflight_requests: Gauge::new("quic_inflight_requests", "Requests currently being handled"),
+            active_streams: Gauge::new(
+                "quic_active_request_streams",
+                "Request streams accepted and not yet finished, including ones waiting for an in-flight slot",
+            ),
+            peak_active_streams: Gauge::new(
+                "quic_active_request_streams_peak",
+                "Most request streams active at once since the server started",
+            ),
+            connection_peak_streams: Histogram::new(
+                "quic_connection_peak_request_streams",
+                "Most request streams active at once on a connection, one observation per closed connection",
+                &[1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0, 512.0],
+            ),
+            requests: Counter::new("quic_requests_total", "HTTP/3 requests accepted"),
+            overloaded_requests: Counter::new(
+                "quic_overloaded_requests_total",
+                "Requests answered with 503 because the server or their connection was at its in-flight budget",
+            ),
+            request_rate: request_rate(DEFAULT_REQUEST_RATE_WINDOWS),
+            rtt: Histogram::new(
+                "quic_rtt_seconds",
+                "Smoothed RTT of open connections, one observation per connection and sample",
+                &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5],
+            ),
+            congestion_window: Histogram::new(
+                "quic_congestion_window_bytes",
+                "Congestion window of open connections, one observation per connection and sample",
+                &[16e3, 32e3, 64e3, 128e3, 256e3, 512e3, 1e6, 2e6, 4e6, 8e6],
+            ),
+            sent_packets: Counter::new(
+                "quic_sent_packets_total",
+                "UDP datagrams sent, counted at each sample and when a connection closes",
+            ),
+            lost_packets: Counter::new(
+                "quic_lost_packets_total",
+                "Packets declared lost, counted at each sample and when a connection closes",
+            ),
+            sent_bytes: Counter::new(
+                "quic_sent_bytes_total",
+                "UDP payload bytes sent, QUIC overhead and retransmissions included, counted like packets",
+            ),
+            received_bytes: Counter::new(
+                "quic_received_bytes_total",
+                "UDP payload bytes received, QUIC overhead included, counted like packets",
+            ),
+            request_sent_bytes: Counter::new(
+                "quic_request_stream_sent_bytes_total",
+                "HTTP/3 bytes (header and data frames) written to request streams",
+            ),
+            request_received_bytes: Counter::new(
+                "quic_request_stream_received_bytes_total",
+                "HTTP/3 bytes (header and data frames) read from request streams",
+            ),
+            handshake_failures: LabeledCounter::new(
+                "quic_handshake_failures_total",
+                "Handshakes that failed, by likely cause",
+                "reason",
+                HandshakeFailure::LABELS,
+            ),
+            request_header_timeouts: Counter::new(
+                "quic_request_header_timeouts_total",
+                "Request streams reset because their header section didn't arrive within the header timeout",
+            ),
+            request_body_timeouts: Counter::new(
+                "quic_request_body_timeouts_total",
+                "Request streams reset because their body stalled for longer than the body read timeout",
+            ),
+            connection_migrations: Counter::new(
+                "quic_connection_migrations_total",
+                "Times a client's connection moved to a new address",
+            ),
+            error_budget_closes: Counter::new(
+                "quic_error_budget_closes_total",
+                "Connections closed for using up their error budget",
+            ),
+            request_body_size: Histogram::new(
+                "quic_request_body_size_bytes",
+                "Request bodies read by the server, in bytes, including ones cut off for being too large",
+                &bounds,
+            ),
+            response_body_size: Histogram::new(
+                "quic_response_body_size_bytes",
+                "Response bodies sent in full, in bytes, after compression",
+                &bounds,
+            ),
+        }
+    }
+
+    /// Average `quic_request_rate` over these windows instead of
+    /// [`DEFAULT_REQUEST_RATE_WINDOWS`], each a whole number of seconds up to
+    /// [`MAX_REQUEST_RATE_WINDOW`].
+    pub fn request_rate_windows(mut self, windows: &[Duration]) -> Self {
+        self.request_rate = request_rate(windows);
+        self
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.connections.render(&mut out);
+        self.accepted_connections.render(&mut out);
+        self.inflight_requests.render(&mut out);
+        self.active_streams.render(&mut out);
+        self.peak_active_streams.render(&mut out);
+        self.connection_peak_streams.render(&mut out);
+        self.requests.render(&mut out);
+        self.overloaded_requests.render(&mut out);
+        self.request_rate.render(&mut out);
+        self.rtt.render(&mut out);
+        self.congestion_window.render(&mut out);
+        self.sent_packets.render(&mut out);
+        self.lost_packets.render(&mut out);
+        self.sent_bytes.render(&mut out);
+        self.received_bytes.render(&mut out);
+        self.request_sent_bytes.render(&mut out);
+        self.request_received_bytes.render(&mut out);
+        self.handshake_failures.render(&mut out);
+        self.request_header_timeouts.render(&mut out);
+        self.request_body_timeouts.render(&mut out);
+        self.connection_migrations.render(&mut out);
+        self.error_budget_closes.render(&mut out);
+        self.request_body_size.render(&mut out);
+        self.response_body_size.render(&mut out);
+        out
+    }
+
+    /// A snapshot of the main totals, read from the same counters `/metrics` renders, so the
+    /// byte counts lag by up to one stats interval. [`crate::server::Server::stats`] doesn't.
+    pub fn stats(&self) -> ServerStats {
+        ServerStats {
+            active_connections: self.connections.get().max(0) as u64,
+            accepted_connections: self.accepted_connections.get(),
+            inflight_requests: self.inflight_requests.get().max(0) as u64,
+            requests: self.requests.get(),
+            sent_bytes: self.sent_bytes.get(),
+            received_bytes: self.received_bytes.get(),
+            handshake_failures: self.handshake_failures.total(),
+            overloaded_requests: self.overloaded_requests.get(),
+            request_header_timeouts: self.request_header_timeouts.get(),
+        }
+    }
+}
+
+/// Server totals for applications that show them in their own UI, from
+/// [`crate::server::Server::stats`] or [`Metrics::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServerStats {
+    pub active_connections: u64,
+    /// Connections whose handshake completed since the server started
+    pub accepted_connections: u64,
+    pub inflight_requests: u64,
+    pub requests: u64,
+    /// UDP payload bytes, QUIC overhead included
+    pub sent_bytes: u64,
+    pub received_bytes: u64,
+    /// Handshakes that failed or timed out, whatever the cause
+    pub handshake_failures: u64,
+    /// Requests turned away with 503 by the in-flight budgets
+    pub overloaded_requests: u64,
+    /// Request streams reset because their headers didn't arrive in time
+    pub request_header_timeouts: u64,
+}
+
+fn request_rate(windows: &[Duration]) -> RateWindows {
+    RateWindows::new("quic_request_rate", "Requests accepted per second, averaged over the last window", windows)
+}
+
+/// A value that only goes up.
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Counter {
+    pub fn new(name: &'static str, help: &'static str) -> Self {
+        Counter { name, help, value: AtomicU64::new(0) }
+    }
+
+    pub fn add(&self, n: u64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", self.name, self.help, self.name);
+        let _ = writeln!(out, "{} {}", self.name, self.get());
+    }
+}
+
+/// Counters sharing a name that differ in the value of one label, all known up front so the
+/// series stay fixed.
+pub struct LabeledCounter {
+    name: &'static str,
+    help: &'static str,
+    label: &'static str,
+    values: &'static [&'static str],
+    counts: Box<[AtomicU64]>,
+}
+
+impl LabeledCounter {
+    pub fn new(name: &'static str, help: &'static str, label: &'static str, values: &'static [&'static str]) -> Self {
+        let counts = values.iter().map(|_| AtomicU64::new(0)).collect();
+        LabeledCounter { name, help, label, values, counts }
+    }
+
+    /// Values not given to [`LabeledCounter::new`] are ignored.
+    pub fn add(&self, value: &str, n: u64) {
+        if let Some(i) = self.values.iter().position(|v| *v == value) {
+            self.counts[i].fetch_add(n, Ordering::Relaxed);
+        }
+    }
+
+    /// The sum over all label values.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().map(|count| count.load(Ordering::Relaxed)).sum()
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", self.name, self.help, self.name);
+        for (value, count) in self.values.iter().zip(&self.counts) {
+            let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", self.name, self.label, value, count.load(Ordering::Relaxed));
+        }
+    }
+}
+
+/// A value that goes up and down.
+pub struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicI64,
+}
+
+impl Gauge {
+    pub fn new(name: &'static str, help: &'static str) -> Self {
+        Gauge { name, help, value: AtomicI64::new(0) }
+    }
+
+    pub fn add(&self, n: i64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Raises the gauge to `n` if it's lower, for high-water marks.
+    pub fn raise(&self, n: i64) {
+        self.value.fetch_max(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge", self.name, self.help, self.name);
+        let _ = writeln!(out, "{} {}", self.name, self.get());
+    }
+}
+
+/// Events per second averaged over sliding windows, rendered as one gauge per window with a
+/// `window` label. Events are counted in a ring of per-second slots, so recording one is a
+/// compare-and-swap on one atomic and rendering sums the slots of the last complete seconds.
+pub struct RateWindows {
+    name: &'static str,
+    help: &'static str,
+    // Whole seconds, at least one and at most the length of `slots`
+    windows: Box<[u64]>,
+    start: Instant,
+    // Slot `s % len` holds the second `s` it counts in its upper bits and the count in the lower
+    // ones, so a slot left over from an earlier lap reads as empty
+    slots: Box<[AtomicU64]>,
+}
+
+const SLOT_COUNT_BITS: u32 = 24;
+const SLOT_COUNT_MASK: u64 = (1 << SLOT_COUNT_BITS) - 1;
+
+impl RateWindows {
+    /// Windows are rounded down to whole seconds; the caller checks they are between one second
+    /// and [`MAX_REQUEST_RATE_WINDOW`].
+    pub fn new(name: &'static str, help: &'static str, windows: &[Duration]) -> Self {
+        let windows: Box<[u64]> = windows.iter().map(|window| window.as_secs().max(1)).collect();
+        let len = windows.iter().max().map_or(1, |longest| longest + 1);
+        let slots = (0..len).map(|_| AtomicU64::new(0)).collect();
+        RateWindows { name, help, windows, start: Instant::now(), slots }
+    }
+
+    pub fn record(&self) {
+        let second = self.start.elapsed().as_secs();
+        let slot = &self.slots[(second % self.slots.len() as u64) as usize];
+        let _ = slot.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |packed| {
+            Some(match packed >> SLOT_COUNT_BITS == second {
+                // Saturates rather than spilling into the second
+                true => packed + u64::from(packed & SLOT_COUNT_MASK < SLOT_COUNT_MASK),
+                false => second << SLOT_COUNT_BITS | 1,
+            })
+        });
+    }
+
+    fn render(&self, out: &mut String) {
+        let now = self.start.elapsed().as_secs();
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge", self.name, self.help, self.name);
+        for &window in &self.windows {
+            // The current second is still filling up, so only complete ones count; right after
+            // startup the average is over the seconds there have been
+            let seconds = window.min(now);
+            let count: u64 = (now - seconds..now)
+                .map(|second| {
+                    let packed = self.slots[(second % self.slots.len() as u64) as usize].load(Ordering::Relaxed);
+                    if packed >> SLOT_COUNT_BITS == second { packed & SLOT_COUNT_MASK } else { 0 }
+                })
+                .sum();
+            let rate = if seconds == 0 { 0.0 } else { count as f64 / seconds as f64 };
+            let label = humantime::format_duration(Duration::from_secs(window));
+            let _ = writeln!(out, "{}{{window=\"{}\"}} {}", self.name, label, rate);
+        }
+    }
+}
+
+/// Counts of observations per bucket, with their sum.
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    // Upper bounds, ascending; the +Inf bucket is implied
+    bounds: Box<[f64]>,
+    state: Mutex<HistogramState>,
+}
+
+struct HistogramState {
+    // Not cumulative; one more than `bounds` for +Inf
+    buckets: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    pub fn new(name: &'static str, help: &'static str, bounds: &[f64]) -> Self {
+        let state = HistogramState { buckets: vec![0; bounds.len() + 1], sum: 0.0 };
+        Histogram { name, help, bounds: bounds.into(), state: Mutex::new(state) }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let bucket = self.bounds.iter().position(|bound| value <= *bound).unwrap_or(self.bounds.len());
+        let mut state = self.state.lock().unwrap();
+        state.buckets[bucket] += 1;
+        state.sum += value;
+    }
+
+    fn render(&self, out: &mut String) {
+        let state = self.state.lock().unwrap();
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", self.name, self.help, self.name);
+        let mut count = 0;
+        for (bound, n) in self.bounds.iter().zip(&state.buckets) {
+            count += n;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", self.name, bound, count);
+        }
+        count += state.buckets[self.bounds.len()];
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", self.name, count);
+        let _ = writeln!(out, "{}_sum {}", self.name, state.sum);
+        let _ = writeln!(out, "{}_count {}", self.name, count);
+    }
+}
+
+/// Open connections whose statistics are sampled into [`Metrics`].
+#[derive(Default)]
+pub(crate) struct Sampler {
+    connections: Mutex<HashMap<usize, Tracked>>,
+}
+
+struct Tracked {
+    connection: quinn::Connection,
+    // Totals at the previous sample, so the counters get only what happened since
+    sent_packets: u64,
+    lost_packets: u64,
+    sent_bytes: u64,
+    received_bytes: u64,
+}
+
+impl Sampler {
+    pub(crate) fn track(&self, connection: &quinn::Connection, metrics: &Metrics) {
+        let tracked = Tracked {
+            connection: connection.clone(),
+            sent_packets: 0,
+            lost_packets: 0,
+            sent_bytes: 0,
+            received_bytes: 0,
+        };
+        self.connections.lock().unwrap().insert(connection.stable_id(), tracked);
+        metrics.connections.add(1);
+        metrics.accepted_connections.add(1);
+    }
+
+    // Counts what happened since the last sample before letting go of the connection
+    pub(crate) fn untrack(&self, connection: &quinn::Connection, metrics: &Metrics) {
+        if let Some(mut tracked) = self.connections.lock().unwrap().remove(&connection.stable_id()) {
+            tracked.count_traffic(&connection.stats(), metrics);
+            metrics.connections.add(-1);
+        }
+    }
+
+    // Bytes sent and received since the last sample, which the counters haven't seen yet
+    pub(crate) fn unsampled_bytes(&self) -> (u64, u64) {
+        let mut unsampled = (0, 0);
+        for tracked in self.connections.lock().unwrap().values() {
+            let stats = tracked.connection.stats();
+            unsampled.0 += stats.udp_tx.bytes.saturating_sub(tracked.sent_bytes);
+            unsampled.1 += stats.udp_rx.bytes.saturating_sub(tracked.received_bytes);
+        }
+        unsampled
+    }
+
+    // A few atomic operations and one stats snapshot per connection
+    pub(crate) fn sample(&self, metrics: &Metrics) {
+        for tracked in self.connections.lock().unwrap().values_mut() {
+            let stats = tracked.connection.stats();
+            metrics.rtt.observe(stats.path.rtt.as_secs_f64());
+            metrics.congestion_window.observe(stats.path.cwnd as f64);
+            tracked.count_traffic(&stats, metrics);
+        }
+    }
+}
+
+impl Tracked {
+    fn count_traffic(&mut self, stats: &quinn::ConnectionStats, metrics: &Metrics) {
+        metrics.sent_packets.add(stats.udp_tx.datagrams.saturating_sub(self.sent_packets));
+        metrics.lost_packets.add(stats.path.lost_packets.saturating_sub(self.lost_packets));
+        metrics.sent_bytes.add(stats.udp_tx.bytes.saturating_sub(self.sent_bytes));
+        metrics.received_bytes.add(stats.udp_rx.bytes.saturating_sub(self.received_bytes));
+        self.sent_packets = stats.udp_tx.datagrams;
+        self.lost_packets = stats.path.lost_packets;
+        self.sent_bytes = stats.udp_tx.bytes;
+        self.received_bytes = stats.udp_rx.bytes;
+    }
+}
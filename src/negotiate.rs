@@ -0,0 +1,127 @@
+use http::HeaderMap;
+use http::header::{ACCEPT, ACCEPT_ENCODING};
+
+// A single entry of an Accept header, e.g. `text/*;q=0.5`
+struct MediaRange<'a> {
+    main_type: &'a str,
+    subtype: &'a str,
+    quality: f32,
+}
+
+impl MediaRange<'_> {
+    // How specific this range is when it matches: exact type > `type/*` > `*/*`
+    fn specificity(&self, media_type: &str) -> Option<u8> {
+        let (main_type, subtype) = media_type.split_once('/')?;
+        match (self.main_type, self.subtype) {
+            ("*", "*") => Some(0),
+            (t, "*") if t.eq_ignore_ascii_case(main_type) => Some(1),
+            (t, s) if t.eq_ignore_ascii_case(main_type) && s.eq_ignore_ascii_case(subtype) => {
+                Some(2)
+            }
+            _ => None,
+        }
+    }
+}
+
+// Parse every Accept header value into media ranges, skipping malformed entries
+fn parse_accept(headers: &HeaderMap) -> Vec<MediaRange<'_>> {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(parse_media_range)
+        .collect()
+}
+
+fn parse_media_range(entry: &str) -> Option<MediaRange<'_>> {
+    let mut params = entry.split(';');
+    let (main_type, subtype) = params.next()?.trim().split_once('/')?;
+    let (main_type, subtype) = (main_type.trim(), subtype.trim());
+    if main_type.is_empty() || subtype.is_empty() || (main_type == "*" && subtype != "*") {
+        return None;
+    }
+
+    let quality = parse_quality(params)?;
+    Some(MediaRange { main_type, subtype, quality })
+}
+
+// The `q` parameter among `params` (1 when absent), or `None` if it's out of range
+fn parse_quality<'a>(params: impl Iterator<Item = &'a str>) -> Option<f32> {
+    let mut quality = 1.0;
+    for param in params {
+        if let Some((name, value)) = param.split_once('=')
+            && name.trim().eq_ignore_ascii_case("q")
+        {
+            quality = value.trim().parse().ok().filter(|q| (0.0..=1.0).contains(q))?;
+        }
+    }
+    Some(quality)
+}
+
+// A single entry of an Accept-Encoding header, e.g. `br;q=0.9`
+fn parse_coding(entry: &str) -> Option<(&str, f32)> {
+    let mut params = entry.split(';');
+    let coding = params.next()?.trim();
+    if coding.is_empty() {
+        return None;
+    }
+    Some((coding, parse_quality(params)?))
+}
+
+// Pick the best of `supported` (listed in server preference order) for the request's Accept header.
+// Each supported type takes the quality of the most specific range matching it. Returns `None`
+// when nothing supported is acceptable, which the caller should answer with 406 Not Acceptable.
+pub fn best_match<'s>(headers: &HeaderMap, supported: &[&'s str]) -> Option<&'s str> {
+    let ranges = parse_accept(headers);
+    if ranges.is_empty() {
+        // No (usable) Accept header means any representation is fine
+        return supported.first().copied();
+    }
+
+    let mut best: Option<(&'s str, f32)> = None;
+    for &media_type in supported {
+        let quality = ranges
+            .iter()
+            .filter_map(|range| Some((range.specificity(media_type)?, range.quality)))
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, quality)| quality)
+            .unwrap_or(0.0);
+
+        if quality > 0.0 && best.is_none_or(|(_, best_quality)| quality > best_quality) {
+            best = Some((media_type, quality));
+        }
+    }
+
+    best.map(|(media_type, _)| media_type)
+}
+
+// Pick the best of `supported` content codings (listed in server preference order) for the
+// request's Accept-Encoding header: the highest quality wins and ties go to the earlier entry.
+// `*` stands for every coding the header doesn't name. Returns `None` when the body should be sent
+// as is, including when there is no Accept-Encoding header at all.
+pub fn best_encoding<'s>(headers: &HeaderMap, supported: &[&'s str]) -> Option<&'s str> {
+    let codings: Vec<(&str, f32)> = headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(parse_coding)
+        .collect();
+
+    let mut best: Option<(&'s str, f32)> = None;
+    for &coding in supported {
+        let quality = codings
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(coding))
+            .or_else(|| codings.iter().find(|(name, _)| *name == "*"))
+            .map(|(_, quality)| *quality)
+            .unwrap_or(0.0);
+
+        if quality > 0.0 && best.is_none_or(|(_, best_quality)| quality > best_quality) {
+            best = Some((coding, quality));
+        }
+    }
+
+    best.map(|(coding, _)| coding)
+}
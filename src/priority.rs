@@ -0,0 +1,851 @@
+// HTTP/3 extensible priorities (RFC 9218): the `priority` request header, PRIORITY_UPDATE frames
+// on the client's control stream, and a server transport that hands them to quinn's stream
+// scheduler so more urgent responses are sent first. The transport also watches each request
+// stream for the client cancelling it (see `cancel.rs`), counts the bytes request streams carry
+// (see `metrics.rs`), rejects request streams dropped before their header section arrived and
+// keeps track of responses the client has yet to acknowledge.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{self, Poll, ready};
+
+use bytes::{Buf, Bytes, BytesMut};
+use h3::quic::{self, ConnectionErrorIncoming, StreamErrorIncoming, StreamId, WriteBuf};
+use h3_quinn::quinn;
+use http::Request;
+use tokio::sync::{mpsc, watch};
+
+use crate::cancel::Cancellations;
+use crate::error_code::Code;
+use crate::metrics::Metrics;
+use crate::uni::convert_connection_error;
+
+/// The request header carrying a priority.
+pub const HEADER: &str = "priority";
+
+// Control stream type and the PRIORITY_UPDATE frame type for request streams
+const CONTROL_STREAM_TYPE: u64 = 0x00;
+const PRIORITY_UPDATE_FRAME: u64 = 0xf0700;
+
+// Longest PRIORITY_UPDATE payload that is parsed; a priority field value is a few bytes
+const MAX_PRIORITY_UPDATE_LEN: u64 = 1024;
+
+// Streams whose priority is remembered before they are opened; past this many the oldest is
+// forgotten, so updates for far-future stream IDs can't grow the table without bound
+const MAX_PENDING_UPDATES: usize = 1024;
+
+/// How urgently a client wants a response (RFC 9218, section 4).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Priority {
+    /// 0 (most urgent) to 7; 3 unless the client says otherwise
+    pub urgency: u8,
+    /// Whether the response is useful in pieces, so it can share bandwidth with others of the
+    /// same urgency instead of waiting its turn
+    pub incremental: bool,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Priority { urgency: 3, incremental: false }
+    }
+}
+
+impl Priority {
+    /// Parse a priority field value such as `u=1, i`. Members that are unknown or malformed are
+    /// ignored and keep their defaults, as RFC 9218 requires.
+    pub fn parse(value: &str) -> Priority {
+        let mut priority = Priority::default();
+        for member in value.split(',') {
+            // Parameters (";key=value") carry nothing we use
+            let member = member.split(';').next().unwrap_or_default().trim();
+            let (key, value) = member.split_once('=').unwrap_or((member, "?1"));
+            match (key, value.trim()) {
+                ("u", value) => {
+                    if let Some(urgency) = value.parse().ok().filter(|urgency| *urgency <= 7) {
+                        priority.urgency = urgency;
+                    }
+                }
+                ("i", "?1") => priority.incremental = true,
+                ("i", "?0") => priority.incremental = false,
+                _ => {}
+            }
+        }
+        priority
+    }
+
+    /// The priority a request asks for in its `priority` header, or the default.
+    pub fn of<T>(req: &Request<T>) -> Priority {
+        req.headers()
+            .get(HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(Priority::parse)
+            .unwrap_or_default()
+    }
+
+    // quinn sends streams with a higher number first and round-robins streams of equal number,
+    // which is what incremental responses want; non-incremental ones share the round-robin too
+    fn send_order(self) -> i32 {
+        7 - i32::from(self.urgency)
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "u={}", self.urgency)?;
+        if self.incremental {
+            write!(f, ", i")?;
+        }
+        Ok(())
+    }
+}
+
+/// Priorities of the request streams on one connection. Setting one takes effect the next time
+/// the stream sends data.
+#[derive(Debug, Clone, Default)]
+pub struct Priorities {
+    streams: Arc<Mutex<Streams>>,
+}
+
+#[derive(Debug, Default)]
+struct Streams {
+    slots: HashMap<u64, Arc<Mutex<Slot>>>,
+    // Streams that got an update before they were opened, oldest first
+    pending: VecDeque<u64>,
+    // quinn accepts request streams in order, so one below this without a slot is closed
+    next_stream: u64,
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+    priority: Priority,
+    // A PRIORITY_UPDATE frame overrides the request header (RFC 9218, section 7)
+    from_frame: bool,
+    applied: bool,
+}
+
+impl Priorities {
+    /// Reprioritize the response on `stream`, e.g. from a handler.
+    pub fn set(&self, stream: StreamId, priority: Priority) {
+        if let Some(slot) = self.streams.lock().unwrap().slots.get(&stream.into_inner()) {
+            let mut slot = slot.lock().unwrap();
+            slot.priority = priority;
+            slot.applied = false;
+        }
+    }
+
+    /// Apply the priority from a request's header, unless the client already sent a
+    /// PRIORITY_UPDATE frame for the stream.
+    pub fn set_from_header(&self, stream: StreamId, priority: Priority) {
+        if let Some(slot) = self.streams.lock().unwrap().slots.get(&stream.into_inner()) {
+            let mut slot = slot.lock().unwrap();
+            if !slot.from_frame {
+                slot.priority = priority;
+                slot.applied = false;
+            }
+        }
+    }
+
+    /// The priority `stream` is currently sent with.
+    pub fn get(&self, stream: StreamId) -> Option<Priority> {
+        let streams = self.streams.lock().unwrap();
+        streams.slots.get(&stream.into_inner()).map(|slot| slot.lock().unwrap().priority)
+    }
+
+    // Updates may arrive before the stream they are for, so streams yet to be opened get a slot
+    // too. Ones for closed streams, or for IDs that aren't client request streams, are dropped.
+    fn update_from_frame(&self, stream: u64, priority: Priority) {
+        let mut streams = self.streams.lock().unwrap();
+        if !streams.slots.contains_key(&stream) {
+            if !stream.is_multiple_of(4) || stream < streams.next_stream {
+                return;
+            }
+            if streams.pending.len() >= MAX_PENDING_UPDATES
+                && let Some(oldest) = streams.pending.pop_front()
+            {
+                streams.slots.remove(&oldest);
+            }
+            streams.pending.push_back(stream);
+        }
+        let mut slot = streams.slots.entry(stream).or_default().lock().unwrap();
+        *slot = Slot { priority, from_frame: true, applied: false };
+    }
+
+    fn open(&self, stream: u64) -> Arc<Mutex<Slot>> {
+        let mut streams = self.streams.lock().unwrap();
+        streams.next_stream = streams.next_stream.max(stream + 4);
+        if !streams.pending.is_empty() {
+            streams.pending.retain(|pending| *pending != stream);
+        }
+        streams.slots.entry(stream).or_default().clone()
+    }
+
+    fn close(&self, stream: u64) {
+        self.streams.lock().unwrap().slots.remove(&stream);
+    }
+}
+
+/// Request streams to reset, rather than finish, if they are dropped, and the code to reset
+/// them with. Otherwise quinn would finish them cleanly, as if the response were complete.
+///
+/// Every stream starts out here with `H3_REQUEST_REJECTED` until its header section has been
+/// received, so one dropped before, e.g. because its headers took too long, tells the client the
+/// request wasn't processed and may be retried (RFC 9114, section 4.1.1). The server then
+/// switches it to `H3_INTERNAL_ERROR` until the response is finished, so a handler that panics
+/// halfway through a body doesn't leave the client with a truncated response that looks whole.
+/// Finishing a stream takes it out of here.
+#[derive(Debug, Clone, Default)]
+pub struct StreamResets {
+    streams: Arc<Mutex<HashMap<u64, Code>>>,
+}
+
+impl StreamResets {
+    /// Let `stream` finish normally when dropped, e.g. once its request has been resolved.
+    pub fn clear(&self, stream: StreamId) {
+        self.streams.lock().unwrap().remove(&stream.into_inner());
+    }
+
+    /// Reset `stream` with `code` when it is dropped, e.g. for a response given up on halfway.
+    pub fn reset_on_drop(&self, stream: StreamId, code: Code) {
+        self.streams.lock().unwrap().insert(stream.into_inner(), code);
+    }
+
+    fn open(&self, stream: u64) {
+        self.streams.lock().unwrap().insert(stream, Code::H3_REQUEST_REJECTED);
+    }
+
+    fn close(&self, stream: u64) -> Option<Code> {
+        self.streams.lock().unwrap().remove(&stream)
+    }
+}
+
+/// Responses the server is done with whose data the client hasn't acknowledged yet. quinn throws
+/// away unsent stream data when a connection closes, so a server closing one of its own accord,
+/// e.g. after GOAWAY, waits for these first or the last responses would be cut off.
+#[derive(Debug, Clone)]
+pub struct Unacknowledged {
+    count: Arc<watch::Sender<usize>>,
+}
+
+impl Default for Unacknowledged {
+    fn default() -> Self {
+        Unacknowledged { count: Arc::new(watch::Sender::new(0)) }
+    }
+}
+
+impl Unacknowledged {
+    /// Wait until the client has acknowledged every response, or the connection is gone.
+    pub async fn settled(&self) {
+        let _ = self.count.subscribe().wait_for(|count| *count == 0).await;
+    }
+
+    // `stopped` completes once the client acknowledged the whole stream, stopped it, or the
+    // connection closed
+    fn track(&self, stopped: impl Future + Send + 'static) {
+        self.count.send_modify(|count| *count += 1);
+        let count = self.count.clone();
+        tokio::spawn(async move {
+            stopped.await;
+            count.send_modify(|count| *count -= 1);
+        });
+    }
+}
+
+/// Server-side h3 transport over quinn whose request streams are sent in priority order, come
+/// with a cancellation token and count their bytes into [`Metrics`]. Everything except
+/// accepting request streams is delegated to `h3_quinn`.
+pub struct Connection {
+    inner: h3_quinn::Connection,
+    bidi_streams: mpsc::UnboundedReceiver<Result<BidiStream<Bytes>, ConnectionErrorIncoming>>,
+    priorities: Priorities,
+    cancellations: Cancellations,
+    stream_resets: StreamResets,
+    unacknowledged: Unacknowledged,
+}
+
+impl Connection {
+    pub fn new(conn: quinn::Connection, metrics: Arc<Metrics>) -> Connection {
+        let (priorities, cancellations, stream_resets) = (Priorities::default(), Cancellations::default(), StreamResets::default());
+        let unacknowledged = Unacknowledged::default();
+        let (tx, bidi_streams) = mpsc::unbounded_channel();
+        tokio::spawn(accept_bidi_streams(
+            conn.clone(),
+            tx,
+            priorities.clone(),
+            cancellations.clone(),
+            stream_resets.clone(),
+            unacknowledged.clone(),
+            metrics,
+        ));
+        Connection { inner: h3_quinn::Connection::new(conn), bidi_streams, priorities, cancellations, stream_resets, unacknowledged }
+    }
+
+    pub fn priorities(&self) -> Priorities {
+        self.priorities.clone()
+    }
+
+    pub fn cancellations(&self) -> Cancellations {
+        self.cancellations.clone()
+    }
+
+    pub fn stream_resets(&self) -> StreamResets {
+        self.stream_resets.clone()
+    }
+
+    pub fn unacknowledged(&self) -> Unacknowledged {
+        self.unacknowledged.clone()
+    }
+}
+
+// Stops when the connection closes or h3 is done with it, like the unidirectional splitter
+async fn accept_bidi_streams(
+    conn: quinn::Connection,
+    tx: mpsc::UnboundedSender<Result<BidiStream<Bytes>, ConnectionErrorIncoming>>,
+    priorities: Priorities,
+    cancellations: Cancellations,
+    stream_resets: StreamResets,
+    unacknowledged: Unacknowledged,
+    metrics: Arc<Metrics>,
+) {
+    loop {
+        let streams = tokio::select! {
+            streams = conn.accept_bi() => streams,
+            _ = tx.closed() => return,
+        };
+        let stream = streams.map(|(send, recv)| {
+            cancellations.watch(&send);
+            stream_resets.open(send.id().into());
+            BidiStream {
+                send: SendStream {
+                    slot: priorities.open(send.id().into()),
+                    priorities: priorities.clone(),
+                    cancellations: cancellations.clone(),
+                    stream_resets: stream_resets.clone(),
+                    unacknowledged: unacknowledged.clone(),
+                    metrics: metrics.clone(),
+                    stream: send,
+                    writing: None,
+                },
+                recv: RecvStream { stream: recv, metrics: metrics.clone() },
+            }
+        });
+        let failed = stream.is_err();
+        if tx.send(stream.map_err(convert_connection_error)).is_err() || failed {
+            return;
+        }
+    }
+}
+
+impl quic::Connection<Bytes> for Connection {
+    type RecvStream = UniStream;
+    type OpenStreams = OpenStreams;
+
+    fn poll_accept_recv(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<UniStream, ConnectionErrorIncoming>> {
+        let stream = ready!(quic::Connection::<Bytes>::poll_accept_recv(&mut self.inner, cx))?;
+        Poll::Ready(Ok(UniStream { inner: stream, tap: PriorityTap::new(self.priorities.clone()) }))
+    }
+
+    fn poll_accept_bidi(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<BidiStream<Bytes>, ConnectionErrorIncoming>> {
+        match ready!(self.bidi_streams.poll_recv(cx)) {
+            Some(result) => Poll::Ready(result),
+            // Only after the connection error was already reported
+            None => Poll::Ready(Err(ConnectionErrorIncoming::InternalError("connection closed".to_string()))),
+        }
+    }
+
+    fn opener(&self) -> OpenStreams {
+        OpenStreams { inner: quic::Connection::<Bytes>::opener(&self.inner) }
+    }
+}
+
+impl quic::OpenStreams<Bytes> for Connection {
+    type BidiStream = BidiStream<Bytes>;
+    type SendStream = h3_quinn::SendStream<Bytes>;
+
+    fn poll_open_bidi(&mut self, _cx: &mut task::Context<'_>) -> Poll<Result<BidiStream<Bytes>, StreamErrorIncoming>> {
+        Poll::Ready(Err(no_request_streams()))
+    }
+
+    fn poll_open_send(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<Self::SendStream, StreamErrorIncoming>> {
+        self.inner.poll_open_send(cx)
+    }
+
+    fn close(&mut self, code: h3::error::Code, reason: &[u8]) {
+        quic::OpenStreams::<Bytes>::close(&mut self.inner, code, reason)
+    }
+}
+
+/// Opens h3's own unidirectional streams; request streams are only ever opened by the client.
+pub struct OpenStreams {
+    inner: h3_quinn::OpenStreams,
+}
+
+impl quic::OpenStreams<Bytes> for OpenStreams {
+    type BidiStream = BidiStream<Bytes>;
+    type SendStream = h3_quinn::SendStream<Bytes>;
+
+    fn poll_open_bidi(&mut self, _cx: &mut task::Context<'_>) -> Poll<Result<BidiStream<Bytes>, StreamErrorIncoming>> {
+        Poll::Ready(Err(no_request_streams()))
+    }
+
+    fn poll_open_send(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<Self::SendStream, StreamErrorIncoming>> {
+        self.inner.poll_open_send(cx)
+    }
+
+    fn close(&mut self, code: h3::error::Code, reason: &[u8]) {
+        quic::OpenStreams::<Bytes>::close(&mut self.inner, code, reason)
+    }
+}
+
+// HTTP/3 servers only open unidirectional streams
+fn no_request_streams() -> StreamErrorIncoming {
+    StreamErrorIncoming::Unknown("the server does not open request streams".into())
+}
+
+/// A request stream.
+pub struct BidiStream<B: Buf> {
+    send: SendStream<B>,
+    recv: RecvStream,
+}
+
+impl<B: Buf> quic::BidiStream<B> for BidiStream<B> {
+    type SendStream = SendStream<B>;
+    type RecvStream = RecvStream;
+
+    fn split(self) -> (SendStream<B>, RecvStream) {
+        (self.send, self.recv)
+    }
+}
+
+impl<B: Buf> quic::RecvStream for BidiStream<B> {
+    type Buf = Bytes;
+
+    fn poll_data(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<Option<Bytes>, StreamErrorIncoming>> {
+        self.recv.poll_data(cx)
+    }
+
+    fn stop_sending(&mut self, error_code: u64) {
+        self.recv.stop_sending(error_code)
+    }
+
+    fn recv_id(&self) -> StreamId {
+        self.recv.recv_id()
+    }
+}
+
+impl<B: Buf> quic::SendStream<B> for BidiStream<B> {
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), StreamErrorIncoming>> {
+        self.send.poll_ready(cx)
+    }
+
+    fn send_data<T: Into<WriteBuf<B>>>(&mut self, data: T) -> Result<(), StreamErrorIncoming> {
+        self.send.send_data(data)
+    }
+
+    fn poll_finish(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), StreamErrorIncoming>> {
+        self.send.poll_finish(cx)
+    }
+
+    fn reset(&mut self, reset_code: u64) {
+        self.send.reset(reset_code)
+    }
+
+    fn send_id(&self) -> StreamId {
+        self.send.send_id()
+    }
+}
+
+impl<B: Buf> quic::SendStreamUnframed<B> for BidiStream<B> {
+    fn poll_send<D: Buf>(&mut self, cx: &mut task::Context<'_>, buf: &mut D) -> Poll<Result<usize, StreamErrorIncoming>> {
+        self.send.poll_send(cx, buf)
+    }
+}
+
+/// The sending half of a request stream. Picks up priority changes before each write.
+pub struct SendStream<B: Buf> {
+    stream: quinn::SendStream,
+    writing: Option<WriteBuf<B>>,
+    slot: Arc<Mutex<Slot>>,
+    priorities: Priorities,
+    cancellations: Cancellations,
+    stream_resets: StreamResets,
+    unacknowledged: Unacknowledged,
+    metrics: Arc<Metrics>,
+}
+
+impl<B: Buf> SendStream<B> {
+    fn apply_priority(&mut self) {
+        let mut slot = self.slot.lock().unwrap();
+        if !slot.applied {
+            let _ = self.stream.set_priority(slot.priority.send_order());
+            slot.applied = true;
+        }
+    }
+}
+
+impl<B: Buf> Drop for SendStream<B> {
+    fn drop(&mut self) {
+        self.priorities.close(self.stream.id().into());
+        self.cancellations.forget(self.stream.id().into());
+        if let Some(code) = self.stream_resets.close(self.stream.id().into()) {
+            let _ = self.stream.reset(quinn::VarInt::from_u64(code.value()).unwrap_or(quinn::VarInt::MAX));
+        } else {
+            // Finished, by h3 or by quinn as it drops the stream
+            self.unacknowledged.track(self.stream.stopped());
+        }
+    }
+}
+
+impl<B: Buf> quic::SendStream<B> for SendStream<B> {
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), StreamErrorIncoming>> {
+        self.apply_priority();
+        if let Some(data) = &mut self.writing {
+            while data.has_remaining() {
+                let written = ready!(Pin::new(&mut self.stream).poll_write(cx, data.chunk()))
+                    .map_err(convert_write_error)?;
+                data.advance(written);
+                self.metrics.request_sent_bytes.add(written as u64);
+            }
+        }
+        self.writing = None;
+        Poll::Ready(Ok(()))
+    }
+
+    fn send_data<T: Into<WriteBuf<B>>>(&mut self, data: T) -> Result<(), StreamErrorIncoming> {
+        if self.writing.is_some() {
+            // h3 always waits for `poll_ready` first
+            return Err(StreamErrorIncoming::ConnectionErrorIncoming {
+                connection_error: ConnectionErrorIncoming::InternalError("send_data called before the stream was ready".to_string()),
+            });
+        }
+        self.writing = Some(data.into());
+        Ok(())
+    }
+
+    fn poll_finish(&mut self, _cx: &mut task::Context<'_>) -> Poll<Result<(), StreamErrorIncoming>> {
+        // A reset after finishing could still abort data the client hasn't received yet
+        self.stream_resets.close(self.stream.id().into());
+        Poll::Ready(self.stream.finish().map_err(|e| StreamErrorIncoming::Unknown(Box::new(e))))
+    }
+
+    fn reset(&mut self, reset_code: u64) {
+        let _ = self.stream.reset(quinn::VarInt::from_u64(reset_code).unwrap_or(quinn::VarInt::MAX));
+    }
+
+    fn send_id(&self) -> StreamId {
+        u64::from(self.stream.id()).try_into().expect("quinn stream ids are valid h3 stream ids")
+    }
+}
+
+impl<B: Buf> quic::SendStreamUnframed<B> for SendStream<B> {
+    fn poll_send<D: Buf>(&mut self, cx: &mut task::Context<'_>, buf: &mut D) -> Poll<Result<usize, StreamErrorIncoming>> {
+        self.apply_priority();
+        let written = ready!(Pin::new(&mut self.stream).poll_write(cx, buf.chunk())).map_err(convert_write_error)?;
+        buf.advance(written);
+        self.metrics.request_sent_bytes.add(written as u64);
+        Poll::Ready(Ok(written))
+    }
+}
+
+/// The receiving half of a request stream.
+pub struct RecvStream {
+    stream: quinn::RecvStream,
+    metrics: Arc<Metrics>,
+}
+
+impl quic::RecvStream for RecvStream {
+    type Buf = Bytes;
+
+    fn poll_data(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<Option<Bytes>, StreamErrorIncoming>> {
+        // `read_chunk` is cancel-safe, so a fresh one per poll loses nothing, and the stream stays
+        // here for `stop_sending` while the client is slow to send
+        let chunk = ready!(std::pin::pin!(self.stream.read_chunk(usize::MAX, true)).poll(cx));
+
+        match chunk {
+            Ok(chunk) => Poll::Ready(Ok(chunk.map(|chunk| {
+                self.metrics.request_received_bytes.add(chunk.bytes.len() as u64);
+                chunk.bytes
+            }))),
+            Err(quinn::ReadError::Reset(code)) => {
+                Poll::Ready(Err(StreamErrorIncoming::StreamTerminated { error_code: code.into_inner() }))
+            }
+            Err(quinn::ReadError::ConnectionLost(e)) => Poll::Ready(Err(StreamErrorIncoming::ConnectionErrorIncoming {
+                connection_error: convert_connection_error(e),
+            })),
+            Err(e) => Poll::Ready(Err(StreamErrorIncoming::Unknown(Box::new(e)))),
+        }
+    }
+
+    fn stop_sending(&mut self, error_code: u64) {
+        if let Ok(code) = quinn::VarInt::from_u64(error_code) {
+            let _ = self.stream.stop(code);
+        }
+    }
+
+    fn recv_id(&self) -> StreamId {
+        u64::from(self.stream.id()).try_into().expect("quinn stream ids are valid h3 stream ids")
+    }
+}
+
+fn convert_write_error(e: quinn::WriteError) -> StreamErrorIncoming {
+    match e {
+        quinn::WriteError::Stopped(code) => StreamErrorIncoming::StreamTerminated { error_code: code.into_inner() },
+        quinn::WriteError::ConnectionLost(e) => {
+            StreamErrorIncoming::ConnectionErrorIncoming { connection_error: convert_connection_error(e) }
+        }
+        e => StreamErrorIncoming::Unknown(Box::new(e)),
+    }
+}
+
+/// A unidirectional stream from the client, passed on to h3 unchanged. On the control stream,
+/// PRIORITY_UPDATE frames (which h3 ignores) are picked out on the way through.
+pub struct UniStream {
+    inner: h3_quinn::RecvStream,
+    tap: PriorityTap,
+}
+
+// Follows the frames of a unidirectional stream from its first byte
+struct PriorityTap {
+    state: TapState,
+    // Bytes of a stream type or frame header (or a PRIORITY_UPDATE payload) not yet complete
+    buf: BytesMut,
+    priorities: Priorities,
+}
+
+enum TapState {
+    StreamType,
+    FrameHeader,
+    PriorityUpdate(usize),
+    SkipFrame(u64),
+    // Not the control stream
+    Done,
+}
+
+impl PriorityTap {
+    fn new(priorities: Priorities) -> PriorityTap {
+        PriorityTap { state: TapState::StreamType, buf: BytesMut::new(), priorities }
+    }
+
+    fn inspect(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if let TapState::SkipFrame(remaining) = self.state {
+                let skipped = remaining.min(data.len() as u64);
+                data = &data[skipped as usize..];
+                self.state = match remaining - skipped {
+                    0 => TapState::FrameHeader,
+                    remaining => TapState::SkipFrame(remaining),
+                };
+                continue;
+            }
+            if let TapState::Done = self.state {
+                return;
+            }
+
+            self.buf.extend_from_slice(data);
+            data = &[];
+            self.advance();
+        }
+    }
+
+    // Consume every complete piece `buf` holds
+    fn advance(&mut self) {
+        loop {
+            match self.state {
+                TapState::StreamType => {
+                    let Some((stream_type, len)) = decode_varint(&self.buf) else { return };
+                    self.buf.advance(len);
+                    self.state = if stream_type == CONTROL_STREAM_TYPE { TapState::FrameHeader } else { TapState::Done };
+                }
+                TapState::FrameHeader => {
+                    let Some((frame_type, type_len)) = decode_varint(&self.buf) else { return };
+                    let Some((frame_len, len_len)) = decode_varint(&self.buf[type_len..]) else { return };
+                    self.buf.advance(type_len + len_len);
+                    self.state = if frame_type == PRIORITY_UPDATE_FRAME && frame_len <= MAX_PRIORITY_UPDATE_LEN {
+                        TapState::PriorityUpdate(frame_len as usize)
+                    } else {
+                        TapState::SkipFrame(frame_len)
+                    };
+                }
+                TapState::PriorityUpdate(len) => {
+                    if self.buf.len() < len {
+                        return;
+                    }
+                    let payload = self.buf.split_to(len);
+                    if let Some((stream, id_len)) = decode_varint(&payload) {
+                        let priority = Priority::parse(&String::from_utf8_lossy(&payload[id_len..]));
+                        self.priorities.update_from_frame(stream, priority);
+                    }
+                    self.state = TapState::FrameHeader;
+                }
+                TapState::SkipFrame(remaining) => {
+                    let skipped = remaining.min(self.buf.len() as u64);
+                    self.buf.advance(skipped as usize);
+                    if skipped < remaining {
+                        self.state = TapState::SkipFrame(remaining - skipped);
+                        return;
+                    }
+                    self.state = TapState::FrameHeader;
+                }
+                TapState::Done => {
+                    self.buf.clear();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl quic::RecvStream for UniStream {
+    type Buf = Bytes;
+
+    fn poll_data(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<Option<Bytes>, StreamErrorIncoming>> {
+        let data = ready!(self.inner.poll_data(cx))?;
+        if let Some(data) = &data {
+            self.tap.inspect(data);
+        }
+        Poll::Ready(Ok(data))
+    }
+
+    fn stop_sending(&mut self, error_code: u64) {
+        self.inner.stop_sending(error_code)
+    }
+
+    fn recv_id(&self) -> StreamId {
+        self.inner.recv_id()
+    }
+}
+
+// Returns the value and how many bytes it took, or `None` if `buf` doesn't hold all of it yet
+pub(crate) fn decode_varint(buf: &[u8]) -> Option<(u64, usize)> {
+    let first = *buf.first()?;
+    let len = 1 << (first >> 6);
+    let bytes = buf.get(1..len)?;
+    let value = bytes.iter().fold(u64::from(first & 0x3f), |value, byte| (value << 8) | u64::from(*byte));
+    Some((value, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn priority(urgency: u8, incremental: bool) -> Priority {
+        Priority { urgency, incremental }
+    }
+
+    #[test]
+    fn parse_ignores_what_it_cannot_use() {
+        for (value, expected) in [
+            ("u=1, i", priority(1, true)),
+            ("u=0,i=?1", priority(0, true)),
+            ("  u=5 ,   i  ", priority(5, true)),
+            ("i, i=?0", priority(3, false)),
+            // Out of range or not an integer
+            ("u=8", priority(3, false)),
+            ("u=-1", priority(3, false)),
+            ("u=1.5", priority(3, false)),
+            ("i=1", priority(3, false)),
+            // Parameters and unknown keys
+            ("u=2;foo=bar, x=7, i;q", priority(2, true)),
+            ("", priority(3, false)),
+            // The last one wins
+            ("u=1, u=6", priority(6, false)),
+        ] {
+            assert_eq!(Priority::parse(value), expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn varints_of_every_length() {
+        // The examples of RFC 9000, appendix A.1
+        for (encoded, value) in [
+            (&[0x25][..], 37),
+            (&[0x40, 0x25][..], 37),
+            (&[0x7b, 0xbd][..], 15293),
+            (&[0x9d, 0x7f, 0x3e, 0x7d][..], 494878333),
+            (&[0xc2, 0x19, 0x7c, 0x5e, 0xff, 0x14, 0xe8, 0x8c][..], 151288809941952652),
+        ] {
+            assert_eq!(decode_varint(encoded), Some((value, encoded.len())));
+        }
+        assert_eq!(decode_varint(&[0x25, 0xff]), Some((37, 1)));
+        assert_eq!(decode_varint(&[]), None);
+        assert_eq!(decode_varint(&[0x9d, 0x7f, 0x3e]), None);
+    }
+
+    fn priority_of(priorities: &Priorities, stream: u64) -> Option<Priority> {
+        priorities.get(StreamId::try_from(stream).unwrap())
+    }
+
+    // A control stream with SETTINGS, an unknown frame and then a PRIORITY_UPDATE for stream 4
+    const CONTROL_STREAM: &[u8] = &[
+        0x00, // control stream
+        0x04, 0x02, 0x06, 0x02, // SETTINGS
+        0x21, 0x03, 0xaa, 0xbb, 0xcc, // reserved frame type
+        0x80, 0x0f, 0x07, 0x00, 0x07, 0x04, b'u', b'=', b'1', b',', b' ', b'i', // PRIORITY_UPDATE
+    ];
+
+    #[test]
+    fn tap_finds_priority_updates_however_the_stream_is_split() {
+        for split in 1..CONTROL_STREAM.len() {
+            let priorities = Priorities::default();
+            let mut tap = PriorityTap::new(priorities.clone());
+            for chunk in CONTROL_STREAM.chunks(split) {
+                tap.inspect(chunk);
+            }
+            assert_eq!(priority_of(&priorities, 4), Some(priority(1, true)), "chunks of {}", split);
+        }
+    }
+
+    #[test]
+    fn tap_ignores_other_streams_and_oversized_updates() {
+        let priorities = Priorities::default();
+        // A QPACK encoder stream whose bytes happen to look like the update
+        let mut tap = PriorityTap::new(priorities.clone());
+        tap.inspect(&[&[0x02][..], &CONTROL_STREAM[1..]].concat());
+        assert_eq!(priority_of(&priorities, 4), None);
+
+        // Too long to be parsed, so it is skipped like any other frame
+        let mut tap = PriorityTap::new(priorities.clone());
+        let mut stream = vec![0x00, 0x80, 0x0f, 0x07, 0x00, 0x44, 0x01, 0x04];
+        stream.extend(std::iter::repeat_n(b' ', 0x400));
+        stream.extend_from_slice(&CONTROL_STREAM[10..]);
+        tap.inspect(&stream);
+        assert_eq!(priority_of(&priorities, 4), Some(priority(1, true)));
+        assert_eq!(priorities.streams.lock().unwrap().slots.len(), 1);
+    }
+
+    #[test]
+    fn updates_for_closed_or_foreign_streams_are_dropped() {
+        let priorities = Priorities::default();
+        priorities.open(0);
+        priorities.open(4);
+        priorities.close(0);
+        priorities.update_from_frame(0, priority(1, false));
+        // Server-initiated, unidirectional
+        priorities.update_from_frame(1, priority(1, false));
+        priorities.update_from_frame(2, priority(1, false));
+        priorities.update_from_frame(4, priority(1, false));
+        let streams = priorities.streams.lock().unwrap();
+        assert_eq!(streams.slots.keys().copied().collect::<Vec<_>>(), [4]);
+        assert!(streams.pending.is_empty());
+    }
+
+    #[test]
+    fn pending_updates_make_room_for_new_ones() {
+        let priorities = Priorities::default();
+        for stream in 0..MAX_PENDING_UPDATES as u64 + 10 {
+            priorities.update_from_frame(stream * 4, priority(1, false));
+        }
+        assert_eq!(priority_of(&priorities, 0), None);
+        assert_eq!(priority_of(&priorities, 9 * 4), None);
+        assert_eq!(priority_of(&priorities, 10 * 4), Some(priority(1, false)));
+        assert_eq!(priority_of(&priorities, (MAX_PENDING_UPDATES as u64 + 9) * 4), Some(priority(1, false)));
+
+        // An opened stream keeps its update and no longer counts as pending
+        priorities.open(10 * 4);
+        assert_eq!(priority_of(&priorities, 10 * 4), Some(priority(1, false)));
+        let streams = priorities.streams.lock().unwrap();
+        assert_eq!(streams.pending.len(), MAX_PENDING_UPDATES - 1);
+        assert_eq!(streams.slots.len(), MAX_PENDING_UPDATES);
+    }
+}
@@ -0,0 +1,69 @@
+use anyhow::bail;
+
+// Switch the process to the given unprivileged user and/or group. Called after the endpoint has
+// bound its (possibly privileged) port and before any traffic is accepted.
+#[cfg(unix)]
+pub fn drop_privileges(user: Option<&str>, group: Option<&str>) -> anyhow::Result<()> {
+    use anyhow::Context;
+    use nix::unistd::{Gid, Group, Uid, User, setgid, setgroups, setuid};
+
+    if user.is_none() && group.is_none() {
+        return Ok(());
+    }
+
+    let user = user
+        .map(|name| {
+            User::from_name(name)
+                .with_context(|| format!("failed to look up user '{}'", name))?
+                .with_context(|| format!("user '{}' does not exist", name))
+        })
+        .transpose()?;
+
+    // Without an explicit group, fall back to the user's primary group
+    let gid = match group {
+        Some(name) => Group::from_name(name)
+            .with_context(|| format!("failed to look up group '{}'", name))?
+            .with_context(|| format!("group '{}' does not exist", name))?
+            .gid,
+        None => user.as_ref().map(|u| u.gid).unwrap_or_else(Gid::current),
+    };
+
+    // Order matters: supplementary groups and the gid can only be changed while still root
+    setgroups(&[gid]).context("failed to drop supplementary groups (is the server running as root?)")?;
+    setgid(gid).with_context(|| format!("failed to switch to gid {}", gid))?;
+
+    if let Some(user) = user {
+        setuid(user.uid).with_context(|| format!("failed to switch to user '{}'", user.name))?;
+
+        // Make sure root cannot be regained
+        if !user.uid.is_root() && setuid(Uid::from_raw(0)).is_ok() {
+            bail!("privileges were not dropped: still able to regain root");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges(user: Option<&str>, group: Option<&str>) -> anyhow::Result<()> {
+    if user.is_some() || group.is_some() {
+        bail!("--user/--group are only supported on Unix");
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    // Resolving the names comes first, so these fail before anything about the process changes
+    #[test]
+    fn unknown_names_are_reported() {
+        let error = drop_privileges(Some("quic-demo-no-such-user"), None).unwrap_err();
+        assert_eq!(error.to_string(), "user 'quic-demo-no-such-user' does not exist");
+        let error = drop_privileges(None, Some("quic-demo-no-such-group")).unwrap_err();
+        assert_eq!(error.to_string(), "group 'quic-demo-no-such-group' does not exist");
+        let error = drop_privileges(Some("quic-demo-no-such-user"), Some("quic-demo-no-such-group")).unwrap_err();
+        assert_eq!(error.to_string(), "user 'quic-demo-no-such-user' does not exist");
+    }
+}
@@ -0,0 +1,41 @@
+// A favicon and quiet answers for the paths browsers request on their own, so testing the server
+// from a browser doesn't fill the log with requests nobody made. Off unless
+// `ServerBuilder::browser_probes` turns it on: an API server shouldn't grow a favicon.
+
+use http::StatusCode;
+use http::header::{CACHE_CONTROL, CONTENT_TYPE};
+
+use crate::response::Response;
+
+// 16x16, 32 bits per pixel
+const FAVICON: &[u8] = include_bytes!("../assets/favicon.ico");
+
+// Requested by browsers without being linked from anything: the favicon, Safari's home screen
+// icons, and Chrome's DevTools workspace discovery
+const PROBES: &[&str] = &[
+    "/favicon.ico",
+    "/apple-touch-icon.png",
+    "/apple-touch-icon-precomposed.png",
+    "/.well-known/appspecific/com.chrome.devtools.json",
+];
+
+/// Whether browsers request `path` on their own. With `browser_probes` on, such requests aren't
+/// logged.
+pub fn is_probe(path: &str) -> bool {
+    PROBES.contains(&path)
+}
+
+/// The favicon, or a plain 404 for the other probe paths, e.g. for a fallback handler that wants
+/// them too. `None` for anything else.
+pub fn respond(path: &str) -> Option<Response> {
+    if !is_probe(path) {
+        return None;
+    }
+    Some(match path {
+        "/favicon.ico" => Response::new(StatusCode::OK)
+            .header(CONTENT_TYPE, "image/x-icon")
+            .header(CACHE_CONTROL, "max-age=86400")
+            .body(FAVICON),
+        _ => Response::text(StatusCode::NOT_FOUND, "404 Not Found"),
+    })
+}
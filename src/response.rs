@@ -0,0 +1,489 @@
+// Responses as values. A handler builds a `Response` (or returns anything `IntoResponse`) and
+// `Response::send` does the framing: the header section, the body, then the end of the stream.
+// Bodies are either complete in memory, streamed from an `AsyncRead` or sent chunk by chunk as a
+// `Stream` of chunks produces them.
+
+use std::fmt;
+use std::io;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::stream::{BoxStream, Stream as FuturesStream, StreamExt};
+use h3::error::{Code, StreamError};
+use h3::quic;
+use h3::server::RequestStream;
+use http::header::{CONTENT_ENCODING, CONTENT_TYPE, HeaderName, HeaderValue, VARY};
+use http::{HeaderMap, StatusCode};
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::compress;
+
+/// A reasonable chunk size for [`send_from_reader`]: large enough to keep per-chunk overhead
+/// low, small enough that a slow reader doesn't pin much memory.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Why [`send_from_reader`] or [`send_chunks`] stopped before the end of the body.
+#[derive(Debug)]
+pub enum SendError {
+    /// Reading or producing the body failed; the response stream was reset
+    Read(io::Error),
+    Stream(StreamError),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Read(e) => write!(f, "failed to read response body: {}", e),
+            SendError::Stream(e) => write!(f, "failed to send response body: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+impl From<StreamError> for SendError {
+    fn from(e: StreamError) -> Self {
+        SendError::Stream(e)
+    }
+}
+
+/// Pump `reader` into the body of the response on `stream`, at most `chunk_size` bytes at a
+/// time, and finish the stream at EOF. The response headers must already be sent. Each chunk
+/// waits for flow control, so a slow client slows down reading rather than piling up memory.
+///
+/// If reading fails, the stream is reset with `H3_INTERNAL_ERROR`, so the client sees an error
+/// instead of a body that merely looks short. Returns the number of bytes sent.
+pub async fn send_from_reader<S, R>(
+    stream: &mut RequestStream<S, Bytes>,
+    mut reader: R,
+    chunk_size: usize,
+) -> Result<u64, SendError>
+where
+    S: quic::SendStream<Bytes>,
+    R: AsyncRead + Unpin,
+{
+    // A zero-sized buffer would read as EOF right away
+    let chunk_size = chunk_size.max(1);
+    let mut sent = 0;
+    loop {
+        let mut chunk = BytesMut::with_capacity(chunk_size);
+        match reader.read_buf(&mut chunk).await {
+            Ok(0) => break,
+            Ok(n) => {
+                stream.send_data(chunk.freeze()).await?;
+                sent += n as u64;
+            }
+            Err(e) => {
+                stream.stop_stream(Code::H3_INTERNAL_ERROR);
+                return Err(SendError::Read(e));
+            }
+        }
+    }
+    stream.finish().await?;
+    Ok(sent)
+}
+
+/// Send every chunk `chunks` yields as its own DATA frame, as soon as it is produced, and finish
+/// the stream when it ends. The response headers must already be sent. Like
+/// [`send_from_reader`], the next chunk is only asked for once flow control has taken the
+/// previous one, and an error from `chunks` resets the stream with `H3_INTERNAL_ERROR`. A client
+/// that goes away fails the pending send, and `chunks` is dropped without being polled again.
+/// Returns the number of bytes sent.
+pub async fn send_chunks<S>(
+    stream: &mut RequestStream<S, Bytes>,
+    mut chunks: impl FuturesStream<Item = io::Result<Bytes>> + Unpin,
+) -> Result<u64, SendError>
+where
+    S: quic::SendStream<Bytes>,
+{
+    let mut sent = 0;
+    while let Some(chunk) = chunks.next().await {
+        match chunk {
+            Ok(chunk) if chunk.is_empty() => {}
+            Ok(chunk) => {
+                sent += chunk.len() as u64;
+                stream.send_data(chunk).await?;
+            }
+            Err(e) => {
+                stream.stop_stream(Code::H3_INTERNAL_ERROR);
+                return Err(SendError::Read(e));
+            }
+        }
+    }
+    stream.finish().await?;
+    Ok(sent)
+}
+
+/// A status HTTP/3 can send as an interim response: 1xx, except 101 Switching Protocols, which
+/// HTTP/3 doesn't allow (RFC 9114, section 4.5).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Informational(StatusCode);
+
+impl Informational {
+    pub const CONTINUE: Informational = Informational(StatusCode::CONTINUE);
+
+    /// `None` unless `status` is one of those.
+    pub fn new(status: StatusCode) -> Option<Informational> {
+        (status.is_informational() && status != StatusCode::SWITCHING_PROTOCOLS).then_some(Informational(status))
+    }
+
+    pub fn status(self) -> StatusCode {
+        self.0
+    }
+}
+
+/// Send an interim response, e.g. `100 Continue`, ahead of the final one, which must still
+/// follow. h3 has no API of its own for this, but `send_response` writes a header section
+/// without checking the status or whether one was sent already, and HTTP/3 carries interim
+/// responses as exactly such HEADERS frames before the final one (RFC 9114, section 4.1).
+pub async fn send_informational<S>(stream: &mut RequestStream<S, Bytes>, status: Informational) -> Result<(), StreamError>
+where
+    S: quic::SendStream<Bytes>,
+{
+    let mut head = http::Response::new(());
+    *head.status_mut() = status.status();
+    stream.send_response(head).await
+}
+
+/// A complete response: status, headers and body.
+pub struct Response {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Payload,
+    // Set once `header` got something invalid and turned this into a 500, which stays as it is
+    invalid_header: bool,
+}
+
+enum Payload {
+    Full(Bytes),
+    Reader(Box<dyn AsyncRead + Send + Unpin>),
+    Chunks(BoxStream<'static, io::Result<Bytes>>),
+}
+
+impl Response {
+    /// A response with no headers and an empty body.
+    pub fn new(status: StatusCode) -> Response {
+        Response { status, headers: HeaderMap::new(), body: Payload::Full(Bytes::new()), invalid_header: false }
+    }
+
+    fn internal_error() -> Response {
+        let mut response = Response::text(StatusCode::INTERNAL_SERVER_ERROR, "500 Internal Server Error");
+        response.invalid_header = true;
+        response
+    }
+
+    /// A `text/plain` response.
+    pub fn text(status: StatusCode, body: impl Into<Bytes>) -> Response {
+        Response::new(status).header(CONTENT_TYPE, "text/plain").body(body)
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
+    }
+
+    /// Set a header, replacing earlier values. An invalid name or value, e.g. one built from
+    /// request data with a newline in it, is logged and turns the response into a plain `500
+    /// Internal Server Error` that later calls leave alone.
+    pub fn header<K, V>(mut self, name: K, value: V) -> Response
+    where
+        HeaderName: TryFrom<K>,
+        HeaderValue: TryFrom<V>,
+    {
+        if self.invalid_header {
+            return self;
+        }
+        let Ok(name) = HeaderName::try_from(name) else {
+            log::error!("Handler set an invalid header name, answering with 500");
+            return Response::internal_error();
+        };
+        let Ok(value) = HeaderValue::try_from(value) else {
+            log::error!("Handler set an invalid value for header {}, answering with 500", name);
+            return Response::internal_error();
+        };
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Send `body` in full.
+    pub fn body(mut self, body: impl Into<Bytes>) -> Response {
+        if !self.invalid_header {
+            self.body = Payload::Full(body.into());
+        }
+        self
+    }
+
+    /// Stream the body from `reader` with [`send_from_reader`], in [`DEFAULT_CHUNK_SIZE`] chunks.
+    pub fn stream(mut self, reader: impl AsyncRead + Send + Unpin + 'static) -> Response {
+        if !self.invalid_header {
+            self.body = Payload::Reader(Box::new(reader));
+        }
+        self
+    }
+
+    /// Send each chunk `chunks` yields as soon as it is produced, with [`send_chunks`].
+    pub fn chunks(mut self, chunks: impl FuturesStream<Item = io::Result<Bytes>> + Send + 'static) -> Response {
+        if !self.invalid_header {
+            self.body = Payload::Chunks(chunks.boxed());
+        }
+        self
+    }
+
+    /// Add the headers in `defaults` this response doesn't set itself, all values of a name
+    /// together; a header the response has keeps its own values.
+    pub fn default_headers(mut self, defaults: &HeaderMap) -> Response {
+        for name in defaults.keys() {
+            if !self.headers.contains_key(name) {
+                for value in defaults.get_all(name) {
+                    self.headers.append(name.clone(), value.clone());
+                }
+            }
+        }
+        self
+    }
+
+    /// Compress a body held in memory with the best coding `request_headers` accept (see
+    /// [`compress::compress_body`]). Adds `Vary: Accept-Encoding` either way, since the choice
+    /// depends on it. Streamed and already encoded bodies are left alone.
+    pub fn compress(mut self, request_headers: &HeaderMap) -> Response {
+        if self.headers.contains_key(CONTENT_ENCODING) {
+            return self;
+        }
+        let Payload::Full(body) = self.body else {
+            return self;
+        };
+        let (encoding, body) = compress::compress_body(request_headers, body);
+        self.body = Payload::Full(body);
+        self.headers.insert(VARY, HeaderValue::from_static("Accept-Encoding"));
+        if let Some(encoding) = encoding {
+            self.headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+        }
+        self
+    }
+
+    /// Send the whole response on `stream` and finish it. Returns the number of body bytes
+    /// sent.
+    pub async fn send<S>(self, stream: &mut RequestStream<S, Bytes>) -> Result<u64, SendError>
+    where
+        S: quic::SendStream<Bytes>,
+    {
+        let mut head = http::Response::new(());
+        *head.status_mut() = self.status;
+        *head.headers_mut() = self.headers;
+        stream.send_response(head).await?;
+
+        #[cfg(feature = "trace-bodies")]
+        let body = {
+            let mut trace = crate::trace_bodies::Capture::new("Response", stream.send_id());
+            match self.body {
+                Payload::Full(body) => {
+                    trace.add(&body);
+                    Payload::Full(body)
+                }
+                // Read in the chunks send_from_reader would use, so the traced bytes are the sent ones
+                Payload::Reader(reader) => Payload::Chunks(
+                    tokio_util::io::ReaderStream::with_capacity(reader, DEFAULT_CHUNK_SIZE)
+                        .inspect(move |chunk| {
+                            if let Ok(chunk) = chunk {
+                                trace.add(chunk);
+                            }
+                        })
+                        .boxed(),
+                ),
+                Payload::Chunks(chunks) => Payload::Chunks(
+                    chunks
+                        .inspect(move |chunk| {
+                            if let Ok(chunk) = chunk {
+                                trace.add(chunk);
+                            }
+                        })
+                        .boxed(),
+                ),
+            }
+        };
+        #[cfg(not(feature = "trace-bodies"))]
+        let body = self.body;
+
+        match body {
+            Payload::Full(body) => {
+                let len = body.len() as u64;
+                if !body.is_empty() {
+                    stream.send_data(body).await?;
+                }
+                stream.finish().await?;
+                Ok(len)
+            }
+            Payload::Reader(reader) => send_from_reader(stream, reader, DEFAULT_CHUNK_SIZE).await,
+            Payload::Chunks(chunks) => send_chunks(stream, chunks).await,
+        }
+    }
+}
+
+/// Anything a handler can answer with.
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+/// `200 OK` with a `text/plain` body.
+impl IntoResponse for &'static str {
+    fn into_response(self) -> Response {
+        Response::text(StatusCode::OK, self)
+    }
+}
+
+/// `200 OK` with a `text/plain` body.
+impl IntoResponse for String {
+    fn into_response(self) -> Response {
+        Response::text(StatusCode::OK, self)
+    }
+}
+
+/// `200 OK` with an `application/octet-stream` body.
+impl IntoResponse for Bytes {
+    fn into_response(self) -> Response {
+        Response::new(StatusCode::OK).header(CONTENT_TYPE, "application/octet-stream").body(self)
+    }
+}
+
+/// Any response with a different status.
+impl<T: IntoResponse> IntoResponse for (StatusCode, T) {
+    fn into_response(self) -> Response {
+        let mut response = self.1.into_response();
+        if !response.invalid_header {
+            response.status = self.0;
+        }
+        response
+    }
+}
+
+/// The response for `Ok`, or the error's for `Err`. With [`HandlerError`] as the error type,
+/// handlers can use `?` on anything that converts into an [`anyhow::Error`] and the client gets a
+/// 500. An error only ever replaces the whole response: once the header section is out, a body
+/// that fails resets the stream with `H3_INTERNAL_ERROR` instead (see [`send_chunks`]).
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self) -> Response {
+        match self {
+            Ok(response) => response.into_response(),
+            Err(e) => e.into_response(),
+        }
+    }
+}
+
+/// A handler's failure: logged, and answered with a plain-text status, `500 Internal Server
+/// Error` unless mapped to another one with [`HandlerError::status`]. The error itself is not
+/// sent to the client.
+#[derive(Debug)]
+pub struct HandlerError {
+    status: StatusCode,
+    error: anyhow::Error,
+}
+
+impl HandlerError {
+    /// Answer with `status` instead, e.g. 503 for a backend that is down.
+    pub fn status(mut self, status: StatusCode) -> HandlerError {
+        self.status = status;
+        self
+    }
+}
+
+impl<E: Into<anyhow::Error>> From<E> for HandlerError {
+    fn from(e: E) -> Self {
+        HandlerError { status: StatusCode::INTERNAL_SERVER_ERROR, error: e.into() }
+    }
+}
+
+impl IntoResponse for HandlerError {
+    fn into_response(self) -> Response {
+        log::error!("Handler failed, answering with {}: {:#}", self.status, self.error);
+        Response::text(self.status, self.status.to_string())
+    }
+}
+
+/// `200 OK` with `value` serialized as an `application/json` body, or a 500 if it can't be
+/// serialized.
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        match serde_json::to_vec(&self.0) {
+            Ok(json) => Response::new(StatusCode::OK).header(CONTENT_TYPE, "application/json").body(json),
+            Err(e) => {
+                log::error!("Failed to serialize JSON response: {}", e);
+                Response::text(StatusCode::INTERNAL_SERVER_ERROR, "500 Internal Server Error")
+            }
+        }
+    }
+}
+
+/// `200 OK` with an `application/octet-stream` body streamed from the reader.
+pub struct Stream<R>(pub R);
+
+impl<R: AsyncRead + Send + Unpin + 'static> IntoResponse for Stream<R> {
+    fn into_response(self) -> Response {
+        Response::new(StatusCode::OK).header(CONTENT_TYPE, "application/octet-stream").stream(self.0)
+    }
+}
+
+/// `200 OK` with the values `records` yields as newline-delimited JSON (`application/x-ndjson`),
+/// one line and one DATA frame per value, sent as they are produced, e.g. rows from a database
+/// cursor. Nothing is buffered beyond the line being sent (see [`send_chunks`]); a value that
+/// can't be serialized resets the stream.
+pub struct NdJson<S>(pub S);
+
+impl<S, T> IntoResponse for NdJson<S>
+where
+    S: FuturesStream<Item = T> + Send + 'static,
+    T: Serialize,
+{
+    fn into_response(self) -> Response {
+        let lines = self.0.map(|record| {
+            let mut line = BytesMut::new().writer();
+            serde_json::to_writer(&mut line, &record).map_err(io::Error::other)?;
+            let mut line = line.into_inner();
+            line.put_u8(b'\n');
+            Ok(line.freeze())
+        });
+        Response::new(StatusCode::OK).header(CONTENT_TYPE, "application/x-ndjson").chunks(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_interim_statuses_are_informational() {
+        assert_eq!(Informational::new(StatusCode::CONTINUE), Some(Informational::CONTINUE));
+        assert!(Informational::new(StatusCode::from_u16(103).unwrap()).is_some());
+        assert_eq!(Informational::new(StatusCode::SWITCHING_PROTOCOLS), None);
+        assert_eq!(Informational::new(StatusCode::OK), None);
+    }
+
+    #[test]
+    fn an_invalid_header_makes_a_500() {
+        let response = Response::new(StatusCode::OK).header("x-name", "one\ntwo").header("x-later", "set").body("hello");
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!response.headers().contains_key("x-later"));
+        assert!(matches!(&response.body, Payload::Full(body) if body == "500 Internal Server Error"));
+
+        let response = (StatusCode::CREATED, Response::new(StatusCode::OK).header("bad name", "value")).into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let response = Response::new(StatusCode::OK).header("x-name", "value");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-name"], "value");
+    }
+}
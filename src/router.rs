@@ -0,0 +1,243 @@
+// Routes registered by library users: handlers keyed by exact path, grouped per virtual host on
+// the server (`ServerBuilder::host`). The server binary's own routes live in `server.rs`.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use http::uri::{PathAndQuery, Uri};
+use http::{Request, StatusCode};
+
+use crate::response::{IntoResponse, Response};
+
+pub(crate) type Handler = Arc<dyn Fn(Request<()>) -> BoxFuture<'static, Response> + Send + Sync>;
+
+/// Handlers for exact request paths, plus an optional fallback for every other path.
+#[derive(Default, Clone)]
+pub struct Router {
+    routes: HashMap<String, Handler>,
+    fallback: Option<Handler>,
+    timeouts: HashMap<String, Duration>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Answer requests for `path` (without the query) with `handler`, replacing any handler
+    /// registered for it before. Servers put the request's
+    /// [`ConnInfo`](crate::server::ConnInfo) into its extensions, for handlers that need the
+    /// client's address or the raw connection, behind an `Arc`:
+    /// `req.extensions().get::<Arc<ConnInfo>>()`.
+    pub fn route<F, Fut, R>(mut self, path: impl Into<String>, handler: F) -> Router
+    where
+        F: Fn(Request<()>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = R> + Send + 'static,
+        R: IntoResponse,
+    {
+        self.routes.insert(path.into(), handler_from(handler));
+        self
+    }
+
+    /// Answer requests whose path has no route with `handler`, e.g. to serve a single-page app's
+    /// index, a JSON 404 or a proxy. Without a fallback such requests get 404 Not Found.
+    pub fn fallback<F, Fut, R>(mut self, handler: F) -> Router
+    where
+        F: Fn(Request<()>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = R> + Send + 'static,
+        R: IntoResponse,
+    {
+        self.fallback = Some(handler_from(handler));
+        self
+    }
+
+    /// Give requests for `path` `timeout` to be answered instead of the server's request
+    /// timeout ([`ServerBuilder::request_timeout`](crate::server::ServerBuilder::request_timeout)),
+    /// e.g. a short one for a health check or a long one for generating a report. Requests that
+    /// take longer have their stream reset. The path doesn't need a route of its own, so
+    /// fallback paths can be given one too. Only servers apply it; [`Router::handle`] doesn't.
+    pub fn route_timeout(mut self, path: impl Into<String>, timeout: Duration) -> Router {
+        self.timeouts.insert(path.into(), timeout);
+        self
+    }
+
+    pub(crate) fn timeout_for(&self, path: &str) -> Option<Duration> {
+        self.timeouts.get(path).copied()
+    }
+
+    /// The response of the handler for `req`'s path or else the fallback, or `None` if neither
+    /// exists. The path is normalized first (see [`normalize_path`]); one that can't be gets 400
+    /// Bad Request without reaching a handler.
+    pub async fn handle(&self, mut req: Request<()>) -> Option<Response> {
+        if let Err(reason) = normalize_request(&mut req) {
+            return Some(Response::text(StatusCode::BAD_REQUEST, format!("400 Bad Request: {}", reason)));
+        }
+        let handler = self.routes.get(req.uri().path()).or(self.fallback.as_ref())?;
+        Some(handler(req).await)
+    }
+}
+
+pub(crate) fn handler_from<F, Fut, R>(handler: F) -> Handler
+where
+    F: Fn(Request<()>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = R> + Send + 'static,
+    R: IntoResponse,
+{
+    Arc::new(move |req| {
+        let response = handler(req);
+        Box::pin(async move { response.await.into_response() })
+    })
+}
+
+/// `path` in the form routes are matched in: an empty path is `/`, and `.` and `..` segments
+/// (also percent-encoded, `%2e`) are resolved as in RFC 3986, section 5.2.4, so `/a/./b/../c` is
+/// `/a/c`. Fails for a path that doesn't start with `/` or whose `..` segments climb above the
+/// root, which should get 400 Bad Request.
+pub fn normalize_path(path: &str) -> Result<String, &'static str> {
+    if path.is_empty() {
+        return Ok("/".to_string());
+    }
+    let Some(rest) = path.strip_prefix('/') else {
+        return Err("path must start with /");
+    };
+    let mut segments: Vec<&str> = Vec::new();
+    let mut rest = rest.split('/').peekable();
+    while let Some(segment) = rest.next() {
+        // A trailing dot segment still names a directory: `/a/..` is `/`, `/a/.` is `/a/`
+        let last = rest.peek().is_none();
+        if is_dot(segment, 1) {
+            if last {
+                segments.push("");
+            }
+        } else if is_dot(segment, 2) {
+            segments.pop().ok_or("path climbs above the root")?;
+            if last {
+                segments.push("");
+            }
+        } else {
+            segments.push(segment);
+        }
+    }
+    Ok(format!("/{}", segments.join("/")))
+}
+
+// Whether `segment` is `count` dots, any of them written as `%2e`
+fn is_dot(mut segment: &str, count: usize) -> bool {
+    for _ in 0..count {
+        segment = match segment.strip_prefix('.') {
+            Some(rest) => rest,
+            None if segment.get(..3).is_some_and(|dot| dot.eq_ignore_ascii_case("%2e")) => &segment[3..],
+            None => return false,
+        };
+    }
+    segment.is_empty()
+}
+
+// Undoes percent-encoding in a path or part of one; `None` for a broken `%` escape or bytes that
+// aren't UTF-8
+pub(crate) fn percent_decode(text: &str) -> Option<String> {
+    let mut out = Vec::with_capacity(text.len());
+    let mut bytes = text.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            // from_str_radix would take a sign, reading `%+1` as 0x01
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            out.push(byte);
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Replace the path of `req`'s URI with its [`normalize_path`] form, leaving the query alone.
+/// CONNECT requests, which have no path, and the `*` of `OPTIONS *` are left as they are.
+pub fn normalize_request<T>(req: &mut Request<T>) -> Result<(), &'static str> {
+    let path = req.uri().path();
+    if req.method() == http::Method::CONNECT || path == "*" {
+        return Ok(());
+    }
+    let normalized = normalize_path(path)?;
+    if normalized == path {
+        return Ok(());
+    }
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{}?{}", normalized, query),
+        None => normalized,
+    };
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = Some(PathAndQuery::try_from(path_and_query).map_err(|_| "invalid path")?);
+    *req.uri_mut() = Uri::from_parts(parts).map_err(|_| "invalid path")?;
+    Ok(())
+}
+
+/// The host a request is for: `:authority` without the port, or the Host header if there is no
+/// `:authority`. Lowercased and without a trailing dot, so `WWW.Example.com.` and
+/// `www.example.com:4433` are the same host.
+pub fn host_of<T>(req: &Request<T>) -> Option<String> {
+    let host = match req.uri().authority() {
+        Some(authority) => authority.host().to_string(),
+        None => {
+            let value = req.headers().get(http::header::HOST)?.to_str().ok()?;
+            value.parse::<http::uri::Authority>().ok()?.host().to_string()
+        }
+    };
+    Some(normalize_host(&host))
+}
+
+pub(crate) fn normalize_host(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dot_segments_are_resolved() {
+        for (path, normalized) in [
+            ("", Ok("/")),
+            ("/", Ok("/")),
+            ("/a/b", Ok("/a/b")),
+            ("/a/./b", Ok("/a/b")),
+            ("/a/%2e%2E/b", Ok("/b")),
+            ("/a/.%2e/b", Ok("/b")),
+            ("/a/.", Ok("/a/")),
+            ("/a/%2E", Ok("/a/")),
+            ("/a/b/..", Ok("/a/")),
+            ("/a/...", Ok("/a/...")),
+            ("/a/%2", Ok("/a/%2")),
+            ("/a/%2ex", Ok("/a/%2ex")),
+            ("/..", Err("path climbs above the root")),
+            ("/a/../..", Err("path climbs above the root")),
+            ("a/b", Err("path must start with /")),
+        ] {
+            assert_eq!(normalize_path(path).as_deref(), normalized.as_deref(), "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn escapes_are_decoded() {
+        for (text, decoded) in [
+            ("", Some("")),
+            ("plain", Some("plain")),
+            ("a%2Fb", Some("a/b")),
+            ("%e2%82%AC", Some("\u{20ac}")),
+            ("%", None),
+            ("%2", None),
+            ("100%", None),
+            ("%zz", None),
+            ("%+1", None),
+            ("%ff", None),
+            ("%c3", None),
+        ] {
+            assert_eq!(percent_decode(text).as_deref(), decoded, "{:?}", text);
+        }
+    }
+}
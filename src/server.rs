@@ -0,0 +1,2322 @@
+// HTTP/3 server: endpoint setup, the accept loop and per-connection request handling.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, bail};
+use bytes::Bytes;
+use futures::StreamExt;
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use h3::error::StreamError;
+use h3::server::{RequestResolver, RequestStream};
+use h3_quinn::quinn;
+use h3::quic;
+use http::header::{ACCEPT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, LOCATION, RETRY_AFTER};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode};
+use quinn::congestion::CubicConfig;
+use quinn::{ConnectionIdGenerator, Endpoint, Incoming, ServerConfig, TransportConfig};
+use rustls::CipherSuite;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use tokio::io::AsyncReadExt;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, broadcast};
+use tokio::task::{JoinError, JoinHandle, JoinSet};
+use tokio_util::sync::CancellationToken;
+
+use crate::body::{self, Body, BodyError};
+use crate::cancel::Cancellations;
+use crate::client_cert::{self, PeerCertificate};
+use crate::compress::Encoding;
+use crate::error_code::{self, Code};
+use crate::events::{Emitter, Event};
+use crate::fingerprint::Fingerprint;
+use crate::handshake::HandshakeFailure;
+use crate::datagram::{self, DatagramError};
+use crate::masque::{self, Datagrams};
+use crate::metrics::{DEFAULT_BODY_SIZE_BUCKETS, DEFAULT_REQUEST_RATE_WINDOWS, MAX_REQUEST_RATE_WINDOW, Metrics, Sampler, ServerStats};
+use crate::priority::{self, Priorities, Priority, StreamResets};
+use crate::response::{self, Informational, IntoResponse, Json, NdJson, Response, Stream};
+use crate::router::{self, Router};
+use crate::settings::H3Settings;
+use crate::simulate::{self, NetworkConditions};
+use crate::socket::{self, CidGenerator, SocketOptions};
+use crate::static_files::DirectoryListing;
+use crate::{generate, kv, negotiate, probes, static_files, uni};
+#[cfg(feature = "templates")]
+use crate::template;
+
+/// Names the self-signed certificate is valid for unless configured otherwise.
+pub const DEFAULT_SUBJECT_ALT_NAMES: &[&str] = &["localhost", "127.0.0.1"];
+
+/// ALPN protocol of HTTP/3, always offered first.
+pub const H3_ALPN: &[u8] = b"h3";
+
+/// Most bytes `/debug/stream` sends for one request (10 GB); larger `?bytes=` or `?mb=` values
+/// are cut down to it.
+pub const DEBUG_STREAM_MAX: u64 = 10_000_000_000;
+
+/// Longest `/debug/slow` pretends to work for (60 seconds); larger `?secs=` values are cut down
+/// to it.
+pub const DEBUG_SLOW_MAX_SECS: u64 = 60;
+
+/// Most records `/debug/ndjson` sends (1000, so 100 seconds' worth); larger `?records=` values
+/// are cut down to it.
+pub const DEBUG_NDJSON_MAX_RECORDS: u64 = 1000;
+
+/// The control stream plus the QPACK encoder and decoder streams every HTTP/3 peer opens.
+pub const MIN_UNI_STREAMS: u32 = 3;
+
+// How often connections are checked for a new peer address
+const MIGRATION_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Default of [`ServerBuilder::max_handshakes`].
+pub const DEFAULT_MAX_HANDSHAKES: usize = 256;
+
+/// Longest connection ID QUIC allows (RFC 9000 §17.2).
+pub const MAX_CID_LENGTH: usize = 20;
+
+/// Check that `len` is a connection ID length QUIC allows, 0 to [`MAX_CID_LENGTH`] bytes.
+pub fn check_cid_length(len: usize) -> anyhow::Result<usize> {
+    if len > MAX_CID_LENGTH {
+        bail!("connection ID length must be at most {} bytes, got {}", MAX_CID_LENGTH, len);
+    }
+    Ok(len)
+}
+
+/// Windows of the request rate gauge must be whole seconds, from 1s to an hour.
+pub fn check_request_rate_windows(windows: &[Duration]) -> anyhow::Result<()> {
+    if windows.is_empty() {
+        bail!("request rate windows must not be empty");
+    }
+    for window in windows {
+        if window.subsec_nanos() != 0 || *window < Duration::from_secs(1) || *window > MAX_REQUEST_RATE_WINDOW {
+            bail!("request rate windows must be whole seconds from 1s to 1h, got {:?}", window);
+        }
+    }
+    Ok(())
+}
+
+/// Parse a route timeout given as `PATH=DURATION`, e.g. `/health=500ms`.
+pub fn parse_route_timeout(text: &str) -> anyhow::Result<(String, Duration)> {
+    let Some((path, timeout)) = text.split_once('=') else {
+        bail!("expected PATH=DURATION, got {:?}", text);
+    };
+    if !path.starts_with('/') {
+        bail!("route timeout path must start with '/', got {:?}", path);
+    }
+    Ok((path.to_string(), humantime::parse_duration(timeout)?))
+}
+
+/// Parse a redirect given as `PATH=LOCATION`, e.g. `/docs=https://example.com/docs`.
+pub fn parse_redirect(text: &str) -> anyhow::Result<(String, HeaderValue)> {
+    let Some((path, location)) = text.split_once('=') else {
+        bail!("expected PATH=LOCATION, got {:?}", text);
+    };
+    if !path.starts_with('/') {
+        bail!("redirect path must start with '/', got {:?}", path);
+    }
+    let location = HeaderValue::from_str(location).with_context(|| format!("invalid redirect location {:?}", location))?;
+    Ok((path.to_string(), location))
+}
+
+/// Parse a default response header given as `NAME: VALUE`, e.g. `X-Content-Type-Options: nosniff`.
+pub fn parse_response_header(text: &str) -> anyhow::Result<(HeaderName, HeaderValue)> {
+    let Some((name, value)) = text.split_once(':') else {
+        bail!("expected NAME: VALUE, got {:?}", text);
+    };
+    let name = HeaderName::from_bytes(name.trim().as_bytes()).with_context(|| format!("invalid header name {:?}", name.trim()))?;
+    let value = HeaderValue::from_str(value.trim()).with_context(|| format!("invalid value for header {}", name))?;
+    Ok((name, value))
+}
+
+/// Accepted initial RTT estimates: anything above zero, up to well past a geostationary
+/// satellite hop (about 600ms) or several of them.
+pub const INITIAL_RTT_RANGE: RangeInclusive<Duration> = Duration::from_millis(1)..=Duration::from_secs(10);
+
+/// Accepted initial congestion windows, in bytes: from RFC 9002's minimum window of two
+/// 1200-byte datagrams (section 7.2) up to 16 MiB, past which the first flight is a burst few
+/// paths absorb without loss.
+pub const INITIAL_CWND_RANGE: RangeInclusive<u64> = 2400..=16 * 1024 * 1024;
+
+/// Check that `bytes` lies within [`INITIAL_CWND_RANGE`].
+pub fn check_initial_cwnd(bytes: u64) -> anyhow::Result<u64> {
+    if !INITIAL_CWND_RANGE.contains(&bytes) {
+        bail!(
+            "initial congestion window must be between {} and {} bytes, got {}",
+            INITIAL_CWND_RANGE.start(),
+            INITIAL_CWND_RANGE.end(),
+            bytes
+        );
+    }
+    Ok(bytes)
+}
+
+/// Check that `rtt` lies within [`INITIAL_RTT_RANGE`].
+pub fn check_initial_rtt(rtt: Duration) -> anyhow::Result<Duration> {
+    if !INITIAL_RTT_RANGE.contains(&rtt) {
+        bail!(
+            "initial RTT must be between {:?} and {:?}, got {:?}",
+            INITIAL_RTT_RANGE.start(),
+            INITIAL_RTT_RANGE.end(),
+            rtt
+        );
+    }
+    Ok(rtt)
+}
+
+// Pages served with the `templates` feature
+#[cfg(feature = "templates")]
+static TEMPLATES: std::sync::LazyLock<minijinja::Environment<'static>> = std::sync::LazyLock::new(|| {
+    let mut env = minijinja::Environment::new();
+    env.add_template("hello.html", include_str!("../templates/hello.html")).unwrap();
+    env
+});
+
+/// What to do with new requests while a connection is at its in-flight limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverloadPolicy {
+    /// Wait for an in-flight request to finish
+    Queue,
+    /// Answer immediately with 503 Service Unavailable and a Retry-After header
+    Reject,
+}
+
+/// Details of the connection a request arrived on.
+#[derive(Debug, Clone)]
+pub struct ConnInfo {
+    /// The client's address when the request arrived, so after a migration it is the new one
+    pub remote_address: SocketAddr,
+    /// SNI sent by the client, if any
+    pub server_name: Option<String>,
+    /// Negotiated application protocol
+    pub alpn: Option<String>,
+    /// The client's certificate chain, leaf first, when it authenticated with one (see
+    /// [`ServerBuilder::client_ca`]); empty otherwise
+    pub peer_certificates: Vec<CertificateDer<'static>>,
+    /// The leaf of `peer_certificates`, parsed
+    pub client_certificate: Option<PeerCertificate>,
+    /// The QUIC connection itself, e.g. to open custom streams with [`uni::send`] or send
+    /// datagrams. An escape hatch: h3 owns this connection, so don't accept streams on it, open
+    /// bidirectional streams (HTTP/3 forbids server-initiated ones), read datagrams while
+    /// CONNECT-UDP tunnels are open, or close it, and don't keep clones past the request. See
+    /// the README for why.
+    pub connection: quinn::Connection,
+    /// Send priorities of this connection's responses; a request's own is applied before its
+    /// handler runs
+    pub priorities: Priorities,
+    // From `ServerBuilder::max_datagram_frame_size`
+    datagram_frame_limit: Option<usize>,
+}
+
+impl ConnInfo {
+    /// Largest datagram that can be sent to the peer right now, or `None` if it doesn't accept
+    /// datagrams. Looked up afresh on every call: it follows the path MTU estimate, and is never
+    /// more than [`ServerBuilder::max_datagram_frame_size`].
+    pub fn max_datagram_size(&self) -> Option<usize> {
+        datagram::max_size(&self.connection, self.datagram_frame_limit)
+    }
+
+    /// Send `data` as one QUIC datagram, failing with [`DatagramError::TooLarge`] rather than
+    /// dropping it if it is over [`ConnInfo::max_datagram_size`].
+    pub fn send_datagram(&self, data: Bytes) -> Result<(), DatagramError> {
+        datagram::send(&self.connection, self.datagram_frame_limit, data)
+    }
+}
+
+/// A connection that completed its handshake, passed to [`ServerBuilder::on_connect`].
+///
+/// quinn doesn't tell a server whether the client resumed a TLS session or sent 0-RTT data, so
+/// there is no resumption status here.
+#[derive(Debug, Clone)]
+pub struct ConnectionOpened {
+    pub remote_address: SocketAddr,
+    /// SNI sent by the client, if any
+    pub server_name: Option<String>,
+    /// Negotiated application protocol: `h3`, or one added with [`ServerBuilder::protocol`]
+    pub alpn: Option<String>,
+    pub connection: quinn::Connection,
+}
+
+/// A connection the server is done with, passed to [`ServerBuilder::on_close`].
+#[derive(Debug, Clone)]
+pub struct ConnectionClosed {
+    pub remote_address: SocketAddr,
+    pub alpn: Option<String>,
+    /// Time since the handshake completed
+    pub duration: Duration,
+    /// HTTP/3 requests accepted on the connection (always 0 for custom protocols)
+    pub requests: u64,
+    /// Most request streams that were active on the connection at once
+    pub peak_streams: usize,
+    /// Why the connection closed, or `None` if it was still open and is closed by the server
+    /// now that every request has finished (e.g. after GOAWAY)
+    pub reason: Option<quinn::ConnectionError>,
+    /// Packet, byte and path statistics at the end of the connection
+    pub stats: quinn::ConnectionStats,
+}
+
+/// Outcome of the authorizer for one request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Authorization {
+    Allow,
+    /// Refuse the request with the given status, or 403 Forbidden if `None`
+    Deny(Option<StatusCode>),
+}
+
+type Authorizer = Arc<dyn Fn(&Request<()>, &ConnInfo) -> BoxFuture<'static, Authorization> + Send + Sync>;
+
+type ProtocolHandler = Arc<dyn Fn(quinn::Connection) -> BoxFuture<'static, ()> + Send + Sync>;
+
+type Hook<T> = Arc<dyn Fn(&T) + Send + Sync>;
+
+// Everything a connection task needs to know, shared between all of them
+struct Options {
+    listen: SocketAddr,
+    subject_alt_names: Vec<String>,
+    // PEM files of the certificate chain and its key; a self-signed certificate without them
+    certificate: Option<(PathBuf, PathBuf)>,
+    max_inflight_per_connection: usize,
+    max_inflight_requests: Option<usize>,
+    // Created by `bind` from `max_inflight_requests`
+    inflight_budget: Option<Arc<Semaphore>>,
+    queue_timeout: Duration,
+    overload: OverloadPolicy,
+    retry_after: Duration,
+    max_requests_per_connection: Option<u64>,
+    max_connection_age: Option<Duration>,
+    error_budget: Option<u32>,
+    max_concurrent_bidi_streams: u32,
+    max_concurrent_uni_streams: u32,
+    datagram_receive_buffer: Option<usize>,
+    datagram_send_buffer: Option<usize>,
+    max_datagram_frame_size: Option<usize>,
+    spin_bit: bool,
+    initial_rtt: Option<Duration>,
+    initial_cwnd: Option<u64>,
+    early_data: bool,
+    max_handshakes: usize,
+    handshake_timeout: Duration,
+    header_timeout: Duration,
+    body_read_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    // Overrides of `request_timeout` for the default host's paths
+    route_timeouts: HashMap<String, Duration>,
+    // Default host paths answered with a redirect to the value
+    redirects: HashMap<String, HeaderValue>,
+    shutdown_timeout: Duration,
+    shutdown_code: Code,
+    authorizer: Option<Authorizer>,
+    on_connect: Option<Hook<ConnectionOpened>>,
+    on_close: Option<Hook<ConnectionClosed>>,
+    // Offered after h3, in the order they were added
+    protocols: Vec<(Vec<u8>, ProtocolHandler)>,
+    // Keyed by normalized host name
+    hosts: HashMap<String, Router>,
+    strict_hosts: bool,
+    // Unmatched paths of the default host
+    fallback: Option<router::Handler>,
+    stats_interval: Duration,
+    access_log: bool,
+    slow_request_threshold: Option<Duration>,
+    // Added to every response that doesn't set them itself
+    response_headers: HeaderMap,
+    static_dir: Option<PathBuf>,
+    directory_listing: DirectoryListing,
+    browser_probes: bool,
+    generate_route: bool,
+    debug_routes: bool,
+    client_ca: Option<PathBuf>,
+    max_body_size: Option<u64>,
+    connect_udp: bool,
+    connect_udp_allow_private: bool,
+    h3_settings: H3Settings,
+    cid_length: Option<usize>,
+    cid_generator: Option<CidGenerator>,
+    shards: usize,
+    migration: bool,
+    gso: bool,
+    network: NetworkConditions,
+    metrics: Arc<Metrics>,
+    body_size_buckets: Option<Vec<u64>>,
+    events: Emitter,
+    request_rate_windows: Option<Vec<Duration>>,
+    sampler: Sampler,
+    kv: kv::Store,
+}
+
+impl Options {
+    fn alpn_protocols(&self) -> Vec<Vec<u8>> {
+        std::iter::once(H3_ALPN.to_vec()).chain(self.protocols.iter().map(|(alpn, _)| alpn.clone())).collect()
+    }
+}
+
+pub struct ServerBuilder {
+    options: Options,
+}
+
+impl ServerBuilder {
+    /// UDP address to listen on (default 127.0.0.1:4433).
+    pub fn listen(mut self, addr: SocketAddr) -> Self {
+        self.options.listen = addr;
+        self
+    }
+
+    /// DNS names and IP addresses the self-signed certificate is issued for
+    /// (default [`DEFAULT_SUBJECT_ALT_NAMES`]). Strings that parse as IPs become IP SANs.
+    pub fn subject_alt_names(mut self, names: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.options.subject_alt_names = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Serve the certificate chain in the first PEM file, leaf first, with the private key in
+    /// the second, instead of a self-signed certificate (the default, `None`). Both are read by
+    /// [`ServerBuilder::bind`]; [`ServerBuilder::subject_alt_names`] doesn't apply to them.
+    pub fn certificate(mut self, files: Option<(PathBuf, PathBuf)>) -> Self {
+        self.options.certificate = files;
+        self
+    }
+
+    /// Maximum number of requests a single connection may have in progress at once.
+    /// This is separate from the QUIC stream limit.
+    pub fn max_inflight_per_connection(mut self, max: usize) -> Self {
+        self.options.max_inflight_per_connection = max.max(1);
+        self
+    }
+
+    /// Maximum number of requests the whole server has in progress at once, across all
+    /// connections. `None` (the default) leaves only the per-connection limit.
+    pub fn max_inflight_requests(mut self, max: Option<usize>) -> Self {
+        self.options.max_inflight_requests = max.map(|max| max.max(1));
+        self
+    }
+
+    /// How long a request waits for a server-wide slot under [`OverloadPolicy::Queue`] before
+    /// it gets 503 Service Unavailable (default 5s).
+    pub fn queue_timeout(mut self, timeout: Duration) -> Self {
+        self.options.queue_timeout = timeout;
+        self
+    }
+
+    /// What happens to requests beyond the in-flight limits.
+    pub fn overload_policy(mut self, policy: OverloadPolicy) -> Self {
+        self.options.overload = policy;
+        self
+    }
+
+    /// Retry-After sent with 503 responses under [`OverloadPolicy::Reject`].
+    pub fn retry_after(mut self, retry_after: Duration) -> Self {
+        self.options.retry_after = retry_after;
+        self
+    }
+
+    /// Send GOAWAY and close a connection once it has carried this many requests.
+    pub fn max_requests_per_connection(mut self, max: Option<u64>) -> Self {
+        self.options.max_requests_per_connection = max;
+        self
+    }
+
+    /// Send GOAWAY to a connection this long after its handshake, however busy it is, and close
+    /// it once its requests have finished. Requests still running after a further shutdown
+    /// timeout are cut off, so a never-ending response can't keep the connection open.
+    pub fn max_connection_age(mut self, age: Option<Duration>) -> Self {
+        self.options.max_connection_age = age;
+        self
+    }
+
+    /// Close connections with `H3_EXCESSIVE_LOAD` once their requests have gone wrong this many
+    /// times more than they went right. Each request the peer botches adds one: a malformed or
+    /// invalid header section, headers that don't arrive within the header timeout, or a
+    /// response stream the client stopped. Each other request takes one off, so healthy
+    /// connections with the odd cancelled request never get near it, while a peer doing little
+    /// else gets cut off. Counted in `quic_error_budget_closes_total`. `None` (the default) never
+    /// closes connections for this.
+    pub fn error_budget(mut self, errors: Option<u32>) -> Self {
+        self.options.error_budget = errors.map(|errors| errors.max(1));
+        self
+    }
+
+    pub fn max_concurrent_bidi_streams(mut self, max: u32) -> Self {
+        self.options.max_concurrent_bidi_streams = max;
+        self
+    }
+
+    /// Must be at least [`MIN_UNI_STREAMS`], or [`ServerBuilder::bind`] fails.
+    pub fn max_concurrent_uni_streams(mut self, max: u32) -> Self {
+        self.options.max_concurrent_uni_streams = max;
+        self
+    }
+
+    /// Bytes of incoming datagrams buffered per connection before the oldest are dropped.
+    /// `Some(0)` disables datagrams; `None` keeps quinn's default.
+    pub fn datagram_receive_buffer(mut self, bytes: Option<usize>) -> Self {
+        self.options.datagram_receive_buffer = bytes;
+        self
+    }
+
+    /// Bytes of outgoing datagrams buffered per connection; `None` keeps quinn's default.
+    pub fn datagram_send_buffer(mut self, bytes: Option<usize>) -> Self {
+        self.options.datagram_send_buffer = bytes;
+        self
+    }
+
+    /// Largest datagram sent or accepted on a connection, below whatever the peer and the path
+    /// allow. Larger ones are refused by [`ConnInfo::send_datagram`] and dropped on receipt.
+    /// quinn advertises the smaller of the receive buffer and 65535 bytes to the peer and has no
+    /// separate setting, so the limit is enforced rather than announced. `None` (the default)
+    /// leaves it to the peer and the path.
+    pub fn max_datagram_frame_size(mut self, bytes: Option<usize>) -> Self {
+        self.options.max_datagram_frame_size = bytes;
+        self
+    }
+
+    /// Let connections take part in the QUIC spin bit (RFC 9000 §17.4), so on-path observers
+    /// can measure RTT passively. Off by default: it reveals the RTT to anyone on the path.
+    pub fn spin_bit(mut self, enabled: bool) -> Self {
+        self.options.spin_bit = enabled;
+        self
+    }
+
+    /// RTT assumed before the first sample is taken, e.g. for high-latency links where quinn's
+    /// default (333ms) causes spurious retransmits. `None` keeps quinn's default; values outside
+    /// [`INITIAL_RTT_RANGE`] are rejected by [`ServerBuilder::bind`].
+    pub fn initial_rtt(mut self, rtt: Option<Duration>) -> Self {
+        self.options.initial_rtt = rtt;
+        self
+    }
+
+    /// Congestion window of new connections, in bytes, before any loss or ACK has been seen.
+    /// A larger one lets short transfers over high bandwidth-delay paths finish before slow start
+    /// would have ramped up, at the price of a bigger first burst. `None` keeps quinn's default
+    /// (12000, ten 1200-byte datagrams); values outside [`INITIAL_CWND_RANGE`] are rejected by
+    /// [`ServerBuilder::bind`].
+    pub fn initial_cwnd(mut self, bytes: Option<u64>) -> Self {
+        self.options.initial_cwnd = bytes;
+        self
+    }
+
+    /// Accept 0-RTT early data from clients resuming a session, so their first requests arrive
+    /// with the handshake rather than a round trip after it. Requests are still only handled once
+    /// the handshake has completed, which a replayed ClientHello can't do, so early data saves the
+    /// client its round trip without letting an attacker replay requests. Off by default.
+    pub fn early_data(mut self, enabled: bool) -> Self {
+        self.options.early_data = enabled;
+        self
+    }
+
+    /// Maximum number of connections in the QUIC/TLS handshake at once (default
+    /// [`DEFAULT_MAX_HANDSHAKES`]). Past it, new connections wait in quinn's queue of incoming
+    /// connections until a handshake finishes, so a flood of them can't take all the CPU from
+    /// established connections. Established connections don't count.
+    pub fn max_handshakes(mut self, max: usize) -> Self {
+        self.options.max_handshakes = max.max(1);
+        self
+    }
+
+    /// Abort connections that have not completed the QUIC/TLS handshake within this time.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.options.handshake_timeout = timeout;
+        self
+    }
+
+    /// Reset request streams whose header section hasn't fully arrived this long after the
+    /// server started waiting for it, so clients that dribble headers (Slowloris) can't hold on
+    /// to request slots. Counted in `quic_request_header_timeouts_total`.
+    pub fn header_timeout(mut self, timeout: Duration) -> Self {
+        self.options.header_timeout = timeout;
+        self
+    }
+
+    /// Reset request streams whose body goes this long without any data arriving, and count them
+    /// in `quic_request_body_timeouts_total`. Unlike the connection's idle timeout it applies to
+    /// one stream, so a client that keeps its connection busy can't leave an upload half-sent
+    /// forever. `None` (the default) waits as long as the connection lives.
+    pub fn body_read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.options.body_read_timeout = timeout;
+        self
+    }
+
+    /// Reset request streams that haven't been answered this long after their headers arrived,
+    /// whatever the handler is doing, body included. `None` (the default) lets handlers take as
+    /// long as they like. Paths can have their own with [`ServerBuilder::route_timeout`] or
+    /// [`Router::route_timeout`]. CONNECT-UDP tunnels are never timed out.
+    pub fn request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.options.request_timeout = timeout;
+        self
+    }
+
+    /// Give requests for `path` on the default host `timeout` instead of the request timeout.
+    /// Hosts added with [`ServerBuilder::host`] use [`Router::route_timeout`] instead.
+    pub fn route_timeout(mut self, path: impl Into<String>, timeout: Duration) -> Self {
+        self.options.route_timeouts.insert(path.into(), timeout);
+        self
+    }
+
+    /// Answer requests for `path` on the default host with `308 Permanent Redirect` to
+    /// `location`, ahead of the built-in routes. Hosts added with [`ServerBuilder::host`] route
+    /// on their own.
+    pub fn redirect(mut self, path: impl Into<String>, location: HeaderValue) -> Self {
+        self.options.redirects.insert(path.into(), location);
+        self
+    }
+
+    /// How long shutdown waits for open connections before aborting them. Each connection is sent
+    /// GOAWAY as shutdown begins, so it only has its in-flight requests to finish.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.options.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Application error code connections still open at the end of shutdown are closed with
+    /// (default `H3_NO_ERROR`).
+    pub fn shutdown_code(mut self, code: Code) -> Self {
+        self.options.shutdown_code = code;
+        self
+    }
+
+    /// How often the statistics of open connections (RTT, congestion window, packet loss) are
+    /// sampled into the metrics served on `/metrics`. Zero turns sampling off.
+    pub fn stats_interval(mut self, interval: Duration) -> Self {
+        self.options.stats_interval = interval;
+        self
+    }
+
+    /// Print a line for every request as it arrives (the default). Busy servers can turn it off
+    /// and log only [slow requests](Self::slow_request_threshold).
+    pub fn access_log(mut self, enabled: bool) -> Self {
+        self.options.access_log = enabled;
+        self
+    }
+
+    /// Log every request that takes longer than `threshold` from its headers arriving to the
+    /// end of its response with `log::warn!`, giving its method, path, client and duration.
+    /// CONNECT-UDP tunnels are not timed. `None` (the default) logs none.
+    pub fn slow_request_threshold(mut self, threshold: Option<Duration>) -> Self {
+        self.options.slow_request_threshold = threshold;
+        self
+    }
+
+    /// Upper bounds in bytes, ascending, of the `quic_request_body_size_bytes` and
+    /// `quic_response_body_size_bytes` histograms. `None` keeps
+    /// [`DEFAULT_BODY_SIZE_BUCKETS`](crate::metrics::DEFAULT_BODY_SIZE_BUCKETS).
+    pub fn body_size_buckets(mut self, bounds: Option<Vec<u64>>) -> Self {
+        self.options.body_size_buckets = bounds;
+        self
+    }
+
+    /// Publish lifecycle [`Event`]s to subscribers of [`Server::events`], keeping up to `capacity`
+    /// of them for subscribers that fall behind; past that, the oldest are dropped. `None` (the
+    /// default) publishes none.
+    pub fn event_buffer(mut self, capacity: Option<usize>) -> Self {
+        self.options.events = capacity.map_or_else(Emitter::default, |capacity| Emitter::new(capacity.max(1)));
+        self
+    }
+
+    /// Windows, in whole seconds up to [`MAX_REQUEST_RATE_WINDOW`](crate::metrics::MAX_REQUEST_RATE_WINDOW),
+    /// that the `quic_request_rate` gauge averages requests per second over. `None` keeps
+    /// [`DEFAULT_REQUEST_RATE_WINDOWS`](crate::metrics::DEFAULT_REQUEST_RATE_WINDOWS).
+    pub fn request_rate_windows(mut self, windows: Option<Vec<Duration>>) -> Self {
+        self.options.request_rate_windows = windows;
+        self
+    }
+
+    /// Add this header to every response, e.g. `Strict-Transport-Security` or
+    /// `X-Content-Type-Options: nosniff`, unless the handler sets the header itself. Calling it
+    /// again with the same name adds another value.
+    pub fn response_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.options.response_headers.append(name, value);
+        self
+    }
+
+    /// Serve the files in this directory under `/static/`, preferring precompressed `.br` and
+    /// `.gz` siblings the client accepts. `None` (the default) serves no files.
+    pub fn static_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.options.static_dir = dir;
+        self
+    }
+
+    /// How directories under `/static/` without an `index.html` are answered (404 Not Found
+    /// by default). Directories with one get their index either way.
+    pub fn directory_listing(mut self, listing: DirectoryListing) -> Self {
+        self.options.directory_listing = listing;
+        self
+    }
+
+    /// Serve a built-in `/favicon.ico` and answer the other paths browsers request on their own
+    /// (`/apple-touch-icon.png`, Chrome's DevTools probe) without logging them, for testing from
+    /// a browser. Off by default; routes of virtual hosts and the fallback still come first.
+    pub fn browser_probes(mut self, enabled: bool) -> Self {
+        self.options.browser_probes = enabled;
+        self
+    }
+
+    /// Serve `/generate?bytes=N`: N bytes of deterministic data, produced as they're sent, that
+    /// clients can check with [`generate::verify`], and `/debug/stream?bytes=N` (or `?mb=N`), N
+    /// zero bytes, at most [`DEBUG_STREAM_MAX`]. Off by default, as they're for tests and
+    /// benchmarks and let any client ask for as much data as it likes.
+    pub fn generate_route(mut self, enabled: bool) -> Self {
+        self.options.generate_route = enabled;
+        self
+    }
+
+    /// Serve `/debug/slow?secs=N`, which holds a request for up to [`DEBUG_SLOW_MAX_SECS`]
+    /// seconds before answering, `/debug/ndjson?records=N`, which trickles out up to
+    /// [`DEBUG_NDJSON_MAX_RECORDS`] JSON lines 100ms apart, and `/debug/reset?code=`, which resets
+    /// the response stream. Off by default, as they're for trying out timeouts, cancellation,
+    /// streaming and error handling in clients and let any client tie up requests at will.
+    pub fn debug_routes(mut self, enabled: bool) -> Self {
+        self.options.debug_routes = enabled;
+        self
+    }
+
+    /// Require clients to authenticate with a certificate issued by one of the CAs in this PEM
+    /// file (mutual TLS). Handlers find the verified certificate in
+    /// [`ConnInfo::client_certificate`]. `None` (the default) asks for no client certificate.
+    pub fn client_ca(mut self, path: Option<PathBuf>) -> Self {
+        self.options.client_ca = path;
+        self
+    }
+
+    /// Largest request body the server reads, in bytes. Requests declaring a larger
+    /// Content-Length get 413 without their body being read, and bodies that grow past it are
+    /// cut off with 413. `None` (the default) means no limit.
+    pub fn max_body_size(mut self, max: Option<u64>) -> Self {
+        self.options.max_body_size = max;
+        self
+    }
+
+    /// Most keys and value bytes the `/kv/{key}` store holds, [`kv::MAX_ENTRIES`] and
+    /// [`kv::MAX_TOTAL_SIZE`] by default. PUTs that would go past either get 507.
+    pub fn kv_limits(mut self, max_entries: usize, max_total_size: usize) -> Self {
+        self.options.kv = kv::Store::new(max_entries, max_total_size);
+        self
+    }
+
+    /// Act as a MASQUE proxy: tunnel UDP for CONNECT-UDP requests (RFC 9298) to
+    /// `/.well-known/masque/udp/{host}/{port}/`, carrying the packets in HTTP datagrams. Off by
+    /// default, since it lets every client send UDP from the server's address to anywhere;
+    /// restrict who may use it with an [`authorizer`](Self::authorizer).
+    pub fn connect_udp(mut self, enabled: bool) -> Self {
+        self.options.connect_udp = enabled;
+        self
+    }
+
+    /// Let CONNECT-UDP tunnels reach loopback, private and link-local addresses. Off by default,
+    /// so clients can't use the proxy to get at services on the server itself or its network;
+    /// such targets get 403.
+    pub fn connect_udp_allow_private(mut self, allowed: bool) -> Self {
+        self.options.connect_udp_allow_private = allowed;
+        self
+    }
+
+    /// The HTTP/3 SETTINGS to send on every connection, e.g. a limit on the size of request
+    /// header sections. With [`connect_udp`](Self::connect_udp) on, extended CONNECT and
+    /// datagrams are announced whatever these say.
+    pub fn h3_settings(mut self, settings: H3Settings) -> Self {
+        self.options.h3_settings = settings;
+        self
+    }
+
+    /// Length in bytes of the connection IDs the server issues, for QUIC-aware load balancers
+    /// that expect a fixed length. Must be at most [`MAX_CID_LENGTH`]; `None` keeps quinn's
+    /// default of 8. Retry packets and the IDs handed out for migration use the same length.
+    /// With `Some(0)` connections are told apart by the client's address alone, so a client
+    /// that migrates or gets rebound by a NAT loses its connection.
+    pub fn cid_length(mut self, len: Option<usize>) -> Self {
+        self.options.cid_length = len;
+        self
+    }
+
+    /// Issue connection IDs from generators `factory` makes, one per connection, instead of
+    /// quinn's default generator: a deterministic one makes packet captures and qlogs comparable
+    /// across test runs, and one that encodes routing information serves QUIC-aware load
+    /// balancers. Can't be combined with [`ServerBuilder::cid_length`]; the generator decides
+    /// the length.
+    ///
+    /// Advanced, and only for tests outside of production: connection IDs are visible on the
+    /// wire, so a production generator must make them unpredictable and uncorrelated (RFC 9000,
+    /// section 5.1), or observers can link a client's traffic across migrations.
+    pub fn cid_generator<F>(mut self, factory: F) -> Self
+    where
+        F: Fn() -> Box<dyn ConnectionIdGenerator> + Send + Sync + 'static,
+    {
+        self.options.cid_generator = Some(Arc::new(factory));
+        self
+    }
+
+    /// Serve from `shards` endpoints sharing the listen port through `SO_REUSEPORT` instead of
+    /// one (the default, 1), so receiving and decrypting packets, which each endpoint does in a
+    /// single driver task, is spread over the runtime's worker threads. The kernel assigns each
+    /// client address to one endpoint, so connections never move between them, and a client
+    /// whose address changes (migration, NAT rebinding) loses its connection. Only Linux spreads
+    /// packets this way; elsewhere [`ServerBuilder::bind`] falls back to a single endpoint.
+    pub fn shards(mut self, shards: usize) -> Self {
+        self.options.shards = shards.max(1);
+        self
+    }
+
+    /// Let clients move their connections to a new address (the default), e.g. when switching
+    /// from Wi-Fi to cellular or when a NAT rebinds their port; each move is logged and counted
+    /// in `quic_connection_migrations_total`. With `false`, packets from anywhere but the
+    /// address a connection started on are dropped, so such connections time out.
+    pub fn migration(mut self, enabled: bool) -> Self {
+        self.options.migration = enabled;
+        self
+    }
+
+    /// Send packets in batches with GSO where the platform supports it (the default). Turn it
+    /// off on kernels or NICs whose segmentation offload drops or garbles packets.
+    pub fn gso(mut self, enabled: bool) -> Self {
+        self.options.gso = enabled;
+        self
+    }
+
+    /// Drop, delay and reorder the packets the server sends, for trying it out over a bad
+    /// network. The default is a perfect network; [`ServerBuilder::bind`] rejects a loss outside 0..=1.
+    pub fn simulate(mut self, conditions: NetworkConditions) -> Self {
+        self.options.network = conditions;
+        self
+    }
+
+    /// Decide per request whether it may proceed. Denied requests get the returned status
+    /// and never reach a handler. The closure runs before the request body is read.
+    pub fn authorizer<F, Fut>(mut self, authorizer: F) -> Self
+    where
+        F: Fn(&Request<()>, &ConnInfo) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Authorization> + Send + 'static,
+    {
+        self.options.authorizer = Some(Arc::new(move |req, conn| Box::pin(authorizer(req, conn))));
+        self
+    }
+
+    /// Call `hook` for every connection that completes its handshake, before any of its requests
+    /// are handled. It runs on the connection's task, so it should return quickly.
+    pub fn on_connect<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&ConnectionOpened) + Send + Sync + 'static,
+    {
+        self.options.on_connect = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `hook` once the server is done with a connection that [`ServerBuilder::on_connect`]
+    /// saw, with its final statistics. Connections whose handshake fails are only counted in
+    /// `/metrics`.
+    pub fn on_close<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&ConnectionClosed) + Send + Sync + 'static,
+    {
+        self.options.on_close = Some(Arc::new(hook));
+        self
+    }
+
+    /// Route requests for `host` (matched against `:authority` without the port, ignoring case)
+    /// to `router` instead of the built-in routes. Paths the router doesn't know get 404 Not
+    /// Found. Adding a host again replaces its router.
+    pub fn host(mut self, host: &str, router: Router) -> Self {
+        self.options.hosts.insert(router::normalize_host(host), router);
+        self
+    }
+
+    /// Answer requests for paths the built-in routes don't know with `handler` instead of 404
+    /// Not Found. Hosts added with [`ServerBuilder::host`] use their router's
+    /// [`Router::fallback`] instead. Like route handlers, `handler` finds the request's
+    /// [`ConnInfo`] in its extensions, as an `Arc<ConnInfo>`.
+    pub fn fallback<F, Fut, R>(mut self, handler: F) -> Self
+    where
+        F: Fn(Request<()>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = R> + Send + 'static,
+        R: IntoResponse,
+    {
+        self.options.fallback = Some(router::handler_from(handler));
+        self
+    }
+
+    /// Answer requests for hosts not added with [`ServerBuilder::host`] with 404 Not Found
+    /// instead of serving the built-in routes as the default host.
+    pub fn strict_hosts(mut self, strict: bool) -> Self {
+        self.options.strict_hosts = strict;
+        self
+    }
+
+    /// Also offer `alpn` in the handshake, and hand connections that negotiate it to `handler`
+    /// instead of HTTP/3. The handler owns the raw QUIC connection from then on. A client that
+    /// offers both gets h3, since the server's order decides and h3 comes first. Adding the same
+    /// protocol again replaces its handler; `h3` itself is rejected by [`ServerBuilder::bind`].
+    pub fn protocol<F, Fut>(mut self, alpn: impl Into<Vec<u8>>, handler: F) -> Self
+    where
+        F: Fn(quinn::Connection) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let alpn = alpn.into();
+        let handler: ProtocolHandler = Arc::new(move |conn| Box::pin(handler(conn)));
+        match self.options.protocols.iter_mut().find(|(existing, _)| *existing == alpn) {
+            Some((_, existing)) => *existing = handler,
+            None => self.options.protocols.push((alpn, handler)),
+        }
+        self
+    }
+
+    /// Create the endpoint and bind its socket. Requests are only accepted once
+    /// [`Server::serve`] runs, so e.g. privileges can be dropped in between.
+    pub fn bind(self) -> anyhow::Result<Server> {
+        let mut options = self.options;
+        options.inflight_budget = options.max_inflight_requests.map(|max| Arc::new(Semaphore::new(max)));
+        if options.max_concurrent_uni_streams < MIN_UNI_STREAMS {
+            bail!("max_concurrent_uni_streams must be at least {} for HTTP/3", MIN_UNI_STREAMS);
+        }
+        if let Some(rtt) = options.initial_rtt {
+            check_initial_rtt(rtt)?;
+        }
+        if let Some(bytes) = options.initial_cwnd {
+            check_initial_cwnd(bytes)?;
+        }
+        if let Some(len) = options.cid_length {
+            check_cid_length(len)?;
+        }
+        if options.cid_length.is_some() && options.cid_generator.is_some() {
+            bail!("cid_length and cid_generator can't be combined; the generator sets the length");
+        }
+        simulate::check_loss(options.network.loss)?;
+        if let Some(bounds) = &options.body_size_buckets
+            && (bounds.is_empty() || !bounds.is_sorted_by(|a, b| a < b))
+        {
+            bail!("body size buckets must be ascending and not empty, got {:?}", bounds);
+        }
+        if let Some(windows) = &options.request_rate_windows {
+            check_request_rate_windows(windows)?;
+        }
+        if options.body_size_buckets.is_some() || options.request_rate_windows.is_some() {
+            let bounds = options.body_size_buckets.as_deref().unwrap_or(DEFAULT_BODY_SIZE_BUCKETS);
+            let windows = options.request_rate_windows.as_deref().unwrap_or(DEFAULT_REQUEST_RATE_WINDOWS);
+            options.metrics = Arc::new(Metrics::with_body_size_buckets(bounds).request_rate_windows(windows));
+        }
+        if options.protocols.iter().any(|(alpn, _)| alpn == H3_ALPN) {
+            bail!("h3 is always served; it can't be given a protocol handler");
+        }
+
+        let cert = match &options.certificate {
+            Some((chain, key)) => load_cert(chain, key)?,
+            None => generate_self_signed_cert(&options.subject_alt_names)?,
+        };
+        let fingerprint = Fingerprint::of(&cert.cert_chain[0]);
+        let provider = rustls::crypto::CryptoProvider::get_default()
+            .context("no rustls crypto provider installed")?;
+        let key = CertifiedKey::from_der(cert.cert_chain, cert.private_key, provider)?;
+        let tls_config = rustls::ServerConfig::builder();
+        let tls_config = match &options.client_ca {
+            Some(path) => tls_config.with_client_cert_verifier(client_cert::verifier(path)?),
+            None => tls_config.with_no_client_auth(),
+        };
+        let resolver = LoggingResolver {
+            subject: PeerCertificate::parse(&key.cert[0])?.subject,
+            key: Arc::new(key),
+            protocols: options.alpn_protocols(),
+            // QUIC only speaks TLS 1.3
+            suites: provider.cipher_suites.iter().filter(|suite| suite.tls13().is_some()).map(|suite| suite.suite()).collect(),
+        };
+        let mut tls_config = tls_config.with_cert_resolver(Arc::new(resolver));
+        tls_config.alpn_protocols = options.alpn_protocols();
+        // QUIC requires exactly u32::MAX when early data is on (RFC 9001 §4.6.1)
+        if options.early_data {
+            tls_config.max_early_data_size = u32::MAX;
+        }
+
+        let mut server_config = ServerConfig::with_crypto(Arc::new(
+            quinn::crypto::rustls::QuicServerConfig::try_from(tls_config)?
+        ));
+        server_config.transport_config(Arc::new(transport_config(&options)));
+        server_config.migration(options.migration);
+
+        let socket_options = SocketOptions {
+            gso: options.gso,
+            network: options.network,
+            cid_length: options.cid_length,
+            cid_generator: options.cid_generator.clone(),
+        };
+        let endpoints = socket::sharded_endpoints(options.listen, server_config, socket_options, options.shards)
+            .with_context(|| format!("failed to bind UDP socket on {}", options.listen))?;
+        if endpoints.len() < options.shards {
+            println!("SO_REUSEPORT doesn't spread packets over sockets on this platform, serving from a single endpoint");
+        }
+
+        Ok(Server { endpoints, fingerprint, options: Arc::new(options) })
+    }
+}
+
+pub struct Server {
+    // One per shard, all on the same address
+    endpoints: Vec<Endpoint>,
+    fingerprint: Fingerprint,
+    options: Arc<Options>,
+}
+
+/// A server running in the background, returned by [`Server::spawn`]. Dropping it leaves the
+/// server running.
+pub struct ServerHandle {
+    stop: CancellationToken,
+    options: Arc<Options>,
+    task: JoinHandle<anyhow::Result<()>>,
+}
+
+impl ServerHandle {
+    /// Stop accepting connections and drain the open ones for up to the shutdown timeout, like
+    /// Ctrl-C does for [`Server::serve`]. Returns right away; [`ServerHandle::stopped`] waits.
+    pub fn shutdown(&self) {
+        self.stop.cancel();
+    }
+
+    /// See [`Server::stats`].
+    pub fn stats(&self) -> ServerStats {
+        stats(&self.options)
+    }
+
+    /// See [`Server::events`].
+    pub fn events(&self) -> Option<broadcast::Receiver<Event>> {
+        self.options.events.subscribe()
+    }
+
+    /// Wait until the server has shut down and its endpoint is idle.
+    pub async fn stopped(self) -> anyhow::Result<()> {
+        self.task.await.context("server task failed")?
+    }
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder {
+            options: Options {
+                listen: SocketAddr::from(([127, 0, 0, 1], 4433)),
+                subject_alt_names: DEFAULT_SUBJECT_ALT_NAMES.iter().map(|name| name.to_string()).collect(),
+                certificate: None,
+                max_inflight_per_connection: 32,
+                max_inflight_requests: None,
+                inflight_budget: None,
+                queue_timeout: Duration::from_secs(5),
+                overload: OverloadPolicy::Queue,
+                retry_after: Duration::from_secs(1),
+                max_requests_per_connection: None,
+                max_connection_age: None,
+                error_budget: None,
+                max_concurrent_bidi_streams: 100,
+                max_concurrent_uni_streams: 100,
+                datagram_receive_buffer: None,
+                datagram_send_buffer: None,
+                max_datagram_frame_size: None,
+                spin_bit: false,
+                initial_rtt: None,
+                initial_cwnd: None,
+                early_data: false,
+                max_handshakes: DEFAULT_MAX_HANDSHAKES,
+                handshake_timeout: Duration::from_secs(5),
+                header_timeout: Duration::from_secs(10),
+                body_read_timeout: None,
+                request_timeout: None,
+                route_timeouts: HashMap::new(),
+                redirects: HashMap::new(),
+                shutdown_timeout: Duration::from_secs(10),
+                shutdown_code: Code::H3_NO_ERROR,
+                authorizer: None,
+                on_connect: None,
+                on_close: None,
+                protocols: Vec::new(),
+                hosts: HashMap::new(),
+                strict_hosts: false,
+                fallback: None,
+                stats_interval: Duration::from_secs(5),
+                access_log: true,
+                slow_request_threshold: None,
+                response_headers: HeaderMap::new(),
+                static_dir: None,
+                directory_listing: DirectoryListing::NotFound,
+                browser_probes: false,
+                generate_route: false,
+                debug_routes: false,
+                client_ca: None,
+                max_body_size: None,
+                connect_udp: false,
+                connect_udp_allow_private: false,
+                h3_settings: H3Settings::default(),
+                cid_length: None,
+                cid_generator: None,
+                shards: 1,
+                migration: true,
+                gso: true,
+                network: NetworkConditions::default(),
+                metrics: Arc::new(Metrics::default()),
+                body_size_buckets: None,
+                request_rate_windows: None,
+                sampler: Sampler::default(),
+                kv: kv::Store::default(),
+                events: Emitter::default(),
+            },
+        }
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.endpoints[0].local_addr()
+    }
+
+    /// SHA-256 fingerprint of the leaf certificate, for clients to pin.
+    pub fn certificate_fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
+
+    /// The server's metrics, also served on `/metrics`.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.options.metrics.clone()
+    }
+
+    /// Connection and request totals so far, the counterpart of `/metrics` for applications
+    /// that show them themselves: the metrics' atomic counters, plus the bytes open connections
+    /// moved since the last stats sample. Cheap enough to call on every UI refresh.
+    pub fn stats(&self) -> ServerStats {
+        stats(&self.options)
+    }
+
+    /// A new subscription to the server's lifecycle events, which sees those from now on, or
+    /// `None` unless [`ServerBuilder::event_buffer`] turned them on. A subscriber that falls a
+    /// whole buffer behind misses the oldest events, and learns how many from `RecvError::Lagged`.
+    pub fn events(&self) -> Option<broadcast::Receiver<Event>> {
+        self.options.events.subscribe()
+    }
+
+    /// Accept connections until Ctrl-C, then drain them for up to the shutdown timeout.
+    pub async fn serve(self) -> anyhow::Result<()> {
+        self.serve_until(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+    }
+
+    /// Like [`Server::serve`], but shut down when `signal` completes instead of on Ctrl-C, for
+    /// applications that handle signals themselves. Returns once shutdown is complete.
+    pub async fn serve_until(self, signal: impl Future<Output = ()>) -> anyhow::Result<()> {
+        let Server { endpoints, options, .. } = self;
+        tokio::pin!(signal);
+
+        let sampling = (!options.stats_interval.is_zero()).then(|| {
+            let options = options.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(options.stats_interval);
+                loop {
+                    interval.tick().await;
+                    options.sampler.sample(&options.metrics);
+                }
+            })
+        });
+
+        // Every connection task is tracked so shutdown can wait for it and panics get reported
+        let mut connections = JoinSet::new();
+        // Cancelled when shutdown begins, telling every connection to send GOAWAY
+        let draining = CancellationToken::new();
+        // One pending accept per shard, replaced by the next once it yields a connection
+        let mut accepting: FuturesUnordered<_> = endpoints.iter().cloned().map(accept).collect();
+        let handshakes = Arc::new(Semaphore::new(options.max_handshakes));
+        // Logged once each time the limit is hit, not for every connection held back
+        let mut at_handshake_limit = false;
+
+        loop {
+            let permit = match handshakes.clone().try_acquire_owned() {
+                Ok(permit) => {
+                    at_handshake_limit = false;
+                    permit
+                }
+                Err(_) => {
+                    if !at_handshake_limit {
+                        println!("Limit of {} concurrent handshakes reached, holding off new connections", options.max_handshakes);
+                        at_handshake_limit = true;
+                    }
+                    tokio::select! {
+                        permit = handshakes.clone().acquire_owned() => permit.unwrap(),
+                        _ = &mut signal => break,
+                    }
+                }
+            };
+            let (endpoint, incoming) = tokio::select! {
+                Some(accepted) = accepting.next() => accepted,
+                _ = &mut signal => break,
+            };
+            let Some(conn) = incoming else { break };
+            accepting.push(accept(endpoint));
+
+            // The handshake runs in the connection's own task so a stalled peer can't hold up the loop
+            let (options, draining) = (options.clone(), draining.clone());
+            connections.spawn(async move {
+                let remote = conn.remote_address();
+                let handshake = tokio::time::timeout(options.handshake_timeout, conn).await;
+                drop(permit);
+                let failure = match handshake {
+                    Ok(Ok(conn)) => return handle_connection(conn, options, draining).await,
+                    Ok(Err(e)) => {
+                        let failure = HandshakeFailure::classify(&e);
+                        println!("Handshake with {} failed: {} ({})", remote, failure, e);
+                        options.events.emit(|| Event::Error {
+                            connection: None,
+                            remote_address: remote,
+                            message: format!("handshake failed: {} ({})", failure, e),
+                        });
+                        failure
+                    }
+                    // Dropping the pending connection closes it
+                    Err(_) => {
+                        println!("Handshake with {} timed out after {:?}, aborting", remote, options.handshake_timeout);
+                        HandshakeFailure::Timeout
+                    }
+                };
+                options.metrics.handshake_failures.add(failure.label(), 1);
+            });
+
+            while let Some(result) = connections.try_join_next() {
+                report_task_result("Connection", result);
+            }
+        }
+
+        println!("Shutting down, waiting up to {:?} for {} connection(s)", options.shutdown_timeout, connections.len());
+
+        // Refuse new connections while the existing ones drain
+        drop(accepting);
+        for endpoint in &endpoints {
+            endpoint.set_server_config(None);
+        }
+        draining.cancel();
+
+        let drain = async {
+            while let Some(result) = connections.join_next().await {
+                report_task_result("Connection", result);
+            }
+        };
+        if tokio::time::timeout(options.shutdown_timeout, drain).await.is_err() {
+            println!("Shutdown timeout reached, aborting {} connection(s)", connections.len());
+            connections.shutdown().await;
+        }
+
+        if let Some(sampling) = sampling {
+            sampling.abort();
+        }
+        for endpoint in &endpoints {
+            endpoint.close(options.shutdown_code.value().try_into().unwrap(), b"server shutting down");
+        }
+        futures::future::join_all(endpoints.iter().map(Endpoint::wait_idle)).await;
+
+        Ok(())
+    }
+
+    /// Serve in a background task until [`ServerHandle::shutdown`] is called.
+    pub fn spawn(self) -> ServerHandle {
+        let stop = CancellationToken::new();
+        let signal = stop.clone().cancelled_owned();
+        ServerHandle { stop, options: self.options.clone(), task: tokio::spawn(self.serve_until(signal)) }
+    }
+}
+
+fn stats(options: &Options) -> ServerStats {
+    let mut stats = options.metrics.stats();
+    let (sent, received) = options.sampler.unsampled_bytes();
+    stats.sent_bytes += sent;
+    stats.received_bytes += received;
+    stats
+}
+
+async fn accept(endpoint: Endpoint) -> (Endpoint, Option<Incoming>) {
+    let incoming = endpoint.accept().await;
+    (endpoint, incoming)
+}
+
+fn transport_config(options: &Options) -> TransportConfig {
+    let mut transport = TransportConfig::default();
+    transport
+        .max_concurrent_bidi_streams(options.max_concurrent_bidi_streams.into())
+        .max_concurrent_uni_streams(options.max_concurrent_uni_streams.into())
+        // quinn spins by default; keep it opt-in
+        .allow_spin(options.spin_bit);
+    // A receive buffer of zero means the peer is told not to send datagrams at all
+    if let Some(bytes) = options.datagram_receive_buffer {
+        transport.datagram_receive_buffer_size((bytes > 0).then_some(bytes));
+    }
+    if let Some(bytes) = options.datagram_send_buffer {
+        transport.datagram_send_buffer_size(bytes);
+    }
+    if let Some(rtt) = options.initial_rtt {
+        transport.initial_rtt(rtt);
+    }
+    // quinn's default controller, with only the window changed
+    if let Some(bytes) = options.initial_cwnd {
+        let mut cubic = CubicConfig::default();
+        cubic.initial_window(bytes);
+        transport.congestion_controller_factory(Arc::new(cubic));
+    }
+    transport
+}
+
+async fn handle_connection(conn: quinn::Connection, options: Arc<Options>, draining: CancellationToken) {
+    let remote = conn.remote_address();
+    let handshake = conn
+        .handshake_data()
+        .and_then(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>().ok());
+    let opened = ConnectionOpened {
+        remote_address: remote,
+        server_name: handshake.as_ref().and_then(|h| h.server_name.clone()),
+        alpn: handshake
+            .as_ref()
+            .and_then(|h| h.protocol.as_deref())
+            .map(|protocol| String::from_utf8_lossy(protocol).into_owned()),
+        connection: conn.clone(),
+    };
+    let opened_at = Instant::now();
+    if let Some(hook) = &options.on_connect {
+        hook(&opened);
+    }
+    options.events.emit(|| Event::ConnectionOpened {
+        connection: conn.stable_id(),
+        remote_address: remote,
+        server_name: opened.server_name.clone(),
+        alpn: opened.alpn.clone(),
+    });
+    // Stops watching once the connection is done with, so the watcher doesn't keep it open
+    let watching = CancellationToken::new();
+    let _stop_watching = watching.clone().drop_guard();
+    if options.migration {
+        tokio::spawn(watch_migrations(conn.clone(), options.metrics.clone(), watching));
+    }
+
+    // Connections that negotiated a custom protocol never become HTTP/3 connections
+    let alpn = handshake.as_ref().and_then(|h| h.protocol.as_deref());
+    if let Some((_, handler)) = options.protocols.iter().find(|(protocol, _)| Some(protocol.as_slice()) == alpn) {
+        options.sampler.track(&conn, &options.metrics);
+        handler(conn.clone()).await;
+        options.sampler.untrack(&conn, &options.metrics);
+        connection_closed(&options, opened, opened_at, 0, 0);
+        return;
+    }
+
+    let transport = priority::Connection::new(conn.clone(), options.metrics.clone());
+    let cancellations = transport.cancellations();
+    let stream_resets = transport.stream_resets();
+    let unacknowledged = transport.unacknowledged();
+    let peer_certificates = conn
+        .peer_identity()
+        .and_then(|identity| identity.downcast::<Vec<CertificateDer<'static>>>().ok())
+        .map(|chain| *chain)
+        .unwrap_or_default();
+    // rustls has verified the chain, so a parse failure only means x509-parser is stricter
+    let client_certificate = peer_certificates.first().and_then(|leaf| match PeerCertificate::parse(leaf) {
+        Ok(cert) => Some(cert),
+        Err(e) => {
+            log::error!("Cannot read the client certificate of {}: {}", remote, e);
+            None
+        }
+    });
+    let conn_info = Arc::new(ConnInfo {
+        remote_address: remote,
+        server_name: opened.server_name.clone(),
+        alpn: opened.alpn.clone(),
+        peer_certificates,
+        client_certificate,
+        connection: conn,
+        priorities: transport.priorities(),
+        datagram_frame_limit: options.max_datagram_frame_size,
+    });
+    options.sampler.track(&conn_info.connection, &options.metrics);
+
+    let max_inflight = options.max_inflight_per_connection;
+    // CONNECT-UDP needs extended CONNECT and HTTP datagrams announced in SETTINGS
+    let settings = &options.h3_settings;
+    let mut h3_builder = h3::server::builder();
+    h3_builder
+        .enable_extended_connect(settings.enable_extended_connect || options.connect_udp)
+        .enable_datagram(settings.enable_datagram || options.connect_udp)
+        .enable_webtransport(settings.enable_webtransport)
+        .max_webtransport_sessions(settings.max_webtransport_sessions)
+        .send_grease(settings.send_grease);
+    if let Some(max) = settings.max_field_section_size {
+        h3_builder.max_field_section_size(max);
+    }
+    // Fails if the connection is gone before the client's control stream arrived, e.g. a client
+    // closing a connection right after the handshake
+    let mut h3_conn: h3::server::Connection<priority::Connection, Bytes> = match h3_builder.build(transport).await {
+        Ok(h3_conn) => h3_conn,
+        Err(e) => {
+            println!("HTTP/3 setup with {} failed: {}", remote, e);
+            options.sampler.untrack(&conn_info.connection, &options.metrics);
+            connection_closed(&options, opened, opened_at, 0, 0);
+            return;
+        }
+    };
+    let datagrams = Datagrams::new(conn_info.connection.clone(), options.max_datagram_frame_size);
+
+    // Bounds how many of this connection's requests are being handled at once
+    let budget = Arc::new(Semaphore::new(max_inflight));
+    let error_budget = options.error_budget.map(|limit| Arc::new(ErrorBudget { limit, errors: AtomicU32::new(0) }));
+    let mut requests = JoinSet::new();
+    let mut accepted: u64 = 0;
+    let streams = Arc::new(StreamCounts::default());
+    // Counts from the handshake, whatever the connection gets up to meanwhile
+    let aged = async {
+        match options.max_connection_age {
+            Some(age) => tokio::time::sleep_until((opened_at + age).into()).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(aged);
+    // Set once GOAWAY went out for a shutdown or the age limit
+    let mut stopped = None;
+
+    loop {
+        if options.max_requests_per_connection.is_some_and(|max| accepted >= max) {
+            println!("Connection {} reached {} requests, sending GOAWAY", remote, accepted);
+            goaway(&mut h3_conn, remote).await;
+            break;
+        }
+
+        let next = tokio::select! {
+            next = h3_conn.accept() => next,
+            _ = draining.cancelled() => {
+                Stop::Draining.goaway(&mut h3_conn, remote, accepted, &options).await;
+                stopped = Some(Stop::Draining);
+                break;
+            }
+            _ = &mut aged => {
+                Stop::Aged.goaway(&mut h3_conn, remote, accepted, &options).await;
+                stopped = Some(Stop::Aged);
+                break;
+            }
+        };
+        match next {
+            Ok(Some(resolver)) => {
+                accepted += 1;
+                options.metrics.requests.add(1);
+                options.metrics.request_rate.record();
+                let active = ActiveStream::start(&options.metrics, &streams);
+                let permit = match budget.clone().try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(_) if options.overload == OverloadPolicy::Reject => {
+                        println!("Connection {} is at its budget of {} in-flight requests, rejecting with 503", remote, max_inflight);
+                        let (options, stream_resets) = (options.clone(), stream_resets.clone());
+                        requests.spawn(async move {
+                            let _active = active;
+                            reject_overloaded(resolver, &options, &stream_resets, remote).await
+                        });
+                        continue;
+                    }
+                    Err(_) => {
+                        println!("Connection {} hit its budget of {} in-flight requests, waiting", remote, max_inflight);
+                        let acquire = budget.clone().acquire_owned();
+                        tokio::pin!(acquire);
+                        // The wait lasts as long as the slowest running request, so a shutdown or
+                        // the age limit sends GOAWAY meanwhile. This request was accepted before
+                        // it, so it is still served once there is room.
+                        tokio::select! {
+                            permit = &mut acquire => permit.unwrap(),
+                            _ = draining.cancelled() => {
+                                Stop::Draining.goaway(&mut h3_conn, remote, accepted, &options).await;
+                                stopped = Some(Stop::Draining);
+                                acquire.await.unwrap()
+                            }
+                            _ = &mut aged => {
+                                Stop::Aged.goaway(&mut h3_conn, remote, accepted, &options).await;
+                                stopped = Some(Stop::Aged);
+                                acquire.await.unwrap()
+                            }
+                        }
+                    }
+                };
+
+                let (options, conn_info) = (options.clone(), conn_info.clone());
+                let (cancellations, stream_resets) = (cancellations.clone(), stream_resets.clone());
+                let (datagrams, error_budget) = (datagrams.clone(), error_budget.clone());
+                requests.spawn(async move {
+                    // Held until the response is finished
+                    let (_permit, _active) = (permit, active);
+                    let Some(_server_permit) = server_permit(&options, &remote).await else {
+                        reject_overloaded(resolver, &options, &stream_resets, remote).await;
+                        return;
+                    };
+                    let _inflight = Inflight::start(&options.metrics);
+                    let peer_error = handle_request(resolver, &options, &conn_info, &cancellations, &stream_resets, &datagrams).await;
+                    if let Some(error_budget) = error_budget
+                        && error_budget.record(peer_error.is_some())
+                    {
+                        println!(
+                            "Connection {} used up its error budget of {} (last: {}), closing it",
+                            remote,
+                            error_budget.limit,
+                            peer_error.unwrap_or_default()
+                        );
+                        options.metrics.error_budget_closes.add(1);
+                        conn_info.connection.close(Code::H3_EXCESSIVE_LOAD.value().try_into().unwrap(), b"error budget exhausted");
+                    }
+                });
+
+                while let Some(result) = requests.try_join_next() {
+                    report_task_result("Request", result);
+                }
+                if stopped.is_some() {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(_e) => break,
+        }
+    }
+
+    // Let requests that are still running finish; the connection is closed when `h3_conn` drops,
+    // which would throw away whatever of their responses is still on its way
+    let finishing = async {
+        while let Some(result) = requests.join_next().await {
+            report_task_result("Request", result);
+        }
+        unacknowledged.settled().await;
+    };
+    // Server shutdown has a timeout of its own around the whole connection
+    if stopped != Some(Stop::Aged) {
+        finishing.await;
+    } else if tokio::time::timeout(options.shutdown_timeout, finishing).await.is_err() {
+        println!("Connection {} still has {} request(s) running after {:?}, closing it", remote, requests.len(), options.shutdown_timeout);
+        requests.shutdown().await;
+    }
+    options.sampler.untrack(&conn_info.connection, &options.metrics);
+    let peak_streams = streams.peak.load(Ordering::Relaxed);
+    options.metrics.connection_peak_streams.observe(peak_streams as f64);
+    connection_closed(&options, opened, opened_at, accepted, peak_streams);
+}
+
+// Why a connection stopped taking requests before the client was done with it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stop {
+    Draining,
+    Aged,
+}
+
+impl Stop {
+    async fn goaway(self, h3_conn: &mut h3::server::Connection<priority::Connection, Bytes>, remote: SocketAddr, accepted: u64, options: &Options) {
+        match self {
+            Stop::Draining => println!("Shutting down, sending GOAWAY to {} after {} requests", remote, accepted),
+            Stop::Aged => println!(
+                "Connection {} reached the maximum age of {:?}, sending GOAWAY after {} requests",
+                remote,
+                options.max_connection_age.unwrap_or_default(),
+                accepted
+            ),
+        }
+        goaway(h3_conn, remote).await;
+    }
+}
+
+// The requests accepted so far still complete; the client is told that newer streams weren't
+// processed, so it can send them again on a new connection
+async fn goaway(h3_conn: &mut h3::server::Connection<priority::Connection, Bytes>, remote: SocketAddr) {
+    if let Err(e) = h3_conn.shutdown(1).await {
+        log::error!("Failed to send GOAWAY to {}: {}", remote, e);
+    }
+}
+
+// quinn doesn't announce migrations, so the peer address is checked every so often
+async fn watch_migrations(conn: quinn::Connection, metrics: Arc<Metrics>, stop: CancellationToken) {
+    let mut address = conn.remote_address();
+    let mut interval = tokio::time::interval(MIGRATION_CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = conn.closed() => return,
+            _ = stop.cancelled() => return,
+        }
+        let current = conn.remote_address();
+        if current != address {
+            println!("Connection migrated from {} to {}", address, current);
+            metrics.connection_migrations.add(1);
+            address = current;
+        }
+    }
+}
+
+fn connection_closed(options: &Options, opened: ConnectionOpened, opened_at: Instant, requests: u64, peak_streams: usize) {
+    options.events.emit(|| Event::ConnectionClosed {
+        connection: opened.connection.stable_id(),
+        remote_address: opened.remote_address,
+        duration: opened_at.elapsed(),
+        requests,
+        reason: opened.connection.close_reason(),
+    });
+    if let Some(hook) = &options.on_close {
+        hook(&ConnectionClosed {
+            remote_address: opened.remote_address,
+            alpn: opened.alpn,
+            duration: opened_at.elapsed(),
+            requests,
+            peak_streams,
+            reason: opened.connection.close_reason(),
+            stats: opened.connection.stats(),
+        });
+    }
+}
+
+// Returns why the request counts against the connection's error budget, if it does
+async fn handle_request(
+    resolver: RequestResolver<priority::Connection, Bytes>,
+    options: &Options,
+    conn_info: &Arc<ConnInfo>,
+    cancellations: &Cancellations,
+    stream_resets: &StreamResets,
+    datagrams: &Datagrams,
+) -> Option<&'static str> {
+    let Some(resolved) = receive_head(resolver, options, stream_resets, conn_info.remote_address).await else {
+        return Some("request headers timed out");
+    };
+    let (mut req, mut stream) = match resolved {
+        Ok(resolved) => resolved,
+        // h3 has already reset the stream: H3_MESSAGE_ERROR for a malformed header section (e.g.
+        // no :method), H3_REQUEST_INCOMPLETE if it ended before the headers. Other requests on
+        // the connection carry on.
+        Err(e) => {
+            println!("Dropped malformed request from {}: {}", conn_info.remote_address, e);
+            options.events.emit(|| Event::Error {
+                connection: Some(conn_info.connection.stable_id()),
+                remote_address: conn_info.remote_address,
+                message: format!("malformed request: {}", e),
+            });
+            return Some("malformed request");
+        }
+    };
+
+    if options.access_log && !(options.browser_probes && probes::is_probe(req.uri().path())) {
+        println!("Got request for path: {}, protocol: {:?}", req.uri().path(), req.version());
+    }
+
+    // The client may have moved since the connection was set up
+    let migrated;
+    let conn_info = match conn_info.connection.remote_address() {
+        current if current != conn_info.remote_address => {
+            migrated = Arc::new(ConnInfo { remote_address: current, ..ConnInfo::clone(conn_info) });
+            &migrated
+        }
+        _ => conn_info,
+    };
+
+    let checked = check_request(&req)
+        .and_then(|()| body::check_framing(&req))
+        .and_then(|()| router::normalize_request(&mut req));
+    if let Err(reason) = checked {
+        println!("Rejecting request from {} with 400: {}", conn_info.remote_address, reason);
+        let response = Response::text(StatusCode::BAD_REQUEST, format!("400 Bad Request: {}", reason));
+        respond(&mut stream, options, conn_info, response).await;
+        return Some("invalid request");
+    }
+    conn_info.priorities.set_from_header(stream.id(), Priority::of(&req));
+    // Handlers (and the authorizer) find it in the request's extensions
+    let cancelled = cancellations.token(stream.id());
+    req.extensions_mut().insert(cancelled.clone());
+
+    let started = Instant::now();
+    let slow = options.slow_request_threshold.filter(|_| !masque::is_connect_udp(&req));
+    let (method, path, id) = (req.method().clone(), req.uri().path().to_string(), stream.id());
+    let connection = conn_info.connection.stable_id();
+    options.events.emit(|| Event::RequestStarted {
+        connection,
+        stream: id.into_inner(),
+        method: method.clone(),
+        path: path.clone(),
+    });
+    let mut timed_out = false;
+    let serving = RESPONSE_STATUS.scope(Cell::new(None), async {
+        match request_timeout(options, &req) {
+            None => serve_request(req, stream, options, conn_info, datagrams).await,
+            Some(timeout) => {
+                let mut serving = Box::pin(serve_request(req, stream, options, conn_info, datagrams));
+                if tokio::time::timeout(timeout, serving.as_mut()).await.is_err() {
+                    timed_out = true;
+                    println!("Request for {} from {} took longer than {:?}, resetting the stream", path, conn_info.remote_address, timeout);
+                    options.events.emit(|| Event::Error {
+                        connection: Some(connection),
+                        remote_address: conn_info.remote_address,
+                        message: format!("request for {} took longer than {:?}", path, timeout),
+                    });
+                    // Dropping the handler drops the stream, which would otherwise finish a half-sent
+                    // response as if it were complete
+                    stream_resets.reset_on_drop(id, Code::H3_REQUEST_CANCELLED);
+                    drop(serving);
+                    // In case the handler had let go of the stream already
+                    stream_resets.clear(id);
+                }
+            }
+        }
+        RESPONSE_STATUS.with(Cell::get)
+    });
+    let status = serving.await;
+    options.events.emit(|| Event::RequestCompleted { connection, stream: id.into_inner(), status, duration: started.elapsed() });
+    if let Some(threshold) = slow
+        && started.elapsed() > threshold
+    {
+        log::warn!("Slow request: {} {} from {} took {:?}", method, path, conn_info.remote_address, started.elapsed());
+    }
+    // Resetting a timed-out stream may fire the token too, but that was the server's doing
+    (cancelled.is_cancelled() && !timed_out).then_some("response stream stopped by the client")
+}
+
+// The timeout of `req`'s route: its own, else the server's. CONNECT-UDP tunnels live as long as
+// the client wants.
+fn request_timeout(options: &Options, req: &Request<()>) -> Option<Duration> {
+    if masque::is_connect_udp(req) {
+        return None;
+    }
+    let path = req.uri().path();
+    let route = match router::host_of(req).and_then(|host| options.hosts.get(&host)) {
+        Some(router) => router.timeout_for(path),
+        None => options.route_timeouts.get(path).copied(),
+    };
+    route.or(options.request_timeout)
+}
+
+// Everything after the request's header section: authorization, then the handler
+async fn serve_request(
+    mut req: Request<()>,
+    mut stream: RequestStream<priority::BidiStream<Bytes>, Bytes>,
+    options: &Options,
+    conn_info: &Arc<ConnInfo>,
+    datagrams: &Datagrams,
+) {
+    if let Some(authorizer) = &options.authorizer
+        && let Authorization::Deny(status) = authorizer(&req, conn_info).await
+    {
+        let status = status.unwrap_or(StatusCode::FORBIDDEN);
+        println!("Denied request for {} from {} with {}", req.uri().path(), conn_info.remote_address, status);
+        respond(&mut stream, options, conn_info, Response::text(status, status.to_string())).await;
+        return;
+    }
+
+    if masque::is_connect_udp(&req) {
+        if options.connect_udp {
+            masque::tunnel(&req, &mut stream, datagrams, &options.response_headers, options.connect_udp_allow_private).await;
+        } else {
+            respond(&mut stream, options, conn_info, Response::text(StatusCode::NOT_IMPLEMENTED, "501 Not Implemented")).await;
+        }
+        return;
+    }
+
+    // Handlers registered by library users only get the request, so it carries the connection;
+    // shared rather than copied, since it holds the peer's certificates
+    req.extensions_mut().insert(Arc::clone(conn_info));
+
+    // Virtual hosts; anything else is for the default host, the routes below
+    let host = router::host_of(&req);
+    if let Some(router) = host.as_ref().and_then(|host| options.hosts.get(host)) {
+        let response = router.handle(req).await.unwrap_or_else(not_found);
+        respond(&mut stream, options, conn_info, response).await;
+        return;
+    }
+    if options.strict_hosts {
+        println!("No host {:?} for a request from {}", host.unwrap_or_default(), conn_info.remote_address);
+        respond(&mut stream, options, conn_info, not_found()).await;
+        return;
+    }
+
+    if let Some(location) = options.redirects.get(req.uri().path()) {
+        let response = Response::new(StatusCode::PERMANENT_REDIRECT).header(LOCATION, location.clone());
+        respond(&mut stream, options, conn_info, response).await;
+        return;
+    }
+
+    // Reset the response stream with the code from `?code=` (a name like H3_REQUEST_CANCELLED or a
+    // number), for checking how clients report resets
+    if options.debug_routes && req.uri().path() == "/debug/reset" {
+        let code = query_param(&req, "code")
+            .and_then(error_code::parse)
+            .unwrap_or(Code::H3_REQUEST_CANCELLED);
+        println!("Resetting stream for {} with {}", req.uri().path(), code);
+        stream.stop_stream(code);
+        return;
+    }
+
+    if options.generate_route && req.uri().path() == "/debug/stream" {
+        // `?mb=` counts in megabytes (10^6 bytes), for the client's download benchmark
+        let mb = query_param(&req, "mb").and_then(|n| n.parse::<u64>().ok()).map(|mb| mb.saturating_mul(1_000_000));
+        let len = mb.or_else(|| query_param(&req, "bytes").and_then(|n| n.parse().ok())).unwrap_or(1024 * 1024);
+        let len = len.min(DEBUG_STREAM_MAX);
+        record_status(StatusCode::OK);
+        match zeros(len).default_headers(&options.response_headers).send(&mut stream).await {
+            Ok(len) => options.metrics.response_body_size.observe(len as f64),
+            Err(e) => println!("Streaming {} bytes to {} stopped: {}", len, conn_info.remote_address, e),
+        }
+        return;
+    }
+
+    if options.generate_route && req.uri().path() == generate::PATH {
+        let response = generate::respond(query_param(&req, "bytes"));
+        record_status(response.status());
+        match response.default_headers(&options.response_headers).send(&mut stream).await {
+            Ok(len) => options.metrics.response_body_size.observe(len as f64),
+            Err(e) => println!("Generating data for {} stopped: {}", conn_info.remote_address, e),
+        }
+        return;
+    }
+
+    // Pretend to do `?secs=` seconds of work (default 10), giving up early if the client cancels
+    if options.debug_routes && req.uri().path() == "/debug/slow" {
+        let secs = query_param(&req, "secs").and_then(|n| n.parse().ok()).unwrap_or(10).min(DEBUG_SLOW_MAX_SECS);
+        let cancelled = req.extensions().get::<CancellationToken>().cloned().unwrap_or_default();
+        let start = Instant::now();
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(secs)) => {}
+            _ = cancelled.cancelled() => {
+                println!("Request for {} cancelled by the client after {:?}", req.uri().path(), start.elapsed());
+                return;
+            }
+        }
+
+        respond(&mut stream, options, conn_info, format!("Worked for {} seconds", secs)).await;
+        return;
+    }
+
+    if let Some(root) = &options.static_dir
+        && req.uri().path().starts_with(static_files::PREFIX)
+    {
+        respond(&mut stream, options, conn_info, static_files::serve(root, options.directory_listing, &req).await).await;
+        return;
+    }
+
+    // The key-value demo; only PUT has a body to read
+    let path = req.uri().path();
+    let kv_key = kv::key(path);
+    if let Some(key) = kv_key
+        && req.method() != Method::PUT
+    {
+        respond(&mut stream, options, conn_info, options.kv.respond(req.method(), key, req.headers())).await;
+        return;
+    }
+
+    // Routes that read the request body
+    if path == "/upload" || path == "/debug/echo-hex" || kv_key.is_some() {
+        let declared = match body::content_length(&req) {
+            Ok(declared) => declared,
+            Err(e) => {
+                println!("Rejecting upload from {}: {}", conn_info.remote_address, e);
+                respond(&mut stream, options, conn_info, Response::text(StatusCode::BAD_REQUEST, "400 Bad Request")).await;
+                return;
+            }
+        };
+        let digest = match body::content_digest(&req) {
+            Ok(digest) => digest,
+            Err(e) => {
+                println!("Rejecting upload from {}: {}", conn_info.remote_address, e);
+                respond(&mut stream, options, conn_info, Response::text(StatusCode::BAD_REQUEST, "400 Bad Request")).await;
+                stream.stop_sending(Code::H3_NO_ERROR);
+                return;
+            }
+        };
+        let encoding = match body::content_encoding(&req) {
+            Ok(encoding) => encoding,
+            Err(e) => {
+                println!("Rejecting upload from {}: {}", conn_info.remote_address, e);
+                // Accept-Encoding on a 415 tells the client which codings would do (RFC 9110, section 15.5.16)
+                let response = Response::text(StatusCode::UNSUPPORTED_MEDIA_TYPE, "415 Unsupported Media Type")
+                    .header(ACCEPT_ENCODING, Encoding::ALL.map(Encoding::name).join(", "));
+                respond(&mut stream, options, conn_info, response).await;
+                stream.stop_sending(Code::H3_NO_ERROR);
+                return;
+            }
+        };
+        let expects_continue = match body::expects_continue(&req) {
+            Ok(expects) => expects,
+            Err(e) => {
+                println!("Rejecting upload from {}: {}", conn_info.remote_address, e);
+                let response = Response::text(StatusCode::EXPECTATION_FAILED, "417 Expectation Failed");
+                respond(&mut stream, options, conn_info, response).await;
+                stream.stop_sending(Code::H3_NO_ERROR);
+                return;
+            }
+        };
+        // Refuse an oversized upload up front instead of reading it only to throw it away
+        if let (Some(declared), Some(max)) = (declared, options.max_body_size)
+            && declared > max
+        {
+            println!("Rejecting upload of {} bytes from {}: the limit is {} bytes", declared, conn_info.remote_address, max);
+            respond(&mut stream, options, conn_info, payload_too_large()).await;
+            stream.stop_sending(Code::H3_NO_ERROR);
+            return;
+        }
+        // The upload is acceptable as far as its headers tell, so the client may go ahead
+        if expects_continue && let Err(e) = response::send_informational(&mut stream, Informational::CONTINUE).await {
+            println!("Failed to send 100 Continue to {}: {}", conn_info.remote_address, e);
+            return;
+        }
+
+        let (mut send, recv) = stream.split();
+        let mut body = Body::new(recv)
+            .limit(options.max_body_size)
+            .content_length(declared)
+            .read_timeout(options.body_read_timeout)
+            .expect_digest(digest)
+            .decode(encoding);
+        let uploaded = match (path, kv_key) {
+            (_, Some(key)) => body.read_to_vec(kv::MAX_VALUE).await.map(|value| options.kv.respond_put(key, value.into(), req.headers())),
+            ("/upload", None) => upload(&mut body).await.map(IntoResponse::into_response),
+            _ => echo_hex(&mut body).await.map(IntoResponse::into_response),
+        };
+        options.metrics.request_body_size.observe(body.received() as f64);
+        let response = match uploaded {
+            Ok(response) => response,
+            Err(e @ BodyError::TooLarge { .. }) => {
+                println!("Rejecting upload from {}: {}", conn_info.remote_address, e);
+                payload_too_large()
+            }
+            // A body that contradicts its Content-Length is malformed: the client is told to stop
+            // sending it, and gets a 400 before the stream closes (RFC 9114, section 4.1.2)
+            Err(e @ BodyError::LengthMismatch { .. }) => {
+                println!("Rejecting upload from {} with 400: {}", conn_info.remote_address, e);
+                body.stop_sending(Code::H3_MESSAGE_ERROR);
+                Response::text(StatusCode::BAD_REQUEST, format!("400 Bad Request: {}", e))
+            }
+            Err(e @ BodyError::DigestMismatch { .. }) => {
+                println!("Rejecting upload from {} with 400: {}", conn_info.remote_address, e);
+                Response::text(StatusCode::BAD_REQUEST, format!("400 Bad Request: {}", e))
+            }
+            // The body already stopped the receiving side, so the stream is gone once this
+            // side is reset too
+            Err(e @ BodyError::TimedOut { .. }) => {
+                println!("Resetting upload from {}: {}", conn_info.remote_address, e);
+                options.metrics.request_body_timeouts.add(1);
+                send.stop_stream(Code::H3_REQUEST_CANCELLED);
+                return;
+            }
+            Err(e) => {
+                println!("Failed to read upload from {}: {}", conn_info.remote_address, e);
+                Response::text(StatusCode::BAD_REQUEST, "400 Bad Request")
+            }
+        };
+        respond(&mut send, options, conn_info, response).await;
+        return;
+    }
+
+    #[cfg(feature = "templates")]
+    if req.uri().path() == "/hello" {
+        let name = query_param(&req, "name").unwrap_or("world");
+        let response = template::render(&TEMPLATES, "hello.html", minijinja::context! { name });
+        respond(&mut stream, options, conn_info, response).await;
+        return;
+    }
+
+    let response = match req.uri().path() {
+        "/" => "Hello from http3 server".into_response(),
+        "/test" => "Hello from http3 test endpoint".into_response(),
+        "/health" => "hello from http3 health check".into_response(),
+        "/greeting" => greeting(req.headers()),
+        "/debug/headers" => dump_headers(&req).into_response(),
+        "/debug/ndjson" if options.debug_routes => ndjson_records(&req),
+        "/debug/client-cert" => match &conn_info.client_certificate {
+            Some(cert) => describe_certificate(cert).into_response(),
+            None => not_found(),
+        },
+        "/metrics" => Response::new(StatusCode::OK)
+            .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(options.metrics.render()),
+        "/push" => push(conn_info).await,
+        _ => {
+            let response = match &options.fallback {
+                Some(fallback) => fallback(req).await,
+                None if options.browser_probes => probes::respond(req.uri().path()).unwrap_or_else(not_found),
+                None => not_found(),
+            };
+            respond(&mut stream, options, conn_info, response).await;
+            return;
+        }
+    };
+    respond(&mut stream, options, conn_info, response.compress(req.headers())).await;
+}
+
+fn not_found() -> Response {
+    Response::text(StatusCode::NOT_FOUND, "404 Not Found")
+}
+
+fn payload_too_large() -> Response {
+    Response::text(StatusCode::PAYLOAD_TOO_LARGE, "413 Payload Too Large")
+}
+
+tokio::task_local! {
+    // The status of the response sent for the request this task handles, for
+    // `Event::RequestCompleted`
+    static RESPONSE_STATUS: Cell<Option<StatusCode>>;
+}
+
+// Outside of `handle_request` (e.g. for an overloaded connection's 503s) there is nothing to record
+fn record_status(status: StatusCode) {
+    let _ = RESPONSE_STATUS.try_with(|sent| sent.set(Some(status)));
+}
+
+// Send a handler's answer. A failure only concerns this request, so it is logged and that's it.
+async fn respond<S>(stream: &mut RequestStream<S, Bytes>, options: &Options, conn_info: &ConnInfo, response: impl IntoResponse)
+where
+    S: quic::SendStream<Bytes>,
+{
+    let response = response.into_response();
+    record_status(response.status());
+    match response.default_headers(&options.response_headers).send(stream).await {
+        Ok(len) => options.metrics.response_body_size.observe(len as f64),
+        Err(e) => {
+            log::error!("Failed to send response to {}: {}", conn_info.remote_address, e);
+            options.events.emit(|| Event::Error {
+                connection: Some(conn_info.connection.stable_id()),
+                remote_address: conn_info.remote_address,
+                message: format!("failed to send response: {}", e),
+            });
+        }
+    }
+}
+
+// A slot in the server-wide budget, if there is one: `Some(None)` without a limit, and `None` if
+// the request should get 503
+async fn server_permit(options: &Options, remote: &SocketAddr) -> Option<Option<OwnedSemaphorePermit>> {
+    let Some(budget) = &options.inflight_budget else {
+        return Some(None);
+    };
+    if let Ok(permit) = budget.clone().try_acquire_owned() {
+        return Some(Some(permit));
+    }
+    let max = options.max_inflight_requests.unwrap_or_default();
+    if options.overload == OverloadPolicy::Reject {
+        println!("Server is at its budget of {} in-flight requests, rejecting a request from {} with 503", max, remote);
+        return None;
+    }
+    match tokio::time::timeout(options.queue_timeout, budget.clone().acquire_owned()).await {
+        Ok(permit) => Some(Some(permit.unwrap())),
+        Err(_) => {
+            println!("Request from {} waited {:?} for one of the server's {} in-flight slots, rejecting with 503", remote, options.queue_timeout, max);
+            None
+        }
+    }
+}
+
+// A connection's requests that went wrong because of the peer, less one for each that went
+// right since (but never below zero)
+struct ErrorBudget {
+    limit: u32,
+    errors: AtomicU32,
+}
+
+impl ErrorBudget {
+    // Counts a request and returns whether it was the one that used up the budget
+    fn record(&self, error: bool) -> bool {
+        let count = |errors: u32| Some(if error { errors.saturating_add(1) } else { errors.saturating_sub(1) });
+        let previous = self.errors.fetch_update(Ordering::Relaxed, Ordering::Relaxed, count).unwrap();
+        error && previous + 1 == self.limit
+    }
+}
+
+// Counts a request in the in-flight gauge until dropped
+struct Inflight<'a>(&'a Metrics);
+
+impl<'a> Inflight<'a> {
+    fn start(metrics: &'a Metrics) -> Inflight<'a> {
+        metrics.inflight_requests.add(1);
+        Inflight(metrics)
+    }
+}
+
+impl Drop for Inflight<'_> {
+    fn drop(&mut self) {
+        self.0.inflight_requests.add(-1);
+    }
+}
+
+// A connection's request streams from acceptance until their handler finishes, and the most there
+// were at once
+#[derive(Default)]
+struct StreamCounts {
+    active: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+// Counts a request stream on its connection and server-wide until dropped, raising the peaks
+struct ActiveStream {
+    metrics: Arc<Metrics>,
+    counts: Arc<StreamCounts>,
+}
+
+impl ActiveStream {
+    fn start(metrics: &Arc<Metrics>, counts: &Arc<StreamCounts>) -> ActiveStream {
+        let active = counts.active.fetch_add(1, Ordering::Relaxed) + 1;
+        counts.peak.fetch_max(active, Ordering::Relaxed);
+        metrics.active_streams.add(1);
+        metrics.peak_active_streams.raise(metrics.active_streams.get());
+        ActiveStream { metrics: metrics.clone(), counts: counts.clone() }
+    }
+}
+
+impl Drop for ActiveStream {
+    fn drop(&mut self) {
+        self.counts.active.fetch_sub(1, Ordering::Relaxed);
+        self.metrics.active_streams.add(-1);
+    }
+}
+
+type Resolved = (Request<()>, RequestStream<priority::BidiStream<Bytes>, Bytes>);
+
+// Wait for the request's header section, or `None` once the header timeout has passed. The
+// resolver is dropped then, which resets the stream with H3_REQUEST_REJECTED (see
+// `StreamResets`). From the headers on, a stream dropped unfinished is reset with
+// H3_INTERNAL_ERROR.
+async fn receive_head(
+    resolver: RequestResolver<priority::Connection, Bytes>,
+    options: &Options,
+    stream_resets: &StreamResets,
+    remote: SocketAddr,
+) -> Option<Result<Resolved, StreamError>> {
+    match tokio::time::timeout(options.header_timeout, resolver.resolve_request()).await {
+        Ok(resolved) => {
+            if let Ok((_, stream)) = &resolved {
+                stream_resets.reset_on_drop(stream.id(), Code::H3_INTERNAL_ERROR);
+            }
+            Some(resolved)
+        }
+        Err(_) => {
+            println!("Request headers from {} didn't arrive within {:?}, resetting the stream", remote, options.header_timeout);
+            options.metrics.request_header_timeouts.add(1);
+            None
+        }
+    }
+}
+
+// Answer without doing any work so the client backs off instead of queueing
+async fn reject_overloaded(
+    resolver: RequestResolver<priority::Connection, Bytes>,
+    options: &Options,
+    stream_resets: &StreamResets,
+    remote: SocketAddr,
+) {
+    options.metrics.overloaded_requests.add(1);
+    let Some(Ok((_req, mut stream))) = receive_head(resolver, options, stream_resets, remote).await else { return };
+
+    let response = Response::text(StatusCode::SERVICE_UNAVAILABLE, "503 Service Unavailable")
+        .header(RETRY_AFTER, options.retry_after.as_secs().max(1))
+        .default_headers(&options.response_headers);
+    let _ = response.send(&mut stream).await;
+}
+
+// What h3 lets through but no route can serve: it only insists on :method and an authority
+// (RFC 9114, section 4.3.1 lists the rest)
+fn check_request(req: &Request<()>) -> Result<(), &'static str> {
+    if req.method() == Method::CONNECT {
+        return Ok(());
+    }
+    match req.uri().scheme_str() {
+        Some("https") => {}
+        Some(_) => return Err("unsupported :scheme, only https is served"),
+        None => return Err("missing :scheme"),
+    }
+    match req.uri().path_and_query().map(|path| path.path()) {
+        Some(path) if path.starts_with('/') => Ok(()),
+        Some("*") if req.method() == Method::OPTIONS => Ok(()),
+        Some(_) => Err("invalid :path"),
+        None => Err("missing :path"),
+    }
+}
+
+fn query_param<'a>(req: &'a Request<()>, name: &str) -> Option<&'a str> {
+    req.uri()
+        .query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+}
+
+// Log tasks that panicked; aborted tasks (during shutdown) are expected and stay quiet
+fn report_task_result(kind: &str, result: Result<(), JoinError>) {
+    if let Err(e) = result
+        && e.is_panic()
+    {
+        log::error!("{} task panicked: {}", kind, e);
+    }
+}
+
+// `len` zero bytes. `send_from_reader` only reads the next chunk once quinn has taken the previous
+// one, and quinn only takes what fits in the peer's flow-control window plus its send buffer, so a
+// slow reader holds sending back and memory stays flat however large `len` is.
+fn zeros(len: u64) -> Response {
+    Stream(tokio::io::repeat(0).take(len)).into_response().header(CONTENT_LENGTH, len)
+}
+
+// Hash the body while it streams in, without buffering it
+async fn upload(body: &mut Body) -> Result<String, BodyError> {
+    let (received, digest) = body.copy_hashed(&mut tokio::io::sink()).await?;
+    let digest: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(format!("Received {} bytes, SHA-256 {}", received, digest))
+}
+
+// Largest body /debug/echo-hex reads; its dump is about four times the size and built in memory
+const ECHO_HEX_MAX: usize = 1024 * 1024;
+
+// The body's length and a `hexdump -C` style dump of it, without assuming any encoding
+async fn echo_hex(body: &mut Body) -> Result<String, BodyError> {
+    let data = body.read_to_vec(ECHO_HEX_MAX).await?;
+    let mut out = format!("Received {} bytes\n", data.len());
+    for (line, bytes) in data.chunks(16).enumerate() {
+        out.push_str(&format!("{:08x} ", line * 16));
+        for i in 0..16 {
+            if i % 8 == 0 {
+                out.push(' ');
+            }
+            match bytes.get(i) {
+                Some(byte) => out.push_str(&format!("{:02x} ", byte)),
+                None => out.push_str("   "),
+            }
+        }
+        let text: String = bytes.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect();
+        out.push_str(&format!(" |{}|\n", text));
+    }
+    Ok(out)
+}
+
+// `?records=` JSON lines (10 by default, at most `DEBUG_NDJSON_MAX_RECORDS`), one every 100ms,
+// like rows trickling out of a slow query
+#[derive(serde::Serialize)]
+struct Record {
+    seq: u64,
+    of: u64,
+}
+
+fn ndjson_records(req: &Request<()>) -> Response {
+    let of = query_param(req, "records").and_then(|n| n.parse().ok()).unwrap_or(10).min(DEBUG_NDJSON_MAX_RECORDS);
+    let records = futures::stream::iter(0..of).then(move |seq| async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        Record { seq, of }
+    });
+    NdJson(records).into_response()
+}
+
+// Send a custom unidirectional stream next to the response, for clients that split them off
+async fn push(conn_info: &ConnInfo) -> Response {
+    let data = Bytes::from("Hello over a server-initiated unidirectional stream");
+    match uni::send(&conn_info.connection, data).await {
+        Ok(()) => "Sent 1 unidirectional stream".into_response(),
+        Err(e) => {
+            log::error!("Failed to push a stream to {}: {:#}", conn_info.remote_address, e);
+            Response::text(StatusCode::INTERNAL_SERVER_ERROR, "500 Internal Server Error")
+        }
+    }
+}
+
+// Serve the greeting as JSON or plain text, whichever the client's Accept header prefers
+#[derive(serde::Serialize)]
+struct Greeting {
+    message: &'static str,
+}
+
+fn greeting(headers: &http::HeaderMap) -> Response {
+    const MESSAGE: &str = "Hello from http3 server";
+    match negotiate::best_match(headers, &["application/json", "text/plain"]) {
+        Some("application/json") => Json(Greeting { message: MESSAGE }).into_response(),
+        Some(_) => MESSAGE.into_response(),
+        None => Response::text(StatusCode::NOT_ACCEPTABLE, "406 Not Acceptable"),
+    }
+}
+
+// One `name: value` line per header. h3 turns the pseudo-headers into the request's method and
+// URI, so they are rebuilt from there and listed first, the way they arrived on the wire.
+fn dump_headers(req: &http::Request<()>) -> String {
+    let uri = req.uri();
+    let mut out = format!(":method: {}\n", req.method());
+    if let Some(scheme) = uri.scheme_str() {
+        out.push_str(&format!(":scheme: {}\n", scheme));
+    }
+    if let Some(authority) = uri.authority() {
+        out.push_str(&format!(":authority: {}\n", authority));
+    }
+    if let Some(path) = uri.path_and_query() {
+        out.push_str(&format!(":path: {}\n", path));
+    }
+
+    for (name, value) in req.headers() {
+        out.push_str(&format!("{}: {}\n", name, String::from_utf8_lossy(value.as_bytes())));
+    }
+
+    out
+}
+
+fn describe_certificate(cert: &PeerCertificate) -> String {
+    let mut out = format!("subject: {}\nissuer: {}\nserial: {}\n", cert.subject, cert.issuer, cert.serial);
+    for name in &cert.subject_alt_names {
+        out.push_str(&format!("subject alt name: {}\n", name));
+    }
+    out.push_str(&format!("not before: {}\n", humantime::format_rfc3339(cert.not_before)));
+    out.push_str(&format!("not after: {}\n", humantime::format_rfc3339(cert.not_after)));
+    out
+}
+
+// Serves the one certificate, but sees the ClientHello first. rustls asks for it before it checks
+// ALPN, which makes this the place to log what a client that can't speak HTTP/3 offered instead
+// (the handshake failure itself only says that no protocol matched), and at debug level what
+// the handshake is about to negotiate. rustls chooses the ALPN protocol and cipher suite only
+// after the certificate, so they are worked out here the same way: the server's first protocol
+// the client offered, and the client's first suite the server has.
+#[derive(Debug)]
+struct LoggingResolver {
+    key: Arc<CertifiedKey>,
+    subject: String,
+    protocols: Vec<Vec<u8>>,
+    suites: Vec<CipherSuite>,
+}
+
+impl ResolvesServerCert for LoggingResolver {
+    fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let offered: Vec<&[u8]> = hello.alpn().map(|protocols| protocols.collect()).unwrap_or_default();
+        if log::log_enabled!(log::Level::Debug) {
+            let selected = self.protocols.iter().find(|served| offered.contains(&served.as_slice()));
+            let suite = hello.cipher_suites().iter().find(|suite| self.suites.contains(suite));
+            log::debug!(
+                "TLS ClientHello: SNI {}, ALPN offered {:?}, selected {}, cipher suite {}, certificate {:?}",
+                hello.server_name().unwrap_or("none"),
+                offered.iter().map(|protocol| String::from_utf8_lossy(protocol)).collect::<Vec<_>>(),
+                selected.map_or("none".into(), |protocol| String::from_utf8_lossy(protocol)),
+                suite.map_or("none".to_string(), |suite| format!("{:?}", suite)),
+                self.subject,
+            );
+        }
+        if !offered.iter().any(|protocol| self.protocols.iter().any(|served| served == protocol)) {
+            println!(
+                "Client (SNI {:?}) offered ALPN {:?} but the server speaks {:?}; rejecting the handshake",
+                hello.server_name().unwrap_or("none"),
+                offered.iter().map(|protocol| String::from_utf8_lossy(protocol)).collect::<Vec<_>>(),
+                self.protocols.iter().map(|protocol| String::from_utf8_lossy(protocol)).collect::<Vec<_>>()
+            );
+        }
+        Some(self.key.clone())
+    }
+}
+
+struct CertificateChain {
+    cert_chain: Vec<CertificateDer<'static>>,
+    private_key: PrivateKeyDer<'static>
+}
+
+// The chain and key from PEM files, e.g. as issued by a CA
+fn load_cert(chain: &Path, key: &Path) -> anyhow::Result<CertificateChain> {
+    let cert_chain = CertificateDer::pem_file_iter(chain)
+        .with_context(|| format!("cannot read certificate file {}", chain.display()))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("invalid certificate in {}", chain.display()))?;
+    if cert_chain.is_empty() {
+        bail!("no certificates in {}", chain.display());
+    }
+    let private_key = PrivateKeyDer::from_pem_file(key).with_context(|| format!("cannot read private key from {}", key.display()))?;
+    Ok(CertificateChain { cert_chain, private_key })
+}
+
+// generate self signed certificate; rcgen turns names that parse as IP addresses into IP SANs
+fn generate_self_signed_cert(subject_alt_names: &[String]) -> anyhow::Result<CertificateChain> {
+    if subject_alt_names.is_empty() {
+        bail!("the self-signed certificate needs at least one subject alternative name");
+    }
+    let certified_key = rcgen::generate_simple_self_signed(subject_alt_names.to_vec())?;
+    let private_key_der = certified_key.signing_key.serialize_der();
+    let private_key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(private_key_der));
+    let cert_chain = vec![certified_key.cert.der().clone()];
+    Ok(CertificateChain { cert_chain, private_key })
+}
@@ -0,0 +1,132 @@
+// HTTP/3 SETTINGS (RFC 9114, section 7.2.4): the ones the server sends, and the ones a peer sent,
+// which the client reads off the peer's control stream because h3 keeps them to itself.
+
+use std::fmt;
+
+use crate::priority::decode_varint;
+
+pub const QPACK_MAX_TABLE_CAPACITY: u64 = 0x01;
+pub const MAX_FIELD_SECTION_SIZE: u64 = 0x06;
+pub const QPACK_BLOCKED_STREAMS: u64 = 0x07;
+/// RFC 9220 extended CONNECT
+pub const ENABLE_CONNECT_PROTOCOL: u64 = 0x08;
+/// RFC 9297 HTTP datagrams
+pub const H3_DATAGRAM: u64 = 0x33;
+/// draft-ietf-webtrans-http3; the sessions limit has the ID h3 uses, from draft 02
+pub const ENABLE_WEBTRANSPORT: u64 = 0x2b60_3742;
+pub const WEBTRANSPORT_MAX_SESSIONS: u64 = 0x2b60_3743;
+
+/// The SETTINGS the server sends on every connection. h3's QPACK has no dynamic table, so
+/// `SETTINGS_QPACK_MAX_TABLE_CAPACITY` and `SETTINGS_QPACK_BLOCKED_STREAMS` always keep their
+/// default of 0 and can't be set here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct H3Settings {
+    /// Largest header section the server accepts, in bytes; bigger requests are rejected.
+    /// `None` announces no limit
+    pub max_field_section_size: Option<u64>,
+    /// Accept extended CONNECT (`:protocol`). CONNECT-UDP turns it on whatever this says
+    pub enable_extended_connect: bool,
+    /// Accept HTTP datagrams. CONNECT-UDP turns them on whatever this says
+    pub enable_datagram: bool,
+    /// Announce WebTransport support. The server has no WebTransport handler, so this is only
+    /// for interop testing
+    pub enable_webtransport: bool,
+    pub max_webtransport_sessions: u64,
+    /// Add a reserved setting of the `0x1f * N + 0x21` form, so peers that choke on unknown
+    /// settings are found out (on by default)
+    pub send_grease: bool,
+}
+
+impl Default for H3Settings {
+    fn default() -> Self {
+        H3Settings {
+            max_field_section_size: None,
+            enable_extended_connect: false,
+            enable_datagram: false,
+            enable_webtransport: false,
+            max_webtransport_sessions: 0,
+            send_grease: true,
+        }
+    }
+}
+
+/// SETTINGS a peer sent, as identifier and value pairs in the order they arrived, GREASE and
+/// unknown identifiers included. The getters fall back to each setting's default when it's absent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerSettings {
+    pub entries: Vec<(u64, u64)>,
+}
+
+impl PeerSettings {
+    pub fn get(&self, id: u64) -> Option<u64> {
+        self.entries.iter().find(|(entry, _)| *entry == id).map(|(_, value)| *value)
+    }
+
+    /// `None` if the peer didn't send one, which means no limit.
+    pub fn max_field_section_size(&self) -> Option<u64> {
+        self.get(MAX_FIELD_SECTION_SIZE)
+    }
+
+    pub fn qpack_max_table_capacity(&self) -> u64 {
+        self.get(QPACK_MAX_TABLE_CAPACITY).unwrap_or(0)
+    }
+
+    pub fn qpack_blocked_streams(&self) -> u64 {
+        self.get(QPACK_BLOCKED_STREAMS).unwrap_or(0)
+    }
+
+    pub fn enable_connect_protocol(&self) -> bool {
+        self.get(ENABLE_CONNECT_PROTOCOL).is_some_and(|value| value != 0)
+    }
+
+    pub fn h3_datagram(&self) -> bool {
+        self.get(H3_DATAGRAM).is_some_and(|value| value != 0)
+    }
+
+    pub fn enable_webtransport(&self) -> bool {
+        self.get(ENABLE_WEBTRANSPORT).is_some_and(|value| value != 0)
+    }
+
+    pub fn webtransport_max_sessions(&self) -> u64 {
+        self.get(WEBTRANSPORT_MAX_SESSIONS).unwrap_or(0)
+    }
+
+    // The payload of a SETTINGS frame; `None` if it doesn't consist of whole varint pairs
+    pub(crate) fn parse(mut payload: &[u8]) -> Option<PeerSettings> {
+        let mut entries = Vec::new();
+        while !payload.is_empty() {
+            let (id, len) = decode_varint(payload)?;
+            let (value, value_len) = decode_varint(&payload[len..])?;
+            entries.push((id, value));
+            payload = &payload[len + value_len..];
+        }
+        Some(PeerSettings { entries })
+    }
+}
+
+impl fmt::Display for PeerSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (id, value)) in self.entries.iter().enumerate() {
+            let separator = if i == 0 { "" } else { ", " };
+            match name(*id) {
+                Some(name) => write!(f, "{}{}={}", separator, name, value)?,
+                None if id.checked_sub(0x21).is_some_and(|n| n % 0x1f == 0) => write!(f, "{}GREASE({:#x})={}", separator, id, value)?,
+                None => write!(f, "{}{:#x}={}", separator, id, value)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn name(id: u64) -> Option<&'static str> {
+    Some(match id {
+        QPACK_MAX_TABLE_CAPACITY => "QPACK_MAX_TABLE_CAPACITY",
+        MAX_FIELD_SECTION_SIZE => "MAX_FIELD_SECTION_SIZE",
+        QPACK_BLOCKED_STREAMS => "QPACK_BLOCKED_STREAMS",
+        ENABLE_CONNECT_PROTOCOL => "ENABLE_CONNECT_PROTOCOL",
+        H3_DATAGRAM => "H3_DATAGRAM",
+        ENABLE_WEBTRANSPORT => "ENABLE_WEBTRANSPORT",
+        WEBTRANSPORT_MAX_SESSIONS => "WEBTRANSPORT_MAX_SESSIONS",
+        _ => return None,
+    })
+}
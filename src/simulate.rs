@@ -0,0 +1,112 @@
+// Simulated adverse networks: a UDP socket wrapper that drops, delays and (through jitter)
+// reorders outgoing packets before quinn's real socket sends them. Only the sending side is
+// affected, so a lossy link in both directions needs the wrapper on both endpoints (see
+// `socket::endpoint`).
+
+use std::fmt;
+use std::io::{self, IoSliceMut};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use anyhow::bail;
+use h3_quinn::quinn;
+use quinn::udp::{RecvMeta, Transmit};
+use quinn::{AsyncUdpSocket, UdpPoller};
+
+/// What happens to each packet an endpoint sends. The default is a perfect network.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NetworkConditions {
+    /// Share of packets dropped, from 0 to 1
+    pub loss: f64,
+    /// Added to every packet's one-way delay
+    pub delay: Duration,
+    /// Extra delay picked uniformly from zero to this for every packet, so packets overtake
+    /// each other
+    pub jitter: Duration,
+}
+
+impl NetworkConditions {
+    pub fn is_perfect(&self) -> bool {
+        *self == NetworkConditions::default()
+    }
+}
+
+pub fn check_loss(loss: f64) -> anyhow::Result<f64> {
+    if !(0.0..=1.0).contains(&loss) {
+        bail!("packet loss must be between 0 and 1, got {}", loss);
+    }
+    Ok(loss)
+}
+
+// Wraps `inner` so its outgoing packets suffer `conditions`
+pub(crate) fn wrap(inner: Arc<dyn AsyncUdpSocket>, conditions: NetworkConditions) -> Arc<dyn AsyncUdpSocket> {
+    if conditions.is_perfect() {
+        return inner;
+    }
+    Arc::new(SimulatedSocket { inner, conditions })
+}
+
+struct SimulatedSocket {
+    inner: Arc<dyn AsyncUdpSocket>,
+    conditions: NetworkConditions,
+}
+
+impl fmt::Debug for SimulatedSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SimulatedSocket").field("conditions", &self.conditions).finish_non_exhaustive()
+    }
+}
+
+impl AsyncUdpSocket for SimulatedSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        self.inner.clone().create_io_poller()
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        if fastrand::f64() < self.conditions.loss {
+            return Ok(());
+        }
+
+        let jitter = self.conditions.jitter.mul_f64(fastrand::f64());
+        let delay = self.conditions.delay + jitter;
+        if delay.is_zero() {
+            return self.inner.try_send(transmit);
+        }
+
+        // Sent later from a task of its own; if the socket is busy by then, the packet is lost
+        // like on any congested link
+        let inner = self.inner.clone();
+        let (destination, ecn, src_ip) = (transmit.destination, transmit.ecn, transmit.src_ip);
+        let contents = transmit.contents.to_vec();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let transmit = Transmit { destination, ecn, contents: &contents, segment_size: None, src_ip };
+            let _ = inner.try_send(&transmit);
+        });
+        Ok(())
+    }
+
+    fn poll_recv(&self, cx: &mut Context, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> Poll<io::Result<usize>> {
+        self.inner.poll_recv(cx, bufs, meta)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    // One datagram per transmit, so loss applies to single packets
+    fn max_transmit_segments(&self) -> usize {
+        1
+    }
+
+    fn max_receive_segments(&self) -> usize {
+        self.inner.max_receive_segments()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
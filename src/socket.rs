@@ -0,0 +1,152 @@
+// The endpoint's UDP socket. quinn sends with generic segmentation offload (GSO) where the
+// platform supports it: one system call hands the kernel a batch of packets, which it splits.
+// Some kernels, NICs and virtual network drivers get that wrong, so it can be turned off here.
+// For sharding, several endpoints can share one port through SO_REUSEPORT.
+
+use std::fmt;
+use std::io::{self, IoSliceMut};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use h3_quinn::quinn;
+use quinn::udp::{RecvMeta, Transmit};
+use quinn::{AsyncUdpSocket, ConnectionIdGenerator, Endpoint, EndpointConfig, ServerConfig, UdpPoller};
+use quinn_proto::RandomConnectionIdGenerator;
+
+use crate::simulate::{self, NetworkConditions};
+
+/// Makes a connection ID generator for each connection, see [`EndpointConfig::cid_generator`].
+pub type CidGenerator = Arc<dyn Fn() -> Box<dyn ConnectionIdGenerator> + Send + Sync>;
+
+/// How an endpoint's socket sends packets.
+#[derive(Clone)]
+pub struct SocketOptions {
+    /// Batch outgoing packets with GSO where the platform has it (Linux 4.18+, Windows). With
+    /// `false` every packet is its own send; on other platforms this changes nothing.
+    pub gso: bool,
+    /// Simulated loss and delay of outgoing packets
+    pub network: NetworkConditions,
+    /// Length of the connection IDs the endpoint issues, random bytes; `None` keeps quinn's
+    /// default (8 bytes)
+    pub cid_length: Option<usize>,
+    /// Issues the endpoint's connection IDs instead of quinn's default generator; takes
+    /// precedence over `cid_length`
+    pub cid_generator: Option<CidGenerator>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        SocketOptions { gso: true, network: NetworkConditions::default(), cid_length: None, cid_generator: None }
+    }
+}
+
+impl fmt::Debug for SocketOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SocketOptions")
+            .field("gso", &self.gso)
+            .field("network", &self.network)
+            .field("cid_length", &self.cid_length)
+            .field("cid_generator", &self.cid_generator.as_ref().map(|_| "custom"))
+            .finish()
+    }
+}
+
+/// A quinn endpoint bound to `addr`. With `server_config` it accepts connections like
+/// [`Endpoint::server`], otherwise it can only connect like [`Endpoint::client`].
+pub fn endpoint(addr: SocketAddr, server_config: Option<ServerConfig>, options: SocketOptions) -> io::Result<Endpoint> {
+    endpoint_on(std::net::UdpSocket::bind(addr)?, server_config, options)
+}
+
+/// Up to `shards` server endpoints on `addr`, their sockets sharing the port through
+/// `SO_REUSEPORT`. The kernel picks a socket by hashing each packet's source and destination, so
+/// a connection stays with one endpoint as long as the client keeps its address, and each
+/// endpoint's driver task processes its own share of the packets. Where the kernel doesn't spread
+/// packets over the sockets (anything but Linux), this is the one endpoint [`endpoint`] binds.
+pub fn sharded_endpoints(
+    addr: SocketAddr,
+    server_config: ServerConfig,
+    options: SocketOptions,
+    #[cfg_attr(not(any(target_os = "linux", target_os = "android")), allow(unused_variables))] shards: usize,
+) -> io::Result<Vec<Endpoint>> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if shards > 1 {
+        let first = reuse_port_socket(addr)?;
+        // Port 0 was resolved by the first bind; the others have to join that port
+        let addr = first.local_addr()?;
+        let mut endpoints = vec![endpoint_on(first, Some(server_config.clone()), options.clone())?];
+        for _ in 1..shards {
+            endpoints.push(endpoint_on(reuse_port_socket(addr)?, Some(server_config.clone()), options.clone())?);
+        }
+        return Ok(endpoints);
+    }
+    Ok(vec![endpoint(addr, Some(server_config), options)?])
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn reuse_port_socket(addr: SocketAddr) -> io::Result<std::net::UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_port(true)?;
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
+fn endpoint_on(socket: std::net::UdpSocket, server_config: Option<ServerConfig>, options: SocketOptions) -> io::Result<Endpoint> {
+    let runtime = quinn::default_runtime().ok_or_else(|| io::Error::other("no async runtime found"))?;
+    let mut socket = runtime.wrap_udp_socket(socket)?;
+    if !options.gso {
+        socket = Arc::new(NoGsoSocket { inner: socket });
+    }
+    let socket = simulate::wrap(socket, options.network);
+    let mut config = EndpointConfig::default();
+    if let Some(generator) = options.cid_generator {
+        config.cid_generator(move || generator());
+    } else if let Some(len) = options.cid_length {
+        config.cid_generator(move || Box::new(RandomConnectionIdGenerator::new(len)));
+    }
+    Endpoint::new_with_abstract_socket(config, server_config, socket, runtime)
+}
+
+// quinn only batches as many packets into one send as the socket says it can take
+struct NoGsoSocket {
+    inner: Arc<dyn AsyncUdpSocket>,
+}
+
+impl fmt::Debug for NoGsoSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NoGsoSocket").finish_non_exhaustive()
+    }
+}
+
+impl AsyncUdpSocket for NoGsoSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        self.inner.clone().create_io_poller()
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        self.inner.try_send(transmit)
+    }
+
+    fn poll_recv(&self, cx: &mut Context, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> Poll<io::Result<usize>> {
+        self.inner.poll_recv(cx, bufs, meta)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn max_transmit_segments(&self) -> usize {
+        1
+    }
+
+    fn max_receive_segments(&self) -> usize {
+        self.inner.max_receive_segments()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
@@ -0,0 +1,263 @@
+// Files served from a directory under /static/ (--static-dir). A file may have precompressed
+// siblings (`app.js.br`, `app.js.gz`); when the client accepts one of their codings, the sibling
+// is sent as is. Otherwise the file is compressed on the fly like other responses, or sent
+// uncompressed. A directory is answered with its `index.html`, else with a generated listing or
+// an error, as configured.
+
+use std::path::{Component, Path, PathBuf};
+
+use std::fs::Metadata;
+
+use http::header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, LOCATION, VARY};
+use http::{HeaderMap, Request, StatusCode};
+
+use crate::compress::{self, Encoding};
+use crate::negotiate;
+use crate::response::Response;
+use crate::router::percent_decode;
+
+/// URL prefix static files are served under.
+pub const PREFIX: &str = "/static/";
+
+// Larger files are streamed uncompressed instead of being read into memory to compress them
+const MAX_DYNAMIC_COMPRESSION: u64 = 8 * 1024 * 1024;
+
+/// How a directory without an `index.html` is answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DirectoryListing {
+    /// An HTML page linking to the directory's entries, except hidden (dot) files
+    List,
+    /// 403 Forbidden
+    Forbidden,
+    /// 404 Not Found, as if the directory didn't exist
+    NotFound,
+}
+
+/// The file `req` asks for from `root`, or 404 Not Found. A directory gets its `index.html`, or
+/// else what `listing` says.
+pub(crate) async fn serve(root: &Path, listing: DirectoryListing, req: &Request<()>) -> Response {
+    let Some(path) = resolve(root, req.uri().path()) else {
+        return not_found();
+    };
+    let Ok(metadata) = tokio::fs::metadata(&path).await else {
+        return not_found();
+    };
+    if !metadata.is_dir() {
+        return serve_file(&path, metadata, req).await;
+    }
+    let index = path.join("index.html");
+    let index = match tokio::fs::metadata(&index).await {
+        Ok(metadata) if metadata.is_file() => Some((index, metadata)),
+        _ => None,
+    };
+    // Not even the redirect below may give away that the directory exists
+    if index.is_none() && listing == DirectoryListing::NotFound {
+        return not_found();
+    }
+    // Links in the index and the listing are relative to the directory
+    if !req.uri().path().ends_with('/') {
+        let location = match req.uri().query() {
+            Some(query) => format!("{}/?{}", req.uri().path(), query),
+            None => format!("{}/", req.uri().path()),
+        };
+        return Response::new(StatusCode::MOVED_PERMANENTLY).header(LOCATION, location);
+    }
+    if let Some((index, metadata)) = index {
+        return serve_file(&index, metadata, req).await;
+    }
+    match listing {
+        DirectoryListing::List => list(&path, req).await,
+        DirectoryListing::Forbidden => Response::text(StatusCode::FORBIDDEN, "403 Forbidden"),
+        DirectoryListing::NotFound => not_found(),
+    }
+}
+
+async fn serve_file(path: &Path, metadata: Metadata, req: &Request<()>) -> Response {
+    if !metadata.is_file() {
+        return not_found();
+    }
+    let response = Response::new(StatusCode::OK)
+        .header(CONTENT_TYPE, content_type(path))
+        // The same path may be answered with a precompressed sibling, so caches must key on this
+        .header(VARY, "Accept-Encoding");
+
+    if let Some((encoding, sibling)) = precompressed(path, req.headers()).await
+        && let Ok(file) = tokio::fs::File::open(&sibling).await
+        && let Ok(sibling_metadata) = file.metadata().await
+    {
+        return response
+            .header(CONTENT_ENCODING, encoding.name())
+            .header(CONTENT_LENGTH, sibling_metadata.len())
+            .stream(file);
+    }
+
+    let len = metadata.len();
+    if (compress::MIN_SIZE as u64..=MAX_DYNAMIC_COMPRESSION).contains(&len)
+        && Encoding::negotiate(req.headers()).is_some()
+        && let Ok(contents) = tokio::fs::read(path).await
+    {
+        return response.body(contents).compress(req.headers());
+    }
+
+    match tokio::fs::File::open(path).await {
+        Ok(file) => response.header(CONTENT_LENGTH, len).stream(file),
+        Err(_) => not_found(),
+    }
+}
+
+// The file under `root` for a request path (`root` itself for the prefix alone), refusing
+// anything that could step outside it. The path is percent-decoded first, so an encoded `..%2F`
+// is caught too.
+fn resolve(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode(request_path.strip_prefix(PREFIX)?)?;
+    if decoded.contains('\0') {
+        return None;
+    }
+    let relative = Path::new(&decoded);
+    if !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+        return None;
+    }
+    Some(root.join(relative))
+}
+
+// An HTML page linking to the entries of `dir`, directories first. Names that aren't UTF-8 can't
+// be linked to and are left out, like hidden files.
+async fn list(dir: &Path, req: &Request<()>) -> Response {
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return not_found();
+    };
+    let mut names = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let Ok(name) = entry.file_name().into_string() else { continue };
+        if name.starts_with('.') {
+            continue;
+        }
+        // Follows symlinks, like serving does
+        let is_dir = tokio::fs::metadata(entry.path()).await.is_ok_and(|metadata| metadata.is_dir());
+        names.push((!is_dir, if is_dir { name + "/" } else { name }));
+    }
+    names.sort();
+
+    let path = req.uri().path();
+    let title = format!("Index of {}", escape_html(&percent_decode(path).unwrap_or_else(|| path.to_string())));
+    let mut html = format!("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body><h1>{0}</h1>\n<ul>\n", title);
+    if path != PREFIX {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for (_, name) in &names {
+        html.push_str(&format!("<li><a href=\"{}\">{}</a></li>\n", percent_encode(name), escape_html(name)));
+    }
+    html.push_str("</ul></body></html>\n");
+    Response::new(StatusCode::OK).header(CONTENT_TYPE, "text/html; charset=utf-8").body(html).compress(req.headers())
+}
+
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// A name as a relative URL path: unreserved characters and a directory's trailing slash as they
+// are, everything else percent-encoded, e.g. `a b:c` as `a%20b%3Ac`
+fn percent_encode(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    out
+}
+
+// The best precompressed sibling of `path` the request accepts, if any exists
+async fn precompressed(path: &Path, headers: &HeaderMap) -> Option<(Encoding, PathBuf)> {
+    let mut available = Vec::new();
+    for encoding in Encoding::ALL {
+        let mut sibling = path.as_os_str().to_owned();
+        sibling.push(".");
+        sibling.push(encoding.extension());
+        let sibling = PathBuf::from(sibling);
+        if tokio::fs::metadata(&sibling).await.is_ok_and(|m| m.is_file()) {
+            available.push((encoding, sibling));
+        }
+    }
+
+    let names: Vec<&str> = available.iter().map(|(encoding, _)| encoding.name()).collect();
+    let name = negotiate::best_encoding(headers, &names)?;
+    available.into_iter().find(|(encoding, _)| encoding.name() == name)
+}
+
+fn not_found() -> Response {
+    Response::text(StatusCode::NOT_FOUND, "404 Not Found")
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_stay_under_the_root() {
+        let root = Path::new("/srv/static");
+        for (request_path, resolved) in [
+            ("/static/", Some("/srv/static/")),
+            ("/static/app.js", Some("/srv/static/app.js")),
+            ("/static/css/site.css", Some("/srv/static/css/site.css")),
+            ("/static/a%20b.txt", Some("/srv/static/a b.txt")),
+            ("/static/notes..txt", Some("/srv/static/notes..txt")),
+            ("/static/..%2Fsecret", None),
+            ("/static/%2e%2e/secret", None),
+            ("/static/%2E%2E%2Fsecret", None),
+            ("/static/css/..%2F..%2Fsecret", None),
+            ("/static/%2Fetc%2Fpasswd", None),
+            ("/static/./app.js", None),
+            ("/static/app.js%00.png", None),
+            ("/static/%", None),
+            ("/static/%ff", None),
+            ("/other/app.js", None),
+        ] {
+            assert_eq!(resolve(root, request_path), resolved.map(PathBuf::from), "{}", request_path);
+        }
+    }
+
+    #[test]
+    fn listing_names_are_escaped() {
+        assert_eq!(escape_html(r#"<a href="x">Tom & Jerry's</a>"#), "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;");
+        assert_eq!(escape_html("plain.txt"), "plain.txt");
+
+        assert_eq!(percent_encode("a b:c"), "a%20b%3Ac");
+        assert_eq!(percent_encode("dir/"), "dir/");
+        assert_eq!(percent_encode("file-1_v2.~tar.gz"), "file-1_v2.~tar.gz");
+        assert_eq!(percent_encode("100%?#\""), "100%25%3F%23%22");
+        assert_eq!(percent_encode("caf\u{e9}"), "caf%C3%A9");
+    }
+}
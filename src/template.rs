@@ -0,0 +1,20 @@
+// HTML rendering with minijinja (enabled by the `templates` feature)
+
+use http::StatusCode;
+use http::header::CONTENT_TYPE;
+use minijinja::{Environment, Value};
+
+use crate::response::Response;
+
+/// Render template `name` from `env` with `context` into a complete response. Template errors
+/// are logged and answered with a 500 instead of the page.
+pub fn render(env: &Environment<'_>, name: &str, context: Value) -> Response {
+    let rendered = env.get_template(name).and_then(|template| template.render(context));
+    match rendered {
+        Ok(html) => Response::new(StatusCode::OK).header(CONTENT_TYPE, "text/html; charset=utf-8").body(html),
+        Err(e) => {
+            log::error!("Failed to render template {}: {:#}", name, e);
+            Response::text(StatusCode::INTERNAL_SERVER_ERROR, "500 Internal Server Error")
+        }
+    }
+}
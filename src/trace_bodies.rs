@@ -0,0 +1,56 @@
+// Request and response bodies logged at trace level, for debugging content negotiation and
+// serialization. Only built with the `trace-bodies` feature, and even then silent unless RUST_LOG
+// enables the `quic_demo::bodies` target at trace level.
+//
+// WARNING: bodies can contain passwords, tokens and other personal data, and this writes them to
+// the log as they are. Never enable it where real users' traffic passes.
+
+use h3::quic::StreamId;
+
+/// Log target of the body traces.
+pub const TARGET: &str = "quic_demo::bodies";
+
+/// Bytes of each body that make it into the log; the rest is only counted.
+pub const MAX_TRACED_BODY: usize = 4096;
+
+/// Collects the start of one body as it passes and logs it when dropped, i.e. once the body has
+/// been read or sent in full, or abandoned.
+pub struct Capture(Option<Captured>);
+
+struct Captured {
+    what: &'static str,
+    stream: StreamId,
+    start: Vec<u8>,
+    len: u64,
+}
+
+impl Capture {
+    /// `what` names the body in the log line, e.g. "Request".
+    pub fn new(what: &'static str, stream: StreamId) -> Capture {
+        let enabled = log::log_enabled!(target: TARGET, log::Level::Trace);
+        Capture(enabled.then(|| Captured { what, stream, start: Vec::new(), len: 0 }))
+    }
+
+    pub fn add(&mut self, chunk: &[u8]) {
+        let Some(captured) = &mut self.0 else { return };
+        let room = MAX_TRACED_BODY.saturating_sub(captured.start.len());
+        captured.start.extend_from_slice(&chunk[..room.min(chunk.len())]);
+        captured.len += chunk.len() as u64;
+    }
+}
+
+impl Drop for Captured {
+    fn drop(&mut self) {
+        let rest = self.len - self.start.len() as u64;
+        let marker = if rest > 0 { format!("... [truncated, {} more bytes]", rest) } else { String::new() };
+        log::trace!(
+            target: TARGET,
+            "{} body on {}, {} bytes: {}{}",
+            self.what,
+            self.stream,
+            self.len,
+            self.start.escape_ascii(),
+            marker
+        );
+    }
+}
@@ -0,0 +1,262 @@
+// Raw unidirectional QUIC streams alongside HTTP/3 on the same connection, for experimenting with
+// custom sub-protocols. HTTP/3 claims every unidirectional stream the peer opens and aborts types
+// it doesn't know (RFC 9114, section 6.2), so custom streams start with their own stream type and
+// `Connection` splits them off before h3 sees them. On the way it reads the peer's SETTINGS off its
+// control stream, which h3 doesn't expose.
+
+use std::sync::Arc;
+use std::task::{self, Poll, ready};
+
+use anyhow::{Context, bail};
+use bytes::{Buf, Bytes, BytesMut};
+use h3::quic::{self, ConnectionErrorIncoming, StreamErrorIncoming, StreamId};
+use h3_quinn::quinn;
+use tokio::sync::{mpsc, watch};
+
+use crate::settings::PeerSettings;
+
+/// Stream type that marks a custom unidirectional stream. Not registered and not of the reserved
+/// `0x1f * N + 0x21` form, so it can't collide with HTTP/3 or its GREASE values.
+pub const STREAM_TYPE: u64 = 0x3f5e;
+
+// HTTP/3 control stream type and the SETTINGS frame it opens with (RFC 9114, sections 6.2.1, 7.2.4)
+const CONTROL_STREAM_TYPE: u64 = 0x00;
+const SETTINGS_FRAME_TYPE: u64 = 0x04;
+// A SETTINGS frame larger than this is handed to h3 without being parsed
+const MAX_SETTINGS_LEN: u64 = 16 * 1024;
+
+/// Open a unidirectional stream on `conn`, send `data` on it and finish it. The peer has to split
+/// custom streams off with [`Connection`] (the library `Client` does).
+pub async fn send(conn: &quinn::Connection, data: Bytes) -> anyhow::Result<()> {
+    let mut stream = conn.open_uni().await.context("failed to open a unidirectional stream")?;
+    stream.write_all(&encode_varint(STREAM_TYPE)).await?;
+    stream.write_chunk(data).await?;
+    stream.finish()?;
+    // Wait until the peer has everything, so the caller knows the data made it
+    if let Some(code) = stream.stopped().await? {
+        bail!("peer stopped the stream with code {}", code);
+    }
+    Ok(())
+}
+
+/// Custom unidirectional streams the peer opened, type prefix already stripped.
+pub struct UniStreams {
+    streams: mpsc::UnboundedReceiver<quinn::RecvStream>,
+}
+
+impl UniStreams {
+    /// The next stream, or `None` once the connection is gone.
+    pub async fn accept(&mut self) -> Option<quinn::RecvStream> {
+        self.streams.recv().await
+    }
+}
+
+/// An h3 transport over quinn that hands custom unidirectional streams to [`UniStreams`] and
+/// everything else to h3. Requests (bidirectional streams) pass straight through to `h3_quinn`.
+pub struct Connection {
+    inner: h3_quinn::Connection,
+    h3_streams: mpsc::UnboundedReceiver<Result<RecvStream, ConnectionErrorIncoming>>,
+    peer_settings: watch::Receiver<Option<PeerSettings>>,
+}
+
+impl Connection {
+    pub fn new(conn: quinn::Connection) -> (Connection, UniStreams) {
+        let (h3_tx, h3_streams) = mpsc::unbounded_channel();
+        let (custom_tx, custom_streams) = mpsc::unbounded_channel();
+        let (settings_tx, peer_settings) = watch::channel(None);
+        tokio::spawn(split_uni_streams(conn.clone(), h3_tx, custom_tx, settings_tx));
+
+        let connection = Connection { inner: h3_quinn::Connection::new(conn), h3_streams, peer_settings };
+        (connection, UniStreams { streams: custom_streams })
+    }
+
+    /// The peer's SETTINGS once its control stream has delivered them. The channel closes without
+    /// a value if the connection goes away first.
+    pub fn peer_settings(&self) -> watch::Receiver<Option<PeerSettings>> {
+        self.peer_settings.clone()
+    }
+}
+
+// Read each incoming stream's type and route it. Stops when the connection closes or h3 is done
+// with it, so the task never keeps the connection alive on its own.
+async fn split_uni_streams(
+    conn: quinn::Connection,
+    h3_tx: mpsc::UnboundedSender<Result<RecvStream, ConnectionErrorIncoming>>,
+    custom_tx: mpsc::UnboundedSender<quinn::RecvStream>,
+    settings_tx: watch::Sender<Option<PeerSettings>>,
+) {
+    loop {
+        let stream = tokio::select! {
+            stream = conn.accept_uni() => stream,
+            _ = h3_tx.closed() => return,
+        };
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                let _ = h3_tx.send(Err(convert_connection_error(e)));
+                return;
+            }
+        };
+
+        // Each stream is peeked in its own task so a peer that is slow to send the type can't
+        // hold up the others (h3's control stream among them)
+        let (h3_tx, custom_tx, settings_tx) = (h3_tx.clone(), custom_tx.clone(), settings_tx.clone());
+        tokio::spawn(async move {
+            let Ok((stream_type, prefix)) = read_varint(&mut stream).await else { return };
+            if stream_type == STREAM_TYPE {
+                let _ = custom_tx.send(stream);
+            } else {
+                let prefix = match stream_type {
+                    CONTROL_STREAM_TYPE => read_settings(&mut stream, prefix, &settings_tx).await,
+                    _ => prefix,
+                };
+                let stream = RecvStream { prefix: Some(prefix), inner: stream };
+                let _ = h3_tx.send(Ok(stream));
+            }
+        });
+    }
+}
+
+// Reads the SETTINGS frame a control stream starts with and returns every byte read, stream type
+// included, for h3 to process as if it had read them itself. Anything unexpected (another frame,
+// a read error) is left for h3 to deal with.
+async fn read_settings(stream: &mut quinn::RecvStream, stream_type: Bytes, settings_tx: &watch::Sender<Option<PeerSettings>>) -> Bytes {
+    let mut read = BytesMut::from(stream_type);
+    let Ok((frame_type, bytes)) = read_varint(stream).await else { return read.freeze() };
+    read.extend_from_slice(&bytes);
+    if frame_type != SETTINGS_FRAME_TYPE {
+        return read.freeze();
+    }
+    let Ok((len, bytes)) = read_varint(stream).await else { return read.freeze() };
+    read.extend_from_slice(&bytes);
+    if len > MAX_SETTINGS_LEN {
+        return read.freeze();
+    }
+    let mut payload = vec![0; len as usize];
+    if stream.read_exact(&mut payload).await.is_err() {
+        return read.freeze();
+    }
+    read.extend_from_slice(&payload);
+    if let Some(settings) = PeerSettings::parse(&payload) {
+        settings_tx.send_replace(Some(settings));
+    }
+    read.freeze()
+}
+
+// Returns the value and the bytes it was read from, so they can be replayed to h3
+async fn read_varint(stream: &mut quinn::RecvStream) -> anyhow::Result<(u64, Bytes)> {
+    let mut buf = [0u8; 8];
+    stream.read_exact(&mut buf[..1]).await?;
+    // The top two bits of the first byte give the encoded length: 1, 2, 4 or 8 bytes
+    let len = 1 << (buf[0] >> 6);
+    stream.read_exact(&mut buf[1..len]).await?;
+
+    let value = buf[1..len].iter().fold(u64::from(buf[0] & 0x3f), |value, byte| (value << 8) | u64::from(*byte));
+    Ok((value, Bytes::copy_from_slice(&buf[..len])))
+}
+
+pub(crate) fn encode_varint(value: u64) -> Vec<u8> {
+    match value {
+        0..0x40 => vec![value as u8],
+        0x40..0x4000 => (value as u16 | 0x4000).to_be_bytes().to_vec(),
+        0x4000..0x4000_0000 => (value as u32 | 0x8000_0000).to_be_bytes().to_vec(),
+        _ => (value | 0xc000_0000_0000_0000).to_be_bytes().to_vec(),
+    }
+}
+
+// Same mapping h3_quinn uses, which it doesn't export
+pub(crate) fn convert_connection_error(e: quinn::ConnectionError) -> ConnectionErrorIncoming {
+    match e {
+        quinn::ConnectionError::ApplicationClosed(close) => {
+            ConnectionErrorIncoming::ApplicationClose { error_code: close.error_code.into() }
+        }
+        quinn::ConnectionError::TimedOut => ConnectionErrorIncoming::Timeout,
+        e => ConnectionErrorIncoming::Undefined(Arc::new(e)),
+    }
+}
+
+impl<B: Buf> quic::Connection<B> for Connection {
+    type RecvStream = RecvStream;
+    type OpenStreams = h3_quinn::OpenStreams;
+
+    fn poll_accept_recv(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<RecvStream, ConnectionErrorIncoming>> {
+        match self.h3_streams.poll_recv(cx) {
+            Poll::Ready(Some(result)) => Poll::Ready(result),
+            // Only after the connection error was already reported
+            Poll::Ready(None) => Poll::Ready(Err(ConnectionErrorIncoming::InternalError(
+                "connection closed".to_string(),
+            ))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_accept_bidi(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<Self::BidiStream, ConnectionErrorIncoming>> {
+        quic::Connection::<B>::poll_accept_bidi(&mut self.inner, cx)
+    }
+
+    fn opener(&self) -> h3_quinn::OpenStreams {
+        quic::Connection::<B>::opener(&self.inner)
+    }
+}
+
+impl<B: Buf> quic::OpenStreams<B> for Connection {
+    type BidiStream = h3_quinn::BidiStream<B>;
+    type SendStream = h3_quinn::SendStream<B>;
+
+    fn poll_open_bidi(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<Self::BidiStream, StreamErrorIncoming>> {
+        self.inner.poll_open_bidi(cx)
+    }
+
+    fn poll_open_send(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<Self::SendStream, StreamErrorIncoming>> {
+        self.inner.poll_open_send(cx)
+    }
+
+    fn close(&mut self, code: h3::error::Code, reason: &[u8]) {
+        quic::OpenStreams::<B>::close(&mut self.inner, code, reason)
+    }
+}
+
+/// A unidirectional stream for h3, with the stream type that was read to route it put back in front.
+pub struct RecvStream {
+    prefix: Option<Bytes>,
+    inner: quinn::RecvStream,
+}
+
+// h3's own unidirectional streams (control, QPACK) carry little data, so small reads are fine
+const READ_BUF_SIZE: usize = 4096;
+
+impl quic::RecvStream for RecvStream {
+    type Buf = Bytes;
+
+    fn poll_data(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<Option<Bytes>, StreamErrorIncoming>> {
+        if let Some(prefix) = self.prefix.take() {
+            return Poll::Ready(Ok(Some(prefix)));
+        }
+
+        let mut buf = [0u8; READ_BUF_SIZE];
+        match ready!(self.inner.poll_read(cx, &mut buf)) {
+            Ok(0) => Poll::Ready(Ok(None)),
+            Ok(n) => Poll::Ready(Ok(Some(Bytes::copy_from_slice(&buf[..n])))),
+            Err(quinn::ReadError::Reset(code)) => {
+                Poll::Ready(Err(StreamErrorIncoming::StreamTerminated { error_code: code.into_inner() }))
+            }
+            Err(quinn::ReadError::ConnectionLost(e)) => Poll::Ready(Err(StreamErrorIncoming::ConnectionErrorIncoming {
+                connection_error: convert_connection_error(e),
+            })),
+            Err(e) => Poll::Ready(Err(StreamErrorIncoming::Unknown(Box::new(e)))),
+        }
+    }
+
+    fn stop_sending(&mut self, error_code: u64) {
+        if let Ok(code) = quinn::VarInt::from_u64(error_code) {
+            let _ = self.inner.stop(code);
+        }
+    }
+
+    fn recv_id(&self) -> StreamId {
+        u64::from(self.inner.id()).try_into().expect("quinn stream ids are valid h3 stream ids")
+    }
+}
@@ -0,0 +1,48 @@
+// A request the authorizer denies is answered with the status it picked, 403 Forbidden unless
+// it names another, and never reaches its handler.
+
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use http::StatusCode;
+use quic_demo::router::Router;
+use quic_demo::server::{Authorization, Server};
+use quic_demo::simulate::NetworkConditions;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn denied_requests_skip_the_handler() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counted = calls.clone();
+    let router = Router::new().route("/secret", move |_req| {
+        counted.fetch_add(1, Ordering::SeqCst);
+        async { "the secret" }
+    });
+    let builder = Server::builder().host("localhost", router).authorizer(|req, _conn| {
+        let authorization = match req.headers().get("authorization").map(|value| value.as_bytes()) {
+            None => Authorization::Deny(Some(StatusCode::UNAUTHORIZED)),
+            Some(b"Bearer right") => Authorization::Allow,
+            Some(_) => Authorization::Deny(None),
+        };
+        async move { authorization }
+    });
+    let (addr, _serving) = common::spawn(builder);
+
+    let endpoint = common::client_endpoint(NetworkConditions::default());
+    let (mut send_request, _conn) = common::connect(&endpoint, addr).await;
+
+    let response = common::get(&mut send_request, "/secret").await.unwrap();
+    assert_eq!(response, (StatusCode::UNAUTHORIZED, "401 Unauthorized".to_string()));
+    let response = common::send(&mut send_request, http::Method::GET, "/secret", &[("authorization", "Bearer wrong")], b"")
+        .await
+        .unwrap();
+    assert_eq!(response, (StatusCode::FORBIDDEN, "403 Forbidden".to_string()));
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+    let response = common::send(&mut send_request, http::Method::GET, "/secret", &[("authorization", "Bearer right")], b"")
+        .await
+        .unwrap();
+    assert_eq!(response, (StatusCode::OK, "the secret".to_string()));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,58 @@
+// A large streamed response to a client that stops reading must be held back by flow control
+// instead of piling up in the server's memory.
+
+mod common;
+
+use std::time::Duration;
+
+use bytes::Buf;
+use quic_demo::server::Server;
+use quic_demo::simulate::NetworkConditions;
+
+// Far more than quinn's windows and buffers, which add up to a few MB per stream
+const BODY_LEN: u64 = 256 * 1024 * 1024;
+const MAX_GROWTH: u64 = 64 * 1024 * 1024;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slow_reader_keeps_server_memory_flat() {
+    let (addr, _serving) = common::spawn(Server::builder().generate_route(true));
+
+    let endpoint = common::client_endpoint(NetworkConditions::default());
+    let (mut send_request, _) = common::connect(&endpoint, addr).await;
+
+    let req = http::Request::get(format!("https://localhost/debug/stream?bytes={}", BODY_LEN))
+        .body(())
+        .unwrap();
+    let mut stream = send_request.send_request(req).await.unwrap();
+    stream.finish().await.unwrap();
+    let response = stream.recv_response().await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+
+    // Server and client share this process, so its RSS covers whatever the server buffers
+    let before = resident_bytes();
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let stalled = resident_bytes();
+    assert!(
+        stalled.saturating_sub(before) < MAX_GROWTH,
+        "memory grew by {} bytes while the client was not reading",
+        stalled - before
+    );
+
+    // Resume reading and make sure everything still arrives
+    let mut received = 0;
+    let mut peak = stalled;
+    while let Some(chunk) = stream.recv_data().await.unwrap() {
+        received += chunk.remaining() as u64;
+        if received % (16 * 1024 * 1024) < chunk.remaining() as u64 {
+            peak = peak.max(resident_bytes());
+        }
+    }
+    assert_eq!(received, BODY_LEN);
+    assert!(peak.saturating_sub(before) < MAX_GROWTH, "memory grew by {} bytes while streaming", peak - before);
+}
+
+fn resident_bytes() -> u64 {
+    let statm = std::fs::read_to_string("/proc/self/statm").unwrap();
+    let pages: u64 = statm.split_whitespace().nth(1).unwrap().parse().unwrap();
+    pages * 4096
+}
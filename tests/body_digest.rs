@@ -0,0 +1,57 @@
+// An upload sent with `Content-Digest: sha-256=:...:` is hashed as it streams in and gets `400`
+// when its SHA-256 differs, while one with a matching digest, or a digest the server can't check,
+// goes through as usual.
+
+mod common;
+
+use bytes::Bytes;
+use http::{Method, StatusCode};
+use quic_demo::client::{Client, RequestBody};
+use quic_demo::server::Server;
+
+// SHA-256 of "hello world", in base64 and hex
+const HELLO_WORLD: &str = "uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=";
+const HELLO_WORLD_HEX: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn uploads_are_checked_against_their_content_digest() {
+    let (addr, _serving) = common::spawn(Server::builder());
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    let upload = |digest: Option<&str>, body: &'static str| {
+        let mut req = client.request(Method::POST, "/upload");
+        if let Some(digest) = digest {
+            req = req.header("content-digest", digest);
+        }
+        let req = req.body(()).unwrap();
+        let client = &client;
+        async move { client.send(req, Some(&RequestBody::Inline(Bytes::from(body)))).await.unwrap() }
+    };
+
+    let response = upload(Some(&format!("sha-256=:{}:", HELLO_WORLD)), "hello world").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, format!("Received 11 bytes, SHA-256 {}", HELLO_WORLD_HEX));
+
+    // Among other algorithms, only sha-256 is checked
+    let response = upload(Some(&format!("sha-512=:AAAA:, sha-256=:{}:", HELLO_WORLD)), "hello world").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(upload(Some("sha-512=:AAAA:"), "hello world").await.status, StatusCode::OK);
+
+    let response = upload(Some(&format!("sha-256=:{}:", HELLO_WORLD)), "hello there").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    let message = String::from_utf8(response.body.to_vec()).unwrap();
+    assert!(message.contains(&format!("not the {} its Content-Digest declared", HELLO_WORLD_HEX)), "{}", message);
+
+    // Not base64, or not 32 bytes
+    assert_eq!(upload(Some("sha-256=:hello:"), "hello world").await.status, StatusCode::BAD_REQUEST);
+    assert_eq!(upload(Some("sha-256=:AAAA:"), "hello world").await.status, StatusCode::BAD_REQUEST);
+
+    // Other body routes check it too
+    let req = client.request(Method::PUT, "/kv/greeting").header("content-digest", format!("sha-256=:{}:", HELLO_WORLD));
+    let response = client.send(req.body(()).unwrap(), Some(&RequestBody::Inline(Bytes::from("hi")))).await.unwrap();
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    let response = client.send(client.request(Method::GET, "/kv/greeting").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    client.close().await;
+}
@@ -0,0 +1,35 @@
+// A client that stops sending halfway through a request body is told to stop with
+// `H3_REQUEST_CANCELLED` once `body_read_timeout` passes without a chunk, and the stream is
+// counted in `quic_request_body_timeouts_total`.
+
+mod common;
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use h3::error::{Code, StreamError};
+use quic_demo::server::Server;
+use quic_demo::simulate::NetworkConditions;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stalled_uploads_are_stopped() {
+    let server = common::bind(Server::builder().body_read_timeout(Some(Duration::from_millis(300))));
+    let metrics = server.metrics();
+    let (addr, _serving) = common::serve(server);
+
+    let endpoint = common::client_endpoint(NetworkConditions::default());
+    let (mut send_request, _conn) = common::connect(&endpoint, addr).await;
+
+    let req = http::Request::post("https://localhost/upload").header("content-length", "10").body(()).unwrap();
+    let mut stream = send_request.send_request(req).await.unwrap();
+    stream.send_data(Bytes::from_static(b"hello")).await.unwrap();
+
+    // The other half never comes, so the server stops reading and resets its side as well
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let stopped = stream.send_data(Bytes::from_static(b"world")).await.unwrap_err();
+    match stopped {
+        StreamError::RemoteTerminate { code, .. } => assert_eq!(code, Code::H3_REQUEST_CANCELLED),
+        other => panic!("expected STOP_SENDING, got {:?}", other),
+    }
+    assert!(metrics.render().contains("quic_request_body_timeouts_total 1\n"), "{}", metrics.render());
+}
@@ -0,0 +1,57 @@
+// `ServerBuilder::certificate` serves the chain and key from PEM files instead of a self-signed
+// certificate, and `bind` names the file it couldn't use.
+
+mod common;
+
+use std::path::PathBuf;
+
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::fingerprint::Fingerprint;
+use quic_demo::server::Server;
+
+// A certificate for localhost, written to `<name>.crt.pem` and `<name>.key.pem` in a directory of
+// this test's own
+fn write_certificate(name: &str) -> (rcgen::CertifiedKey<rcgen::KeyPair>, PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("quic-demo-certificate-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let (cert, key) = (dir.join(format!("{}.crt.pem", name)), dir.join(format!("{}.key.pem", name)));
+    std::fs::write(&cert, certified.cert.pem()).unwrap();
+    std::fs::write(&key, certified.signing_key.serialize_pem()).unwrap();
+    (certified, cert, key)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_the_certificate_from_the_files() {
+    let (certified, cert, key) = write_certificate("served");
+    let fingerprint = Fingerprint::of(certified.cert.der());
+
+    let server = common::bind(Server::builder().certificate(Some((cert, key))));
+    assert_eq!(server.certificate_fingerprint(), fingerprint);
+    let (addr, _serving) = common::serve(server);
+
+    let client = Client::builder(addr, "localhost").pin_certificate(fingerprint).connect().await.unwrap();
+    let response = client.send(client.request(Method::GET, "/health").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    client.close().await;
+}
+
+#[test]
+fn unusable_files_are_named() {
+    let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+    let (_, cert, key) = write_certificate("unusable");
+    let missing = cert.with_file_name("missing.pem");
+
+    let bind = |cert: &PathBuf, key: &PathBuf| {
+        let builder = Server::builder().listen("127.0.0.1:0".parse().unwrap());
+        format!("{:#}", builder.certificate(Some((cert.clone(), key.clone()))).bind().err().unwrap())
+    };
+    let error = bind(&missing, &key);
+    assert!(error.contains(&format!("cannot read certificate file {}", missing.display())), "{}", error);
+    let error = bind(&cert, &missing);
+    assert!(error.contains(&format!("cannot read private key from {}", missing.display())), "{}", error);
+    // A certificate where the key should be
+    let error = bind(&cert, &cert);
+    assert!(error.contains(&format!("cannot read private key from {}", cert.display())), "{}", error);
+}
@@ -0,0 +1,71 @@
+// A custom connection ID generator replaces quinn's random one for every ID the server issues,
+// so two runs with a deterministic generator hand out the same IDs.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use h3_quinn::quinn::{ConnectionId, ConnectionIdGenerator};
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::server::Server;
+
+// Counts up from 1, as 8-byte big-endian IDs
+struct Sequential {
+    next: u64,
+    issued: Arc<Mutex<Vec<ConnectionId>>>,
+}
+
+impl ConnectionIdGenerator for Sequential {
+    fn generate_cid(&mut self) -> ConnectionId {
+        self.next += 1;
+        let cid = ConnectionId::new(&self.next.to_be_bytes());
+        self.issued.lock().unwrap().push(cid);
+        cid
+    }
+
+    fn cid_len(&self) -> usize {
+        8
+    }
+
+    fn cid_lifetime(&self) -> Option<Duration> {
+        None
+    }
+}
+
+async fn issued_ids() -> Vec<ConnectionId> {
+    let issued = Arc::new(Mutex::new(Vec::new()));
+    let generator_issued = issued.clone();
+    let server = common::bind(
+        Server::builder().cid_generator(move || Box::new(Sequential { next: 0, issued: generator_issued.clone() })),
+    );
+    let addr = server.local_addr().unwrap();
+    let handle = server.spawn();
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    let response = client.send(client.request(Method::GET, "/").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    client.close().await;
+    handle.shutdown();
+    handle.stopped().await.unwrap();
+
+    issued.lock().unwrap().clone()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn deterministic_generator_issues_the_same_ids() {
+    let first = issued_ids().await;
+    assert!(!first.is_empty(), "the server never asked the generator for an ID");
+    assert_eq!(first, issued_ids().await);
+}
+
+#[test]
+fn cid_length_and_generator_conflict() {
+    let result = Server::builder()
+        .listen("127.0.0.1:0".parse().unwrap())
+        .cid_length(Some(4))
+        .cid_generator(|| Box::new(Sequential { next: 0, issued: Arc::default() }))
+        .bind();
+    assert!(result.is_err());
+}
@@ -0,0 +1,55 @@
+// With `ServerBuilder::client_ca` set, a client has to present a certificate issued by that CA
+// (mutual TLS), and /debug/client-cert describes the one it presented.
+
+mod common;
+
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::server::Server;
+use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_certificates_are_required_and_reported() {
+    let mut params = CertificateParams::new(Vec::new()).unwrap();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.distinguished_name.push(DnType::CommonName, "Test CA");
+    let ca = CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap();
+
+    let key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::new(vec!["alice.example".to_string()]).unwrap();
+    params.distinguished_name.push(DnType::CommonName, "alice");
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    let alice = params.signed_by(&key, &ca).unwrap();
+
+    let dir = std::env::temp_dir().join(format!("quic-demo-client-certificate-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let ca_file = dir.join("ca.pem");
+    std::fs::write(&ca_file, ca.pem()).unwrap();
+
+    let (addr, _serving) = common::spawn(Server::builder().client_ca(Some(ca_file)));
+
+    // Without a certificate the handshake fails, or the connection is closed before the request
+    let anonymous = async {
+        let client = Client::builder(addr, "localhost").connect().await?;
+        client.send(client.request(Method::GET, "/debug/client-cert").body(()).unwrap(), None).await?;
+        anyhow::Ok(())
+    };
+    assert!(anonymous.await.is_err());
+
+    let client = Client::builder(addr, "localhost")
+        .client_certificate(
+            vec![alice.der().clone()],
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+        )
+        .connect()
+        .await
+        .unwrap();
+    let response = client.send(client.request(Method::GET, "/debug/client-cert").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    let description = String::from_utf8(response.body.to_vec()).unwrap();
+    assert!(description.contains("subject: CN=alice\n"), "{}", description);
+    assert!(description.contains("issuer: CN=Test CA\n"), "{}", description);
+    assert!(description.contains("subject alt name: DNS:alice.example\n"), "{}", description);
+    client.close().await;
+}
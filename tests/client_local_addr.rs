@@ -0,0 +1,44 @@
+// `ClientBuilder::local_addr` binds the client's socket where it's told, so the server sees
+// connections from that port, and refuses addresses it can't bind or reach the server from.
+
+mod common;
+
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::server::Server;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn connects_from_the_given_port() {
+    let peers = Arc::new(Mutex::new(Vec::new()));
+    let seen = peers.clone();
+    let (addr, _serving) = common::spawn(
+        Server::builder().on_connect(move |conn| seen.lock().unwrap().push(conn.remote_address)),
+    );
+
+    // A port that was free a moment ago
+    let local = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let client = Client::builder(addr, "localhost").local_addr(local).connect().await.unwrap();
+    assert_eq!(client.local_addr().unwrap(), local);
+    let response = client.send(client.request(Method::GET, "/").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(*peers.lock().unwrap(), [local]);
+
+    // Taken by the first client now
+    let error = Client::builder(addr, "localhost").local_addr(local).connect().await.err().unwrap();
+    assert!(format!("{:#}", error).contains(&format!("failed to bind UDP socket on {}", local)), "{:#}", error);
+
+    client.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_a_local_address_of_another_family() {
+    let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+
+    let server: SocketAddr = "127.0.0.1:4433".parse().unwrap();
+    let local: SocketAddr = "[::1]:0".parse().unwrap();
+    let error = Client::builder(server, "localhost").local_addr(local).connect().await.err().unwrap();
+    assert!(error.to_string().contains("another address family"), "{:#}", error);
+}
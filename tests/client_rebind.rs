@@ -0,0 +1,43 @@
+// The reusable `Client` moving to a new socket in the middle of a download, like a phone
+// switching networks: the transfer carries on over the same connection.
+
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::server::Server;
+
+const BODY_LEN: usize = 64 * 1024 * 1024;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn download_survives_rebinding() {
+    let (addr, _serving) = common::spawn(Server::builder().generate_route(true));
+
+    let client = Arc::new(Client::builder(addr, "localhost").connect().await.unwrap());
+    let before = client.local_addr().unwrap();
+
+    let downloading = tokio::spawn({
+        let client = client.clone();
+        async move {
+            let req = client.request(Method::GET, &format!("/debug/stream?bytes={}", BODY_LEN)).body(()).unwrap();
+            client.send(req, None).await
+        }
+    });
+    // Well into the transfer, but not through it
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!downloading.is_finished(), "the download finished before the client could rebind");
+
+    let after = client.rebind(SocketAddr::new(before.ip(), 0)).unwrap();
+    assert_ne!(before.port(), after.port());
+
+    let response = downloading.await.unwrap().unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body.len(), BODY_LEN);
+    assert_eq!(client.connections_established(), 1);
+
+    client.close().await;
+}
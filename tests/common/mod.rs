@@ -0,0 +1,151 @@
+// Helpers shared by the integration tests: a server on a free local port that stops with the
+// test, a client endpoint that trusts its self-signed certificate, and an h3 connection on top
+// of it. Not every test uses every helper.
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use bytes::{BufMut, Bytes};
+use h3::error::StreamError;
+use h3_quinn::quinn;
+use http::{Method, StatusCode};
+use quic_demo::server::{Server, ServerBuilder};
+use quic_demo::simulate::NetworkConditions;
+use quic_demo::socket::{self, SocketOptions};
+use tokio::task::JoinHandle;
+
+pub type SendRequest = h3::client::SendRequest<h3_quinn::OpenStreams, Bytes>;
+
+// Binds `builder` to a free port on 127.0.0.1, for tests that need the server before it serves
+pub fn bind(builder: ServerBuilder) -> Server {
+    let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+    builder.listen("127.0.0.1:0".parse().unwrap()).bind().unwrap()
+}
+
+// Serves in the background until the returned guard is dropped
+pub fn serve(server: Server) -> (SocketAddr, Serving) {
+    let addr = server.local_addr().unwrap();
+    (addr, Serving(tokio::spawn(server.serve())))
+}
+
+pub fn spawn(builder: ServerBuilder) -> (SocketAddr, Serving) {
+    serve(bind(builder))
+}
+
+pub struct Serving(JoinHandle<anyhow::Result<()>>);
+
+impl Drop for Serving {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+pub fn client_endpoint(conditions: NetworkConditions) -> quinn::Endpoint {
+    let mut tls_config = rustls::ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(SkipServerVerification))
+        .with_no_client_auth();
+    tls_config.alpn_protocols = vec![b"h3".to_vec()];
+
+    let client_config = quinn::ClientConfig::new(Arc::new(
+        quinn::crypto::rustls::QuicClientConfig::try_from(tls_config).unwrap(),
+    ));
+    let options = SocketOptions { network: conditions, ..SocketOptions::default() };
+    let mut endpoint = socket::endpoint("127.0.0.1:0".parse().unwrap(), None, options).unwrap();
+    endpoint.set_default_client_config(client_config);
+    endpoint
+}
+
+// Starts a handshake with the server and goes quiet: a real client's Initial packet is caught and
+// sent on from a socket that never answers what comes back. The handshake lasts as long as the
+// socket is kept.
+pub async fn stalled_handshake(addr: SocketAddr) -> tokio::net::UdpSocket {
+    let silent = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = client_endpoint(NetworkConditions::default());
+    let connecting = endpoint.connect(silent.local_addr().unwrap(), "localhost").unwrap();
+    let mut initial = vec![0; 65536];
+    let (len, _) = silent.recv_from(&mut initial).await.unwrap();
+    drop(connecting);
+    silent.send_to(&initial[..len], addr).await.unwrap();
+    silent
+}
+
+// Connects and drives the connection in the background. The QUIC connection comes along for
+// tests that look underneath HTTP/3, e.g. at datagrams.
+pub async fn connect(endpoint: &quinn::Endpoint, addr: SocketAddr) -> (h3::client::SendRequest<h3_quinn::OpenStreams, Bytes>, quinn::Connection) {
+    let conn = endpoint.connect(addr, "localhost").unwrap().await.unwrap();
+    let (mut driver, send_request) = h3::client::new(h3_quinn::Connection::new(conn.clone())).await.unwrap();
+    tokio::spawn(async move { futures::future::poll_fn(|cx| driver.poll_close(cx)).await });
+    (send_request, conn)
+}
+
+// Sends a request over a raw h3 connection and reads the whole response. The server may stop
+// reading the body early, so sending it can fail without failing the request.
+pub async fn send(
+    send_request: &mut SendRequest,
+    method: Method,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<(StatusCode, String), StreamError> {
+    let mut req = http::Request::builder().method(method).uri(format!("https://localhost{}", path));
+    for (name, value) in headers {
+        req = req.header(*name, *value);
+    }
+    let mut stream = send_request.send_request(req.body(()).unwrap()).await?;
+    if !body.is_empty() {
+        let _ = stream.send_data(Bytes::copy_from_slice(body)).await;
+    }
+    let _ = stream.finish().await;
+    let status = stream.recv_response().await?.status();
+    let mut received = Vec::new();
+    while let Some(chunk) = stream.recv_data().await? {
+        received.put(chunk);
+    }
+    Ok((status, String::from_utf8(received).unwrap()))
+}
+
+pub async fn get(send_request: &mut SendRequest, path: &str) -> Result<(StatusCode, String), StreamError> {
+    send(send_request, Method::GET, path, &[], b"").await
+}
+
+#[derive(Debug)]
+struct SkipServerVerification;
+
+impl rustls::client::danger::ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        _server_name: &rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &rustls::pki_types::CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &rustls::pki_types::CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        rustls::crypto::aws_lc_rs::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
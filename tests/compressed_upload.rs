@@ -0,0 +1,48 @@
+// Compressed uploads are decoded as they arrive, in bounded steps: the original data is what gets
+// hashed, a cut-off body gets `400`, and one that expands past the decoded size cap gets `413`
+// even though the server sets no body size limit.
+
+mod common;
+
+use bytes::Bytes;
+use http::{Method, StatusCode};
+use quic_demo::body::MAX_DECODED_SIZE;
+use quic_demo::client::{Client, RequestBody};
+use quic_demo::compress::Encoding;
+use quic_demo::server::Server;
+use sha2::{Digest, Sha256};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn uploads_are_decoded_within_the_cap() {
+    let (addr, _serving) = common::spawn(Server::builder());
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    let upload = |encoding: Encoding, body: Vec<u8>| {
+        let req = client.request(Method::POST, "/upload").header("content-encoding", encoding.name());
+        let req = req.body(()).unwrap();
+        let client = &client;
+        async move { client.send(req, Some(&RequestBody::Inline(Bytes::from(body)))).await.unwrap() }
+    };
+
+    // Several decode steps' worth, not all alike so brotli doesn't shrink it to nothing
+    let original: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let digest: String = Sha256::digest(&original).iter().map(|b| format!("{:02x}", b)).collect();
+    for encoding in Encoding::ALL {
+        let compressed = encoding.compress(&original).unwrap();
+        let response = upload(encoding, compressed.clone()).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", encoding.name());
+        assert!(String::from_utf8(response.body.to_vec()).unwrap().ends_with(&digest), "{}", encoding.name());
+
+        let cut_off = compressed[..compressed.len() / 2].to_vec();
+        assert_eq!(upload(encoding, cut_off).await.status, StatusCode::BAD_REQUEST, "{}", encoding.name());
+    }
+
+    // A few hundred KiB that decode to more than the cap
+    let zeros = vec![0; MAX_DECODED_SIZE as usize + 1];
+    for encoding in Encoding::ALL {
+        let response = upload(encoding, encoding.compress(&zeros).unwrap()).await;
+        assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE, "{}", encoding.name());
+    }
+
+    client.close().await;
+}
@@ -0,0 +1,64 @@
+// CONNECT-UDP tunnels to loopback targets are refused with 403 unless the server allows private
+// addresses; once allowed, datagrams go through both ways and a reply that's too large to send
+// back is dropped without ending the tunnel.
+
+mod common;
+
+use bytes::{Buf, Bytes};
+use h3::ext::Protocol;
+use http::{Method, Request, StatusCode};
+use quic_demo::server::Server;
+use quic_demo::simulate::NetworkConditions;
+use tokio::net::UdpSocket;
+
+fn connect_udp(port: u16) -> Request<()> {
+    let mut req = Request::builder()
+        .method(Method::CONNECT)
+        .uri(format!("https://localhost/.well-known/masque/udp/127.0.0.1/{}/", port))
+        .body(())
+        .unwrap();
+    req.extensions_mut().insert(Protocol::CONNECT_UDP);
+    req
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn loopback_targets_need_to_be_allowed() {
+    let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+
+    // Answers every packet with a copy and then one too large for the server's frame limit
+    let target = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let target_port = target.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let mut buf = vec![0; 2048];
+        while let Ok((len, from)) = target.recv_from(&mut buf).await {
+            let _ = target.send_to(&vec![0; 500], from).await;
+            let _ = target.send_to(&buf[..len], from).await;
+        }
+    });
+
+    let endpoint = common::client_endpoint(NetworkConditions::default());
+
+    let (addr, serving) = common::spawn(Server::builder().connect_udp(true));
+    let (mut send_request, _conn) = common::connect(&endpoint, addr).await;
+    let mut stream = send_request.send_request(connect_udp(target_port)).await.unwrap();
+    assert_eq!(stream.recv_response().await.unwrap().status(), StatusCode::FORBIDDEN);
+    drop(serving);
+
+    let (addr, _serving) = common::spawn(
+        Server::builder()
+            .connect_udp(true)
+            .connect_udp_allow_private(true)
+            .max_datagram_frame_size(Some(200)),
+    );
+    let (mut send_request, conn) = common::connect(&endpoint, addr).await;
+    let mut stream = send_request.send_request(connect_udp(target_port)).await.unwrap();
+    assert_eq!(stream.recv_response().await.unwrap().status(), StatusCode::OK);
+
+    // Quarter stream ID 0 (the first request stream), context ID 0, then the payload
+    conn.send_datagram(Bytes::from_static(b"\x00\x00ping")).unwrap();
+    let mut datagram = conn.read_datagram().await.unwrap();
+    assert_eq!(datagram.get_u16(), 0);
+    assert_eq!(datagram, "ping");
+
+    stream.finish().await.unwrap();
+}
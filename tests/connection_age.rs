@@ -0,0 +1,96 @@
+// `ServerBuilder::max_connection_age` sends GOAWAY to a connection once it is that old, even while
+// it is busy, lets the request in flight finish and then closes it, so the client's next request
+// goes on a new connection, even while requests wait for room in its in-flight budget.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::events::Event;
+use quic_demo::server::Server;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn busy_connections_are_rotated() {
+    let server = common::bind(
+        Server::builder()
+            .debug_routes(true)
+            .max_connection_age(Some(Duration::from_millis(500)))
+            .event_buffer(Some(64)),
+    );
+    let mut events = server.events().unwrap();
+    let (addr, _serving) = common::serve(server);
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    let started = Instant::now();
+    // Still running when the connection reaches its age
+    let response = client.send(client.request(Method::GET, "/debug/slow?secs=1").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+
+    let (requests, elapsed) = loop {
+        match events.recv().await.unwrap() {
+            Event::ConnectionClosed { requests, reason, .. } => {
+                assert!(reason.is_none(), "{:?}", reason);
+                break (requests, started.elapsed());
+            }
+            _ => continue,
+        }
+    };
+    assert_eq!(requests, 1);
+    assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+
+    let response = client.send(client.request(Method::GET, "/").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(client.connections_established(), 2);
+
+    client.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn requests_outliving_the_grace_period_are_cut_off() {
+    let (addr, _serving) = common::spawn(
+        Server::builder()
+            .debug_routes(true)
+            .max_connection_age(Some(Duration::from_millis(200)))
+            .shutdown_timeout(Duration::from_millis(200)),
+    );
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    let started = Instant::now();
+    let result = client.send(client.request(Method::POST, "/debug/slow?secs=30").body(()).unwrap(), None).await;
+    assert!(result.is_err(), "the request outlived its connection");
+    assert!(started.elapsed() < Duration::from_secs(5), "{:?}", started.elapsed());
+
+    client.close().await;
+}
+
+// A request queued behind the connection's in-flight budget doesn't hold GOAWAY back: it goes out
+// on time, and new requests move to a new connection while the old ones finish
+#[tokio::test(flavor = "multi_thread")]
+async fn queued_requests_dont_delay_goaway() {
+    let (addr, _serving) = common::spawn(
+        Server::builder()
+            .debug_routes(true)
+            .max_inflight_per_connection(1)
+            .max_connection_age(Some(Duration::from_millis(300)))
+            .shutdown_timeout(Duration::from_secs(10)),
+    );
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    let slow = || client.send(client.request(Method::POST, "/debug/slow?secs=2").body(()).unwrap(), None);
+    let later = async {
+        tokio::time::sleep(Duration::from_millis(800)).await;
+        let started = Instant::now();
+        let response = client.send(client.request(Method::GET, "/").body(()).unwrap(), None).await.unwrap();
+        (response.status, started.elapsed())
+    };
+    let (first, second, (status, elapsed)) = tokio::join!(slow(), slow(), later);
+    assert_eq!(first.unwrap().status, StatusCode::OK);
+    assert_eq!(second.unwrap().status, StatusCode::OK);
+    assert_eq!(status, StatusCode::OK);
+    assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+    assert_eq!(client.connections_established(), 2);
+
+    client.close().await;
+}
@@ -0,0 +1,49 @@
+// `ConnInfo::send_datagram` checks each datagram against the limit in force when it is sent, the
+// peer's and the server's own `max_datagram_frame_size`, and refuses larger ones with a typed
+// error instead of dropping them.
+
+mod common;
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use http::{Method, Request, StatusCode};
+use quic_demo::datagram::DatagramError;
+use quic_demo::server::{ConnInfo, Server};
+use quic_demo::simulate::NetworkConditions;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn oversized_datagrams_are_refused() {
+    let (addr, _serving) = common::spawn(Server::builder().max_datagram_frame_size(Some(100)).fallback(
+        |req: Request<()>| async move {
+            let conn = req.extensions().get::<Arc<ConnInfo>>().unwrap();
+            let small = conn.send_datagram(Bytes::from(vec![1; 100]));
+            let large = match conn.send_datagram(Bytes::from(vec![2; 101])) {
+                Err(DatagramError::TooLarge { size, max }) => format!("too large: {} > {}", size, max),
+                other => format!("{:?}", other),
+            };
+            format!("max {:?}, small {:?}, large {}", conn.max_datagram_size(), small, large)
+        },
+    ));
+
+    let endpoint = common::client_endpoint(NetworkConditions::default());
+    let (mut send_request, conn) = common::connect(&endpoint, addr).await;
+
+    let req = Request::builder().method(Method::GET).uri(format!("https://localhost:{}/datagram", addr.port())).body(()).unwrap();
+    let mut stream = send_request.send_request(req).await.unwrap();
+    stream.finish().await.unwrap();
+    let response = stream.recv_response().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut body = Vec::new();
+    while let Some(chunk) = stream.recv_data().await.unwrap() {
+        body.extend_from_slice(bytes::Buf::chunk(&chunk));
+    }
+    let body = String::from_utf8(body).unwrap();
+    assert_eq!(body, "max Some(100), small Ok(()), large too large: 101 > 100");
+
+    // Only the datagram within the limit arrived
+    let datagram = conn.read_datagram().await.unwrap();
+    assert_eq!(datagram, vec![1; 100]);
+
+    conn.close(0u32.into(), b"");
+}
@@ -0,0 +1,39 @@
+// The /debug routes only exist when `debug_routes` is turned on, since they let any client hold
+// server resources, and once they do, /debug/reset resets the stream with the code asked for.
+
+mod common;
+
+use h3::error::{Code, StreamError};
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::server::Server;
+use quic_demo::simulate::NetworkConditions;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn debug_routes_are_off_by_default() {
+    let (addr, _serving) = common::spawn(Server::builder());
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    for path in ["/debug/slow?secs=1", "/debug/ndjson?records=1", "/debug/reset"] {
+        let response = client.send(client.request(Method::GET, path).body(()).unwrap(), None).await.unwrap();
+        assert_eq!(response.status, StatusCode::NOT_FOUND, "{}", path);
+    }
+
+    client.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reset_uses_the_requested_code() {
+    let (addr, _serving) = common::spawn(Server::builder().debug_routes(true));
+
+    let endpoint = common::client_endpoint(NetworkConditions::default());
+    let (mut send_request, _conn) = common::connect(&endpoint, addr).await;
+
+    let req = http::Request::get("https://localhost/debug/reset?code=H3_EXCESSIVE_LOAD").body(()).unwrap();
+    let mut stream = send_request.send_request(req).await.unwrap();
+    stream.finish().await.unwrap();
+    match stream.recv_response().await.unwrap_err() {
+        StreamError::RemoteTerminate { code, .. } => assert_eq!(code, Code::H3_EXCESSIVE_LOAD),
+        other => panic!("expected a reset, got {:?}", other),
+    }
+}
@@ -0,0 +1,51 @@
+// `Client::download` counts a large body chunk by chunk without keeping it, and `?mb=` sizes
+// /debug/stream in megabytes, up to its maximum and only with the route turned on.
+
+mod common;
+
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::server::{DEBUG_STREAM_MAX, Server};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn download_counts_the_whole_body() {
+    let (addr, _serving) = common::spawn(Server::builder().generate_route(true));
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    let (mut chunks, mut counted) = (0, 0);
+    let download = client
+        .download(client.request(Method::GET, "/debug/stream?mb=20").body(()).unwrap(), |len| {
+            chunks += 1;
+            counted += len as u64;
+        })
+        .await
+        .unwrap();
+    assert_eq!(download.status, StatusCode::OK);
+    assert_eq!(download.headers["content-length"], "20000000");
+    assert_eq!(download.received, 20_000_000);
+    assert_eq!(counted, download.received);
+    assert!(chunks > 1, "the body came in {} chunk(s)", chunks);
+
+    client.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn debug_stream_is_opt_in_and_bounded() {
+    let (addr, serving) = common::spawn(Server::builder());
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    let response = client.send(client.request(Method::GET, "/debug/stream?bytes=10").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    client.close().await;
+    drop(serving);
+
+    let (addr, _serving) = common::spawn(Server::builder().generate_route(true));
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    // Only the head is read; the body goes away with the response
+    for query in ["mb=18446744073709551615", "bytes=18446744073709551615"] {
+        let req = client.request(Method::GET, &format!("/debug/stream?{}", query)).body(()).unwrap();
+        let response = client.request_streaming(req, None).await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.headers["content-length"], DEBUG_STREAM_MAX.to_string().as_str());
+    }
+    client.close().await;
+}
@@ -0,0 +1,56 @@
+// A connection whose requests keep going wrong is closed with H3_EXCESSIVE_LOAD once it has used
+// up its error budget, while one that errs no more often than it succeeds stays open.
+
+mod common;
+
+use std::time::Duration;
+
+use http::header::TE;
+use http::{Method, StatusCode};
+use quic_demo::client::{Client, Reconnect};
+use quic_demo::server::Server;
+
+// Refused with 400: HTTP/3 only allows `TE: trailers`
+async fn bad(client: &Client) -> anyhow::Result<StatusCode> {
+    let req = client.request(Method::GET, "/").header(TE, "gzip").body(()).unwrap();
+    Ok(client.send(req, None).await?.status)
+}
+
+async fn good(client: &Client) -> anyhow::Result<StatusCode> {
+    Ok(client.send(client.request(Method::GET, "/").body(()).unwrap(), None).await?.status)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn closes_connections_that_keep_erring() {
+    let server = common::bind(Server::builder().error_budget(Some(3)));
+    let metrics = server.metrics();
+    let (addr, _serving) = common::serve(server);
+
+    let (client, mut drivers) = Client::builder(addr, "localhost").connect_with_drivers().await.unwrap();
+    let driving = tokio::spawn(drivers.next().await.unwrap().run());
+    assert_eq!(bad(&client).await.unwrap(), StatusCode::BAD_REQUEST);
+    assert_eq!(bad(&client).await.unwrap(), StatusCode::BAD_REQUEST);
+    // Takes one off again
+    assert_eq!(good(&client).await.unwrap(), StatusCode::OK);
+    assert_eq!(bad(&client).await.unwrap(), StatusCode::BAD_REQUEST);
+    // The third error in excess: the connection may close before the 400 gets out
+    let _ = bad(&client).await;
+
+    let closed = tokio::time::timeout(Duration::from_secs(5), driving).await.expect("the connection is still open").unwrap();
+    assert!(closed.to_string().contains("H3_EXCESSIVE_LOAD"), "{}", closed);
+    assert!(metrics.render().contains("quic_error_budget_closes_total 1\n"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn leaves_connections_that_mostly_succeed_alone() {
+    let (addr, _serving) = common::spawn(Server::builder().error_budget(Some(2)));
+
+    let client = Client::builder(addr, "localhost").reconnect(Reconnect::Never).connect().await.unwrap();
+    for _ in 0..20 {
+        assert_eq!(bad(&client).await.unwrap(), StatusCode::BAD_REQUEST);
+        assert_eq!(good(&client).await.unwrap(), StatusCode::OK);
+    }
+    assert_eq!(client.connections_established(), 1);
+
+    client.close().await;
+}
@@ -0,0 +1,82 @@
+// Subscribers to the server's events see a connection's lifecycle in order, and one that falls
+// behind misses the oldest events instead of holding up the server.
+
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::events::Event;
+use quic_demo::server::Server;
+use tokio::sync::Notify;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::error::RecvError;
+
+async fn next(events: &mut Receiver<Event>) -> Event {
+    tokio::time::timeout(Duration::from_secs(5), events.recv()).await.expect("no event within 5s").unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn connection_and_request_lifecycle() {
+    let server = common::bind(Server::builder().event_buffer(Some(64)));
+    let mut events = server.events().unwrap();
+    let (addr, _serving) = common::serve(server);
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    let local = client.local_addr().unwrap().port();
+    for path in ["/", "/nowhere"] {
+        client.send(client.request(Method::GET, path).body(()).unwrap(), None).await.unwrap();
+    }
+    client.close().await;
+
+    let Event::ConnectionOpened { connection, remote_address, alpn, .. } = next(&mut events).await else {
+        panic!("expected the connection first");
+    };
+    assert_eq!(remote_address.port(), local);
+    assert_eq!(alpn.as_deref(), Some("h3"));
+
+    for (expected_path, expected_status) in [("/", StatusCode::OK), ("/nowhere", StatusCode::NOT_FOUND)] {
+        let Event::RequestStarted { connection: on, stream, method, path } = next(&mut events).await else {
+            panic!("expected a request to start");
+        };
+        assert_eq!((on, method, path.as_str()), (connection, Method::GET, expected_path));
+        let Event::RequestCompleted { connection: on, stream: completed, status, .. } = next(&mut events).await else {
+            panic!("expected the request to complete");
+        };
+        assert_eq!((on, completed, status), (connection, stream, Some(expected_status)));
+    }
+
+    let Event::ConnectionClosed { connection: closed, requests, .. } = next(&mut events).await else {
+        panic!("expected the connection to close");
+    };
+    assert_eq!((closed, requests), (connection, 2));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slow_subscribers_lose_the_oldest_events() {
+    let closed = Arc::new(Notify::new());
+    let notify = closed.clone();
+    let server = common::bind(Server::builder().event_buffer(Some(4)).on_close(move |_| notify.notify_one()));
+    let mut events = server.events().unwrap();
+    let (addr, _serving) = common::serve(server);
+
+    // 1 opened, 2 per request, 1 closed: far more than fit
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    for _ in 0..10 {
+        let response = client.send(client.request(Method::GET, "/").body(()).unwrap(), None).await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+    }
+    client.close().await;
+    // The close is the last event, published just before the hook runs
+    closed.notified().await;
+
+    assert_eq!(events.recv().await.unwrap_err(), RecvError::Lagged(18));
+    let mut kept = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        kept.push(event);
+    }
+    assert_eq!(kept.len(), 4);
+    assert!(matches!(kept.last(), Some(Event::ConnectionClosed { requests: 10, .. })), "{:?}", kept.last());
+}
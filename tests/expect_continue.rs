@@ -0,0 +1,54 @@
+// An upload sent with `Expect: 100-continue` gets an interim 100 once its headers pass the
+// server's checks and the final response after its body, while one the server would refuse gets
+// its 413 or 417 right away, without the body ever being sent.
+
+mod common;
+
+use bytes::{Buf, Bytes};
+use http::StatusCode;
+use quic_demo::server::Server;
+use quic_demo::simulate::NetworkConditions;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn continue_comes_before_the_body() {
+    let (addr, _serving) = common::spawn(Server::builder());
+
+    let endpoint = common::client_endpoint(NetworkConditions::default());
+    let (mut send_request, _conn) = common::connect(&endpoint, addr).await;
+
+    let req = http::Request::post("https://localhost/upload")
+        .header("expect", "100-continue")
+        .header("content-length", "5")
+        .body(())
+        .unwrap();
+    let mut stream = send_request.send_request(req).await.unwrap();
+    assert_eq!(stream.recv_response().await.unwrap().status(), StatusCode::CONTINUE);
+
+    stream.send_data(Bytes::from_static(b"hello")).await.unwrap();
+    stream.finish().await.unwrap();
+    assert_eq!(stream.recv_response().await.unwrap().status(), StatusCode::OK);
+    let mut body = Vec::new();
+    while let Some(mut chunk) = stream.recv_data().await.unwrap() {
+        body.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+    }
+    assert!(String::from_utf8(body).unwrap().starts_with("Received 5 bytes"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refused_uploads_are_answered_without_continue() {
+    let (addr, _serving) = common::spawn(Server::builder().max_body_size(Some(4)));
+
+    let endpoint = common::client_endpoint(NetworkConditions::default());
+    let (mut send_request, _conn) = common::connect(&endpoint, addr).await;
+
+    for (expect, status) in [("100-continue", StatusCode::PAYLOAD_TOO_LARGE), ("something-else", StatusCode::EXPECTATION_FAILED)] {
+        let req = http::Request::post("https://localhost/upload")
+            .header("expect", expect)
+            .header("content-length", "5")
+            .body(())
+            .unwrap();
+        let mut stream = send_request.send_request(req).await.unwrap();
+        // The first header section is already the final response
+        assert_eq!(stream.recv_response().await.unwrap().status(), status, "{}", expect);
+    }
+}
@@ -0,0 +1,53 @@
+// Requests whose framing headers can't be trusted get 400 before any handler runs: unparseable or
+// conflicting Content-Length values, HTTP/1.1 connection-specific fields, and bodies that turn
+// out longer or shorter than their declared length.
+
+mod common;
+
+use http::{Method, StatusCode};
+use quic_demo::server::Server;
+use quic_demo::simulate::NetworkConditions;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn malformed_framing_is_rejected() {
+    let (addr, _serving) = common::spawn(Server::builder());
+
+    let endpoint = common::client_endpoint(NetworkConditions::default());
+    let (mut send_request, _) = common::connect(&endpoint, addr).await;
+
+    let bad_headers: &[&[(&str, &str)]] = &[
+        &[("content-length", "5"), ("content-length", "6")],
+        &[("content-length", "five")],
+        &[("content-length", "-5")],
+        &[("transfer-encoding", "chunked")],
+        &[("connection", "keep-alive")],
+        &[("keep-alive", "timeout=5")],
+        &[("upgrade", "websocket")],
+        &[("te", "gzip")],
+    ];
+    for headers in bad_headers {
+        for path in ["/", "/upload"] {
+            let (status, body) = common::send(&mut send_request, Method::POST, path, headers, b"hello").await.unwrap();
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{} with {:?}", path, headers);
+            assert!(body.starts_with("400 Bad Request: "), "{}", body);
+        }
+    }
+
+    // Repeating the same length is fine, and so is TE: trailers
+    let same_length: &[(&str, &str)] = &[("content-length", "5"), ("content-length", "5"), ("te", "trailers")];
+    let (status, body) = common::send(&mut send_request, Method::POST, "/upload", same_length, b"hello").await.unwrap();
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.starts_with("Received 5 bytes"), "{}", body);
+
+    // The body contradicts its declared length, one way or the other
+    for (declared, sent) in [("10", &b"hello"[..]), ("3", &b"hello hello"[..])] {
+        let headers = [("content-length", declared)];
+        let (status, body) = common::send(&mut send_request, Method::POST, "/upload", &headers, sent).await.unwrap();
+        assert_eq!(status, StatusCode::BAD_REQUEST, "declared {} bytes, sent {}", declared, sent.len());
+        assert!(body.starts_with("400 Bad Request: request body"), "{}", body);
+    }
+
+    // None of it took the connection down
+    let (status, _) = common::send(&mut send_request, Method::GET, "/health", &[], b"").await.unwrap();
+    assert_eq!(status, StatusCode::OK);
+}
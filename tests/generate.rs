@@ -0,0 +1,48 @@
+// /generate is off unless enabled, and then sends exactly the requested number of bytes of data
+// that `generate::verify` accepts, and that it rejects once a byte is changed.
+
+mod common;
+
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::generate;
+use quic_demo::server::Server;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn generated_data_verifies() {
+    let (addr, _serving) = common::spawn(Server::builder().generate_route(true));
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+
+    // Not a multiple of the 8-byte counters, so the last one is cut off
+    let response = client.send(client.request(Method::GET, "/generate?bytes=1000003").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers["content-length"], "1000003");
+    assert_eq!(response.body.len(), 1_000_003);
+    assert_eq!(generate::verify(0, &response.body), None);
+    assert_eq!(&response.body[8..16], &1u64.to_be_bytes());
+
+    let mut corrupted = response.body.to_vec();
+    corrupted[12345] ^= 1;
+    assert_eq!(generate::verify(0, &corrupted), Some(12345));
+    // A chunk checked at the wrong offset doesn't pass either
+    assert_eq!(generate::verify(8, &response.body[..16]), Some(15));
+
+    let response = client.send(client.request(Method::GET, "/generate?bytes=0").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.is_empty());
+    let response = client.send(client.request(Method::GET, "/generate?bytes=lots").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    client.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn generate_is_off_by_default() {
+    let (addr, _serving) = common::spawn(Server::builder());
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+
+    let response = client.send(client.request(Method::GET, "/generate?bytes=10").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    client.close().await;
+}
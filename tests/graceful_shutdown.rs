@@ -0,0 +1,46 @@
+// Shutdown sends GOAWAY on every open connection: requests already in flight still get their
+// responses, and the server closes each connection once they have, instead of waiting for the
+// client to go away or the shutdown timeout to run out.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::events::Event;
+use quic_demo::server::Server;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn in_flight_requests_complete_after_goaway() {
+    let server = common::bind(Server::builder().debug_routes(true).shutdown_timeout(Duration::from_secs(30)).event_buffer(Some(16)));
+    let addr = server.local_addr().unwrap();
+    let server = server.spawn();
+    let mut events = server.events().unwrap();
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    let req = client.request(Method::GET, "/debug/slow?secs=1").body(()).unwrap();
+    let started = Instant::now();
+    let (response, ()) = tokio::join!(client.send(req, None), async {
+        // Shut down once the server has the request
+        while !matches!(events.recv().await.unwrap(), Event::RequestStarted { .. }) {}
+        server.shutdown();
+    });
+    let response = response.unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "Worked for 1 seconds");
+
+    // The client keeps its connection open, so only GOAWAY lets the server finish this early
+    tokio::time::timeout(Duration::from_secs(10), server.stopped()).await.expect("shutdown waited for the client").unwrap();
+    assert!(started.elapsed() < Duration::from_secs(10), "{:?}", started.elapsed());
+    let closed = loop {
+        match events.recv().await.unwrap() {
+            Event::ConnectionClosed { requests, reason, .. } => break (requests, reason),
+            _ => continue,
+        }
+    };
+    assert_eq!(closed.0, 1);
+    assert!(closed.1.is_none(), "{:?}", closed.1);
+
+    client.close().await;
+}
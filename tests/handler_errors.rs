@@ -0,0 +1,61 @@
+// A handler that fails before its response starts gets a proper error response; one that fails
+// after the header section is out gets its stream reset, never a body that merely looks short.
+
+mod common;
+
+use std::io;
+
+use anyhow::anyhow;
+use bytes::Bytes;
+use futures::StreamExt;
+use h3::error::{Code, StreamError};
+use http::StatusCode;
+use quic_demo::response::{HandlerError, Response};
+use quic_demo::router::Router;
+use quic_demo::server::Server;
+use quic_demo::simulate::NetworkConditions;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn errors_before_and_after_the_header_section() {
+    let router = Router::new()
+        .route("/fails", |_req| async {
+            let answer: u32 = "forty-two".parse()?;
+            Ok::<_, HandlerError>(answer.to_string())
+        })
+        .route("/unavailable", |_req| async {
+            Err::<String, _>(HandlerError::from(anyhow!("backend down")).status(StatusCode::SERVICE_UNAVAILABLE))
+        })
+        .route("/fails-midway", |_req| async {
+            let chunks = futures::stream::iter([Ok(Bytes::from("partial")), Err(io::Error::other("disk gone"))]);
+            Response::new(StatusCode::OK).chunks(chunks)
+        })
+        .route("/panics-midway", |_req| async {
+            let chunks = futures::stream::iter([true, false]).map(|more| match more {
+                true => Ok(Bytes::from("partial")),
+                false => panic!("handler bug"),
+            });
+            Response::new(StatusCode::OK).chunks(chunks)
+        });
+    let (addr, _serving) = common::spawn(Server::builder().host("localhost", router));
+
+    let endpoint = common::client_endpoint(NetworkConditions::default());
+    let (mut send_request, _) = common::connect(&endpoint, addr).await;
+
+    let response = common::get(&mut send_request, "/fails").await.unwrap();
+    assert_eq!(response, (StatusCode::INTERNAL_SERVER_ERROR, "500 Internal Server Error".to_string()));
+
+    let response = common::get(&mut send_request, "/unavailable").await.unwrap();
+    assert_eq!(response, (StatusCode::SERVICE_UNAVAILABLE, "503 Service Unavailable".to_string()));
+
+    // The reset can overtake the header section, so the client may not even see the 200
+    for path in ["/fails-midway", "/panics-midway"] {
+        match common::get(&mut send_request, path).await {
+            Err(StreamError::RemoteTerminate { code, .. }) => assert_eq!(code, Code::H3_INTERNAL_ERROR, "{}", path),
+            other => panic!("{}: expected a reset, got {:?}", path, other),
+        }
+    }
+
+    // The connection is still fine for other requests
+    let (status, _) = common::get(&mut send_request, "/fails").await.unwrap();
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+}
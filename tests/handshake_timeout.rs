@@ -0,0 +1,28 @@
+// A client that starts a handshake and goes quiet is dropped once `handshake_timeout` passes and
+// counted under `quic_handshake_failures_total{reason="timeout"}`.
+
+mod common;
+
+use std::time::Duration;
+
+use quic_demo::server::Server;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stalled_handshakes_time_out() {
+    let server = common::bind(Server::builder().handshake_timeout(Duration::from_millis(300)));
+    let metrics = server.metrics();
+    let (addr, _serving) = common::serve(server);
+
+    let _silent = common::stalled_handshake(addr).await;
+
+    let timed_out = "quic_handshake_failures_total{reason=\"timeout\"} 1\n";
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!metrics.render().contains(timed_out), "{}", metrics.render());
+    for _ in 0..50 {
+        if metrics.render().contains(timed_out) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("no handshake timeout:\n{}", metrics.render());
+}
@@ -0,0 +1,39 @@
+// A request stream whose header section stops halfway is reset with `H3_REQUEST_REJECTED` once
+// `header_timeout` passes, and counted in `quic_request_header_timeouts_total`; the connection
+// itself carries on.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use h3::error::Code;
+use http::StatusCode;
+use quic_demo::server::Server;
+use quic_demo::simulate::NetworkConditions;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dribbled_headers_are_reset() {
+    let server = common::bind(Server::builder().header_timeout(Duration::from_millis(300)));
+    let metrics = server.metrics();
+    let (addr, _serving) = common::serve(server);
+
+    let endpoint = common::client_endpoint(NetworkConditions::default());
+    let (mut send_request, conn) = common::connect(&endpoint, addr).await;
+
+    // A HEADERS frame announcing 100 bytes, of which only three ever come
+    let (mut send, mut recv) = conn.open_bi().await.unwrap();
+    send.write_all(&[0x01, 0x40, 0x64, 0xd1, 0xd7, 0x50]).await.unwrap();
+    let started = Instant::now();
+    let reset = recv.read_to_end(1024).await.unwrap_err();
+    assert!(started.elapsed() >= Duration::from_millis(250), "{:?}", started.elapsed());
+    match reset {
+        quinn::ReadToEndError::Read(quinn::ReadError::Reset(code)) => {
+            assert_eq!(code.into_inner(), Code::H3_REQUEST_REJECTED.value())
+        }
+        other => panic!("expected a reset, got {:?}", other),
+    }
+    assert!(metrics.render().contains("quic_request_header_timeouts_total 1\n"), "{}", metrics.render());
+
+    let (status, _) = common::get(&mut send_request, "/").await.unwrap();
+    assert_eq!(status, StatusCode::OK);
+}
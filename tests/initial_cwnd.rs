@@ -0,0 +1,39 @@
+// `initial_cwnd` sets the congestion window connections start with, and out-of-range windows are
+// refused at bind time.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use http::Method;
+use quic_demo::client::Client;
+use quic_demo::server::Server;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn connections_start_with_the_configured_window() {
+    let windows = Arc::new(Mutex::new(Vec::new()));
+    let seen = windows.clone();
+    let (addr, _serving) = common::spawn(
+        Server::builder()
+            .initial_cwnd(Some(1_000_000))
+            .on_connect(move |conn| seen.lock().unwrap().push(conn.connection.stats().path.cwnd)),
+    );
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    client.send(client.request(Method::GET, "/health").body(()).unwrap(), None).await.unwrap();
+    client.close().await;
+
+    // Slow start may already have grown it by the handshake's few acknowledged kilobytes
+    let windows = windows.lock().unwrap();
+    assert_eq!(windows.len(), 1);
+    assert!((1_000_000..1_100_000).contains(&windows[0]), "cwnd {}", windows[0]);
+}
+
+#[test]
+fn out_of_range_windows_are_refused() {
+    for bytes in [0, 2399, 16 * 1024 * 1024 + 1] {
+        let error = Server::builder().listen("127.0.0.1:0".parse().unwrap()).initial_cwnd(Some(bytes)).bind().err();
+        let error = error.unwrap_or_else(|| panic!("{} bytes were accepted", bytes)).to_string();
+        assert!(error.contains("initial congestion window"), "{}", error);
+    }
+}
@@ -0,0 +1,131 @@
+// The key-value demo keeps values across requests and connections, answers each method with the
+// status that says what happened to the key, refuses writes whose If-Match no longer holds, and
+// answers 507 once it is full.
+
+mod common;
+
+use bytes::Bytes;
+use http::header::{ALLOW, ETAG, IF_MATCH};
+use http::{Method, StatusCode};
+use quic_demo::client::{Client, RequestBody};
+use quic_demo::server::Server;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn values_are_stored_replaced_and_deleted() {
+    let (addr, _serving) = common::spawn(Server::builder());
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    let send = |method: Method, path: &str, body: Option<&'static str>| {
+        let req = client.request(method, path).body(()).unwrap();
+        let client = &client;
+        async move {
+            let body = body.map(|body| RequestBody::Inline(Bytes::from(body)));
+            client.send(req, body.as_ref()).await.unwrap()
+        }
+    };
+
+    assert_eq!(send(Method::GET, "/kv/colour", None).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(send(Method::PUT, "/kv/colour", Some("blue")).await.status, StatusCode::CREATED);
+    let response = send(Method::GET, "/kv/colour", None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "blue");
+
+    assert_eq!(send(Method::PUT, "/kv/colour", Some("green")).await.status, StatusCode::NO_CONTENT);
+    assert_eq!(send(Method::GET, "/kv/colour", None).await.body, "green");
+
+    // Another connection sees the same store
+    let other = Client::builder(addr, "localhost").connect().await.unwrap();
+    let response = other.send(other.request(Method::GET, "/kv/colour").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.body, "green");
+    other.close().await;
+
+    assert_eq!(send(Method::DELETE, "/kv/colour", None).await.status, StatusCode::NO_CONTENT);
+    assert_eq!(send(Method::DELETE, "/kv/colour", None).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(send(Method::GET, "/kv/colour", None).await.status, StatusCode::NOT_FOUND);
+
+    let response = send(Method::POST, "/kv/colour", Some("red")).await;
+    assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(response.headers[ALLOW], "GET, PUT, DELETE");
+
+    // Not a key: the store holds no values under nested or empty paths
+    assert_eq!(send(Method::PUT, "/kv/a/b", Some("x")).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(send(Method::PUT, "/kv/", Some("x")).await.status, StatusCode::NOT_FOUND);
+
+    client.close().await;
+}
+
+// PUT and DELETE with If-Match only go through while the value is still the one the ETag names
+#[tokio::test(flavor = "multi_thread")]
+async fn if_match_guards_writes() {
+    let (addr, _serving) = common::spawn(Server::builder());
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    let send = |method: Method, if_match: Option<&str>, body: Option<&'static str>| {
+        let mut req = client.request(method, "/kv/counter");
+        if let Some(if_match) = if_match {
+            req = req.header(IF_MATCH, if_match);
+        }
+        let req = req.body(()).unwrap();
+        let client = &client;
+        async move {
+            let body = body.map(|body| RequestBody::Inline(Bytes::from(body)));
+            client.send(req, body.as_ref()).await.unwrap()
+        }
+    };
+
+    // Nothing to match yet, even with `*`
+    assert_eq!(send(Method::PUT, Some("*"), Some("1")).await.status, StatusCode::PRECONDITION_FAILED);
+    let created = send(Method::PUT, None, Some("1")).await;
+    assert_eq!(created.status, StatusCode::CREATED);
+    let first = created.headers[ETAG].to_str().unwrap().to_string();
+    assert_eq!(send(Method::GET, None, None).await.headers[ETAG], first.as_str());
+
+    let replaced = send(Method::PUT, Some(&first), Some("2")).await;
+    assert_eq!(replaced.status, StatusCode::NO_CONTENT);
+    let second = replaced.headers[ETAG].to_str().unwrap().to_string();
+    assert_ne!(first, second);
+
+    // A writer still holding the first ETag lost the race
+    assert_eq!(send(Method::PUT, Some(&first), Some("3")).await.status, StatusCode::PRECONDITION_FAILED);
+    assert_eq!(send(Method::DELETE, Some(&first), None).await.status, StatusCode::PRECONDITION_FAILED);
+    assert_eq!(send(Method::GET, None, None).await.body, "2");
+
+    // Weak tags never match, lists match on any member
+    let weak = format!("W/{}", second);
+    assert_eq!(send(Method::PUT, Some(&weak), Some("3")).await.status, StatusCode::PRECONDITION_FAILED);
+    let list = format!("\"nope\", {}", second);
+    assert_eq!(send(Method::DELETE, Some(&list), None).await.status, StatusCode::NO_CONTENT);
+    assert_eq!(send(Method::GET, None, None).await.status, StatusCode::NOT_FOUND);
+
+    client.close().await;
+}
+
+// The store holds a bounded number of keys and bytes; writes past either get 507 and change nothing
+#[tokio::test(flavor = "multi_thread")]
+async fn full_store_refuses_writes() {
+    let (addr, _serving) = common::spawn(Server::builder().kv_limits(2, 10));
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    let send = |method: Method, path: &str, body: Option<&'static str>| {
+        let req = client.request(method, path).body(()).unwrap();
+        let client = &client;
+        async move {
+            let body = body.map(|body| RequestBody::Inline(Bytes::from(body)));
+            client.send(req, body.as_ref()).await.unwrap()
+        }
+    };
+
+    assert_eq!(send(Method::PUT, "/kv/a", Some("1234")).await.status, StatusCode::CREATED);
+    assert_eq!(send(Method::PUT, "/kv/b", Some("1234")).await.status, StatusCode::CREATED);
+    // A third key, then a value that would take the total past 10 bytes
+    assert_eq!(send(Method::PUT, "/kv/c", Some("1")).await.status, StatusCode::INSUFFICIENT_STORAGE);
+    assert_eq!(send(Method::PUT, "/kv/a", Some("1234567")).await.status, StatusCode::INSUFFICIENT_STORAGE);
+    assert_eq!(send(Method::GET, "/kv/a", None).await.body, "1234");
+
+    // Replacing a value frees its size, deleting one frees its key
+    assert_eq!(send(Method::PUT, "/kv/a", Some("123456")).await.status, StatusCode::NO_CONTENT);
+    assert_eq!(send(Method::DELETE, "/kv/b", None).await.status, StatusCode::NO_CONTENT);
+    assert_eq!(send(Method::PUT, "/kv/c", Some("1234")).await.status, StatusCode::CREATED);
+
+    client.close().await;
+}
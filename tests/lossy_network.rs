@@ -0,0 +1,60 @@
+// Requests must still complete over a bad network: 5% loss each way and a 100ms RTT, with
+// jitter reordering packets.
+
+mod common;
+
+use std::time::Duration;
+
+use bytes::Buf;
+use quic_demo::server::Server;
+use quic_demo::simulate::NetworkConditions;
+
+const BAD_NETWORK: NetworkConditions = NetworkConditions {
+    loss: 0.05,
+    // Each way, so the RTT is about 100ms
+    delay: Duration::from_millis(45),
+    jitter: Duration::from_millis(10),
+};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn requests_survive_loss_and_latency() {
+    let (addr, _serving) = common::spawn(Server::builder().simulate(BAD_NETWORK).generate_route(true));
+
+    let endpoint = common::client_endpoint(BAD_NETWORK);
+    let (mut send_request, _) = common::connect(&endpoint, addr).await;
+
+    // Small requests, several in flight at once
+    let requests: Vec<_> = (0..10)
+        .map(|_| {
+            let mut send_request = send_request.clone();
+            tokio::spawn(async move {
+                let req = http::Request::get("https://localhost/health").body(()).unwrap();
+                let mut stream = send_request.send_request(req).await.unwrap();
+                stream.finish().await.unwrap();
+                let response = stream.recv_response().await.unwrap();
+                let mut body = Vec::new();
+                while let Some(chunk) = stream.recv_data().await.unwrap() {
+                    body.extend_from_slice(chunk.chunk());
+                }
+                (response.status(), body)
+            })
+        })
+        .collect();
+    for request in requests {
+        let (status, body) = request.await.unwrap();
+        assert_eq!(status, http::StatusCode::OK);
+        assert_eq!(body, b"hello from http3 health check");
+    }
+
+    // A body spanning many round trips, so lost packets are certain to need retransmitting
+    let len: u64 = 512 * 1024;
+    let req = http::Request::get(format!("https://localhost/debug/stream?bytes={}", len)).body(()).unwrap();
+    let mut stream = send_request.send_request(req).await.unwrap();
+    stream.finish().await.unwrap();
+    assert_eq!(stream.recv_response().await.unwrap().status(), http::StatusCode::OK);
+    let mut received = 0;
+    while let Some(chunk) = stream.recv_data().await.unwrap() {
+        received += chunk.remaining() as u64;
+    }
+    assert_eq!(received, len);
+}
@@ -0,0 +1,27 @@
+// Past `max_handshakes` connections in the handshake at once, a new one waits for a slot, here
+// until a stalled handshake holding the only one times out.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use http::StatusCode;
+use quic_demo::server::Server;
+use quic_demo::simulate::NetworkConditions;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn handshakes_wait_for_a_slot() {
+    let (addr, _serving) = common::spawn(Server::builder().max_handshakes(1).handshake_timeout(Duration::from_millis(500)));
+
+    let _silent = common::stalled_handshake(addr).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let started = Instant::now();
+    let endpoint = common::client_endpoint(NetworkConditions::default());
+    let (mut send_request, _conn) = common::connect(&endpoint, addr).await;
+    let waited = started.elapsed();
+    assert!(waited >= Duration::from_millis(350), "connected after {:?}", waited);
+
+    let (status, _) = common::get(&mut send_request, "/").await.unwrap();
+    assert_eq!(status, StatusCode::OK);
+}
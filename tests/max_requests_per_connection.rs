@@ -0,0 +1,26 @@
+// A connection that has carried `max_requests_per_connection` requests is sent GOAWAY and
+// closed, and the client carries on over a new one.
+
+mod common;
+
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::server::Server;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn connections_are_replaced_after_their_requests() {
+    let server = common::bind(Server::builder().max_requests_per_connection(Some(3)));
+    let metrics = server.metrics();
+    let (addr, _serving) = common::serve(server);
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    for _ in 0..5 {
+        let response = client.send(client.request(Method::GET, "/").body(()).unwrap(), None).await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+    }
+    assert_eq!(client.connections_established(), 2);
+    assert!(metrics.render().contains("quic_connections_accepted_total 2\n"), "{}", metrics.render());
+    assert!(metrics.render().contains("quic_requests_total 5\n"), "{}", metrics.render());
+
+    client.close().await;
+}
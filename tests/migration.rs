@@ -0,0 +1,41 @@
+// A client that moves to a new address (here: rebinds its socket to another port, like after a
+// NAT rebinding) keeps its connection, and handlers see the address it moved to.
+
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use http::StatusCode;
+use quic_demo::server::{Authorization, Server};
+use quic_demo::simulate::NetworkConditions;
+
+const HEALTH: &str = "hello from http3 health check";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn handlers_follow_a_migrated_client() {
+    // The authorizer sees every request's ConnInfo, so it records the addresses
+    let seen = Arc::new(Mutex::new(Vec::<SocketAddr>::new()));
+    let recorder = seen.clone();
+    let (addr, _serving) = common::spawn(Server::builder().authorizer(move |_req, conn| {
+        recorder.lock().unwrap().push(conn.remote_address);
+        async { Authorization::Allow }
+    }));
+
+    let endpoint = common::client_endpoint(NetworkConditions::default());
+    let (mut send_request, _) = common::connect(&endpoint, addr).await;
+    let before = endpoint.local_addr().unwrap();
+    assert_eq!(common::get(&mut send_request, "/health").await.unwrap(), (StatusCode::OK, HEALTH.to_string()));
+
+    endpoint.rebind(std::net::UdpSocket::bind("127.0.0.1:0").unwrap()).unwrap();
+    let after = endpoint.local_addr().unwrap();
+    assert_ne!(before, after);
+    assert_eq!(common::get(&mut send_request, "/health").await.unwrap(), (StatusCode::OK, HEALTH.to_string()));
+    assert_eq!(*seen.lock().unwrap(), [before, after]);
+
+    // Give the server's migration check a moment to notice
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    let (_, metrics) = common::get(&mut send_request, "/metrics").await.unwrap();
+    assert!(metrics.contains("quic_connection_migrations_total 1\n"), "{}", metrics);
+}
@@ -0,0 +1,84 @@
+// Accept negotiation: each supported type takes the quality of the most specific range matching
+// it, `q=0` rules a type out, malformed entries are skipped, ties go to the server's order, and
+// /greeting answers 406 when nothing it has is acceptable.
+
+mod common;
+
+use http::header::{ACCEPT, CONTENT_TYPE};
+use http::{HeaderMap, HeaderValue, Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::negotiate::best_match;
+use quic_demo::server::Server;
+
+const SUPPORTED: &[&str] = &["application/json", "text/plain"];
+
+fn accept(values: &[&'static str]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for value in values {
+        headers.append(ACCEPT, HeaderValue::from_static(value));
+    }
+    headers
+}
+
+#[test]
+fn quality_zero_excludes_a_type() {
+    assert_eq!(best_match(&accept(&["application/json;q=0, */*"]), SUPPORTED), Some("text/plain"));
+    assert_eq!(best_match(&accept(&["application/*;q=0, */*;q=0.1"]), SUPPORTED), Some("text/plain"));
+    assert_eq!(best_match(&accept(&["*/*;q=0"]), SUPPORTED), None);
+    // The exact type outranks the wildcard that would allow it
+    assert_eq!(best_match(&accept(&["text/plain;q=0, text/*"]), &["text/plain"]), None);
+}
+
+#[test]
+fn the_most_specific_range_decides() {
+    // text/plain only gets text/*'s 0.2, however high */* is
+    assert_eq!(best_match(&accept(&["text/*;q=0.2, */*;q=0.8"]), &["text/plain", "application/json"]), Some("application/json"));
+    assert_eq!(best_match(&accept(&["text/plain;q=0.9, text/*;q=0.1, */*;q=0.5"]), SUPPORTED), Some("text/plain"));
+    assert_eq!(best_match(&accept(&["*/*;q=0.5, application/json;q=0.4"]), SUPPORTED), Some("text/plain"));
+    // Case doesn't matter, and several headers count as one list
+    assert_eq!(best_match(&accept(&["TEXT/Plain", "application/json;q=0.5"]), SUPPORTED), Some("text/plain"));
+}
+
+#[test]
+fn malformed_entries_are_skipped() {
+    for bad in ["application/json;q=abc", "application/json;q=2", "application/json;q=-1", "*/json", "json", "/json"] {
+        let headers = HeaderMap::from_iter([(ACCEPT, HeaderValue::from_str(&format!("{}, text/plain;q=0.5", bad)).unwrap())]);
+        assert_eq!(best_match(&headers, SUPPORTED), Some("text/plain"), "{}", bad);
+    }
+    // Nothing usable is like no Accept header at all: the server's first choice
+    assert_eq!(best_match(&accept(&["application/json;q=x, nonsense"]), SUPPORTED), Some("application/json"));
+    assert_eq!(best_match(&HeaderMap::new(), SUPPORTED), Some("application/json"));
+}
+
+#[test]
+fn ties_go_to_the_server_order() {
+    assert_eq!(best_match(&accept(&["text/plain, application/json"]), SUPPORTED), Some("application/json"));
+    assert_eq!(best_match(&accept(&["*/*"]), SUPPORTED), Some("application/json"));
+    assert_eq!(best_match(&accept(&["text/plain;q=0.5, application/json;q=0.5"]), &["text/plain", "application/json"]), Some("text/plain"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn greeting_follows_the_accept_header() {
+    let (addr, _serving) = common::spawn(Server::builder());
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    let greeting = |accept: &'static str| {
+        let req = client.request(Method::GET, "/greeting").header(ACCEPT, accept).body(()).unwrap();
+        let client = &client;
+        async move { client.send(req, None).await.unwrap() }
+    };
+
+    let response = greeting("application/json").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers[CONTENT_TYPE], "application/json");
+    assert_eq!(response.body, r#"{"message":"Hello from http3 server"}"#);
+
+    let response = greeting("application/json;q=0, text/*").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "Hello from http3 server");
+
+    assert_eq!(greeting("image/png").await.status, StatusCode::NOT_ACCEPTABLE);
+    assert_eq!(greeting("application/json;q=0, text/plain;q=0").await.status, StatusCode::NOT_ACCEPTABLE);
+
+    client.close().await;
+}
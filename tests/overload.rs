@@ -0,0 +1,84 @@
+// Requests beyond the in-flight limits get `503 Service Unavailable` with the configured
+// Retry-After under `OverloadPolicy::Reject`, both on a busy connection and on a busy server.
+// Under `OverloadPolicy::Queue` a request waits for a server-wide slot, and gets the 503 once
+// `queue_timeout` passes without one.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::server::{OverloadPolicy, Server};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn saturated_limits_reject_with_retry_after() {
+    let server = common::bind(
+        Server::builder()
+            .debug_routes(true)
+            .max_inflight_per_connection(1)
+            .max_inflight_requests(Some(1))
+            .overload_policy(OverloadPolicy::Reject)
+            .retry_after(Duration::from_secs(7)),
+    );
+    let metrics = server.metrics();
+    let (addr, _serving) = common::serve(server);
+
+    let busy = Client::builder(addr, "localhost").connect().await.unwrap();
+    let other = Client::builder(addr, "localhost").connect().await.unwrap();
+    let slow = busy.send(busy.request(Method::GET, "/debug/slow?secs=1").body(()).unwrap(), None);
+    let overloaded = async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        // The same connection is over its own limit, another one over the server's
+        for client in [&busy, &other] {
+            let response = client.send(client.request(Method::GET, "/").body(()).unwrap(), None).await.unwrap();
+            assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(response.headers["retry-after"], "7");
+        }
+    };
+    let (slow, ()) = tokio::join!(slow, overloaded);
+    assert!(metrics.render().contains("quic_overloaded_requests_total 2\n"), "{}", metrics.render());
+
+    // Once the slow request is done there is room again
+    assert_eq!(slow.unwrap().status, StatusCode::OK);
+    let response = other.send(other.request(Method::GET, "/").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+
+    busy.close().await;
+    other.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn queued_requests_give_up_after_the_queue_timeout() {
+    for (queue_timeout, status) in [(Duration::from_millis(300), StatusCode::SERVICE_UNAVAILABLE), (Duration::from_secs(5), StatusCode::OK)] {
+        let (addr, _serving) = common::spawn(
+            Server::builder()
+                .debug_routes(true)
+                .max_inflight_requests(Some(1))
+                .overload_policy(OverloadPolicy::Queue)
+                .queue_timeout(queue_timeout),
+        );
+
+        let busy = Client::builder(addr, "localhost").connect().await.unwrap();
+        let queued = Client::builder(addr, "localhost").connect().await.unwrap();
+        let slow = busy.send(busy.request(Method::GET, "/debug/slow?secs=1").body(()).unwrap(), None);
+        let waiting = async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let started = Instant::now();
+            let response = queued.send(queued.request(Method::GET, "/").body(()).unwrap(), None).await.unwrap();
+            (response, started.elapsed())
+        };
+        let (slow, (response, waited)) = tokio::join!(slow, waiting);
+        assert_eq!(slow.unwrap().status, StatusCode::OK);
+        assert_eq!(response.status, status, "{:?}", queue_timeout);
+        match status {
+            // Turned away at the timeout, long before the slot would have come free
+            StatusCode::SERVICE_UNAVAILABLE => assert!(waited >= queue_timeout && waited < Duration::from_millis(700), "{:?}", waited),
+            // Served once the slow request let go of the slot, 800ms later
+            _ => assert!(waited >= Duration::from_millis(600), "{:?}", waited),
+        }
+
+        busy.close().await;
+        queued.close().await;
+    }
+}
@@ -0,0 +1,27 @@
+// Dot segments in a request path are resolved before routing, also when percent-encoded, and a
+// path that climbs above the root is refused with 400.
+
+mod common;
+
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::server::Server;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dot_segments_are_normalized() {
+    let (addr, _serving) = common::spawn(Server::builder());
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+
+    for path in ["/./test", "/static/../test", "/%2e/test"] {
+        let response = client.send(client.request(Method::GET, path).body(()).unwrap(), None).await.unwrap();
+        assert_eq!(response.status, StatusCode::OK, "{}", path);
+        assert_eq!(response.body, "Hello from http3 test endpoint", "{}", path);
+    }
+    for path in ["/../test", "/a/%2E%2e/../test"] {
+        let response = client.send(client.request(Method::GET, path).body(()).unwrap(), None).await.unwrap();
+        assert_eq!(response.status, StatusCode::BAD_REQUEST, "{}", path);
+    }
+
+    client.close().await;
+}
@@ -0,0 +1,51 @@
+// Responses are sent in the order of their requests' `priority` urgency: a download asked for
+// with u=0 overtakes a u=7 one of the same size that started first, on the same connection.
+
+mod common;
+
+use std::time::Duration;
+
+use bytes::Buf;
+use quic_demo::server::Server;
+use quic_demo::simulate::NetworkConditions;
+use tokio::sync::mpsc;
+
+const BODY_LEN: u64 = 32 * 1024 * 1024;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn urgent_responses_overtake_others() {
+    let (addr, _serving) = common::spawn(Server::builder().generate_route(true));
+    let endpoint = common::client_endpoint(NetworkConditions::default());
+    let (send_request, _conn) = common::connect(&endpoint, addr).await;
+
+    let (done, mut finished) = mpsc::unbounded_channel();
+    let download = |priority: &'static str| {
+        let (mut send_request, done) = (send_request.clone(), done.clone());
+        async move {
+            let req = http::Request::get(format!("https://localhost/debug/stream?bytes={}", BODY_LEN))
+                .header("priority", priority)
+                .body(())
+                .unwrap();
+            let mut stream = send_request.send_request(req).await.unwrap();
+            stream.finish().await.unwrap();
+            assert_eq!(stream.recv_response().await.unwrap().status(), http::StatusCode::OK);
+            let mut received = 0;
+            while let Some(chunk) = stream.recv_data().await.unwrap() {
+                received += chunk.remaining() as u64;
+            }
+            assert_eq!(received, BODY_LEN);
+            done.send(priority).unwrap();
+        }
+    };
+
+    let background = tokio::spawn(download("u=7"));
+    // Under way, but far from done
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let urgent = tokio::spawn(download("u=0"));
+    let (background, urgent) = tokio::join!(background, urgent);
+    background.unwrap();
+    urgent.unwrap();
+
+    assert_eq!(finished.recv().await, Some("u=0"));
+    assert_eq!(finished.recv().await, Some("u=7"));
+}
@@ -0,0 +1,29 @@
+// `ServerBuilder::redirect` answers its path on the default host with a 308 to the location,
+// ahead of the built-in route of the same path, and leaves every other path alone.
+
+mod common;
+
+use http::header::LOCATION;
+use http::{HeaderValue, Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::server::{self, Server};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn redirected_paths_get_a_308() {
+    let (path, location) = server::parse_redirect("/docs=https://example.com/docs").unwrap();
+    let builder = Server::builder().redirect(path, location).redirect("/health", HeaderValue::from_static("/test"));
+    let (addr, _serving) = common::spawn(builder);
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    for (path, location) in [("/docs", "https://example.com/docs"), ("/health", "/test")] {
+        let response = client.send(client.request(Method::GET, path).body(()).unwrap(), None).await.unwrap();
+        assert_eq!(response.status, StatusCode::PERMANENT_REDIRECT, "{}", path);
+        assert_eq!(response.headers[LOCATION], location);
+    }
+    let response = client.send(client.request(Method::GET, "/docs/intro").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    client.close().await;
+
+    assert!(server::parse_redirect("docs=/elsewhere").is_err());
+    assert!(server::parse_redirect("/docs").is_err());
+}
@@ -0,0 +1,44 @@
+// `Router::route_timeout` replaces the server's request timeout for its path, longer or shorter,
+// while other paths keep the server's; a request over its timeout has its stream reset with
+// `H3_REQUEST_CANCELLED`.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use h3::error::{Code, StreamError};
+use http::StatusCode;
+use quic_demo::router::Router;
+use quic_demo::server::Server;
+use quic_demo::simulate::NetworkConditions;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn route_timeouts_override_the_request_timeout() {
+    let slow = |_req| async {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        "done"
+    };
+    let router = Router::new()
+        .route("/default", slow)
+        .route("/shorter", slow)
+        .route("/longer", slow)
+        .route_timeout("/shorter", Duration::from_millis(100))
+        .route_timeout("/longer", Duration::from_secs(5));
+    let (addr, _serving) =
+        common::spawn(Server::builder().host("localhost", router).request_timeout(Some(Duration::from_millis(300))));
+
+    let endpoint = common::client_endpoint(NetworkConditions::default());
+    let (mut send_request, _conn) = common::connect(&endpoint, addr).await;
+
+    let response = common::get(&mut send_request, "/longer").await.unwrap();
+    assert_eq!(response, (StatusCode::OK, "done".to_string()));
+    for (path, timeout) in [("/default", Duration::from_millis(300)), ("/shorter", Duration::from_millis(100))] {
+        let started = Instant::now();
+        match common::get(&mut send_request, path).await {
+            Err(StreamError::RemoteTerminate { code, .. }) => assert_eq!(code, Code::H3_REQUEST_CANCELLED, "{}", path),
+            other => panic!("{}: expected a reset, got {:?}", path, other),
+        }
+        let took = started.elapsed();
+        assert!(took >= timeout && took < timeout + Duration::from_millis(150), "{} took {:?}", path, took);
+    }
+}
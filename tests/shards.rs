@@ -0,0 +1,31 @@
+// A sharded server binds every shard to the one port, also when the port is picked by the OS,
+// and clients on different source ports all get served, whichever shard the kernel hands them to.
+
+mod common;
+
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::server::Server;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clients_are_served_by_every_shard() {
+    let server = common::bind(Server::builder().shards(4));
+    let addr = server.local_addr().unwrap();
+    let handle = server.spawn();
+
+    let mut clients = Vec::new();
+    for _ in 0..8 {
+        clients.push(Client::builder(addr, "localhost").connect().await.unwrap());
+    }
+    for client in &clients {
+        let response = client.send(client.request(Method::GET, "/health").body(()).unwrap(), None).await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+    }
+    assert_eq!(handle.stats().accepted_connections, 8);
+
+    for client in clients {
+        client.close().await;
+    }
+    handle.shutdown();
+    handle.stopped().await.unwrap();
+}
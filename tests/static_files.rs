@@ -0,0 +1,101 @@
+// Directories under /static/ are answered with their index.html if they have one, else with a
+// listing, 403 or 404 as `directory_listing` says, after a 301 to the path with a trailing slash;
+// missing files are 404. A file with precompressed siblings is answered with the best one the
+// client accepts, sent as is.
+
+mod common;
+
+use std::path::PathBuf;
+
+use http::header::{CONTENT_ENCODING, CONTENT_LENGTH, LOCATION, VARY};
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::server::Server;
+use quic_demo::static_files::DirectoryListing;
+
+// `site/index.html`, `files/report.txt`, `files/.hidden` and `app.js` with Brotli and gzip
+// siblings (not really compressed, to tell them apart) in a directory `name` of this test's own
+fn write_tree(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("quic-demo-static-files-{}", std::process::id())).join(name);
+    std::fs::create_dir_all(root.join("site")).unwrap();
+    std::fs::create_dir_all(root.join("files")).unwrap();
+    std::fs::write(root.join("site/index.html"), "<h1>Welcome</h1>").unwrap();
+    std::fs::write(root.join("files/report.txt"), "quarterly numbers").unwrap();
+    std::fs::write(root.join("files/.hidden"), "secret").unwrap();
+    std::fs::write(root.join("app.js"), "console.log('hi')").unwrap();
+    std::fs::write(root.join("app.js.br"), "brotli bytes").unwrap();
+    std::fs::write(root.join("app.js.gz"), "gzip bytes").unwrap();
+    root
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn directories_get_their_index_or_what_the_listing_policy_says() {
+    let root = write_tree("directories");
+
+    for listing in [DirectoryListing::List, DirectoryListing::Forbidden, DirectoryListing::NotFound] {
+        let (addr, _serving) = common::spawn(Server::builder().static_dir(Some(root.clone())).directory_listing(listing));
+        let client = Client::builder(addr, "localhost").connect().await.unwrap();
+        let get = |path: &'static str| client.send(client.request(Method::GET, path).body(()).unwrap(), None);
+
+        // An index.html is served whatever the policy
+        let response = get("/static/site").await.unwrap();
+        assert_eq!(response.status, StatusCode::MOVED_PERMANENTLY, "{:?}", listing);
+        assert_eq!(response.headers[LOCATION], "/static/site/");
+        let response = get("/static/site/?lang=en").await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, "<h1>Welcome</h1>");
+        assert_eq!(get("/static/site?lang=en").await.unwrap().headers[LOCATION], "/static/site/?lang=en");
+
+        let response = get("/static/files/").await.unwrap();
+        match listing {
+            DirectoryListing::List => {
+                assert_eq!(response.status, StatusCode::OK);
+                let html = String::from_utf8(response.body.to_vec()).unwrap();
+                assert!(html.contains(r#"<a href="report.txt">report.txt</a>"#), "{}", html);
+                assert!(!html.contains(".hidden"), "{}", html);
+            }
+            DirectoryListing::Forbidden => assert_eq!(response.status, StatusCode::FORBIDDEN),
+            DirectoryListing::NotFound => {
+                assert_eq!(response.status, StatusCode::NOT_FOUND);
+                // Not even a redirect gives the directory away
+                assert_eq!(get("/static/files").await.unwrap().status, StatusCode::NOT_FOUND);
+            }
+        }
+
+        let response = get("/static/files/report.txt").await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, "quarterly numbers");
+        for path in ["/static/missing.txt", "/static/files/missing/", "/static/..%2Fstatic-files.rs"] {
+            assert_eq!(get(path).await.unwrap().status, StatusCode::NOT_FOUND, "{}", path);
+        }
+
+        client.close().await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn precompressed_siblings_are_preferred() {
+    let (addr, _serving) = common::spawn(Server::builder().static_dir(Some(write_tree("precompressed"))));
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+
+    for (accept, encoding, body) in [
+        (Some("br, gzip"), Some("br"), "brotli bytes"),
+        (Some("gzip"), Some("gzip"), "gzip bytes"),
+        (Some("br;q=0.5, gzip"), Some("gzip"), "gzip bytes"),
+        (Some("zstd"), None, "console.log('hi')"),
+        (None, None, "console.log('hi')"),
+    ] {
+        let mut req = client.request(Method::GET, "/static/app.js");
+        if let Some(accept) = accept {
+            req = req.header("accept-encoding", accept);
+        }
+        let response = client.send(req.body(()).unwrap(), None).await.unwrap();
+        assert_eq!(response.status, StatusCode::OK, "{:?}", accept);
+        assert_eq!(response.headers.get(CONTENT_ENCODING).map(|value| value.to_str().unwrap()), encoding, "{:?}", accept);
+        assert_eq!(response.headers[CONTENT_LENGTH], body.len().to_string(), "{:?}", accept);
+        assert_eq!(response.headers[VARY], "Accept-Encoding");
+        assert_eq!(response.body, body, "{:?}", accept);
+    }
+
+    client.close().await;
+}
@@ -0,0 +1,25 @@
+// Unknown paths must come back as 404 with the not-found body, not as a 200 that only looks like
+// an error, while the known ones keep answering 200.
+
+mod common;
+
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::server::Server;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unknown_path_is_not_found() {
+    let (addr, _serving) = common::spawn(Server::builder());
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+
+    let response = client.send(client.request(Method::GET, "/unknown").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(response.body, "404 Not Found");
+
+    let response = client.send(client.request(Method::GET, "/").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "Hello from http3 server");
+
+    client.close().await;
+}
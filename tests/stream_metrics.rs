@@ -0,0 +1,53 @@
+// The active request stream gauges go up while requests overlap and back down when they finish,
+// keeping the peak, and a closed connection's own peak lands in its histogram.
+
+mod common;
+
+use futures::future::join_all;
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::server::Server;
+
+// The value of an unlabeled series in a /metrics page
+fn metric(page: &str, name: &str) -> f64 {
+    page.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("no {} in\n{}", name, page))
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn peak_concurrent_streams_are_tracked() {
+    let (addr, _serving) = common::spawn(Server::builder().debug_routes(true));
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    let slow = (0..5).map(|_| client.send(client.request(Method::GET, "/debug/slow?secs=1").body(()).unwrap(), None));
+    for response in join_all(slow).await {
+        assert_eq!(response.unwrap().status, StatusCode::OK);
+    }
+    client.close().await;
+
+    let observer = Client::builder(addr, "localhost").connect().await.unwrap();
+    let scrape = || async {
+        let response = observer.send(observer.request(Method::GET, "/metrics").body(()).unwrap(), None).await.unwrap();
+        String::from_utf8(response.body.to_vec()).unwrap()
+    };
+    // The first connection's close is recorded once its last request task has been joined
+    let mut page = scrape().await;
+    for _ in 0..50 {
+        if metric(&page, "quic_connection_peak_request_streams_count") >= 1.0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        page = scrape().await;
+    }
+
+    assert_eq!(metric(&page, "quic_active_request_streams_peak"), 5.0);
+    // Only the scrape itself is still open
+    assert_eq!(metric(&page, "quic_active_request_streams"), 1.0);
+    assert_eq!(metric(&page, "quic_connection_peak_request_streams_count"), 1.0);
+    assert_eq!(metric(&page, "quic_connection_peak_request_streams_sum"), 5.0);
+
+    observer.close().await;
+}
@@ -0,0 +1,86 @@
+// `Client::request_streaming` returns as soon as the response head is in and hands out the body
+// chunk by chunk, outside the request timeout, and a body dropped halfway stops the stream
+// without harming the connection. A server producing records one by one gives up as soon as the
+// client resets the stream.
+
+mod common;
+
+use std::time::Duration;
+
+use bytes::{Buf, BytesMut};
+use http::{Method, StatusCode};
+use quic_demo::client::Client;
+use quic_demo::server::Server;
+use quic_demo::simulate::NetworkConditions;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn body_is_read_at_the_callers_pace() {
+    let (addr, _serving) = common::spawn(Server::builder().debug_routes(true));
+
+    // Five records 100ms apart take longer than the timeout, which only covers the head
+    let client = Client::builder(addr, "localhost")
+        .request_timeout(Duration::from_millis(250))
+        .connect()
+        .await
+        .unwrap();
+    let req = client.request(Method::GET, "/debug/ndjson?records=5").body(()).unwrap();
+    let mut response = client.request_streaming(req, None).await.unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    let mut body = BytesMut::new();
+    while let Some(chunk) = response.body.chunk().await.unwrap() {
+        body.extend_from_slice(&chunk);
+    }
+    assert_eq!(response.body.received(), body.len() as u64);
+    assert!(response.body.trailers().await.unwrap().is_none());
+    let lines: Vec<_> = std::str::from_utf8(&body).unwrap().lines().map(String::from).collect();
+    assert_eq!(lines.len(), 5, "{:?}", lines);
+    assert!(lines[4].contains(r#""seq":4"#), "{}", lines[4]);
+
+    client.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dropping_the_body_stops_the_stream() {
+    let (addr, _serving) = common::spawn(Server::builder().generate_route(true));
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    // Far more than fits in flow control, so the server is still sending when the body goes
+    let req = client.request(Method::GET, "/debug/stream?mb=1000").body(()).unwrap();
+    let mut response = client.request_streaming(req, None).await.unwrap();
+    assert!(!response.body.chunk().await.unwrap().unwrap().is_empty());
+    drop(response);
+
+    let response = client.send(client.request(Method::GET, "/").body(()).unwrap(), None).await.unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(client.connections_established(), 1);
+
+    client.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_reset_stream_stops_the_records() {
+    let server = common::bind(Server::builder().debug_routes(true));
+    let metrics = server.metrics();
+    let (addr, _serving) = common::serve(server);
+
+    let endpoint = common::client_endpoint(NetworkConditions::default());
+    let (mut send_request, _conn) = common::connect(&endpoint, addr).await;
+
+    // A thousand records would take 100 seconds
+    let req = http::Request::get("https://localhost/debug/ndjson?records=1000").body(()).unwrap();
+    let mut stream = send_request.send_request(req).await.unwrap();
+    stream.finish().await.unwrap();
+    assert_eq!(stream.recv_response().await.unwrap().status(), StatusCode::OK);
+    assert!(stream.recv_data().await.unwrap().unwrap().has_remaining());
+    // quinn sends STOP_SENDING for a receive stream dropped before its end
+    drop(stream);
+
+    // The handler notices at the next record instead of running out the other 99 seconds
+    for _ in 0..50 {
+        if metrics.render().contains("quic_active_request_streams 0\n") {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("the stream is still open:\n{}", metrics.render());
+}
@@ -0,0 +1,114 @@
+// A reconnect to a server that allows early data resumes with 0-RTT. When a restarted server
+// can't resume the session and rejects the early data, idempotent requests are sent again on a
+// fresh connection and the others fail with `RequestError::ZeroRttRejected`.
+
+mod common;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::{Method, StatusCode};
+use quic_demo::client::{Client, Reconnect, RequestBody, RequestError, ZeroRtt};
+use quic_demo::server::{Server, ServerHandle};
+
+// The old server's socket may take a moment to be released after it stopped
+async fn restart(addr: SocketAddr) -> ServerHandle {
+    for _ in 0..100 {
+        if let Ok(server) = Server::builder().listen(addr).early_data(true).bind() {
+            return server.spawn();
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("{} is still in use", addr);
+}
+
+async fn get(client: &Client) -> anyhow::Result<StatusCode> {
+    Ok(client.send(client.request(Method::GET, "/").body(()).unwrap(), None).await?.status)
+}
+
+// One full handshake, so the server has issued a ticket to resume with next time
+async fn first_connection(client: &Client) {
+    assert_eq!(client.connection_info().await.unwrap().zero_rtt, ZeroRtt::NotAttempted);
+    assert_eq!(get(client).await.unwrap(), StatusCode::OK);
+    client.close().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resumed_connections_send_early_data() {
+    let server = common::bind(Server::builder().early_data(true));
+    let addr = server.local_addr().unwrap();
+    let server = server.spawn();
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    first_connection(&client).await;
+
+    assert_eq!(get(&client).await.unwrap(), StatusCode::OK);
+    assert_eq!(client.connection_info().await.unwrap().zero_rtt, ZeroRtt::Accepted);
+    assert_eq!(client.connections_established(), 2);
+
+    client.close().await;
+    server.shutdown();
+    server.stopped().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejected_early_data_is_replayed_when_idempotent() {
+    let server = common::bind(Server::builder().early_data(true));
+    let addr = server.local_addr().unwrap();
+    let server = server.spawn();
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    first_connection(&client).await;
+
+    // A new server on the same address has its own session cache, so it can't resume
+    server.shutdown();
+    server.stopped().await.unwrap();
+    let server = restart(addr).await;
+
+    assert_eq!(get(&client).await.unwrap(), StatusCode::OK);
+    // One connection for the rejected early data, one for the retry
+    assert_eq!(client.connections_established(), 3);
+    assert_ne!(client.connection_info().await.unwrap().zero_rtt, ZeroRtt::Rejected);
+
+    client.close().await;
+    server.shutdown();
+    server.stopped().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejected_early_data_is_reported_when_not_replayable() {
+    let server = common::bind(Server::builder().early_data(true));
+    let addr = server.local_addr().unwrap();
+    let server = server.spawn();
+
+    let client = Client::builder(addr, "localhost").connect().await.unwrap();
+    first_connection(&client).await;
+    server.shutdown();
+    server.stopped().await.unwrap();
+    let server = restart(addr).await;
+
+    // POST isn't replayed under the default policy
+    let req = client.request(Method::POST, "/upload").body(()).unwrap();
+    let Err(error) = client.send(req, Some(&RequestBody::Inline(Bytes::from("hi")))).await else {
+        panic!("POST was sent again");
+    };
+    assert!(matches!(error.downcast_ref(), Some(RequestError::ZeroRttRejected)), "{:#}", error);
+    // The next request gets a connection of its own
+    assert_eq!(get(&client).await.unwrap(), StatusCode::OK);
+    client.close().await;
+
+    // Nor is anything else without reconnects
+    let client = Client::builder(addr, "localhost").reconnect(Reconnect::Never).connect().await.unwrap();
+    first_connection(&client).await;
+    server.shutdown();
+    server.stopped().await.unwrap();
+    let server = restart(addr).await;
+
+    let error = get(&client).await.unwrap_err();
+    assert!(matches!(error.downcast_ref(), Some(RequestError::ZeroRttRejected)), "{:#}", error);
+
+    client.close().await;
+    server.shutdown();
+    server.stopped().await.unwrap();
+}